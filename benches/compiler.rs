@@ -0,0 +1,121 @@
+//! Measures the phases of the compiler separately: reading the tokens,
+//! parsing along with the semantic checks, and compiling to the module
+//! text. Run with `cargo bench`, or `cargo bench -- lex` for one phase.
+
+use std::io::sink;
+
+use criterion::{
+    black_box,
+    criterion_group,
+    criterion_main,
+    BenchmarkId,
+    Criterion,
+    Throughput
+};
+use pascal_compiler::{Code, SimpleBuffer, TokenStream};
+
+/// A phase run on a source, returning what it found to keep it alive
+type Phase = fn(&str) -> usize;
+
+const SMALL: &str = include_str!("../tests/data/correct/fibonacci.pas");
+
+/// The statements repeated in the generated programs, ten lines long
+const BLOCK: &str = "  for i := 1 to 10 do begin
+    a := a + i * 2;
+    if a > 100 then
+      b := b - a div 3
+    else
+      b := b + 1;
+    x := x * 1.5 + 0.25
+  end;
+  while b > 0 do
+    b := b - 7;
+";
+
+/// A program of about `lines` lines of loops, conditions and assignments.
+fn generated(lines: usize) -> String {
+    let mut program = String::from(
+        "program Generated;\n\
+         \n\
+         var\n  \
+           a, b, i: integer;\n  \
+           x: real;\n\
+         \n\
+         begin\n  \
+           a := 0;\n  \
+           b := 0;\n  \
+           x := 0.0;\n"
+    );
+    for _ in 0..lines / 10 {
+        program += BLOCK;
+    }
+    program += "  writeln_int(a)\nend.\n";
+    program
+}
+
+fn inputs() -> Vec<(&'static str, String)> {
+    vec![
+        ("small", SMALL.to_string()),
+        ("medium", generated(1_000)),
+        ("large", generated(100_000)),
+    ]
+}
+
+fn token_stream(source: &str) -> TokenStream<SimpleBuffer> {
+    TokenStream::new(SimpleBuffer::new(source.as_bytes(), None))
+}
+
+fn lex(source: &str) -> usize {
+    let mut ts = token_stream(source);
+    let mut count = 0;
+    while let Ok(token) = ts.next() {
+        if token.category() == "eof" {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+fn check(source: &str) -> usize {
+    let errors = Code::new(token_stream(source), Box::new(sink()))
+        .check()
+        .unwrap();
+    errors.count()
+}
+
+fn compile(source: &str) -> usize {
+    let errors = Code::new(token_stream(source), Box::new(sink()))
+        .compile()
+        .unwrap();
+    errors.count()
+}
+
+fn phases(c: &mut Criterion) {
+    let inputs = inputs();
+    let phases: [(&str, Phase); 3] = [
+        ("lex", lex),
+        ("check", check),
+        ("compile", compile),
+    ];
+
+    for (phase, run) in phases.iter() {
+        let mut group = c.benchmark_group(*phase);
+        for (name, source) in &inputs {
+            assert!(*phase == "lex" || run(source) == 0, "{} fails", name);
+            if *name == "large" {
+                group.sample_size(10);
+            }
+            group.throughput(Throughput::Bytes(source.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::from_parameter(name),
+                source.as_str(),
+                |b, source| b.iter(|| run(black_box(source)))
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, phases);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pascal_compiler::{check_bytes, Code, SimpleBuffer, TokenStream};
+
+// Any bytes must come out as diagnostics rather than a panic,
+// both from the checks and from the code generation
+fuzz_target!(|data: &[u8]| {
+    let _ = check_bytes(data);
+    let ts = TokenStream::new(SimpleBuffer::new(data, None));
+    let _ = Code::new(ts, Box::new(std::io::sink())).compile();
+});
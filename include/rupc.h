@@ -0,0 +1,55 @@
+/* The C interface of the rupc Pascal compiler, built with
+ * `cargo rustc --lib --release --features capi --crate-type cdylib`. */
+
+#ifndef RUPC_H
+#define RUPC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The program compiled */
+#define RUPC_OK 0
+/* The program has errors, which the diagnostics describe */
+#define RUPC_ERRORS 1
+/* A required pointer is null */
+#define RUPC_INVALID_ARGUMENT (-1)
+/* The compiler failed on its own */
+#define RUPC_INTERNAL_ERROR (-2)
+
+/* Bytes allocated by the compiler, followed by a zero byte
+ * not counted in len, so the text outputs are also C strings. */
+typedef struct RupcBuffer {
+    uint8_t *data;
+    size_t len;
+} RupcBuffer;
+
+/* Compiles the program in source, len bytes long, into the module text
+ * and stores it in out. The diagnostics go to diagnostics unless it is
+ * NULL. Both buffers must be released with rupc_free. */
+int rupc_compile(
+    const uint8_t *source,
+    size_t len,
+    RupcBuffer *out,
+    RupcBuffer *diagnostics
+);
+
+/* Like rupc_compile, but stores the binary module in out. */
+int rupc_compile_wasm(
+    const uint8_t *source,
+    size_t len,
+    RupcBuffer *out,
+    RupcBuffer *diagnostics
+);
+
+/* Releases a buffer the compiler stored, after which it is empty. */
+void rupc_free(RupcBuffer *buffer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
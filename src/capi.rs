@@ -0,0 +1,220 @@
+//! The compiler as a C library. The functions take the source code
+//! as bytes and hand out buffers the caller releases with `rupc_free`;
+//! include/rupc.h declares them.
+
+use std::{
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::CompilerSession;
+
+/// The program compiled
+pub const RUPC_OK: c_int = 0;
+/// The program has errors, which the diagnostics describe
+pub const RUPC_ERRORS: c_int = 1;
+/// A required pointer is null
+pub const RUPC_INVALID_ARGUMENT: c_int = -1;
+/// The compiler failed on its own
+pub const RUPC_INTERNAL_ERROR: c_int = -2;
+
+/// Bytes allocated by the compiler. They are followed by a zero byte
+/// not counted in `len`, so the text outputs are also C strings.
+#[repr(C)]
+pub struct RupcBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl RupcBuffer {
+    const EMPTY: RupcBuffer = RupcBuffer { data: ptr::null_mut(), len: 0 };
+
+    fn new(mut bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        bytes.push(0);
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        RupcBuffer { data, len }
+    }
+}
+
+/// Compiles the program in `source`, `len` bytes long, into the module
+/// text and stores it in `out`. The diagnostics, rendered with the lines
+/// they point at, go to `diagnostics` unless it is null. Returns `RUPC_OK`, `RUPC_ERRORS`
+/// with an empty `out`, or a negative code on failure.
+///
+/// # Safety
+///
+/// `source` must point to `len` readable bytes, `out` and `diagnostics`
+/// to writable buffers, and the buffers stored in them must be released
+/// with `rupc_free`.
+#[no_mangle]
+pub unsafe extern "C" fn rupc_compile(
+    source: *const u8,
+    len: usize,
+    out: *mut RupcBuffer,
+    diagnostics: *mut RupcBuffer
+) -> c_int {
+    compile_with(source, len, out, diagnostics, Ok)
+}
+
+/// Like `rupc_compile`, but stores the binary module in `out`.
+///
+/// # Safety
+///
+/// The same as for `rupc_compile`.
+#[no_mangle]
+pub unsafe extern "C" fn rupc_compile_wasm(
+    source: *const u8,
+    len: usize,
+    out: *mut RupcBuffer,
+    diagnostics: *mut RupcBuffer
+) -> c_int {
+    compile_with(source, len, out, diagnostics, |text| {
+        wat::parse_bytes(&text)
+            .map(|binary| binary.into_owned())
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// Releases a buffer the compiler stored, after which it is empty.
+///
+/// # Safety
+///
+/// `buffer` must be null or point to a buffer stored by the compiler
+/// or already released.
+#[no_mangle]
+pub unsafe extern "C" fn rupc_free(buffer: *mut RupcBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        if !buffer.data.is_null() {
+            let bytes =
+                ptr::slice_from_raw_parts_mut(buffer.data, buffer.len + 1);
+            drop(Box::from_raw(bytes));
+        }
+        *buffer = RupcBuffer::EMPTY;
+    }
+}
+
+unsafe fn compile_with(
+    source: *const u8,
+    len: usize,
+    out: *mut RupcBuffer,
+    diagnostics: *mut RupcBuffer,
+    finish: impl FnOnce(Vec<u8>) -> Result<Vec<u8>, String>
+) -> c_int {
+    if (source.is_null() && len > 0) || out.is_null() {
+        return RUPC_INVALID_ARGUMENT;
+    }
+    *out = RupcBuffer::EMPTY;
+    if let Some(diagnostics) = diagnostics.as_mut() {
+        *diagnostics = RupcBuffer::EMPTY;
+    }
+    let source = if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(source, len)
+    };
+
+    // A panic must not unwind into the caller
+    let compiled = panic::catch_unwind(AssertUnwindSafe(|| {
+        let (text, messages) = compile(source);
+        (text.map(finish), messages)
+    }));
+    let (code, module, messages) = match compiled {
+        Ok((Some(Ok(module)), messages)) => (RUPC_OK, module, messages),
+        Ok((Some(Err(e)), _)) => (RUPC_INTERNAL_ERROR, Vec::new(), e),
+        Ok((None, messages)) => (RUPC_ERRORS, Vec::new(), messages),
+        Err(_) => (
+            RUPC_INTERNAL_ERROR,
+            Vec::new(),
+            "the compiler panicked".to_string()
+        ),
+    };
+
+    *out = RupcBuffer::new(module);
+    if let Some(diagnostics) = diagnostics.as_mut() {
+        *diagnostics = RupcBuffer::new(messages.into_bytes());
+    }
+    code
+}
+
+/// The module text, if the program has no errors,
+/// and the rendered diagnostics.
+fn compile(source: &[u8]) -> (Option<Vec<u8>>, String) {
+    let compilation = CompilerSession::new().compile(None, source);
+    let messages = compilation.all_errors().iter()
+        .map(|e| compilation.source_map.render(e) + "\n")
+        .collect();
+
+    let text = if compilation.succeeded() {
+        Some(compilation.text)
+    } else {
+        None
+    };
+    (text, messages)
+}
+
+#[cfg(test)]
+mod capi_tests {
+    use super::*;
+
+    fn compile(
+        source: &str,
+        wasm: bool
+    ) -> (c_int, Vec<u8>, String) {
+        let mut out = RupcBuffer::EMPTY;
+        let mut diagnostics = RupcBuffer::EMPTY;
+        let compile = if wasm { rupc_compile_wasm } else { rupc_compile };
+
+        unsafe {
+            let code = compile(
+                source.as_ptr(), source.len(), &mut out, &mut diagnostics
+            );
+            let module = std::slice::from_raw_parts(out.data, out.len);
+            let module = module.to_vec();
+            let messages = std::ffi::CStr::from_ptr(diagnostics.data.cast())
+                .to_string_lossy()
+                .into_owned();
+
+            rupc_free(&mut out);
+            rupc_free(&mut diagnostics);
+            assert!(out.data.is_null() && diagnostics.data.is_null());
+            (code, module, messages)
+        }
+    }
+
+    #[test]
+    fn test_capi_compile() {
+        let source = "program P; var a: integer; begin a := 1 end.";
+
+        let (code, text, messages) = compile(source, false);
+        assert_eq!(code, RUPC_OK, "{}", messages);
+        assert!(text.starts_with(b"(module"));
+        assert_eq!(messages, "");
+
+        let (code, binary, _) = compile(source, true);
+        assert_eq!(code, RUPC_OK);
+        assert!(binary.starts_with(b"\0asm"));
+    }
+
+    #[test]
+    fn test_capi_errors() {
+        let (code, module, messages) =
+            compile("program P; begin a := 1 end.", false);
+
+        assert_eq!(code, RUPC_ERRORS);
+        assert!(module.is_empty());
+        assert!(messages.contains("identifier not found"), "{}", messages);
+    }
+
+    #[test]
+    fn test_capi_invalid_argument() {
+        let mut out = RupcBuffer::EMPTY;
+        let code = unsafe {
+            rupc_compile(ptr::null(), 4, &mut out, ptr::null_mut())
+        };
+
+        assert_eq!(code, RUPC_INVALID_ARGUMENT);
+        unsafe { rupc_free(ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    io::{self, IsTerminal, Write},
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    error::{formats::{self, SarifLog}, CompilationError},
+    source::SourceMap
+};
+
+/// How much the compiler tells about what it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the diagnostics and the failures
+    Quiet,
+    Normal,
+    /// Also every program compiled and every file written
+    Verbose,
+}
+
+/// How the diagnostics are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// With the offending lines to standard error
+    Human,
+    /// As JSON objects, one per line, to standard output
+    Json,
+    /// As a SARIF log of all the programs to standard output
+    /// once the compiler is done
+    Sarif,
+}
+
+/// How the messages of the compiler are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Console {
+    pub colored: bool,
+    pub verbosity: Verbosity,
+    pub format: MessageFormat,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            colored: false,
+            verbosity: Verbosity::Normal,
+            format: MessageFormat::Human,
+        }
+    }
+}
+
+static CONSOLE: OnceLock<Console> = OnceLock::new();
+
+/// The diagnostics printed as SARIF at the end
+static SARIF: Mutex<Option<SarifLog>> = Mutex::new(None);
+
+/// What is printed, in the order it is printed in
+enum Printed {
+    /// A message to standard error
+    Message(String),
+    /// A line to standard output
+    Output(String),
+    /// A diagnostic for the SARIF log
+    Sarif(CompilationError),
+}
+
+/// What a thread printed while it worked on one of several inputs
+pub struct Captured(Vec<Printed>);
+
+thread_local! {
+    /// The messages held back while the thread works
+    /// on one of several inputs
+    static CAPTURED: RefCell<Option<Vec<Printed>>> = const {
+        RefCell::new(None)
+    };
+}
+
+impl Console {
+    /// The console for the --color choice, which is auto, always or
+    /// never. Auto colors the messages when standard error is a terminal
+    /// and the NO_COLOR environment variable is not set.
+    pub fn new(
+        color: &str,
+        verbosity: Verbosity,
+        format: MessageFormat
+    ) -> Self {
+        let no_color = std::env::var_os("NO_COLOR")
+            .is_some_and(|v| !v.is_empty());
+        let colored = match color {
+            "always" => true,
+            "never" => false,
+            _ => !no_color && std::io::stderr().is_terminal(),
+        };
+        Self { colored, verbosity, format }
+    }
+}
+
+/// Sets how the messages are shown for the rest of the run.
+pub fn init(console: Console) {
+    CONSOLE.get_or_init(|| console);
+}
+
+fn console() -> Console {
+    CONSOLE.get().copied().unwrap_or_default()
+}
+
+/// Runs `f`, holding back what it prints on this thread
+/// and returning it to be printed later with `replay`.
+pub fn captured<R>(f: impl FnOnce() -> R) -> (R, Captured) {
+    let outer = CAPTURED.with(|c| c.replace(Some(Vec::new())));
+    let result = f();
+    let printed = CAPTURED.with(|c| c.replace(outer));
+    (result, Captured(printed.unwrap_or_default()))
+}
+
+/// Prints what was held back by `captured`.
+pub fn replay(captured: Captured) {
+    for printed in captured.0 {
+        print(printed);
+    }
+}
+
+/// Prints it now or holds it back if the thread is captured.
+fn print(printed: Printed) {
+    let printed = CAPTURED.with(|c| match &mut *c.borrow_mut() {
+        Some(held) => {
+            held.push(printed);
+            None
+        },
+        None => Some(printed),
+    });
+    match printed {
+        Some(Printed::Message(msg)) => eprintln!("{}", msg),
+        Some(Printed::Output(line)) => output(&line),
+        Some(Printed::Sarif(err)) => {
+            let mut log = SARIF.lock().unwrap_or_else(|e| e.into_inner());
+            log.get_or_insert_with(SarifLog::new).push(&err);
+        },
+        None => (),
+    }
+}
+
+/// Prints a message whatever the verbosity.
+pub fn message(msg: &str) {
+    print(Printed::Message(msg.to_string()));
+}
+
+/// Prints the diagnostic in the format asked for: in the context
+/// of the source code, as JSON or into the SARIF log.
+pub fn diagnostic(source_map: &SourceMap, err: &CompilationError) {
+    match console().format {
+        MessageFormat::Human => {
+            message(&source_map.render_styled(err, console().colored));
+        },
+        MessageFormat::Json => print(Printed::Output(formats::json_line(err))),
+        MessageFormat::Sarif => print(Printed::Sarif(err.clone())),
+    }
+}
+
+/// Prints the SARIF log of all the diagnostics if it was asked for,
+/// even if there were none.
+pub fn finish() {
+    if console().format != MessageFormat::Sarif {
+        return;
+    }
+    let log = SARIF.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let log = log.unwrap_or_default().to_json();
+    match serde_json::to_string_pretty(&log) {
+        Ok(text) => output(&text),
+        Err(e) => eprintln!("Failed to write the SARIF log: {}", e),
+    }
+}
+
+/// Prints the line to standard output. A reader that stops reading
+/// early, like `head`, closes the pipe, which is not an error.
+pub fn output(line: &str) {
+    let written = writeln!(io::stdout().lock(), "{}", line);
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Failed to write to standard output: {}", e);
+        }
+    }
+}
+
+/// Prints a message about the progress unless asked to be quiet.
+pub fn status(msg: &str) {
+    if console().verbosity >= Verbosity::Normal {
+        message(msg);
+    }
+}
+
+/// Prints a message only when asked to be verbose.
+pub fn verbose(msg: &str) {
+    if console().verbosity >= Verbosity::Verbose {
+        message(msg);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::{Lint, LintLevel};
+
+/// The lint and the level a directive in a comment sets for the
+/// declaration or the statement after it: `{$WARN name ON}` makes it
+/// warn, `{$WARN name OFF}` or `{%suppress name}` turns it off and
+/// `{$WARN name ERROR}` makes it an error. The name may be written with
+/// underscores for dashes. Returns none if the comment is no such
+/// directive and what is wrong with it if it is a malformed one.
+pub fn directive(comment: &str) -> Option<Result<(Lint, LintLevel), String>> {
+    let body = comment.strip_prefix('{')?;
+    let body = body.strip_suffix('}').unwrap_or(body);
+    let (command, rest) = match body.chars().next()? {
+        '$' => ("warn", &body[1..]),
+        '%' => ("suppress", &body[1..]),
+        _ => return None,
+    };
+    let mut words = rest.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case(command) {
+        return None;
+    }
+
+    Some(level(command, words.collect()))
+}
+
+fn level(
+    command: &str,
+    words: Vec<&str>
+) -> Result<(Lint, LintLevel), String> {
+    let name = match words.first() {
+        Some(name) => name.to_lowercase().replace('_', "-"),
+        None => {
+            return Err(format!("the {} directive names no lint", command))
+        },
+    };
+    let lint = Lint::from_name(&name)
+        .ok_or_else(|| format!("unknown lint \"{}\" in the directive", name))?;
+
+    let level = match (command, words.get(1)) {
+        ("suppress", None) => LintLevel::Allow,
+        ("warn", Some(state)) if state.eq_ignore_ascii_case("on") => {
+            LintLevel::Warn
+        },
+        ("warn", Some(state)) if state.eq_ignore_ascii_case("off") => {
+            LintLevel::Allow
+        },
+        ("warn", Some(state)) if state.eq_ignore_ascii_case("error") => {
+            LintLevel::Deny
+        },
+        ("warn", _) => {
+            return Err("expected ON, OFF or ERROR in the directive".into())
+        },
+        _ => return Err("the suppress directive takes one lint".into()),
+    };
+    Ok((lint, level))
+}
+
+#[cfg(test)]
+mod directives_tests {
+    use super::*;
+
+    #[test]
+    fn test_directives() {
+        let unreachable = Lint::UnreachableCode;
+        assert_eq!(
+            directive("{$WARN unreachable_code OFF}"),
+            Some(Ok((unreachable, LintLevel::Allow)))
+        );
+        assert_eq!(
+            directive("{$warn unreachable-code error}"),
+            Some(Ok((unreachable, LintLevel::Deny)))
+        );
+        assert_eq!(
+            directive("{%suppress case-mismatch}"),
+            Some(Ok((Lint::CaseMismatch, LintLevel::Allow)))
+        );
+        assert_eq!(directive("{ WARN unreachable_code OFF }"), None);
+        assert_eq!(directive("{$mode objfpc}"), None);
+    }
+
+    #[test]
+    fn test_malformed_directives() {
+        assert_eq!(
+            directive("{$WARN unused_variable OFF}"),
+            Some(Err(
+                "unknown lint \"unused-variable\" in the directive".into()
+            ))
+        );
+        assert_eq!(
+            directive("{$WARN case-mismatch}"),
+            Some(Err("expected ON, OFF or ERROR in the directive".into()))
+        );
+        assert_eq!(
+            directive("{%suppress}"),
+            Some(Err("the suppress directive names no lint".into()))
+        );
+    }
+}
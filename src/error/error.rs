@@ -2,19 +2,74 @@ use std::fmt::{Display, Formatter};
 use std::error::Error;
 use crate::position::FilePosition;
 
-#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompilationErrorKind {
     LexicalError,
     SyntaxError,
     SemanticError,
+    InternalError,
+    /// Points at a likely mistake without failing the compilation
+    Warning,
+    /// Tells why the analysis stopped early
+    Note,
+}
+
+impl CompilationErrorKind {
+    /// Whether a diagnostic of the kind fails the compilation.
+    pub fn is_error(self) -> bool {
+        !matches!(
+            self,
+            CompilationErrorKind::Warning | CompilationErrorKind::Note
+        )
+    }
+}
+
+/// A mechanical change of the source that fixes an error: the text
+/// from `start` up to `end` replaced with `replacement`, which is
+/// an insertion when they are the same.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FixIt {
+    pub start: FilePosition,
+    pub end: FilePosition,
+    pub replacement: String,
+}
+
+impl FixIt {
+    pub fn insertion(pos: FilePosition, text: &str) -> Self {
+        FixIt { start: pos, end: pos, replacement: text.to_string() }
+    }
+
+    pub fn replacement(
+        start: FilePosition,
+        end: FilePosition,
+        text: &str
+    ) -> Self {
+        FixIt { start, end, replacement: text.to_string() }
+    }
+
+    /// What the fix does, as shown after "help: ".
+    pub fn description(&self) -> String {
+        let text = self.replacement.trim();
+        if self.start == self.end {
+            format!("insert '{}' here", text)
+        } else {
+            format!("replace with '{}'", text)
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompilationError {
     kind: CompilationErrorKind,
     pos: FilePosition,
     path: Option<String>,
-    msg: String
+    msg: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fixes: Vec<FixIt>,
 }
 
 impl CompilationError {
@@ -25,15 +80,27 @@ impl CompilationError {
         msg: &str
     ) -> Self {
         CompilationError {
-            kind: kind,
+            kind,
             path: path.clone(),
-            pos: pos.clone(),
+            pos,
             msg: String::from(msg),
+            fixes: Vec::new(),
         }
     }
 
+    /// The same error with a suggested fix.
+    pub fn with_fix(mut self, fix: FixIt) -> Self {
+        self.fixes.push(fix);
+        self
+    }
+
+    /// The changes that fix the error, if it is a mechanical one.
+    pub fn fixes(&self) -> &[FixIt] {
+        &self.fixes
+    }
+
     pub fn kind(&self) -> CompilationErrorKind {
-        self.kind.clone()
+        self.kind
     }
 
     pub fn msg(&self) -> &str {
@@ -41,7 +108,17 @@ impl CompilationError {
     }
 
     pub fn pos(&self) -> FilePosition {
-        self.pos.clone()
+        self.pos
+    }
+
+    /// The name of the file the error is in.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The same error reported at another position.
+    pub fn at(self, pos: FilePosition) -> Self {
+        CompilationError { pos, ..self }
     }
 
 }
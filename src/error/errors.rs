@@ -1,30 +1,99 @@
 use std::fmt::{Display, Formatter};
-use std::collections::LinkedList;
+use std::error::Error;
+use std::ops::Index;
+use std::{slice, vec};
 use crate::error::CompilationError;
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Errors {
-    list: LinkedList<CompilationError>
+    list: Vec<CompilationError>
+}
+
+impl Default for Errors {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Errors {
     pub fn new() -> Self {
         Errors {
-            list: LinkedList::new()
+            list: Vec::new()
         }
     }
 
     pub fn push(&mut self, err: CompilationError) {
-        self.list.push_back(err)
+        self.list.push(err)
     }
 
     pub fn count(&self) -> usize {
         self.list.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// The number of diagnostics that are errors rather than
+    /// warnings or notes.
+    pub fn error_count(&self) -> usize {
+        self.list.iter().filter(|e| e.kind().is_error()).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.list.iter().any(|e| e.kind().is_error())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&CompilationError> {
+        self.list.get(index)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, CompilationError> {
+        self.list.iter()
+    }
+}
+
+impl Index<usize> for Errors {
+    type Output = CompilationError;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.list.get(index).unwrap_or_else(|| panic!(
+            "index out of bounds: there are {} errors but the index is {}",
+            self.count(), index
+        ))
+    }
+}
+
+impl IntoIterator for Errors {
+    type Item = CompilationError;
+
+    type IntoIter = vec::IntoIter<CompilationError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Errors {
+    type Item = &'a CompilationError;
+
+    type IntoIter = slice::Iter<'a, CompilationError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
+impl Error for Errors {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.list.first().map(|e| e as &(dyn Error + 'static))
+    }
 }
 
 impl Display for Errors {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        if let Some(e) = self.list.iter().nth(0) {
+        if let Some(e) = self.list.first() {
             write!(f, "{}", e)?
         }
 
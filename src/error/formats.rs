@@ -0,0 +1,202 @@
+use serde_json::{json, Value};
+
+use crate::error::{CompilationError, CompilationErrorKind, FixIt};
+
+const SARIF_VERSION: &str = "2.1.0";
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+const KINDS: [CompilationErrorKind; 6] = [
+    CompilationErrorKind::LexicalError,
+    CompilationErrorKind::SyntaxError,
+    CompilationErrorKind::SemanticError,
+    CompilationErrorKind::InternalError,
+    CompilationErrorKind::Warning,
+    CompilationErrorKind::Note,
+];
+
+/// The name of the kind in the machine-readable diagnostics,
+/// which is also the rule a SARIF result refers to.
+pub fn kind_name(kind: CompilationErrorKind) -> &'static str {
+    match kind {
+        CompilationErrorKind::LexicalError => "lexical-error",
+        CompilationErrorKind::SyntaxError => "syntax-error",
+        CompilationErrorKind::SemanticError => "semantic-error",
+        CompilationErrorKind::InternalError => "internal-error",
+        CompilationErrorKind::Warning => "warning",
+        CompilationErrorKind::Note => "note",
+    }
+}
+
+/// The diagnostic as a JSON object on a single line.
+pub fn json_line(err: &CompilationError) -> String {
+    let fixes: Vec<_> = err.fixes().iter()
+        .map(|fix| json!({
+            "start": { "line": fix.start.line, "col": fix.start.col },
+            "end": { "line": fix.end.line, "col": fix.end.col },
+            "replacement": fix.replacement,
+        }))
+        .collect();
+    json!({
+        "kind": kind_name(err.kind()),
+        "path": err.path(),
+        "line": err.pos().line,
+        "col": err.pos().col,
+        "message": err.msg(),
+        "fixes": fixes,
+    }).to_string()
+}
+
+/// The fix as a SARIF object changing the file at `uri`.
+fn sarif_fix(uri: &str, fix: &FixIt) -> Value {
+    json!({
+        "description": { "text": fix.description() },
+        "artifactChanges": [{
+            "artifactLocation": { "uri": uri },
+            "replacements": [{
+                "deletedRegion": {
+                    "startLine": fix.start.line,
+                    "startColumn": fix.start.col,
+                    "endLine": fix.end.line,
+                    "endColumn": fix.end.col,
+                },
+                "insertedContent": { "text": fix.replacement },
+            }],
+        }],
+    })
+}
+
+/// The diagnostics of one or more programs gathered into a SARIF 2.1.0
+/// log, the format code scanning services and review tools read.
+#[derive(Debug, Clone, Default)]
+pub struct SarifLog {
+    results: Vec<Value>,
+}
+
+impl SarifLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, err: &CompilationError) {
+        let level = match err.kind() {
+            CompilationErrorKind::Warning => "warning",
+            CompilationErrorKind::Note => "note",
+            _ => "error",
+        };
+        let region = json!({
+            "startLine": err.pos().line,
+            "startColumn": err.pos().col,
+        });
+        let uri = err.path().map(|path| path.replace('\\', "/"));
+        let location = match &uri {
+            Some(uri) => json!({
+                "artifactLocation": { "uri": uri },
+                "region": region,
+            }),
+            None => json!({ "region": region }),
+        };
+
+        let mut result = json!({
+            "ruleId": kind_name(err.kind()),
+            "level": level,
+            "message": { "text": err.msg() },
+            "locations": [{ "physicalLocation": location }],
+        });
+        // A fix has to name the file it changes
+        if let (Some(uri), false) = (&uri, err.fixes().is_empty()) {
+            let fixes: Vec<_> = err.fixes().iter()
+                .map(|fix| sarif_fix(uri, fix))
+                .collect();
+            result["fixes"] = json!(fixes);
+        }
+        self.results.push(result);
+    }
+
+    /// The log with a single run of the compiler.
+    pub fn to_json(&self) -> Value {
+        let rules: Vec<_> = KINDS.iter()
+            .map(|&kind| json!({
+                "id": kind_name(kind),
+                "shortDescription": { "text": format!("{:?}", kind) },
+            }))
+            .collect();
+
+        json!({
+            "$schema": SARIF_SCHEMA,
+            "version": SARIF_VERSION,
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "rupc",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    }
+                },
+                "columnKind": "unicodeCodePoints",
+                "results": self.results,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod formats_tests {
+    use super::*;
+    use crate::position::FilePosition;
+
+    fn warning() -> CompilationError {
+        CompilationError::new(
+            CompilationErrorKind::Warning,
+            &Some("dir\\a.pas".into()),
+            FilePosition::new(3, 7),
+            "unreachable statement"
+        )
+    }
+
+    #[test]
+    fn test_json_line() {
+        assert_eq!(
+            json_line(&warning()),
+            r#"{"col":7,"fixes":[],"kind":"warning","line":3,"#.to_string()
+                + r#""message":"unreachable statement","path":"dir\\a.pas"}"#
+        );
+    }
+
+    #[test]
+    fn test_sarif_log() {
+        let mut log = SarifLog::new();
+        log.push(&warning().with_fix(FixIt::insertion(
+            FilePosition::new(2, 9), ";"
+        )));
+        log.push(&CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            &None,
+            FilePosition::new(1, 1),
+            "expected a program"
+        ));
+
+        let log = log.to_json();
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"][1]["id"], "syntax-error");
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], "warning");
+        assert_eq!(results[0]["level"], "warning");
+        assert_eq!(results[0]["message"]["text"], "unreachable statement");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "dir/a.pas");
+        assert_eq!(location["region"]["startLine"], 3);
+        assert_eq!(location["region"]["startColumn"], 7);
+        let fix = &results[0]["fixes"][0];
+        assert_eq!(fix["description"]["text"], "insert ';' here");
+        let change = &fix["artifactChanges"][0];
+        assert_eq!(change["artifactLocation"]["uri"], "dir/a.pas");
+        let replacement = &change["replacements"][0];
+        assert_eq!(replacement["deletedRegion"]["endColumn"], 9);
+        assert_eq!(replacement["insertedContent"]["text"], ";");
+        assert_eq!(results[1]["level"], "error");
+        assert!(results[1]["locations"][0]["physicalLocation"]
+            .get("artifactLocation").is_none());
+    }
+}
@@ -0,0 +1,13 @@
+use crate::error::CompilationError;
+
+/// Receives diagnostics as soon as they are reported,
+/// before the compilation finishes.
+pub trait DiagnosticHandler {
+    fn handle(&mut self, error: CompilationError);
+}
+
+impl<F: FnMut(CompilationError)> DiagnosticHandler for F {
+    fn handle(&mut self, error: CompilationError) {
+        self(error)
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+/// A check for a likely mistake that is not an error by itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A declaration hides a predefined type, constant or procedure
+    ShadowedPredefined,
+    /// A case statement without an else part does not handle
+    /// some values of the enumeration it selects by
+    IncompleteCase,
+    /// A statement follows one that never finishes
+    UnreachableCode,
+    /// A field named in a with statement hides a variable
+    /// or another field of the same name
+    ShadowedVariable,
+    /// A declaration is named with a single letter, other than a
+    /// variable that is the control variable of a loop
+    SingleLetterName,
+    /// An identifier is spelled differently from another declaration
+    /// or its own, which name the same thing as the case is ignored
+    CaseMismatch,
+    /// A field is named with a word reserved in standard Pascal
+    ReservedWordField,
+}
+
+/// How a lint reports what it finds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Not at all
+    Allow,
+    /// As a warning, which does not stop the compilation
+    Warn,
+    /// As an error
+    Deny,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::ShadowedPredefined,
+        Lint::IncompleteCase,
+        Lint::UnreachableCode,
+        Lint::ShadowedVariable,
+        Lint::SingleLetterName,
+        Lint::CaseMismatch,
+        Lint::ReservedWordField,
+    ];
+
+    /// The name the lint is selected by on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::ShadowedPredefined => "shadowed-predefined",
+            Lint::IncompleteCase => "incomplete-case",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ShadowedVariable => "shadowed-variable",
+            Lint::SingleLetterName => "single-letter-name",
+            Lint::CaseMismatch => "case-mismatch",
+            Lint::ReservedWordField => "reserved-word-field",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|l| l.name() == name)
+    }
+
+    fn default_level(self) -> LintLevel {
+        match self {
+            Lint::ShadowedPredefined
+            | Lint::IncompleteCase
+            | Lint::UnreachableCode
+            | Lint::ShadowedVariable => LintLevel::Warn,
+            // The style lints are only run when asked for
+            Lint::SingleLetterName
+            | Lint::CaseMismatch
+            | Lint::ReservedWordField => LintLevel::Allow,
+        }
+    }
+}
+
+/// The levels of the lints, the default one unless set otherwise
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lints {
+    levels: HashMap<Lint, LintLevel>,
+}
+
+impl Lints {
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or(lint.default_level())
+    }
+}
+
+#[cfg(test)]
+mod lints_tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_names() {
+        for lint in Lint::ALL.iter() {
+            assert_eq!(Lint::from_name(lint.name()), Some(*lint));
+        }
+        assert_eq!(Lint::from_name("shadowed"), None);
+    }
+
+    #[test]
+    fn test_lint_levels() {
+        let mut lints = Lints::default();
+        assert_eq!(lints.level(Lint::ShadowedPredefined), LintLevel::Warn);
+        lints.set(Lint::ShadowedPredefined, LintLevel::Deny);
+        assert_eq!(lints.level(Lint::ShadowedPredefined), LintLevel::Deny);
+    }
+}
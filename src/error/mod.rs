@@ -1,5 +1,14 @@
+#[allow(clippy::module_inception)]
 pub mod error;
+pub mod directives;
 pub mod errors;
+pub mod formats;
+pub mod handler;
+pub mod lints;
+pub mod policy;
 
 pub use errors::Errors;
-pub use error::{CompilationError, CompilationErrorKind};
+pub use error::{CompilationError, CompilationErrorKind, FixIt};
+pub use handler::DiagnosticHandler;
+pub use lints::{Lint, LintLevel, Lints};
+pub use policy::ErrorPolicy;
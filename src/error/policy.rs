@@ -0,0 +1,27 @@
+/// Decides when the analysis gives up on a program with errors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorPolicy {
+    max_errors: Option<usize>,
+}
+
+impl ErrorPolicy {
+    /// Goes on to the end of the program whatever the errors.
+    pub fn unlimited() -> Self {
+        Self { max_errors: None }
+    }
+
+    /// Stops once `max` errors have been reported.
+    pub fn max_errors(max: usize) -> Self {
+        Self { max_errors: Some(max.max(1)) }
+    }
+
+    /// Stops at the first error.
+    pub fn fail_fast() -> Self {
+        Self::max_errors(1)
+    }
+
+    /// Whether to stop after reporting the given number of errors.
+    pub fn stops_after(&self, errors: usize) -> bool {
+        self.max_errors.is_some_and(|max| errors >= max)
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{
+    error::Errors,
+    tokenization::{
+        Buffer, Keyword, Operator, Punctuation, Token, TokenStream, Trivia
+    },
+};
+
+// More blank lines in a row are squeezed into one
+const MAX_NEWLINES: usize = 2;
+
+/// Lines indented one level deeper than the line
+/// that opened them with `begin`, `repeat`, `record` or `case`.
+struct Block {
+    /// The level of the line that opened the block
+    level: usize,
+    /// The open headers of the statement the block belongs to
+    pending: usize,
+    /// Whether the block holds the elements of a case statement,
+    /// whose else part starts on the level of the case
+    case: bool,
+}
+
+/// How the reserved words are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeywordCase {
+    Lower,
+    Upper,
+}
+
+impl KeywordCase {
+    pub fn apply(self, word: &str) -> String {
+        match self {
+            KeywordCase::Lower => word.to_lowercase(),
+            KeywordCase::Upper => word.to_uppercase(),
+        }
+    }
+}
+
+/// The layout of formatted and printed programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// The number of spaces per indentation level
+    pub indent: usize,
+    pub keyword_case: KeywordCase,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { indent: 2, keyword_case: KeywordCase::Lower }
+    }
+}
+
+/// Formats a program: reindents its lines and writes the keywords
+/// in lower case, keeping the comments, the line breaks and the
+/// spacing within lines. A program with lexical errors is
+/// not formatted.
+pub fn format<T: Buffer>(ts: TokenStream<T>) -> Result<String, Errors> {
+    format_with(ts, &FormatOptions::default())
+}
+
+/// Formats a program like `format` does, with the indentation
+/// and the keyword case given by `options`.
+pub fn format_with<T: Buffer>(
+    ts: TokenStream<T>,
+    options: &FormatOptions
+) -> Result<String, Errors> {
+    let mut ts = ts.with_trivia();
+    let mut formatter = Formatter::new(options.clone());
+    let mut errors = Errors::new();
+
+    loop {
+        let token = ts.next_with_trivia();
+        for e in ts.take_errors() {
+            errors.push(e);
+        }
+
+        match token {
+            Ok(token) => {
+                formatter.trivia(token.leading);
+                if token.token == Token::EOF {
+                    break;
+                }
+                formatter.token(&token.token, token.text);
+            },
+            Err(e) => {
+                errors.push(e);
+                break;
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(formatter.finish())
+    } else {
+        Err(errors)
+    }
+}
+
+struct Formatter {
+    options: FormatOptions,
+    out: String,
+    blocks: Vec<Block>,
+    /// How many `then`, `do` and `else` headers wait for
+    /// their statements to end, each indenting the lines by a level
+    pending: usize,
+    /// Whether the lines are in a `var` or `type` section
+    section: bool,
+    /// The level of the current line
+    level: usize,
+    at_line_start: bool,
+    prev: Token,
+}
+
+impl Formatter {
+    fn new(options: FormatOptions) -> Self {
+        Self {
+            options,
+            out: String::new(),
+            blocks: Vec::new(),
+            pending: 0,
+            section: false,
+            level: 0,
+            at_line_start: true,
+            prev: Token::EOF,
+        }
+    }
+
+    fn trivia(&mut self, trivia: Vec<Trivia>) {
+        for t in trivia {
+            match t {
+                Trivia::Whitespace(text) => {
+                    let newlines = text.matches('\n').count();
+                    if self.out.is_empty() {
+                        continue;
+                    } else if newlines > 0 {
+                        self.trim_line_end();
+                        for _ in 0..newlines.min(MAX_NEWLINES) {
+                            self.out.push('\n');
+                        }
+                        self.at_line_start = true;
+                    } else if !self.at_line_start {
+                        self.out += &text;
+                    }
+                },
+                Trivia::Comment(text) => {
+                    if self.at_line_start {
+                        self.start_line(self.base() + self.pending);
+                    }
+                    self.out += &text;
+                }
+            }
+        }
+    }
+
+    fn token(&mut self, token: &Token, text: String) {
+        if self.at_line_start {
+            if let Token::K(
+                Keyword::Begin | Keyword::Procedure | Keyword::Program
+                | Keyword::Var | Keyword::Type
+            ) = token {
+                self.section = false;
+            }
+            let level = self.line_level(token);
+            self.start_line(level);
+            if let Token::K(Keyword::Var | Keyword::Type) = token {
+                self.section = true;
+            }
+        }
+
+        if is_word(token) {
+            self.out += &self.options.keyword_case.apply(&text);
+        } else {
+            self.out += &text;
+        }
+
+        match token {
+            Token::K(
+                Keyword::Begin | Keyword::Repeat | Keyword::Record
+                | Keyword::Case
+            ) => {
+                self.blocks.push(Block {
+                    level: self.level,
+                    pending: self.pending,
+                    case: *token == Token::K(Keyword::Case),
+                });
+                self.pending = 0;
+            },
+            Token::K(Keyword::End | Keyword::Until) => {
+                self.pending = self.blocks.pop().map_or(0, |b| b.pending);
+            },
+            Token::K(Keyword::Then | Keyword::Do) => self.pending += 1,
+            // An `else if` chain stays on the level of its first `if`
+            Token::K(Keyword::If) if self.prev == Token::K(Keyword::Else) => {
+                self.pending = self.pending.saturating_sub(1);
+            },
+            Token::P(Punctuation::Semicolon) => self.pending = 0,
+            _ => ()
+        }
+        self.prev = token.clone();
+    }
+
+    fn finish(mut self) -> String {
+        self.trim_line_end();
+        while self.out.ends_with('\n') {
+            self.out.pop();
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    /// The level of the lines in the innermost block.
+    fn base(&self) -> usize {
+        match self.blocks.last() {
+            Some(block) => block.level + 1,
+            None => self.section as usize,
+        }
+    }
+
+    fn line_level(&self, first: &Token) -> usize {
+        match first {
+            Token::K(Keyword::End | Keyword::Until) => {
+                self.blocks.last().map_or(0, |b| b.level)
+            },
+            Token::K(Keyword::Else) if self.pending == 0 => {
+                match self.blocks.last() {
+                    Some(block) if block.case => block.level,
+                    _ => self.base(),
+                }
+            },
+            Token::K(Keyword::Begin | Keyword::Else) => {
+                self.base() + self.pending.saturating_sub(1)
+            },
+            _ => self.base() + self.pending
+        }
+    }
+
+    fn start_line(&mut self, level: usize) {
+        self.level = level;
+        self.out += &" ".repeat(level * self.options.indent);
+        self.at_line_start = false;
+    }
+
+    fn trim_line_end(&mut self) {
+        let len = self.out.trim_end_matches(&[' ', '\t', '\r'][..]).len();
+        self.out.truncate(len);
+    }
+}
+
+/// Whether the token is a reserved word.
+fn is_word(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::K(_) | Token::O(
+            Operator::And | Operator::Or | Operator::Xor | Operator::Not
+            | Operator::IntegerDivide | Operator::Modulus
+        )
+    )
+}
+
+#[cfg(test)]
+mod formatting_tests {
+    use super::*;
+    use crate::tokenization::SimpleBuffer;
+
+    fn format_str(input: &str) -> Result<String, Errors> {
+        let buf = SimpleBuffer::new(input.as_bytes(), None);
+        format(TokenStream::new(buf))
+    }
+
+    #[test]
+    fn test_format_reindents() {
+        let input = "PROGRAM Loops;\n\
+            VAR\n\
+            a, b: integer;\n\
+            \n\n\n\
+            Begin   \n\
+                  a := 0;\n\
+             while a < 3 do begin\n\
+            if a MOD 2 = 0 then\n\
+            writeln_int(a)\n\
+            else\n\
+            b := a;\n\
+            a := a + 1\n\
+            END;\n\
+            repeat\n\
+            { count down }\n\
+            a := a - 1\n\
+            until a = 0\n\
+            end.";
+        let expected = "program Loops;\n\
+            var\n  \
+              a, b: integer;\n\
+            \n\
+            begin\n  \
+              a := 0;\n  \
+              while a < 3 do begin\n    \
+                if a mod 2 = 0 then\n      \
+                  writeln_int(a)\n    \
+                else\n      \
+                  b := a;\n    \
+                a := a + 1\n  \
+              end;\n  \
+              repeat\n    \
+                { count down }\n    \
+                a := a - 1\n  \
+              until a = 0\n\
+            end.\n";
+
+        assert_eq!(format_str(input).unwrap(), expected);
+        assert_eq!(format_str(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_keeps_line_contents() {
+        let input = "program P;begin x:=1{ set }; y := 'It''s' end.";
+        assert_eq!(format_str(input).unwrap(), input.to_string() + "\n");
+    }
+
+    #[test]
+    fn test_format_else_if() {
+        let input = "begin\nif a then\nx\nelse if b then\ny\nelse\nz\nend.";
+        let expected = "begin\n  \
+            if a then\n    \
+              x\n  \
+            else if b then\n    \
+              y\n  \
+            else\n    \
+              z\n\
+            end.\n";
+
+        assert_eq!(format_str(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_case() {
+        let input = "begin\ncase a of\n1: x;\n2, 3: if b then\ny\n\
+            else\nz;\nelse\nx;\ny\nend\nend.";
+        let expected = "begin\n  \
+            case a of\n    \
+              1: x;\n    \
+              2, 3: if b then\n      \
+                y\n    \
+              else\n      \
+                z;\n  \
+            else\n    \
+              x;\n    \
+              y\n  \
+            end\n\
+            end.\n";
+
+        assert_eq!(format_str(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_lexical_error() {
+        let errors = format_str("program P; begin x := 'a end.").unwrap_err();
+        assert_eq!(errors.count(), 1);
+    }
+
+    #[test]
+    fn test_format_with_options() {
+        let input = "program P;\nvar a: integer;\nbegin\n\
+            while a > 0 do\na := a DIV 2\nend.";
+        let expected = "PROGRAM P;\n\
+            VAR a: integer;\n\
+            BEGIN\n    \
+                WHILE a > 0 DO\n        \
+                    a := a DIV 2\n\
+            END.\n";
+        let options = FormatOptions {
+            indent: 4,
+            keyword_case: KeywordCase::Upper,
+        };
+
+        let buf = SimpleBuffer::new(input.as_bytes(), None);
+        let formatted = format_with(TokenStream::new(buf), &options);
+        assert_eq!(formatted.unwrap(), expected);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    runtime,
+    session::CompilerSession,
+    translation::Target,
+    SharedBuffer,
+};
+
+const EXPECTED_EXTENSION: &str = "expected";
+
+/// How running a program went
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    /// The reasons, one per line
+    Failed(Vec<String>),
+    Skipped(String),
+}
+
+/// The number of programs that passed, failed and were skipped
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+/// Compiles and runs every program in the directory, comparing what
+/// it prints with the `.expected` file next to it, and reports the
+/// results to `out` in the Test Anything Protocol.
+pub fn run(
+    dir: &Path,
+    optimize: bool,
+    out: &mut dyn Write
+) -> Result<Summary, String> {
+    let programs = programs(dir)?;
+    let write_error = |e: std::io::Error| e.to_string();
+
+    writeln!(out, "TAP version 13").map_err(write_error)?;
+    writeln!(out, "1..{}", programs.len()).map_err(write_error)?;
+
+    let mut summary = Summary::default();
+    for (i, path) in programs.iter().enumerate() {
+        let name = path.file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        match check(path, optimize) {
+            Outcome::Passed => {
+                summary.passed += 1;
+                writeln!(out, "ok {} - {}", i + 1, name)
+            },
+            Outcome::Failed(reasons) => {
+                summary.failed += 1;
+                writeln!(out, "not ok {} - {}", i + 1, name)
+                    .and_then(|_| reasons.iter()
+                        .try_for_each(|r| writeln!(out, "# {}", r)))
+            },
+            Outcome::Skipped(reason) => {
+                summary.skipped += 1;
+                writeln!(out, "ok {} - {} # SKIP {}", i + 1, name, reason)
+            }
+        }.map_err(write_error)?;
+    }
+
+    writeln!(
+        out, "# {} passed, {} failed, {} skipped",
+        summary.passed, summary.failed, summary.skipped
+    ).map_err(write_error)?;
+
+    Ok(summary)
+}
+
+/// The programs in the directory, sorted by name.
+fn programs(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!(
+        "Failed to read the directory \"{}\": {}", dir.display(), e
+    ))?;
+    let mut programs: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file()
+            && path.extension().is_some_and(|e| e == "pas"))
+        .collect();
+    programs.sort();
+    Ok(programs)
+}
+
+fn check(path: &Path, optimize: bool) -> Outcome {
+    let expected_path = path.with_extension(EXPECTED_EXTENSION);
+    let expected = match std::fs::read_to_string(&expected_path) {
+        Ok(expected) => expected,
+        Err(_) => return Outcome::Skipped(format!(
+            "no {}", expected_path.file_name().unwrap().to_string_lossy()
+        )),
+    };
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => return Outcome::Failed(vec![e.to_string()]),
+    };
+    let name = path.to_string_lossy().into_owned();
+    let output = match execute(&data, &name, optimize) {
+        Ok(output) => output,
+        Err(reasons) => return Outcome::Failed(reasons),
+    };
+
+    match compare(&expected, &output) {
+        Some(reason) => Outcome::Failed(vec![reason]),
+        None => Outcome::Passed,
+    }
+}
+
+/// Compiles and runs the program, returning what it prints.
+fn execute(
+    data: &[u8],
+    name: &str,
+    optimize: bool
+) -> Result<String, Vec<String>> {
+    let compilation = CompilerSession::new()
+        .with_optimizations(optimize)
+        .compile(Some(name), data);
+    if !compilation.succeeded() {
+        return Err(compilation.all_errors().iter()
+            .flat_map(|e| compilation.source_map.render(e)
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>())
+            .collect());
+    }
+
+    let output = SharedBuffer::default();
+    wat::parse_bytes(&compilation.text)
+        .map_err(|e| format!("internal compiler error: {}", e))
+        .and_then(|binary| runtime::run(
+            &binary, &Target::default(), Box::new(output.clone())
+        ))
+        .map_err(|e| vec![e])?;
+
+    Ok(String::from_utf8_lossy(&output.0.take()).into_owned())
+}
+
+/// Describes the first line where the output differs from the expected.
+fn compare(expected: &str, output: &str) -> Option<String> {
+    let mut expected_lines = expected.lines();
+    let mut output_lines = output.lines();
+    for line in 1.. {
+        match (expected_lines.next(), output_lines.next()) {
+            (None, None) => return None,
+            (e, o) if e == o => (),
+            (e, o) => return Some(format!(
+                "line {}: expected {}, found {}",
+                line, describe(e), describe(o)
+            )),
+        }
+    }
+    None
+}
+
+fn describe(line: Option<&str>) -> String {
+    match line {
+        Some(line) => format!("\"{}\"", line),
+        None => "the end of the output".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+
+    #[test]
+    fn test_golden_correct_programs() {
+        let mut out = Vec::new();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data/correct");
+        let summary = run(&dir, false, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+
+        assert_eq!(summary.failed, 0, "{}", report);
+        assert_eq!(summary.skipped, 0, "{}", report);
+        assert!(report.starts_with("TAP version 13\n"), "{}", report);
+        assert!(report.contains("\nok 1 - fibonacci.pas\n"), "{}", report);
+
+        let summary = run(&dir, true, &mut Vec::new()).unwrap();
+        assert_eq!(summary.failed, 0);
+    }
+
+    #[test]
+    fn test_golden_compare() {
+        assert_eq!(compare("1\n2\n", "1\n2\n"), None);
+        assert_eq!(
+            compare("1\n2\n", "1\n3\n"),
+            Some("line 2: expected \"2\", found \"3\"".to_string())
+        );
+        assert_eq!(
+            compare("1\n", "1\n2\n"),
+            Some(
+                "line 2: expected the end of the output, found \"2\"".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_golden_failures() {
+        let data = b"program P; begin writeln_int(x) end.";
+        let errors = execute(data, "x.pas", false).unwrap_err();
+        assert!(errors[0].contains("identifier not found"), "{:?}", errors);
+
+        let data =
+            b"program P; var a: integer; begin writeln_int(1 div a) end.";
+        let errors = execute(data, "x.pas", false).unwrap_err();
+        assert!(errors[0].starts_with("Runtime error"), "{:?}", errors);
+    }
+}
@@ -4,8 +4,50 @@ mod parsing;
 mod semantics;
 mod tokenization;
 mod position;
+mod source;
 mod error;
 mod translation;
+mod optimization;
+mod runtime;
+mod formatting;
+mod metrics;
+mod session;
+#[cfg(feature = "capi")]
+pub mod capi;
 
-pub use parsing::code::Code;
-pub use error::{CompilationError, CompilationErrorKind, Errors};
+pub use parsing::code::{check_bytes, Code};
+pub use formatting::{format, format_with, FormatOptions, KeywordCase};
+pub use metrics::Metrics;
+pub use session::{Compilation, CompilerSession};
+pub use parsing::resolution::{def_use_map, DefUseMap, Resolution};
+pub use parsing::incremental::{EditStats, IncrementalAnalysis};
+pub use parsing::outline::{outline, Symbol};
+pub use parsing::rename::{apply_edits, rename, RenameError, TextEdit};
+pub use parsing::{ast, visit};
+pub use parsing::parser::{parse, parse_stream};
+pub use parsing::printer::print;
+pub use parsing::highlighting::{
+    semantic_tokens,
+    SemanticKind,
+    SemanticToken
+};
+pub use error::{
+    CompilationError,
+    CompilationErrorKind,
+    DiagnosticHandler,
+    ErrorPolicy,
+    Errors,
+    FixIt,
+    Lint,
+    LintLevel,
+    Lints
+};
+pub use tokenization::{Buffer, SimpleBuffer, TokenStream};
+pub use position::FilePosition;
+pub use semantics::{Array, SymbolTable, Type};
+pub use source::SourceMap;
+pub use translation::{
+    Memory, RandomSource, RuntimeErrors, Start, Target
+};
+pub use translation::backend::Backend;
+pub use translation::bindings::{RustBindings, TypeScriptBindings};
@@ -0,0 +1,595 @@
+use std::{collections::HashMap, ops::Range};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument,
+        DidCloseTextDocument,
+        DidOpenTextDocument,
+        Notification as _,
+        PublishDiagnostics,
+        ShowMessage,
+    },
+    request::{DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _},
+    Diagnostic,
+    DiagnosticSeverity,
+    DocumentSymbol,
+    DocumentSymbolResponse,
+    GotoDefinitionResponse,
+    Hover,
+    HoverContents,
+    HoverProviderCapability,
+    Location,
+    MarkupContent,
+    MarkupKind,
+    MessageType,
+    OneOf,
+    Position,
+    PublishDiagnosticsParams,
+    ServerCapabilities,
+    ShowMessageParams,
+    SymbolKind,
+    TextDocumentContentChangeEvent,
+    TextDocumentPositionParams,
+    TextDocumentSyncCapability,
+    TextDocumentSyncKind,
+    Url,
+};
+
+use crate::{
+    error::CompilationErrorKind,
+    parsing::{
+        highlighting::SemanticKind,
+        incremental::IncrementalAnalysis,
+        outline::Symbol,
+        resolution::{DefUseMap, Resolution},
+    },
+    position::FilePosition,
+    semantics::Type,
+    source::SourceMap,
+};
+
+/// Runs a language server over standard input and output
+/// until the client shuts it down.
+pub fn serve() -> Result<(), String> {
+    let (connection, io_threads) = Connection::stdio();
+    run(&connection)?;
+    drop(connection);
+    io_threads.join().map_err(|e| e.to_string())
+}
+
+/// Serves the requests coming through the connection.
+fn run(connection: &Connection) -> Result<(), String> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let capabilities = serde_json::to_value(capabilities)
+        .map_err(|e| e.to_string())?;
+    connection.initialize(capabilities).map_err(|e| e.to_string())?;
+
+    let mut documents = HashMap::new();
+    for msg in &connection.receiver {
+        let replies = match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req).map_err(|e| e.to_string())? {
+                    return Ok(());
+                }
+                vec![Message::Response(request(&documents, req))]
+            },
+            Message::Notification(n) => notification(&mut documents, n),
+            Message::Response(_) => Vec::new(),
+        };
+
+        for reply in replies {
+            connection.sender.send(reply).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// An open document and what is known about it.
+struct Document {
+    analysis: IncrementalAnalysis,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        Self { analysis: IncrementalAnalysis::new(text) }
+    }
+
+    fn text(&self) -> &str {
+        self.analysis.text()
+    }
+
+    fn source_map(&self) -> &SourceMap {
+        self.analysis.source_map()
+    }
+
+    fn identifiers(&self) -> &DefUseMap {
+        self.analysis.identifiers()
+    }
+
+    /// The name of the identifier as its declaration spells it,
+    /// or as it is spelled itself if it is predefined.
+    fn spelling<'a>(&'a self, r: &'a Resolution) -> &'a str {
+        let declaration = self.identifiers().declaration(r).unwrap_or(r);
+        self.text().get(declaration.range.clone()).unwrap_or(&r.name)
+    }
+
+    /// Applies a change the client sent, which replaces either
+    /// a range of the text or the whole of it.
+    fn change(
+        &mut self,
+        change: TextDocumentContentChangeEvent
+    ) -> Result<(), String> {
+        match change.range {
+            Some(range) => {
+                let start = self.offset(range.start);
+                let end = self.offset(range.end).max(start);
+                self.analysis.edit(start..end, &change.text)?;
+            },
+            None => *self = Document::new(&change.text),
+        }
+        Ok(())
+    }
+
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.analysis.errors().iter()
+            .map(|e| {
+                let start = position(e.pos());
+                let end = Position::new(start.line, start.character + 1);
+                Diagnostic {
+                    range: lsp_types::Range::new(start, end),
+                    severity: Some(severity(e.kind())),
+                    source: Some("rupc".to_string()),
+                    message: e.msg().to_string(),
+                    ..Diagnostic::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The identifier at the position.
+    fn resolution_at(&self, pos: Position) -> Option<&Resolution> {
+        let offset = self.source_map().offset(FilePosition::new(
+            pos.line as usize + 1,
+            pos.character as usize + 1
+        ))?;
+        self.identifiers().at(offset)
+    }
+
+    /// The byte offset of the position, the end of the text
+    /// for the positions past it.
+    fn offset(&self, pos: Position) -> usize {
+        self.source_map()
+            .offset(FilePosition::new(
+                pos.line as usize + 1,
+                pos.character as usize + 1
+            ))
+            .unwrap_or(self.analysis.text().len())
+    }
+
+    fn range(&self, range: &Range<usize>) -> lsp_types::Range {
+        lsp_types::Range::new(
+            position(self.source_map().position(range.start)),
+            position(self.source_map().position(range.end))
+        )
+    }
+}
+
+fn notification(
+    documents: &mut HashMap<Url, Document>,
+    n: Notification
+) -> Vec<Message> {
+    // A change that cannot be applied leaves the document as it was
+    let mut failed = None;
+    let uri = match n.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            match n.extract::<lsp_types::DidOpenTextDocumentParams>(
+                DidOpenTextDocument::METHOD
+            ) {
+                Ok(params) => {
+                    let doc = params.text_document;
+                    documents.insert(doc.uri.clone(), Document::new(&doc.text));
+                    doc.uri
+                },
+                Err(_) => return Vec::new(),
+            }
+        },
+        DidChangeTextDocument::METHOD => {
+            match n.extract::<lsp_types::DidChangeTextDocumentParams>(
+                DidChangeTextDocument::METHOD
+            ) {
+                // The changes apply one after another, each
+                // to the text the ones before it left
+                Ok(params) => {
+                    let uri = params.text_document.uri;
+                    let document = match documents.get_mut(&uri) {
+                        Some(document) => document,
+                        None => return Vec::new(),
+                    };
+                    for change in params.content_changes {
+                        if let Err(e) = document.change(change) {
+                            failed = Some(e);
+                            break;
+                        }
+                    }
+                    uri
+                },
+                Err(_) => return Vec::new(),
+            }
+        },
+        DidCloseTextDocument::METHOD => {
+            match n.extract::<lsp_types::DidCloseTextDocumentParams>(
+                DidCloseTextDocument::METHOD
+            ) {
+                Ok(params) => {
+                    documents.remove(&params.text_document.uri);
+                    params.text_document.uri
+                },
+                Err(_) => return Vec::new(),
+            }
+        },
+        _ => return Vec::new(),
+    };
+
+    let diagnostics = documents.get(&uri)
+        .map(Document::diagnostics)
+        .unwrap_or_default();
+
+    let mut messages = Vec::new();
+    if let Some(e) = failed {
+        let params = ShowMessageParams {
+            typ: MessageType::ERROR,
+            message: format!("Failed to apply the change to {}: {}", uri, e),
+        };
+        messages.push(Message::Notification(Notification::new(
+            ShowMessage::METHOD.to_string(),
+            params
+        )));
+    }
+    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+    messages.push(Message::Notification(Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        params
+    )));
+    messages
+}
+
+fn request(documents: &HashMap<Url, Document>, req: Request) -> Response {
+    let id = req.id.clone();
+    let result = match req.method.as_str() {
+        GotoDefinition::METHOD => {
+            extract_position(req, GotoDefinition::METHOD).map(|params| {
+                let uri = &params.text_document.uri;
+                let location = documents.get(uri).and_then(|doc| {
+                    let declaration = doc.identifiers().declaration(
+                        doc.resolution_at(params.position)?
+                    )?;
+                    Some(Location::new(uri.clone(), doc.range(&declaration.range)))
+                });
+                serde_json::to_value(location.map(GotoDefinitionResponse::Scalar))
+            })
+        },
+        HoverRequest::METHOD => {
+            extract_position(req, HoverRequest::METHOD).map(|params| {
+                let hover = documents.get(&params.text_document.uri)
+                    .and_then(|doc| {
+                        let r = doc.resolution_at(params.position)?;
+                        let value = describe(r, doc.spelling(r));
+                        Some(Hover {
+                            contents: HoverContents::Markup(MarkupContent {
+                                kind: MarkupKind::Markdown,
+                                value: format!("```pascal\n{}\n```", value),
+                            }),
+                            range: Some(doc.range(&r.range)),
+                        })
+                    });
+                serde_json::to_value(hover)
+            })
+        },
+        DocumentSymbolRequest::METHOD => {
+            req.extract::<lsp_types::DocumentSymbolParams>(
+                DocumentSymbolRequest::METHOD
+            )
+            .map_err(|e| e.to_string())
+            .map(|(_, params)| {
+                let symbols = documents.get(&params.text_document.uri)
+                    .map(document_symbols);
+                serde_json::to_value(symbols)
+            })
+        },
+        method => {
+            return Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request {}", method)
+            );
+        }
+    };
+
+    match result {
+        Ok(Ok(value)) => Response::new_ok(id, value),
+        Ok(Err(e)) => Response::new_err(
+            id, ErrorCode::InternalError as i32, e.to_string()
+        ),
+        Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e),
+    }
+}
+
+fn extract_position(
+    req: Request,
+    method: &str
+) -> Result<TextDocumentPositionParams, String> {
+    req.extract::<lsp_types::TextDocumentPositionParams>(method)
+        .map(|(_, params)| params)
+        .map_err(|e| e.to_string())
+}
+
+fn document_symbols(doc: &Document) -> DocumentSymbolResponse {
+    let symbols = doc.identifiers().outline(doc.text().as_bytes())
+        .into_iter()
+        .map(|s| document_symbol(doc, s))
+        .collect();
+
+    DocumentSymbolResponse::Nested(symbols)
+}
+
+#[allow(deprecated)]
+fn document_symbol(doc: &Document, symbol: Symbol) -> DocumentSymbol {
+    let kind = match (symbol.kind, &symbol.type_) {
+        (SemanticKind::Program, _) => SymbolKind::MODULE,
+        (SemanticKind::Type, Some(Type::Record(_))) => SymbolKind::STRUCT,
+        (SemanticKind::Type, Some(Type::Scalar(_))) => SymbolKind::ENUM,
+        (SemanticKind::Type, _) => SymbolKind::CLASS,
+        (SemanticKind::Constant, _) => SymbolKind::ENUM_MEMBER,
+        (SemanticKind::Procedure, _) => SymbolKind::FUNCTION,
+        (SemanticKind::Field, _) => SymbolKind::FIELD,
+        _ => SymbolKind::VARIABLE,
+    };
+    let children = symbol.children.into_iter()
+        .map(|s| document_symbol(doc, s))
+        .collect::<Vec<_>>();
+
+    DocumentSymbol {
+        name: symbol.name,
+        detail: symbol.type_.as_ref().map(Type::to_string),
+        kind,
+        tags: None,
+        deprecated: None,
+        range: doc.range(&symbol.range),
+        selection_range: doc.range(&symbol.name_range),
+        children: if children.is_empty() { None } else { Some(children) },
+    }
+}
+
+/// The text shown when hovering over an identifier named `name`.
+fn describe(r: &Resolution, name: &str) -> String {
+    let t = r.type_.as_ref()
+        .map(Type::to_string)
+        .unwrap_or_default();
+    match r.kind {
+        SemanticKind::Variable => format!("var {}: {}", name, t),
+        SemanticKind::Type => format!("type {} = {}", name, t),
+        SemanticKind::Procedure => format!("procedure {}", name),
+        SemanticKind::Program => format!("program {}", name),
+        _ => format!("{}: {}", name, t),
+    }
+}
+
+fn severity(kind: CompilationErrorKind) -> DiagnosticSeverity {
+    match kind {
+        CompilationErrorKind::Warning => DiagnosticSeverity::WARNING,
+        CompilationErrorKind::Note => DiagnosticSeverity::INFORMATION,
+        _ => DiagnosticSeverity::ERROR,
+    }
+}
+
+fn position(pos: FilePosition) -> Position {
+    Position::new(
+        pos.line.saturating_sub(1) as u32,
+        pos.col.saturating_sub(1) as u32
+    )
+}
+
+#[cfg(test)]
+mod lsp_tests {
+    use std::thread;
+
+    use lsp_server::RequestId;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const URI: &str = "file:///test.pas";
+
+    struct Client {
+        connection: Connection,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn request(&mut self, method: &str, params: Value) -> Response {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            self.connection.sender.send(Message::Request(
+                Request::new(id.clone(), method.to_string(), params)
+            )).unwrap();
+
+            loop {
+                match self.connection.receiver.recv().unwrap() {
+                    Message::Response(r) if r.id == id => return r,
+                    _ => ()
+                }
+            }
+        }
+
+        fn notify(&self, method: &str, params: Value) {
+            self.connection.sender.send(Message::Notification(
+                Notification::new(method.to_string(), params)
+            )).unwrap();
+        }
+
+        fn diagnostics(&self) -> Vec<Value> {
+            loop {
+                if let Message::Notification(n) =
+                    self.connection.receiver.recv().unwrap()
+                {
+                    if n.method == PublishDiagnostics::METHOD {
+                        return n.params["diagnostics"]
+                            .as_array().unwrap().clone();
+                    }
+                }
+            }
+        }
+
+        fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+            let params = json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character }
+            });
+            self.request(method, params).result.unwrap()
+        }
+    }
+
+    fn with_server(test: impl FnOnce(&mut Client)) {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || run(&server));
+
+        let mut client = Client { connection: client, next_id: 0 };
+        client.request(Initialize::METHOD, json!({ "capabilities": {} }));
+        client.notify(Initialized::METHOD, json!({}));
+
+        test(&mut client);
+
+        client.request(Shutdown::METHOD, Value::Null);
+        client.notify(Exit::METHOD, Value::Null);
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+
+    fn open(client: &Client, text: &str) {
+        client.notify(DidOpenTextDocument::METHOD, json!({
+            "textDocument": {
+                "uri": URI,
+                "languageId": "pascal",
+                "version": 1,
+                "text": text
+            }
+        }));
+    }
+
+    #[test]
+    fn test_lsp_diagnostics() {
+        with_server(|client| {
+            open(client, "program P;\nbegin\n  a := 1\nend.");
+            let diagnostics = client.diagnostics();
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+
+            client.notify(DidChangeTextDocument::METHOD, json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": "program P;\nbegin\nend." }]
+            }));
+            assert!(client.diagnostics().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_lsp_incremental_changes() {
+        with_server(|client| {
+            open(client, "program P;\nvar a: integer;\nbegin\n  a := 1\nend.");
+            assert!(client.diagnostics().is_empty());
+
+            let change = |line, from, to, text| json!({
+                "range": {
+                    "start": { "line": line, "character": from },
+                    "end": { "line": line, "character": to }
+                },
+                "text": text
+            });
+            client.notify(DidChangeTextDocument::METHOD, json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [change(3, 2, 3, "b"), change(3, 7, 8, "2")]
+            }));
+            let diagnostics = client.diagnostics();
+            assert_eq!(diagnostics.len(), 1);
+
+            client.notify(DidChangeTextDocument::METHOD, json!({
+                "textDocument": { "uri": URI, "version": 3 },
+                "contentChanges": [change(1, 4, 5, "b")]
+            }));
+            assert!(client.diagnostics().is_empty());
+
+            let hover = client.at(HoverRequest::METHOD, 3, 2);
+            assert!(hover["contents"]["value"].as_str().unwrap()
+                .contains("var b: integer"));
+        });
+    }
+
+    #[test]
+    fn test_lsp_definition_and_hover() {
+        with_server(|client| {
+            open(client, "program P;\nvar\n  x: integer;\nbegin\n  x := 1\nend.");
+            client.diagnostics();
+
+            let definition = client.at(GotoDefinition::METHOD, 4, 2);
+            assert_eq!(definition["range"]["start"], json!({
+                "line": 2, "character": 2
+            }));
+
+            let hover = client.at(HoverRequest::METHOD, 4, 2);
+            assert!(hover["contents"]["value"].as_str().unwrap()
+                .contains("var x: integer"));
+
+            assert_eq!(client.at(GotoDefinition::METHOD, 0, 0), Value::Null);
+        });
+    }
+
+    #[test]
+    fn test_lsp_hover_spelling() {
+        with_server(|client| {
+            open(
+                client,
+                "program P;\nvar\n  Total: integer;\nbegin\n  total := 1\nend."
+            );
+            client.diagnostics();
+
+            let hover = client.at(HoverRequest::METHOD, 4, 2);
+            assert!(hover["contents"]["value"].as_str().unwrap()
+                .contains("var Total: integer"));
+        });
+    }
+
+    #[test]
+    fn test_lsp_document_symbols() {
+        with_server(|client| {
+            open(client, "program P;\ntype t = integer;\nvar a, b: t;\nbegin end.");
+            client.diagnostics();
+
+            let params = json!({ "textDocument": { "uri": URI } });
+            let response = client.request(DocumentSymbolRequest::METHOD, params);
+            let symbols = response.result.unwrap();
+            assert_eq!(symbols[0]["name"], "P");
+            assert_eq!(symbols[0]["range"]["end"], json!({
+                "line": 3, "character": 10
+            }));
+
+            let names: Vec<_> = symbols[0]["children"].as_array().unwrap()
+                .iter()
+                .map(|s| s["name"].as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(names, ["t", "a", "b"]);
+        });
+    }
+}
@@ -6,131 +6,1276 @@ mod semantics;
 mod tokenization;
 mod parsing;
 mod position;
+mod source;
 mod error;
 mod translation;
+mod optimization;
+mod runtime;
+mod formatting;
+mod metrics;
+mod session;
+mod golden;
+mod lsp;
+mod repl;
+mod console;
 
-use std::{fs::File, io::{Read, Write}, path::{Path, PathBuf}, str::FromStr};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+    str::FromStr,
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
 use clap::Clap;
 use crate::{
     tokenization::{
         SimpleBuffer,
+        Token,
         TokenStream,
     },
-    parsing::code::Code,
+    parsing::{code::Code, parser},
+    formatting::{FormatOptions, KeywordCase},
+    translation::{
+        backend::Backend,
+        bindings::{RustBindings, TypeScriptBindings},
+        graph,
+        metadata,
+        Memory,
+        RandomSource,
+        RuntimeErrors,
+        Start,
+        Target,
+    },
+    console::{Console, MessageFormat, Verbosity},
+    error::{
+        CompilationError,
+        CompilationErrorKind,
+        ErrorPolicy,
+        Errors,
+        Lint,
+        LintLevel,
+        Lints
+    },
+    position::FilePosition,
+    session::{Compilation, CompilerSession},
+    source::SourceMap,
 };
+#[cfg(feature = "cranelift")]
+use crate::translation::native::{self, Native};
+
+// Stands for standard input or output in place of a path
+const STDIO: &str = "-";
+const STDIN_NAME: &str = "<stdin>";
+// How often watched programs are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
 
 /// A rudimentary Pascal compiler targeting WebAssembly
 #[derive(Clap)]
 #[clap(version = "0.8", author = "anonymous")]
 struct Args {
+    #[clap(subcommand)]
+    command: SubCommand,
+    /// Whether to color the messages: when standard error is
+    /// a terminal and NO_COLOR is not set, always or never
+    #[clap(
+        long,
+        global = true,
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"]
+    )]
+    color: String,
+    /// Print only the diagnostics and the failures
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print every program compiled and every file written
+    #[clap(short, long, global = true)]
+    verbose: bool,
+    /// How to print the diagnostics: with the offending lines, as JSON
+    /// objects one per line or as a SARIF log, the last two to
+    /// standard output
+    #[clap(
+        long,
+        global = true,
+        default_value = "human",
+        possible_values = &["human", "json", "sarif"]
+    )]
+    message_format: String,
+}
+
+#[derive(Clap)]
+enum SubCommand {
+    /// Compile a program to WebAssembly
+    Build(BuildArgs),
+    /// Check a program for errors without producing any files
+    Check(Options),
+    /// Compile a program and run it in the embedded runtime
+    Run(Options),
+    /// Print the tokens of a program
+    Tokens(TokensArgs),
+    /// Print the syntax tree of a program
+    Ast(AstArgs),
+    /// Reindent programs and write their keywords in lower case
+    Fmt(FmtArgs),
+    /// Run the programs in a directory and compare what they print
+    /// with the .expected files next to them
+    Test(TestArgs),
+    /// Rename an identifier, its declaration and all its uses
+    Rename(RenameArgs),
+    /// Print the call graph or the control flow graphs of a program
+    Graph(GraphArgs),
+    /// Run a language server over standard input and output
+    Lsp,
+    /// Run declarations, statements and expressions line by line
+    Repl,
+}
+
+/// The options shared by all the subcommands
+#[derive(Clap)]
+struct Options {
+    /// The programs to compile or glob patterns matching them,
+    /// - to read a program from standard input
+    #[clap(required = true)]
+    inputs: Vec<String>,
+    /// Optimize the generated code
+    #[clap(short = 'O')]
+    optimize: bool,
+    /// The module imported procedures come from
+    #[clap(long, default_value = "imports")]
+    import_module: String,
+    /// Import a procedure from another module, given as procedure=module
+    #[clap(
+        long = "import",
+        number_of_values = 1,
+        parse(try_from_str = parse_import)
+    )]
+    imports: Vec<(String, String)>,
+    /// The name the program is exported under
+    #[clap(long, default_value = "program")]
+    export_name: String,
+    /// Export program variables as globals
+    #[clap(long)]
+    globals: bool,
+    /// Import the memory from the host instead of exporting it
+    #[clap(long)]
+    import_memory: bool,
+    /// The initial size of the memory in 64 KiB pages
+    #[clap(long, default_value = "1")]
+    memory_pages: u32,
+    /// The maximum size of the memory in 64 KiB pages
+    #[clap(long)]
+    max_memory_pages: Option<u32>,
+    /// Whether runtime errors trap or call the imported runtime_error
+    #[clap(
+        long,
+        default_value = "trap",
+        possible_values = &["trap", "callback"]
+    )]
+    runtime_errors: String,
+    /// Whether random calls the imported random_int or a xorshift
+    /// generator defined in the module
+    #[clap(
+        long,
+        default_value = "import",
+        possible_values = &["import", "xorshift"]
+    )]
+    random: String,
+    /// How the program is started: by calling the export, on
+    /// instantiation or through an _initialize export
+    #[clap(
+        long,
+        default_value = "export",
+        possible_values = &["export", "section", "initialize"]
+    )]
+    start: String,
+    /// Stop the analysis after this many errors
+    #[clap(long, conflicts_with = "fail-fast")]
+    max_errors: Option<usize>,
+    /// Stop the analysis at the first error
+    #[clap(long)]
+    fail_fast: bool,
+    /// Do not report what a lint finds, as in -A shadowed-predefined
+    #[clap(
+        short = 'A',
+        long = "allow",
+        number_of_values = 1,
+        parse(try_from_str = parse_lint)
+    )]
+    allowed: Vec<Lint>,
+    /// Report what a lint finds as a warning, which is the default
+    #[clap(
+        short = 'W',
+        long = "warn",
+        number_of_values = 1,
+        parse(try_from_str = parse_lint)
+    )]
+    warned: Vec<Lint>,
+    /// Report what a lint finds as an error
+    #[clap(
+        short = 'D',
+        long = "deny",
+        number_of_values = 1,
+        parse(try_from_str = parse_lint)
+    )]
+    denied: Vec<Lint>,
+    /// Report the time spent in each phase of the compilation
+    /// and how much it produced
+    #[clap(long)]
+    timings: bool,
+    /// How many programs are compiled at once,
+    /// by default as many as there are processors
+    #[clap(short, long)]
+    jobs: Option<usize>,
+}
+
+#[derive(Clap)]
+struct BuildArgs {
+    #[clap(flatten)]
+    options: Options,
+    /// The output file: a .wat file for the module text, a .wasm
+    /// file for the binary, a name without either extension for both,
+    /// a directory ending with / or - to write to standard output
+    #[clap(short)]
+    output: Option<String>,
+    /// The directory the outputs are written to instead of
+    /// next to the programs
+    #[clap(long, conflicts_with = "output")]
+    out_dir: Option<PathBuf>,
+    /// What is written to standard output: the module text, the binary
+    /// or the TypeScript or Rust bindings of its imports and exports,
+    /// which are written next to the output files otherwise
+    #[clap(
+        long,
+        default_value = "wat",
+        possible_values = &["wat", "wasm", "bindings-ts", "bindings-rs"]
+    )]
+    emit: String,
+    /// Optimize the produced binary with Binaryen's wasm-opt
+    #[clap(long)]
+    post_opt: bool,
+    /// The wasm-opt executable used by --post-opt
+    #[clap(long, default_value = "wasm-opt")]
+    wasm_opt: String,
+    /// Rebuild the programs whenever they change
+    #[clap(long)]
+    watch: bool,
+    /// Also compile the program to a native executable with Cranelift
+    #[cfg(feature = "cranelift")]
+    #[clap(long)]
+    native: bool,
+    /// The C compiler that links native executables
+    #[cfg(feature = "cranelift")]
+    #[clap(long, default_value = "cc")]
+    cc: String,
+}
+
+#[derive(Clap)]
+struct TokensArgs {
+    /// The program to read, - to read it from standard input
     input: String,
-    #[clap(short, default_value = "a.wat")]
-    output: String
+    /// Print every token as a JSON object on its own line
+    #[clap(long)]
+    json: bool,
 }
 
-fn main() {
-    let args: Args = Args::parse();
+#[derive(Clap)]
+struct AstArgs {
+    /// The program to read, - to read it from standard input
+    input: String,
+    /// Print the tree as a JSON object
+    #[cfg(feature = "serde")]
+    #[clap(long)]
+    json: bool,
+}
 
-    match PathBuf::from_str(&args.input) {
-        Ok(input_path) => {
-            let filepath =
-                if let Some(p) = input_path.to_str() {
-                    format!("file {}", p)
-                } else {
-                    "input file".into()
-                };
-        
-            if !input_path.exists() {
-                eprintln!("\"{}\" doesn't exists.", filepath);
-                return;
+#[derive(Clap)]
+struct FmtArgs {
+    /// The programs to format or glob patterns matching them,
+    /// - to format standard input into standard output
+    #[clap(required = true)]
+    inputs: Vec<String>,
+    /// Fail on programs that are not formatted instead of formatting them
+    #[clap(long)]
+    check: bool,
+    /// The number of spaces per indentation level
+    #[clap(long, default_value = "2")]
+    indent: usize,
+    /// How to write the reserved words
+    #[clap(
+        long,
+        default_value = "lower",
+        possible_values = &["lower", "upper"]
+    )]
+    keyword_case: String,
+}
+
+#[derive(Clap)]
+struct TestArgs {
+    /// The directory with the programs
+    directory: PathBuf,
+    /// Optimize the generated code
+    #[clap(short = 'O')]
+    optimize: bool,
+}
+
+#[derive(Clap)]
+struct RenameArgs {
+    /// The name of the identifier to rename
+    old: String,
+    /// The new name of the identifier
+    new: String,
+    /// Where the identifier is, as file:line:col,
+    /// - as the file to rename in standard input
+    #[clap(long)]
+    at: String,
+}
+
+#[derive(Clap)]
+struct GraphArgs {
+    /// The program to read, - to read it from standard input
+    input: String,
+    /// The format of the graph
+    #[clap(long, default_value = "dot", possible_values = &["dot"])]
+    format: String,
+    /// Print the control flow graph of every routine
+    /// instead of the call graph
+    #[clap(long)]
+    cfg: bool,
+    /// Optimize the generated code before drawing it
+    #[clap(short = 'O')]
+    optimize: bool,
+}
+
+impl BuildArgs {
+    /// The directory the outputs go to, given either with --out-dir
+    /// or as an -o ending with a separator or naming a directory.
+    fn output_dir(&self) -> Option<&Path> {
+        if let Some(dir) = &self.out_dir {
+            return Some(dir);
+        }
+
+        let output = self.output.as_deref()?;
+        let is_dir = output.ends_with(std::path::is_separator)
+            || Path::new(output).is_dir();
+        (output != STDIO && is_dir).then(|| Path::new(output))
+    }
+
+    /// Where the module compiled from `input` is written. Without -o
+    /// it goes next to the program and is named after it.
+    fn destination(&self, input: &str) -> Destination {
+        let stem = match Path::new(input).file_stem() {
+            Some(stem) if input != STDIO => stem.to_os_string(),
+            _ => "a".into()
+        };
+
+        if let Some(dir) = self.output_dir() {
+            return Destination::both(&dir.join(stem));
+        }
+
+        match self.output.as_deref() {
+            Some(STDIO) => Destination::Stdout,
+            Some(output) => {
+                let path = PathBuf::from(output);
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("wat") => Destination::Files {
+                        text: Some(path),
+                        binary: None,
+                    },
+                    Some("wasm") => Destination::Files {
+                        text: None,
+                        binary: Some(path),
+                    },
+                    _ => Destination::both(&path),
+                }
+            },
+            None if input == STDIO => Destination::both(Path::new(&stem)),
+            None => Destination::both(Path::new(input)),
+        }
+    }
+
+    /// The first of the given flags that need the output to be a file.
+    fn file_flag(&self) -> Option<&'static str> {
+        #[cfg(feature = "cranelift")]
+        if self.native {
+            return Some("--native");
+        }
+
+        if self.post_opt {
+            Some("--post-opt")
+        } else {
+            None
+        }
+    }
+}
+
+impl Options {
+    /// The programs to work on, with the glob patterns expanded.
+    fn inputs(&self) -> Result<Vec<String>, String> {
+        expand_inputs(&self.inputs)
+    }
+
+
+    /// The only program to work on, for subcommands
+    /// that take a single one.
+    fn input(&self) -> Result<String, String> {
+        let mut inputs = self.inputs()?;
+        if inputs.len() > 1 {
+            return Err(format!(
+                "Expected a single program, found {}.",
+                inputs.len()
+            ));
+        }
+        Ok(inputs.remove(0))
+    }
+
+    fn error_policy(&self) -> ErrorPolicy {
+        match (self.fail_fast, self.max_errors) {
+            (true, _) => ErrorPolicy::fail_fast(),
+            (_, Some(max)) => ErrorPolicy::max_errors(max),
+            _ => ErrorPolicy::unlimited(),
+        }
+    }
+
+    /// The levels of the lints, the strictest one given for each.
+    fn lints(&self) -> Lints {
+        let mut lints = Lints::default();
+        let levels = [
+            (&self.allowed, LintLevel::Allow),
+            (&self.warned, LintLevel::Warn),
+            (&self.denied, LintLevel::Deny),
+        ];
+        for (selected, level) in levels.iter() {
+            for lint in selected.iter() {
+                lints.set(*lint, *level);
             }
-        
-            if !input_path.is_file() {
-                eprintln!("\"{}\" is a directory.", filepath);
-                return;
+        }
+        lints
+    }
+
+    /// The session the programs are compiled in,
+    /// targeting the default environment.
+    fn session(&self) -> CompilerSession {
+        CompilerSession::new()
+            .with_optimizations(self.optimize)
+            .with_error_policy(self.error_policy())
+            .with_lints(self.lints())
+    }
+
+    fn target(&self) -> Result<Target, String> {
+        if let Some(max) = self.max_memory_pages {
+            if max < self.memory_pages {
+                return Err(format!(
+                    "The maximum memory size of {} pages is less \
+                    than the initial size of {} pages.",
+                    max, self.memory_pages
+                ));
             }
+        }
 
-            let output_dir = Path::new(&args.output).parent().unwrap();
-            let stem = Path::new(&args.output)
-                .file_stem().unwrap()
-                .to_str().expect("Bad WASM file name");
-
-            let output = output_dir.join(stem.to_string() + ".wat");
-    
-            match File::open(&args.input) {
-                Ok(mut in_file) => {
-                    let mut data = Vec::with_capacity(4096);
-                    if let Err(e) = in_file.read_to_end(&mut data) {
-                        eprintln!("Error reading input file: {}", e);
-                    }
-        
-                    let buf = SimpleBuffer::new(
-                        &data,
-                        Some(args.input.clone())
-                    );
-                    let ts = TokenStream::new(buf);
-            
-                    match File::create(&output) {
-                        Ok(out_file) => {
-                            let output = Box::new(out_file);
-                            let code = Code::new(ts, output);
-
-                            match code.compile() {
-                                Ok(errs) => {
-                                    println!("{}", errs);
-                                },
-                                Err(e) => {
-                                    eprintln!("Critical: {}", e)
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!(
-                                "Failed to open {}: {}.",
-                                args.output, e
-                            );
-                            return;
-                        }
+        Ok(Target {
+            import_module: self.import_module.clone(),
+            procedure_modules: self.imports.iter().cloned().collect(),
+            export_name: self.export_name.clone(),
+            globals: self.globals,
+            memory: Memory {
+                import: self.import_memory,
+                pages: self.memory_pages,
+                max_pages: self.max_memory_pages,
+            },
+            runtime_errors: match self.runtime_errors.as_str() {
+                "callback" => RuntimeErrors::Callback,
+                _ => RuntimeErrors::Trap,
+            },
+            random: match self.random.as_str() {
+                "xorshift" => RandomSource::Xorshift,
+                _ => RandomSource::Import,
+            },
+            start: match self.start.as_str() {
+                "section" => Start::Section,
+                "initialize" => Start::Initialize,
+                _ => Start::Export,
+            },
+        })
+    }
+}
+
+/// The name of the input in diagnostics.
+fn source_name(input: &str) -> String {
+    if input == STDIO {
+        STDIN_NAME.to_string()
+    } else {
+        input.to_string()
+    }
+}
+
+/// The inputs given on the command line, with the glob patterns expanded.
+fn expand_inputs(given: &[String]) -> Result<Vec<String>, String> {
+    let mut inputs = Vec::new();
+    for input in given {
+        if input == STDIO {
+            if given.len() > 1 {
+                return Err(format!(
+                    "{} cannot be combined with other inputs",
+                    STDIO
+                ));
+            }
+            inputs.push(input.clone());
+        } else if input.contains(&['*', '?', '['][..]) {
+            let matched = expand_glob(input)?;
+            if matched.is_empty() {
+                return Err(format!("No files match \"{}\".", input));
+            }
+            inputs.extend(matched);
+        } else {
+            inputs.push(input.clone());
+        }
+    }
+
+    Ok(inputs)
+}
+
+/// The files matching the pattern, in alphabetical order.
+fn expand_glob(pattern: &str) -> Result<Vec<String>, String> {
+    let paths = glob::glob(pattern)
+        .map_err(|e| format!("Invalid pattern \"{}\": {}.", pattern, e))?;
+
+    let mut matched = Vec::new();
+    for path in paths {
+        let path = path.map_err(|e| e.to_string())?;
+        if path.is_file() {
+            matched.push(path.to_string_lossy().into_owned());
+        }
+    }
+    matched.sort();
+
+    Ok(matched)
+}
+
+fn parse_import(arg: &str) -> Result<(String, String), String> {
+    match arg.find('=') {
+        Some(i) if i > 0 && i + 1 < arg.len() => {
+            Ok((arg[..i].to_string(), arg[i + 1..].to_string()))
+        },
+        _ => Err(format!("expected procedure=module, found \"{}\"", arg))
+    }
+}
+
+fn parse_lint(arg: &str) -> Result<Lint, String> {
+    Lint::from_name(arg).ok_or_else(|| {
+        let names: Vec<_> = Lint::ALL.iter().map(|l| l.name()).collect();
+        format!(
+            "unknown lint \"{}\", expected one of: {}",
+            arg, names.join(", ")
+        )
+    })
+}
+
+/// Why a subcommand failed, which decides the exit code.
+enum Failure {
+    /// The diagnostics have been printed already
+    Diagnostics { internal: bool },
+    Message(String),
+    Internal(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Diagnostics { internal: true } | Failure::Internal(_) => 101,
+            _ => 1
+        }
+    }
+}
+
+impl Failure {
+    fn report(&self) {
+        match self {
+            Failure::Diagnostics { .. } => (),
+            Failure::Message(msg) => console::message(msg),
+            Failure::Internal(msg) => {
+                console::message(&format!("internal compiler error: {}", msg))
+            }
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(msg: String) -> Self {
+        Failure::Message(msg)
+    }
+}
+
+/// Collects the generated code in memory.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let verbosity = match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
+    let format = match args.message_format.as_str() {
+        "json" => MessageFormat::Json,
+        "sarif" => MessageFormat::Sarif,
+        _ => MessageFormat::Human,
+    };
+    console::init(Console::new(&args.color, verbosity, format));
+
+    let result = match args.command {
+        SubCommand::Build(args) if args.watch => watch(&args),
+        SubCommand::Build(args) => build(&args),
+        SubCommand::Check(options) => check(&options),
+        SubCommand::Run(options) => run(&options),
+        SubCommand::Tokens(args) => tokens(&args),
+        SubCommand::Ast(args) => ast(&args),
+        SubCommand::Fmt(args) => fmt(&args),
+        SubCommand::Test(args) => test(&args),
+        SubCommand::Rename(args) => rename(&args),
+        SubCommand::Graph(args) => graph(&args),
+        SubCommand::Lsp => lsp::serve().map_err(Failure::Internal),
+        SubCommand::Repl => repl::run().map_err(Failure::Message),
+    };
+
+    if let Err(failure) = &result {
+        failure.report();
+    }
+    console::finish();
+    if let Err(failure) = result {
+        std::process::exit(failure.exit_code());
+    }
+}
+
+fn read_input(input: &str) -> Result<Vec<u8>, String> {
+    if input == STDIO {
+        let mut data = Vec::with_capacity(4096);
+        return match std::io::stdin().read_to_end(&mut data) {
+            Ok(_) => Ok(data),
+            Err(e) => Err(format!("Error reading standard input: {}", e))
+        };
+    }
+
+    let input_path = PathBuf::from_str(input)
+        .map_err(|e| format!("Input path is invalid: {}.", e))?;
+    let filepath =
+        if let Some(p) = input_path.to_str() {
+            format!("file {}", p)
+        } else {
+            "input file".into()
+        };
+
+    if !input_path.exists() {
+        return Err(format!("\"{}\" doesn't exists.", filepath));
+    }
+
+    if !input_path.is_file() {
+        return Err(format!("\"{}\" is a directory.", filepath));
+    }
+
+    let mut in_file = File::open(input)
+        .map_err(|e| format!("Failed to create {}: {}.", filepath, e))?;
+    let mut data = Vec::with_capacity(4096);
+    if let Err(e) = in_file.read_to_end(&mut data) {
+        return Err(format!("Error reading input file: {}", e));
+    }
+
+    Ok(data)
+}
+
+/// Compiles the program, printing the diagnostics,
+/// and returns the module text if there were no errors.
+fn compile(
+    options: &Options,
+    input: &str,
+    target: Target,
+    data: &[u8]
+) -> Result<Vec<u8>, Failure> {
+    let compilation = options.session()
+        .with_target(target)
+        .compile(Some(&source_name(input)), data);
+    reported(options, input, &compilation);
+    diagnosed(compilation.result()).map(|_| compilation.text)
+}
+
+/// Prints the diagnostics of the compilation and, if they were
+/// asked for, its timings to standard error.
+fn reported(options: &Options, input: &str, compilation: &Compilation) {
+    for e in compilation.errors.iter() {
+        console::diagnostic(&compilation.source_map, e);
+    }
+    if options.timings {
+        console::message(&format!(
+            "Timings for {}:\n{}", source_name(input), compilation.metrics
+        ));
+    }
+}
+
+/// Turns the result of an analysis into a failure if it found errors.
+/// The error that stopped the analysis has been reported along with
+/// the others, so it is not printed again.
+fn diagnosed(
+    result: Result<Errors, CompilationError>
+) -> Result<(), Failure> {
+    let internal = |e: &CompilationError| {
+        e.kind() == CompilationErrorKind::InternalError
+    };
+    match result {
+        Ok(errors) if !errors.has_errors() => Ok(()),
+        Ok(errors) => Err(Failure::Diagnostics {
+            internal: errors.iter().any(internal)
+        }),
+        Err(e) => Err(Failure::Diagnostics { internal: internal(&e) }),
+    }
+}
+
+/// Runs `f` on every input. When there are several, they are spread
+/// over `jobs` threads, by default one per processor, and what each
+/// of them prints is held back and printed in the order of the inputs.
+/// The failures are reported along with the rest, and the build goes on
+/// with the next input, failing in the end if any of them failed.
+fn for_each_input(
+    inputs: &[String],
+    jobs: Option<usize>,
+    f: impl Fn(&str) -> Result<(), Failure> + Sync
+) -> Result<(), Failure> {
+    if let [input] = inputs {
+        return f(input);
+    }
+
+    let jobs = jobs
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, inputs.len().max(1));
+    let mut failed = 0;
+    let mut internal = false;
+    let pending = Mutex::new(inputs.iter().enumerate());
+    // The lock is released before the input is worked on
+    let claim = || pending.lock().unwrap_or_else(|e| e.into_inner()).next();
+    thread::scope(|s| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs {
+            let (claim, f, sender) = (&claim, &f, sender.clone());
+            s.spawn(move || {
+                while let Some((i, input)) = claim() {
+                    let outcome = console::captured(|| f(input));
+                    if sender.send((i, outcome)).is_err() {
+                        break;
                     }
-                },
-                Err(e) => {
-                    eprintln!("Failed to create {}: {}.", filepath, e);
-                    return;
                 }
+            });
+        }
+        drop(sender);
+
+        let mut done = BTreeMap::new();
+        let mut printed = 0;
+        for (i, outcome) in receiver {
+            done.insert(i, outcome);
+            while let Some((result, messages)) = done.remove(&printed) {
+                console::replay(messages);
+                if let Err(failure) = result {
+                    failure.report();
+                    failed += 1;
+                    internal |= failure.exit_code() == 101;
+                }
+                printed += 1;
             }
+        }
+    });
 
-            wat::parse_file(&output).and_then(|binary| {
-                let wasm_path = Path::new(&args.output)
-                    .parent()
-                    .unwrap()
-                    .join(format!("{}.wasm", stem));
-
-                let wasm_path = wasm_path
-                    .to_str()
-                    .expect("Bad WASM file path");
-
-                match File::create(wasm_path) {
-                    Ok(mut f) => {
-                        if let Err(e) = f.write_all(&binary) {
-                            eprintln!(
-                                "Failed to write into \"{}\": {}",
-                                wasm_path.to_string(), e
-                            );
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("Failed to create WASM file: {}", e);
-                    }
-                };
+    if failed > 0 {
+        console::status(&format!(
+            "{} of {} programs failed.", failed, inputs.len()
+        ));
+        Err(Failure::Diagnostics { internal })
+    } else {
+        Ok(())
+    }
+}
 
-                Ok(())
-            }).unwrap_or_else(|e| {
-                eprintln!("{}", e)
-            });
+fn build(args: &BuildArgs) -> Result<(), Failure> {
+    let target = args.options.target()?;
+    let inputs = args.options.inputs()?;
+    let output_dir = args.output_dir();
+    if inputs.len() > 1 && args.output.is_some() && output_dir.is_none() {
+        return Err(
+            "-o takes a single program, use --out-dir for several".to_string()
+                .into()
+        );
+    }
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!(
+            "Failed to create \"{}\": {}", dir.display(), e
+        ))?;
+    }
+
+    for_each_input(&inputs, args.options.jobs, |input| {
+        let destination = args.destination(input);
+        build_one(args, input, &destination, target.clone())
+    })
+}
+
+/// Builds the programs, then rebuilds them every time one of them
+/// changes or a new file matches a pattern, until interrupted.
+fn watch(args: &BuildArgs) -> Result<(), Failure> {
+    let inputs = args.options.inputs()?;
+    if inputs.iter().any(|input| input == STDIO) {
+        return Err("--watch needs input files".to_string().into());
+    }
+
+    let mut stamps = modification_times(&inputs);
+    loop {
+        match build(args) {
+            Ok(()) => {
+                console::status("Build finished, watching for changes.")
+            },
+            Err(failure) => {
+                failure.report();
+                console::status("Build failed, watching for changes.");
+            }
+        }
+
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let inputs = args.options.inputs().unwrap_or_default();
+            let now = modification_times(&inputs);
+            if now != stamps {
+                stamps = now;
+                break;
+            }
+        }
+    }
+}
+
+fn modification_times(
+    inputs: &[String]
+) -> Vec<(String, Option<SystemTime>)> {
+    inputs.iter()
+        .map(|input| {
+            let time = std::fs::metadata(input)
+                .and_then(|m| m.modified())
+                .ok();
+            (input.clone(), time)
+        })
+        .collect()
+}
+
+/// Where a build writes the module
+enum Destination {
+    Stdout,
+    /// The module text, the binary or both
+    Files { text: Option<PathBuf>, binary: Option<PathBuf> },
+}
+
+impl Destination {
+    /// Both the module text and the binary, named after `base`.
+    fn both(base: &Path) -> Self {
+        Destination::Files {
+            text: Some(base.with_extension("wat")),
+            binary: Some(base.with_extension("wasm")),
+        }
+    }
+}
+
+fn build_one(
+    args: &BuildArgs,
+    input: &str,
+    destination: &Destination,
+    target: Target
+) -> Result<(), Failure> {
+    let options = &args.options;
+    let (text_path, binary_path) = match destination {
+        Destination::Stdout => match args.file_flag() {
+            Some(flag) => {
+                return Err(format!("{} needs an output file", flag).into())
+            },
+            None => (None, None),
+        },
+        Destination::Files { text, binary } => (text.as_ref(), binary.as_ref()),
+    };
+    if args.post_opt && binary_path.is_none() {
+        return Err("--post-opt needs a .wasm output".to_string().into());
+    }
+
+    console::verbose(&format!("Compiling {}", source_name(input)));
+    let data = read_input(input)?;
+    let provenance = metadata::metadata(&data, &target, options.optimize);
+
+    let text = compile(options, input, target, &data)?;
+    let mut binary = wat::parse_bytes(&text)
+        .map_err(|e| Failure::Internal(e.to_string()))?
+        .into_owned();
+    binary.extend(metadata::custom_section(
+        metadata::SECTION,
+        provenance.as_bytes()
+    ));
+
+    let bindings = match args.emit.as_str() {
+        "bindings-ts" => Some(("ts", TypeScriptBindings.lower(&binary))),
+        "bindings-rs" => Some(("rs", RustBindings.lower(&binary))),
+        _ => None,
+    };
+    let bindings = bindings
+        .map(|(extension, lowered)| lowered.map(|b| (extension, b)))
+        .transpose()
+        .map_err(Failure::Internal)?;
+
+    if let Destination::Stdout = destination {
+        let emitted = match &bindings {
+            Some((_, bindings)) => bindings,
+            None if args.emit == "wasm" => &binary,
+            None => &text,
+        };
+        let mut stdout = std::io::stdout();
+        stdout.write_all(emitted)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Failed to write to standard output: {}", e))?;
+        return Ok(());
+    }
+
+    let bindings_path = bindings.as_ref().and_then(|(extension, _)| {
+        binary_path.or(text_path).map(|base| base.with_extension(extension))
+    });
+    let outputs = [
+        (text_path, &text),
+        (binary_path, &binary),
+        (bindings_path.as_ref(), bindings.as_ref().map_or(&text, |(_, b)| b)),
+    ];
+    for (path, contents) in outputs {
+        if let Some(path) = path {
+            std::fs::write(path, contents).map_err(|e| format!(
+                "Failed to write into \"{}\": {}", path.display(), e
+            ))?;
+            console::verbose(&format!("Wrote {}", path.display()));
+        }
+    }
+
+    #[cfg(feature = "cranelift")]
+    if args.native {
+        if let Some(base) = binary_path.or(text_path) {
+            let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+            let dir = base.parent().unwrap_or_else(|| Path::new(""));
+            build_native(&args.cc, &binary, dir, &stem)?;
+        }
+    }
+
+    if let (true, Some(path)) = (args.post_opt, binary_path) {
+        console::verbose(&format!(
+            "Optimizing {} with {}", path.display(), args.wasm_opt
+        ));
+        post_optimize(&args.wasm_opt, path)?;
+    }
+
+    Ok(())
+}
+
+fn check(options: &Options) -> Result<(), Failure> {
+    for_each_input(&options.inputs()?, options.jobs, |input| {
+        console::verbose(&format!("Checking {}", source_name(input)));
+        let data = read_input(input)?;
+        let compilation = options.session()
+            .check(Some(&source_name(input)), &data);
+        reported(options, input, &compilation);
+        diagnosed(compilation.result())
+    })
+}
+
+fn run(options: &Options) -> Result<(), Failure> {
+    let target = options.target()?;
+    let input = options.input()?;
+    let data = read_input(&input)?;
+
+    let text = compile(options, &input, target.clone(), &data)?;
+    let binary = wat::parse_bytes(&text)
+        .map_err(|e| Failure::Internal(e.to_string()))?;
+    runtime::run(&binary, &target, Box::new(std::io::stdout()))?;
+    Ok(())
+}
+
+fn tokens(args: &TokensArgs) -> Result<(), Failure> {
+    let data = read_input(&args.input)?;
+    let buf = SimpleBuffer::new(&data, Some(source_name(&args.input)));
+    let source_map = buf.source_map();
+    let mut ts = TokenStream::new(buf);
+
+    let mut failed = false;
+    loop {
+        let token = ts.next();
+        for e in ts.take_errors() {
+            console::diagnostic(&source_map, &e);
+            failed = true;
+        }
+
+        match token {
+            Ok(Token::EOF) => break,
+            Ok(token) => {
+                let text = ts.token_text();
+                let start = ts.token_pos();
+                let end_col = start.col + text.chars().count();
+                if args.json {
+                    let range = ts.token_range();
+                    console::output(&serde_json::json!({
+                        "line": start.line,
+                        "col": start.col,
+                        "end_line": start.line,
+                        "end_col": end_col,
+                        "start": range.start,
+                        "end": range.end,
+                        "category": token.category(),
+                        "lexeme": text,
+                    }).to_string());
+                } else {
+                    console::output(&format!(
+                        "{}:{}-{}:{}\t{}\t{}",
+                        start.line, start.col, start.line, end_col,
+                        token.category(), text
+                    ));
+                }
+            },
+            Err(e) => {
+                console::diagnostic(&source_map, &e);
+                failed = true;
+                break;
+            }
+        }
+    }
+
+    if failed {
+        Err(Failure::Diagnostics { internal: false })
+    } else {
+        Ok(())
+    }
+}
+
+fn ast(args: &AstArgs) -> Result<(), Failure> {
+    let data = read_input(&args.input)?;
+    let buf = SimpleBuffer::new(&data, Some(source_name(&args.input)));
+    let source_map = buf.source_map();
+
+    let program = match parser::parse_stream(TokenStream::new(buf)) {
+        Ok(program) => program,
+        Err(errors) => {
+            for e in errors.iter() {
+                console::diagnostic(&source_map, e);
+            }
+            return Err(Failure::Diagnostics { internal: false });
+        }
+    };
+
+    #[cfg(feature = "serde")]
+    if args.json {
+        let json = serde_json::to_string_pretty(&program)
+            .map_err(|e| Failure::Internal(e.to_string()))?;
+        console::output(&json);
+        return Ok(());
+    }
+    console::output(&format!("{:#?}", program));
+    Ok(())
+}
+
+fn fmt(args: &FmtArgs) -> Result<(), Failure> {
+    let options = FormatOptions {
+        indent: args.indent,
+        keyword_case: match args.keyword_case.as_str() {
+            "upper" => KeywordCase::Upper,
+            _ => KeywordCase::Lower,
+        },
+    };
+
+    for_each_input(&expand_inputs(&args.inputs)?, None, |input| {
+        let data = read_input(input)?;
+        let buf = SimpleBuffer::new(&data, Some(source_name(input)));
+        let source_map = buf.source_map();
+
+        let formatted = formatting::format_with(
+            TokenStream::new(buf), &options
+        );
+        let formatted = match formatted {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for e in errors.iter() {
+                    console::diagnostic(&source_map, e);
+                }
+                return Err(Failure::Diagnostics { internal: false });
+            }
+        };
+
+        if args.check {
+            if formatted.as_bytes() != data {
+                return Err(format!(
+                    "{} is not formatted.", source_name(input)
+                ).into());
+            }
+        } else if input == STDIO {
+            // The output ends the last line itself
+            let text = formatted.strip_suffix('\n').unwrap_or(&formatted);
+            console::output(text);
+        } else if formatted.as_bytes() != data {
+            std::fs::write(input, formatted).map_err(|e| format!(
+                "Failed to write into \"{}\": {}", input, e
+            ))?;
         }
-        Err(e) => {
-            eprintln!("Input path is invalid: {}.", e);
-            return;
+
+        Ok(())
+    })
+}
+
+fn test(args: &TestArgs) -> Result<(), Failure> {
+    let summary = golden::run(
+        &args.directory, args.optimize, &mut std::io::stdout()
+    )?;
+    if summary.failed > 0 {
+        return Err(format!(
+            "{} of {} programs failed.",
+            summary.failed, summary.passed + summary.failed
+        ).into());
+    }
+
+    Ok(())
+}
+
+fn rename(args: &RenameArgs) -> Result<(), Failure> {
+    let location = || format!(
+        "Expected --at to be file:line:col, found \"{}\".", args.at
+    );
+    let mut parts = args.at.rsplitn(3, ':');
+    let (col, line, input) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(col), Some(line), Some(input)) => (col, line, input),
+        _ => return Err(location().into()),
+    };
+    let pos = match (line.parse(), col.parse()) {
+        (Ok(line), Ok(col)) => FilePosition::new(line, col),
+        _ => return Err(location().into()),
+    };
+
+    let data = read_input(input)?;
+    let source_map = SourceMap::new(&data, Some(source_name(input)));
+    let offset = source_map.offset(pos).ok_or_else(|| format!(
+        "{}:{}:{} is outside of the program.", input, pos.line, pos.col
+    ))?;
+
+    let edits = parsing::rename::rename(&data, offset, &args.new)
+        .map_err(|e| format!("Cannot rename: {}.", e))?;
+    let found = edits.iter()
+        .find(|e| e.range.start <= offset && offset <= e.range.end)
+        .map(|e| String::from_utf8_lossy(&data[e.range.clone()]));
+    if let Some(found) = found {
+        if !found.eq_ignore_ascii_case(&args.old) {
+            return Err(format!(
+                "Found \"{}\" at {}, not \"{}\".", found, args.at, args.old
+            ).into());
         }
     }
+
+    let renamed = parsing::rename::apply_edits(&data, &edits);
+    if input == STDIO {
+        std::io::stdout().write_all(&renamed)
+            .map_err(|e| format!("Failed to write the program: {}", e))?;
+    } else if renamed != data {
+        std::fs::write(input, renamed).map_err(|e| format!(
+            "Failed to write into \"{}\": {}", input, e
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn graph(args: &GraphArgs) -> Result<(), Failure> {
+    let data = read_input(&args.input)?;
+    let buf = SimpleBuffer::new(&data, Some(source_name(&args.input)));
+    let source_map = buf.source_map();
+
+    let functions = Rc::new(RefCell::new(Vec::new()));
+    let collected = functions.clone();
+    let result = Code::new(TokenStream::new(buf), Box::new(std::io::sink()))
+        .with_optimizations(args.optimize)
+        .with_diagnostic_handler(move |e| {
+            console::diagnostic(&source_map, &e)
+        })
+        .with_function_handler(move |name, body| {
+            collected.borrow_mut().push(graph::Function {
+                name: name.to_string(),
+                body: body.to_vec(),
+            })
+        })
+        .compile();
+    diagnosed(result)?;
+
+    let functions = functions.take();
+    let dot = if args.cfg {
+        graph::control_flow_dot(&functions)
+    } else {
+        graph::call_graph_dot(&functions)
+    };
+    // The output ends the last line itself
+    console::output(dot.strip_suffix('\n').unwrap_or(&dot));
+
+    Ok(())
+}
+
+/// Compiles the binary to an object file and links it
+/// into an executable next to it.
+#[cfg(feature = "cranelift")]
+fn build_native(
+    cc: &str,
+    binary: &[u8],
+    output_dir: &Path,
+    stem: &str
+) -> Result<(), String> {
+    let object = Native.lower(binary)?;
+    let object_path = output_dir.join(format!("{}.o", stem));
+    if let Err(e) = std::fs::write(&object_path, object) {
+        return Err(format!(
+            "Failed to write into \"{}\": {}",
+            object_path.display(), e
+        ));
+    }
+
+    native::link(cc, &object_path, &output_dir.join(stem))
+}
+
+/// Runs wasm-opt over the binary at `path`, replacing it with the result.
+fn post_optimize(wasm_opt: &str, path: &Path) -> Result<(), String> {
+    let result = Command::new(wasm_opt)
+        .arg("-O")
+        .arg(path)
+        .arg("-o")
+        .arg(path)
+        .output();
+
+    match result {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut msg = format!(
+                "{} failed on \"{}\" ({})",
+                wasm_opt, path.display(), output.status
+            );
+            if !stderr.trim().is_empty() {
+                msg += &format!(": {}", stderr.trim());
+            }
+            Err(msg)
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(format!(
+            "{} was not found, install Binaryen or pass \
+            its location with --wasm-opt",
+            wasm_opt
+        )),
+        Err(e) => Err(format!("Failed to run {}: {}", wasm_opt, e))
+    }
 }
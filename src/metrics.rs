@@ -0,0 +1,54 @@
+use std::{fmt::Display, time::Duration};
+
+/// Where the time of a compilation went and how much it produced.
+/// The phases overlap in the single pass of the compiler, so each
+/// of them only counts the time spent in it alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Reading the tokens
+    pub lexing: Duration,
+    /// Parsing, semantic analysis and generating the instructions
+    pub parsing: Duration,
+    pub optimization: Duration,
+    /// Writing out and validating the module text
+    pub emission: Duration,
+    pub tokens: usize,
+    pub statements: usize,
+    pub errors: usize,
+    /// The instructions in the function bodies, after the optimizations
+    pub instructions: usize,
+    /// The size of the module text in bytes
+    pub output_size: usize,
+}
+
+impl Metrics {
+    pub fn total(&self) -> Duration {
+        self.lexing + self.parsing + self.optimization + self.emission
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (phase, time) in [
+            ("lexing", self.lexing),
+            ("parsing and semantics", self.parsing),
+            ("optimization", self.optimization),
+            ("emission", self.emission),
+            ("total", self.total()),
+        ] {
+            writeln!(f, "  {:<22}{:>10.3} ms", phase, millis(time))?;
+        }
+
+        write!(
+            f,
+            "  {} tokens, {} statements, {} errors, \
+            {} instructions, {} bytes of output",
+            self.tokens, self.statements, self.errors,
+            self.instructions, self.output_size
+        )
+    }
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,232 @@
+use crate::translation::{
+    instruction::{operand_start, Instruction, ValueType},
+    names
+};
+
+/// Computes each pure arithmetic expression repeated within a basic
+/// block once, keeping its value in a new local for the later uses.
+/// The locals introduced are added to `locals`.
+pub fn eliminate_common_subexpressions(
+    body: &mut Vec<Instruction>,
+    locals: &mut Vec<(String, ValueType)>
+) {
+    while let Some((occurrences, t)) = longest_repeated(body) {
+        let name = names::generated("cse", locals.len());
+
+        // Later occurrences first, so the earlier indices stay valid
+        for &(start, end) in occurrences[1..].iter().rev() {
+            body.splice(start..=end, Some(Instruction::LocalGet(name.clone())));
+        }
+        body.insert(occurrences[0].1 + 1, Instruction::LocalTee(name.clone()));
+
+        locals.push((name, t));
+    }
+}
+
+/// Finds the longest expression computed more than once in a basic block
+/// with the same values of the locals it reads, returning
+/// the ranges it occupies and the type of its value.
+fn longest_repeated(
+    body: &[Instruction]
+) -> Option<(Vec<(usize, usize)>, ValueType)> {
+    let mut best: Option<(Vec<(usize, usize)>, ValueType)> = None;
+    let mut block_start = 0;
+
+    for (i, instr) in body.iter().enumerate() {
+        if is_block_boundary(instr) {
+            block_start = i + 1;
+            continue;
+        }
+
+        let t = match instr {
+            Instruction::Binary(t, _) => *t,
+            Instruction::Compare(..) => ValueType::I32,
+            _ => continue
+        };
+
+        let start = match operand_start(body, i + 1) {
+            Some(start) if start >= block_start => start,
+            _ => continue
+        };
+
+        let expression = &body[start..=i];
+        if expression.iter().any(|e| matches!(e, Instruction::LocalTee(_))) {
+            continue;
+        }
+
+        let len = expression.len();
+        if best.as_ref().is_some_and(|(o, _)| o[0].1 - o[0].0 + 1 >= len) {
+            continue;
+        }
+
+        let occurrences = repetitions(body, start, i);
+        if occurrences.len() > 1 {
+            best = Some((occurrences, t));
+        }
+    }
+
+    best
+}
+
+/// Finds the repetitions of the expression at `start..=end` that follow it
+/// in the same basic block before any of the locals it reads is changed.
+fn repetitions(body: &[Instruction], start: usize, end: usize) -> Vec<(usize, usize)> {
+    let expression = &body[start..=end];
+    let len = expression.len();
+    let reads: Vec<&String> = expression.iter()
+        .filter_map(|e| match e {
+            Instruction::LocalGet(name) | Instruction::GlobalGet(name) => {
+                Some(name)
+            },
+            _ => None
+        })
+        .collect();
+    // The host may change exported globals during a call
+    let reads_globals = expression.iter()
+        .any(|e| matches!(e, Instruction::GlobalGet(_)));
+    // And the memory, if it is imported
+    let reads_memory = expression.iter()
+        .any(|e| matches!(e, Instruction::Load(..)));
+
+    let mut occurrences = vec![(start, end)];
+    let mut i = end + 1;
+    while i < body.len() {
+        match &body[i] {
+            instr if is_block_boundary(instr) => break,
+            Instruction::LocalSet(name)
+            | Instruction::LocalTee(name)
+            | Instruction::GlobalSet(name) if reads.contains(&name) => break,
+            Instruction::Store(..) if reads_memory => break,
+            Instruction::Call(_) if reads_globals || reads_memory => break,
+            _ => ()
+        }
+
+        let candidate = i + len - 1;
+        if candidate < body.len()
+            && &body[i..=candidate] == expression
+            && operand_start(body, candidate + 1) == Some(i) {
+            occurrences.push((i, candidate));
+            i = candidate + 1;
+        } else {
+            i += 1;
+        }
+    }
+
+    occurrences
+}
+
+fn is_block_boundary(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Block(_)
+        | Instruction::Loop(_)
+        | Instruction::If
+        | Instruction::Else
+        | Instruction::End
+        | Instruction::Br(_)
+        | Instruction::BrIf(_)
+        | Instruction::Return
+        | Instruction::Unreachable
+    )
+}
+
+#[cfg(test)]
+mod cse_tests {
+    use crate::translation::instruction::BinaryOp;
+
+    use super::*;
+
+    fn get(name: &str) -> Instruction {
+        Instruction::LocalGet(name.to_string())
+    }
+
+    fn int(v: &str) -> Instruction {
+        Instruction::Const(ValueType::I32, v.to_string())
+    }
+
+    fn binary(op: BinaryOp) -> Instruction {
+        Instruction::Binary(ValueType::I32, op)
+    }
+
+    #[test]
+    fn test_reuse_repeated_expression() {
+        // (a + 4*i) * (a + 4*i)
+        let address = vec![
+            get("a"), int("4"), get("i"), binary(BinaryOp::Mul),
+            binary(BinaryOp::Add)
+        ];
+        let mut body = [
+            address.clone(), address, vec![binary(BinaryOp::Mul)]
+        ].concat();
+        let mut locals = Vec::new();
+
+        eliminate_common_subexpressions(&mut body, &mut locals);
+
+        assert_eq!(locals, vec![("cse.0".to_string(), ValueType::I32)]);
+        assert_eq!(body, vec![
+            get("a"), int("4"), get("i"), binary(BinaryOp::Mul),
+            binary(BinaryOp::Add),
+            Instruction::LocalTee("cse.0".to_string()),
+            get("cse.0"),
+            binary(BinaryOp::Mul),
+        ]);
+    }
+
+    #[test]
+    fn test_keep_expression_after_assignment() {
+        let sum = vec![get("a"), get("b"), binary(BinaryOp::Add)];
+        let mut body = [
+            sum.clone(),
+            vec![Instruction::LocalSet("a".to_string())],
+            sum.clone(),
+            vec![Instruction::LocalSet("c".to_string())],
+        ].concat();
+        let expected = body.clone();
+        let mut locals = Vec::new();
+
+        eliminate_common_subexpressions(&mut body, &mut locals);
+
+        assert!(locals.is_empty());
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_keep_expression_in_other_block() {
+        let sum = vec![get("a"), get("b"), binary(BinaryOp::Add)];
+        let mut body = [
+            sum.clone(),
+            vec![Instruction::If],
+            sum.clone(),
+            vec![Instruction::LocalSet("c".to_string()), Instruction::End],
+        ].concat();
+        let expected = body.clone();
+        let mut locals = Vec::new();
+
+        eliminate_common_subexpressions(&mut body, &mut locals);
+
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_keep_load_after_store() {
+        let load = vec![
+            int("0"), Instruction::Load(ValueType::I32, 4),
+            int("1"), binary(BinaryOp::Add)
+        ];
+        let mut body = [
+            load.clone(),
+            vec![
+                Instruction::LocalSet("a".to_string()),
+                int("0"), int("7"), Instruction::Store(ValueType::I32, 4)
+            ],
+            load,
+            vec![Instruction::LocalSet("b".to_string())],
+        ].concat();
+        let expected = body.clone();
+        let mut locals = Vec::new();
+
+        eliminate_common_subexpressions(&mut body, &mut locals);
+
+        assert_eq!(body, expected);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::translation::instruction::{Instruction, ValueType};
+
+/// Drops the instructions that can never run: those following
+/// an unconditional branch, return or trap up to the end of
+/// the enclosing block, and conditional branches on a constant false condition.
+/// A conditional branch on a constant true condition
+/// becomes unconditional.
+pub fn eliminate_dead_code(body: &mut Vec<Instruction>) {
+    let instrs = std::mem::take(body);
+    let mut depth = 0;
+    // The depth of the block whose remaining instructions are unreachable
+    let mut dead_from: Option<usize> = None;
+
+    for instr in instrs {
+        if let Some(dead_depth) = dead_from {
+            match instr {
+                ref i if i.opens_block() => depth += 1,
+                Instruction::End if depth == dead_depth => {
+                    dead_from = None;
+                    depth -= 1;
+                    body.push(instr);
+                },
+                Instruction::End => depth -= 1,
+                Instruction::Else if depth == dead_depth => {
+                    dead_from = None;
+                    body.push(instr);
+                },
+                _ => ()
+            }
+            continue;
+        }
+
+        match instr {
+            Instruction::BrIf(label) => {
+                let condition = match body.last() {
+                    Some(Instruction::Const(ValueType::I32, v)) => {
+                        v.parse::<i32>().ok()
+                    },
+                    _ => None
+                };
+
+                match condition {
+                    Some(0) => {
+                        body.pop();
+                    },
+                    Some(_) => {
+                        body.pop();
+                        body.push(Instruction::Br(label));
+                        dead_from = Some(depth);
+                    },
+                    None => body.push(Instruction::BrIf(label))
+                }
+            },
+            Instruction::Br(_)
+            | Instruction::Return
+            | Instruction::Unreachable => {
+                body.push(instr);
+                dead_from = Some(depth);
+            },
+            ref i if i.opens_block() => {
+                depth += 1;
+                body.push(instr);
+            },
+            Instruction::End => {
+                depth -= 1;
+                body.push(instr);
+            },
+            _ => body.push(instr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod dce_tests {
+    use super::*;
+
+    fn int(v: &str) -> Instruction {
+        Instruction::Const(ValueType::I32, v.to_string())
+    }
+
+    fn set(name: &str) -> Instruction {
+        Instruction::LocalSet(name.to_string())
+    }
+
+    #[test]
+    fn test_drop_after_return() {
+        let mut body = vec![
+            int("1"), Instruction::If,
+            Instruction::Return, int("2"), set("a"),
+            Instruction::Else,
+            int("3"), set("a"),
+            Instruction::End,
+            int("4"), set("b"),
+            Instruction::Return,
+            Instruction::Block("end".to_string()),
+            int("5"), set("c"),
+            Instruction::End,
+            int("6"), set("d"),
+        ];
+
+        eliminate_dead_code(&mut body);
+
+        assert_eq!(body, vec![
+            int("1"), Instruction::If,
+            Instruction::Return,
+            Instruction::Else,
+            int("3"), set("a"),
+            Instruction::End,
+            int("4"), set("b"),
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn test_constant_conditional_branches() {
+        let end = || "end".to_string();
+        let mut body = vec![
+            Instruction::Block(end()),
+            Instruction::Loop("continue".to_string()),
+            int("0"), Instruction::BrIf(end()),
+            int("1"), Instruction::BrIf(end()),
+            int("2"), set("a"),
+            Instruction::End,
+            Instruction::End,
+        ];
+
+        eliminate_dead_code(&mut body);
+
+        assert_eq!(body, vec![
+            Instruction::Block(end()),
+            Instruction::Loop("continue".to_string()),
+            Instruction::Br(end()),
+            Instruction::End,
+            Instruction::End,
+        ]);
+    }
+}
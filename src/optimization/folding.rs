@@ -0,0 +1,255 @@
+use crate::{
+    tokenization::Relation,
+    translation::instruction::{matching_end, BinaryOp, Instruction, ValueType}
+};
+
+/// Replaces operations on constant operands with their results
+/// and drops the branches of an `if` that cannot be taken.
+pub fn fold_constants(body: &mut Vec<Instruction>) {
+    let instrs = std::mem::take(body);
+    fold_into(body, instrs);
+}
+
+fn fold_into(folded: &mut Vec<Instruction>, instrs: Vec<Instruction>) {
+    let mut rest = instrs.into_iter();
+    while let Some(instr) = rest.next() {
+        match instr {
+            Instruction::Binary(t, op) => {
+                let value = constant_operands(folded, t)
+                    .and_then(|(a, b)| binary(t, op, &a, &b));
+                push_folded(folded, instr, value, 2);
+            },
+            Instruction::Compare(t, rel) => {
+                let value = constant_operands(folded, t)
+                    .and_then(|(a, b)| compare(t, rel, &a, &b))
+                    .map(|r| (r as i32).to_string());
+                push_folded(folded, instr, value, 2);
+            },
+            Instruction::Eqz(ValueType::I32) => {
+                let value = match folded.last() {
+                    Some(Instruction::Const(ValueType::I32, v)) => {
+                        v.parse::<i32>().ok().map(|v| ((v == 0) as i32).to_string())
+                    },
+                    _ => None
+                };
+                push_folded(folded, instr, value, 1);
+            },
+            Instruction::If => {
+                let condition = match folded.last() {
+                    Some(Instruction::Const(ValueType::I32, v)) => v.parse::<i32>().ok(),
+                    _ => None
+                };
+
+                match condition {
+                    Some(c) => {
+                        folded.pop();
+
+                        // The remaining instructions, starting with the `if`
+                        let tail: Vec<Instruction> = std::iter::once(instr)
+                            .chain(rest)
+                            .collect();
+                        let (else_index, end) = matching_end(&tail, 0)
+                            .expect("every if must be closed");
+
+                        let branch = match (c != 0, else_index) {
+                            (true, Some(e)) => 1..e,
+                            (true, None) => 1..end,
+                            (false, Some(e)) => e + 1..end,
+                            (false, None) => end..end,
+                        };
+
+                        let mut tail = tail;
+                        let after = tail.split_off(end + 1);
+                        let taken = tail.drain(branch).collect();
+                        fold_into(folded, taken);
+                        fold_into(folded, after);
+                        return;
+                    },
+                    None => folded.push(instr)
+                }
+            },
+            _ => folded.push(instr)
+        }
+    }
+}
+
+fn push_folded(
+    folded: &mut Vec<Instruction>,
+    instr: Instruction,
+    value: Option<String>,
+    operands: usize
+) {
+    match value {
+        Some(v) => {
+            let t = match instr {
+                Instruction::Binary(t, _) => t,
+                _ => ValueType::I32
+            };
+            folded.truncate(folded.len() - operands);
+            folded.push(Instruction::Const(t, v));
+        },
+        None => folded.push(instr)
+    }
+}
+
+fn constant_operands(
+    folded: &[Instruction],
+    t: ValueType
+) -> Option<(String, String)> {
+    match folded {
+        [.., Instruction::Const(ta, a), Instruction::Const(tb, b)]
+            if *ta == t && *tb == t => Some((a.clone(), b.clone())),
+        _ => None
+    }
+}
+
+fn binary(t: ValueType, op: BinaryOp, a: &str, b: &str) -> Option<String> {
+    match t {
+        ValueType::I32 => {
+            let (a, b) = (a.parse::<i32>().ok()?, b.parse::<i32>().ok()?);
+            let v = match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                // Division traps on zero and on overflow,
+                // that is left to happen at run time
+                BinaryOp::DivS => a.checked_div(b)?,
+                BinaryOp::RemS if b == 0 => return None,
+                BinaryOp::RemS => a.wrapping_rem(b),
+                BinaryOp::RemU if b == 0 => return None,
+                BinaryOp::RemU => ((a as u32) % (b as u32)) as i32,
+                BinaryOp::And => a & b,
+                BinaryOp::Or => a | b,
+                BinaryOp::Xor => a ^ b,
+                BinaryOp::Shl => a.wrapping_shl(b as u32),
+                BinaryOp::ShrS => a.wrapping_shr(b as u32),
+                BinaryOp::ShrU => (a as u32).wrapping_shr(b as u32) as i32,
+                BinaryOp::Div => return None,
+            };
+            Some(v.to_string())
+        },
+        ValueType::F32 => {
+            let (a, b) = (a.parse::<f32>().ok()?, b.parse::<f32>().ok()?);
+            let v = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                _ => return None
+            };
+            if v.is_finite() {
+                Some(format!("{:?}", v))
+            } else {
+                None
+            }
+        },
+        ValueType::Unknown => None
+    }
+}
+
+fn compare(t: ValueType, rel: Relation, a: &str, b: &str) -> Option<bool> {
+    let ordering = match t {
+        ValueType::I32 => {
+            a.parse::<i32>().ok()?.cmp(&b.parse::<i32>().ok()?)
+        },
+        ValueType::F32 => {
+            a.parse::<f32>().ok()?.partial_cmp(&b.parse::<f32>().ok()?)?
+        },
+        ValueType::Unknown => return None
+    };
+
+    Some(match rel {
+        Relation::Eq => ordering.is_eq(),
+        Relation::Ne => ordering.is_ne(),
+        Relation::Lt => ordering.is_lt(),
+        Relation::Le => ordering.is_le(),
+        Relation::Gt => ordering.is_gt(),
+        Relation::Ge => ordering.is_ge(),
+    })
+}
+
+#[cfg(test)]
+mod folding_tests {
+    use super::*;
+
+    fn int(v: &str) -> Instruction {
+        Instruction::Const(ValueType::I32, v.to_string())
+    }
+
+    fn add() -> Instruction {
+        Instruction::Binary(ValueType::I32, BinaryOp::Add)
+    }
+
+    #[test]
+    fn test_fold_nested_expression() {
+        // 2 + 5*(2-2) + 2
+        let mut body = vec![
+            int("2"), int("5"), int("2"), int("2"),
+            Instruction::Binary(ValueType::I32, BinaryOp::Sub),
+            Instruction::Binary(ValueType::I32, BinaryOp::Mul),
+            add(), int("2"), add(),
+        ];
+
+        fold_constants(&mut body);
+
+        assert_eq!(body, vec![int("4")]);
+    }
+
+    #[test]
+    fn test_fold_real_expression() {
+        let mut body = vec![
+            Instruction::Const(ValueType::F32, "1.5".to_string()),
+            Instruction::Const(ValueType::F32, "2.0".to_string()),
+            Instruction::Binary(ValueType::F32, BinaryOp::Mul),
+        ];
+
+        fold_constants(&mut body);
+
+        assert_eq!(body, vec![Instruction::Const(ValueType::F32, "3.0".to_string())]);
+    }
+
+    #[test]
+    fn test_keep_division_by_zero() {
+        let mut body = vec![
+            int("1"), int("0"),
+            Instruction::Binary(ValueType::I32, BinaryOp::DivS),
+        ];
+        let expected = body.clone();
+
+        fold_constants(&mut body);
+
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_keep_variable_operands() {
+        let mut body = vec![
+            Instruction::LocalGet("a".to_string()), int("1"), add(),
+        ];
+        let expected = body.clone();
+
+        fold_constants(&mut body);
+
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_remove_dead_branches() {
+        let set = |n: &str| Instruction::LocalSet(n.to_string());
+        let mut body = vec![
+            int("1"), int("2"),
+            Instruction::Compare(ValueType::I32, Relation::Lt),
+            Instruction::If, int("3"), set("a"),
+            Instruction::Else, int("4"), set("a"),
+            Instruction::End,
+            int("0"),
+            Instruction::If, int("5"), set("b"),
+            Instruction::End,
+            int("6"), set("c"),
+        ];
+
+        fold_constants(&mut body);
+
+        assert_eq!(body, vec![int("3"), set("a"), int("6"), set("c")]);
+    }
+}
@@ -0,0 +1,21 @@
+mod cse;
+mod dce;
+mod folding;
+mod peephole;
+mod strength;
+
+use crate::translation::instruction::{Instruction, ValueType};
+
+/// Runs the optimization passes over a function body,
+/// returning the locals they introduce.
+pub fn optimize(body: &mut Vec<Instruction>) -> Vec<(String, ValueType)> {
+    let mut locals = Vec::new();
+
+    folding::fold_constants(body);
+    strength::reduce_strength(body);
+    peephole::peephole(body);
+    dce::eliminate_dead_code(body);
+    cse::eliminate_common_subexpressions(body, &mut locals);
+
+    locals
+}
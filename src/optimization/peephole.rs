@@ -0,0 +1,80 @@
+use crate::translation::instruction::{BinaryOp, Instruction, ValueType};
+
+/// Rewrites short instruction sequences into cheaper equivalents:
+/// a `local.set` followed by a `local.get` of the same local becomes
+/// `local.tee` and additions of a constant zero are dropped.
+pub fn peephole(body: &mut Vec<Instruction>) {
+    let instrs = std::mem::take(body);
+    for instr in instrs {
+        match (body.last(), &instr) {
+            (Some(Instruction::LocalSet(a)), Instruction::LocalGet(b))
+                if a == b => {
+                let name = b.clone();
+                body.pop();
+                body.push(Instruction::LocalTee(name));
+            },
+            (
+                Some(Instruction::Const(ValueType::I32, v)),
+                Instruction::Binary(ValueType::I32, BinaryOp::Add)
+            ) if v == "0" => {
+                body.pop();
+            },
+            _ => body.push(instr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod peephole_tests {
+    use super::*;
+
+    fn get(name: &str) -> Instruction {
+        Instruction::LocalGet(name.to_string())
+    }
+
+    fn set(name: &str) -> Instruction {
+        Instruction::LocalSet(name.to_string())
+    }
+
+    fn int(v: &str) -> Instruction {
+        Instruction::Const(ValueType::I32, v.to_string())
+    }
+
+    #[test]
+    fn test_set_get_to_tee() {
+        let mut body = vec![int("1"), set("a"), get("a"), set("b"), get("c")];
+
+        peephole(&mut body);
+
+        assert_eq!(body, vec![
+            int("1"), Instruction::LocalTee("a".to_string()), set("b"), get("c")
+        ]);
+    }
+
+    #[test]
+    fn test_drop_zero_addition() {
+        let mut body = vec![
+            get("a"), int("0"),
+            Instruction::Binary(ValueType::I32, BinaryOp::Add),
+            set("a")
+        ];
+
+        peephole(&mut body);
+
+        assert_eq!(body, vec![get("a"), set("a")]);
+    }
+
+    #[test]
+    fn test_keep_plain_subtraction() {
+        let mut body = vec![
+            int("1"), get("a"),
+            Instruction::Binary(ValueType::I32, BinaryOp::Sub),
+            set("b")
+        ];
+        let expected = body.clone();
+
+        peephole(&mut body);
+
+        assert_eq!(body, expected);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::translation::instruction::{
+    operand_start, BinaryOp, Instruction, ValueType
+};
+
+/// Simplifies integer operations with a constant operand:
+/// drops the identities `x + 0`, `x - 0`, `x * 1` and `x div 1`
+/// and turns multiplications and divisions by
+/// a power of two into shifts.
+pub fn reduce_strength(body: &mut Vec<Instruction>) {
+    let instrs = std::mem::take(body);
+    for instr in instrs {
+        match instr {
+            Instruction::Binary(ValueType::I32, op) => {
+                if !reduce_right(body, op) && !reduce_left(body, op) {
+                    body.push(instr);
+                }
+            },
+            _ => body.push(instr)
+        }
+    }
+}
+
+/// Reduces an operation whose second operand is a constant.
+fn reduce_right(body: &mut Vec<Instruction>, op: BinaryOp) -> bool {
+    let c = match body.last() {
+        Some(Instruction::Const(ValueType::I32, v)) => v.parse::<i32>().ok(),
+        _ => None
+    };
+
+    let c = match c {
+        Some(c) => c,
+        None => return false
+    };
+
+    match (op, c) {
+        (BinaryOp::Add, 0)
+        | (BinaryOp::Sub, 0)
+        | (BinaryOp::Or, 0)
+        | (BinaryOp::Xor, 0)
+        | (BinaryOp::Shl, 0)
+        | (BinaryOp::ShrS, 0)
+        | (BinaryOp::Mul, 1)
+        | (BinaryOp::DivS, 1) => {
+            body.pop();
+            true
+        },
+        (BinaryOp::Mul, c) if is_power_of_two(c) => {
+            body.pop();
+            body.push(int(c.trailing_zeros() as i32));
+            body.push(Instruction::Binary(ValueType::I32, BinaryOp::Shl));
+            true
+        },
+        (BinaryOp::DivS, c) if is_power_of_two(c) => {
+            // An arithmetic shift rounds towards negative infinity while
+            // the division truncates, so negative dividends are biased by
+            // 2^k - 1 first. That needs the dividend twice, which is only
+            // cheap when it is a local.
+            let name = match body.iter().rev().nth(1) {
+                Some(Instruction::LocalGet(name)) => name.clone(),
+                _ => return false
+            };
+
+            let k = c.trailing_zeros() as i32;
+            body.pop();
+            body.extend(vec![
+                Instruction::LocalGet(name),
+                int(31),
+                Instruction::Binary(ValueType::I32, BinaryOp::ShrS),
+                int(32 - k),
+                Instruction::Binary(ValueType::I32, BinaryOp::ShrU),
+                Instruction::Binary(ValueType::I32, BinaryOp::Add),
+                int(k),
+                Instruction::Binary(ValueType::I32, BinaryOp::ShrS),
+            ]);
+            true
+        },
+        _ => false
+    }
+}
+
+/// Reduces a commutative operation whose first operand is a constant.
+fn reduce_left(body: &mut Vec<Instruction>, op: BinaryOp) -> bool {
+    let start = match operand_start(body, body.len()) {
+        Some(start) if start > 0 => start,
+        _ => return false
+    };
+
+    let c = match &body[start - 1] {
+        Instruction::Const(ValueType::I32, v) => v.parse::<i32>().ok(),
+        _ => None
+    };
+
+    match (op, c) {
+        (BinaryOp::Add, Some(0))
+        | (BinaryOp::Or, Some(0))
+        | (BinaryOp::Xor, Some(0))
+        | (BinaryOp::Mul, Some(1)) => {
+            body.remove(start - 1);
+            true
+        },
+        (BinaryOp::Mul, Some(c)) if is_power_of_two(c) => {
+            body.remove(start - 1);
+            body.push(int(c.trailing_zeros() as i32));
+            body.push(Instruction::Binary(ValueType::I32, BinaryOp::Shl));
+            true
+        },
+        _ => false
+    }
+}
+
+fn is_power_of_two(c: i32) -> bool {
+    c > 1 && c.count_ones() == 1
+}
+
+fn int(v: i32) -> Instruction {
+    Instruction::Const(ValueType::I32, v.to_string())
+}
+
+#[cfg(test)]
+mod strength_tests {
+    use super::*;
+
+    fn get(name: &str) -> Instruction {
+        Instruction::LocalGet(name.to_string())
+    }
+
+    fn binary(op: BinaryOp) -> Instruction {
+        Instruction::Binary(ValueType::I32, op)
+    }
+
+    #[test]
+    fn test_drop_identities() {
+        let mut body = vec![
+            get("a"), int(1), binary(BinaryOp::Mul),
+            int(0), binary(BinaryOp::Add),
+            int(1), binary(BinaryOp::DivS),
+            int(1), get("b"), binary(BinaryOp::Mul),
+            binary(BinaryOp::Sub),
+        ];
+
+        reduce_strength(&mut body);
+
+        assert_eq!(body, vec![get("a"), get("b"), binary(BinaryOp::Sub)]);
+    }
+
+    #[test]
+    fn test_multiplication_to_shift() {
+        let mut body = vec![
+            get("a"), int(8), binary(BinaryOp::Mul),
+            int(4), get("b"), binary(BinaryOp::Mul),
+        ];
+
+        reduce_strength(&mut body);
+
+        assert_eq!(body, vec![
+            get("a"), int(3), binary(BinaryOp::Shl),
+            get("b"), int(2), binary(BinaryOp::Shl),
+        ]);
+    }
+
+    #[test]
+    fn test_division_to_shift() {
+        let mut body = vec![get("a"), int(4), binary(BinaryOp::DivS)];
+
+        reduce_strength(&mut body);
+
+        assert_eq!(body, vec![
+            get("a"), get("a"), int(31), binary(BinaryOp::ShrS),
+            int(30), binary(BinaryOp::ShrU), binary(BinaryOp::Add),
+            int(2), binary(BinaryOp::ShrS),
+        ]);
+
+        // The lowering rounds towards zero like the division does
+        for a in [-9, -8, -7, -1, 0, 1, 7, 8, 9, i32::MIN, i32::MAX].iter() {
+            let biased = a.wrapping_add(((a >> 31) as u32 >> 30) as i32);
+            assert_eq!(biased >> 2, a / 4);
+        }
+    }
+
+    #[test]
+    fn test_keep_other_operations() {
+        let mut body = vec![
+            get("a"), get("b"), int(2), binary(BinaryOp::Add),
+            binary(BinaryOp::DivS),
+            int(6), binary(BinaryOp::Mul),
+            int(-4), binary(BinaryOp::Mul),
+        ];
+        let expected = body.clone();
+
+        reduce_strength(&mut body);
+
+        assert_eq!(body, expected);
+    }
+}
@@ -0,0 +1,264 @@
+//! The syntax tree of a program as it is written, before the names
+//! in it are resolved and the types are checked. The compiler itself
+//! generates the code as it parses, and the tree is only built for
+//! the tools that work on the structure of programs. The names,
+//! statements and expressions keep the byte offsets of their text in
+//! the source; the ones a tool makes up have empty ranges instead.
+
+use std::ops::Range;
+
+use crate::tokenization::{Operator, Relation};
+
+/// An identifier as it is spelled in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Name {
+    pub text: String,
+    pub range: Range<usize>,
+}
+
+impl Name {
+    /// A name that is not in the source.
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), range: 0..0 }
+    }
+}
+
+// <program> ::= program <identifier> ; <block> .
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub name: Name,
+    pub block: Block,
+}
+
+// <block> ::=
+    // <type definition part>
+    // <variable declaration part>
+    // <statement part>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Block {
+    pub types: Vec<TypeDefinition>,
+    pub variables: Vec<VariableDeclaration>,
+    pub statements: Vec<Statement>,
+}
+
+// <type definition> ::= <identifier> = <type>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeDefinition {
+    pub name: Name,
+    pub type_: TypeExpression,
+}
+
+// <variable declaration> ::= <identifier> {,<identifier>} : <type>
+/// The declaration of variables or, in a record, of fields.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VariableDeclaration {
+    pub names: Vec<Name>,
+    pub type_: TypeExpression,
+}
+
+/// A type as it is written where it is used or defined.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TypeExpression {
+    /// A type identifier
+    Named(Name),
+    /// `(red, green, blue)`
+    Enumeration(Vec<Name>),
+    /// `low..high`, where the bounds are constants
+    Subrange(Box<Expression>, Box<Expression>),
+    /// `[packed] array [index {, index}] of element`
+    Array {
+        packed: bool,
+        indices: Vec<TypeExpression>,
+        element: Box<TypeExpression>,
+    },
+    /// `[packed] record fields end`
+    Record {
+        packed: bool,
+        fields: Vec<VariableDeclaration>,
+    },
+    /// `^name`
+    Pointer(Name),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Statement {
+    pub kind: StatementKind,
+    /// The text of the statement, empty for an empty statement
+    pub range: Range<usize>,
+}
+
+impl Statement {
+    /// A statement that is not in the source.
+    pub fn new(kind: StatementKind) -> Self {
+        Self { kind, range: 0..0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kind == StatementKind::Empty
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatementKind {
+    Empty,
+    Assignment {
+        target: Variable,
+        value: Expression,
+    },
+    /// A procedure called with the arguments, if any
+    Call {
+        name: Name,
+        arguments: Vec<Expression>,
+    },
+    /// `begin statements end`
+    Compound(Vec<Statement>),
+    If {
+        condition: Expression,
+        then: Box<Statement>,
+        else_: Option<Box<Statement>>,
+    },
+    Case {
+        selector: Expression,
+        elements: Vec<CaseElement>,
+        /// The statements of the else part, if there is one
+        else_: Option<Vec<Statement>>,
+    },
+    While {
+        condition: Expression,
+        body: Box<Statement>,
+    },
+    Repeat {
+        body: Vec<Statement>,
+        condition: Expression,
+    },
+    For {
+        variable: Name,
+        initial: Expression,
+        direction: Direction,
+        final_: Expression,
+        body: Box<Statement>,
+    },
+    With {
+        records: Vec<Variable>,
+        body: Box<Statement>,
+    },
+}
+
+/// The labels of a case statement and the statement they select.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaseElement {
+    pub labels: Vec<CaseLabel>,
+    pub statement: Statement,
+}
+
+/// A constant or, when `high` is given, a range of constants.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaseLabel {
+    pub low: Expression,
+    pub high: Option<Expression>,
+}
+
+/// Whether a for loop counts up or down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    To,
+    Downto,
+}
+
+/// A variable with the selectors applied to it in order,
+/// as in `a[i].next^`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variable {
+    pub name: Name,
+    pub selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Selector {
+    /// `[i, j]`
+    Index(Vec<Expression>),
+    /// `.field`
+    Field(Name),
+    /// `^`
+    Dereference,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExpressionKind {
+    /// A variable or a constant identifier, which are told apart
+    /// only once the names are resolved
+    Variable(Variable),
+    /// An unsigned number as it is written
+    Number(String),
+    /// A string or a character without the quotes
+    Literal(String),
+    Nil,
+    /// A function called with the arguments
+    Call {
+        name: Name,
+        arguments: Vec<Expression>,
+    },
+    /// A sign before a term or `not` before a factor
+    Unary {
+        operator: Operator,
+        operand: Box<Expression>,
+    },
+    /// An adding or a multiplying operator
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Relation {
+        relation: Relation,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// An expression in brackets
+    Parenthesized(Box<Expression>),
+}
+
+impl Expression {
+    /// An expression that is not in the source.
+    pub fn new(kind: ExpressionKind) -> Self {
+        Self { kind, range: 0..0 }
+    }
+
+    /// How tightly the expression binds its operands: a relation
+    /// least, then a sign and the adding operators, then the
+    /// multiplying operators and most of all a factor.
+    pub fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExpressionKind::Relation { .. } => 0,
+            ExpressionKind::Unary { operator: Operator::Not, .. } => 3,
+            ExpressionKind::Unary { .. } => 1,
+            ExpressionKind::Binary { operator, .. } => match operator {
+                Operator::Plus | Operator::Minus
+                | Operator::Or | Operator::Xor => 1,
+                _ => 2,
+            },
+            _ => 3,
+        }
+    }
+}
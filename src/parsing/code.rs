@@ -1,15 +1,30 @@
-use std::{collections::HashSet, io::Write, iter::FromIterator};
+use std::{
+    collections::HashMap, fmt::Display, io::Write, ops::Range, time::Instant
+};
 
-use crate::{error::{
+use crate::{metrics::Metrics, position::{FilePosition, START_POSITION}, error::{
         CompilationError,
         CompilationErrorKind,
-        Errors
+        DiagnosticHandler,
+        directives::directive,
+        FixIt,
+        ErrorPolicy,
+        Errors,
+        Lint,
+        LintLevel,
+        Lints
     }, semantics::{
+        evaluation::{self, EvaluationError, Value},
+        Array,
         Enumeration,
         Identifier,
         Fields,
         Identifiers,
-        Scope,
+        Scopes,
+        Spans,
+        StandardFunction,
+        StandardProcedure,
+        SymbolTable,
         Type,
         Types,
         boolean
@@ -20,47 +35,285 @@ use crate::{error::{
         Keyword,
         Relation,
         TokenStream,
-        Buffer
-    }, translation::Wasm};
+        Buffer,
+        SimpleBuffer
+    }, translation::{
+        instruction::Instruction,
+        names::{self, FOR_LIMIT},
+        Target,
+        Wasm
+    }};
+
+use super::{
+    highlighting::SemanticKind,
+    recovery::{self, TokenSet},
+    resolution::Resolution
+};
 
 type ParseResult = Result<(), CompilationError>;
 
+/// How many pages of memory a module can have at most
+const MAX_PAGES: u32 = 65536;
+/// The words reserved in standard Pascal that are not reserved here,
+/// so that they can name fields
+const STANDARD_RESERVED_WORDS: [&str; 7] =
+    ["const", "file", "function", "goto", "in", "label", "set"];
+type ResolutionHandler = Box<dyn FnMut(Resolution)>;
+type MetricsHandler = Box<dyn FnMut(Metrics)>;
+
+/// Where the value of a variable is kept
+enum Place {
+    /// In the local or the global of the name
+    Variable(String),
+    /// In the linear memory at the address on
+    /// the stack plus the offset
+    Memory(u32),
+}
+
+/// The value of an expression of an array type, which the
+/// code evaluating the expression does not put on the stack
+enum Aggregate {
+    /// The array at the address on the stack plus the offset
+    Stored(u32),
+    /// A string literal
+    Literal(String),
+}
+
 pub struct Code<T: Buffer> {
     token_stream: TokenStream<T>,
     lookahead: Token,
-    scope: Box<Scope>,
+    scope: Scopes,
     errors: Errors,
+    handler: Option<Box<dyn DiagnosticHandler>>,
+    policy: ErrorPolicy,
+    lints: Lints,
+    /// Whether the policy stopped the analysis
+    stopped: bool,
+    resolution_handler: Option<ResolutionHandler>,
+    metrics_handler: Option<MetricsHandler>,
+    /// The time spent reading tokens and the tokens
+    /// and statements read so far
+    metrics: Metrics,
+    /// The source range of the identifier consumed last
+    id_range: Range<usize>,
+    /// Where the identifier consumed last starts
+    id_pos: FilePosition,
+    /// Where the token consumed last ends
+    last_end: usize,
+    /// Where the fields of the records and the constants
+    /// of the enumerations are declared
+    members: Vec<(Type, Spans)>,
+    /// The global declarations, once the program has been analyzed
+    symbols: Option<SymbolTable>,
+    sync_sets: Vec<TokenSet>,
+    /// Whether a statement with errors is left out of the module
+    /// instead of stopping the generation
+    partial: bool,
+    /// Whether each of the statements being compiled has had errors
+    transactions: Vec<bool>,
+    /// Whether the variables have been found not to fit in the memory
+    memory_exceeded: bool,
+    /// The value of the expression compiled last, if it is constant
+    constant: Option<Value>,
+    /// Whether the statement compiled last never finishes, as it leaves
+    /// the program or all of its branches do
+    terminates: bool,
+    /// The variables named with a single letter, as they are spelled and
+    /// where they are declared, that have not been the control variables
+    /// of loops
+    short_variables: Vec<(String, FilePosition, LintLevel)>,
+    /// How each name is spelled where it is declared first
+    spellings: HashMap<String, (String, FilePosition)>,
+    /// The array the expression compiled last evaluates to
+    aggregate: Option<Aggregate>,
+    /// The types pointed to that are not declared yet, with where
+    /// they are named, while the type definitions are read
+    pointed_types: Option<Vec<(String, Range<usize>, FilePosition)>>,
     wasm: Wasm,
 }
 
 impl<T: Buffer> Code<T> {
-    const CONTINUE: &'static str = "continue";
-    const END: &'static str = "end";
-    const R0: &'static str = "r0";
 
     pub fn new(
         token_stream: TokenStream<T>,
         output: Box<dyn Write>
     ) -> Code<T> {
         Code {
-            token_stream: token_stream,
+            token_stream,
             lookahead: Token::EOF,
-            scope: Box::new(Scope::default()),
+            scope: Scopes::default(),
             errors: Errors::new(),
+            handler: None,
+            policy: ErrorPolicy::default(),
+            lints: Lints::default(),
+            stopped: false,
+            resolution_handler: None,
+            metrics_handler: None,
+            metrics: Metrics::default(),
+            id_range: 0..0,
+            id_pos: START_POSITION,
+            last_end: 0,
+            members: Vec::new(),
+            symbols: None,
+            sync_sets: Vec::new(),
+            partial: false,
+            transactions: Vec::new(),
+            memory_exceeded: false,
+            constant: None,
+            terminates: false,
+            short_variables: Vec::new(),
+            spellings: HashMap::new(),
+            aggregate: None,
+            pointed_types: None,
             wasm: Wasm::new(output),
         }
     }
 
+    /// Sets a handler that receives every diagnostic as it is reported.
+    /// The diagnostics are still collected and returned when
+    /// the compilation finishes.
+    pub fn with_diagnostic_handler(
+        mut self,
+        handler: impl DiagnosticHandler + 'static
+    ) -> Self {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets when the analysis gives up after errors. By default
+    /// it goes on to the end of the program.
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets how each lint reports what it finds. By default the lints
+    /// report warnings, which do not stop the compilation.
+    pub fn with_lints(mut self, lints: Lints) -> Self {
+        self.lints = lints;
+        self
+    }
+
+    /// Sets a handler that receives every identifier
+    /// declared or resolved, along with what it is.
+    pub(crate) fn with_resolution_handler(
+        mut self,
+        handler: impl FnMut(Resolution) + 'static
+    ) -> Self {
+        self.resolution_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets a handler that receives the statistics
+    /// of the compilation once it finishes.
+    pub fn with_metrics_handler(
+        mut self,
+        handler: impl FnMut(Metrics) + 'static
+    ) -> Self {
+        self.metrics_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets a handler that receives the name and the body
+    /// of every function generated, after the optimizations.
+    pub(crate) fn with_function_handler(
+        mut self,
+        handler: impl FnMut(&str, &[Instruction]) + 'static
+    ) -> Self {
+        self.wasm.set_function_handler(Box::new(handler));
+        self
+    }
+
+    /// Sets how the generated module is embedded in the host.
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.wasm.set_target(target);
+        self
+    }
+
+    /// Makes the compiler check the module before writing it, which
+    /// it does by default. Without the check the module is streamed
+    /// to the output as it is generated instead of held in memory.
+    pub fn with_validation(mut self, enabled: bool) -> Self {
+        self.wasm.set_validation(enabled);
+        self
+    }
+
+    /// Makes the compiler generate the module even if the program has
+    /// errors, leaving out every statement that has them. By default
+    /// the first error stops the generation. An error anywhere
+    /// but in a statement still stops it.
+    pub fn with_partial_output(mut self, enabled: bool) -> Self {
+        self.partial = enabled;
+        self
+    }
+
+    /// Enables the optimization of the generated code.
+    pub fn with_optimizations(mut self, enabled: bool) -> Self {
+        self.wasm.set_optimize(enabled);
+        self
+    }
+
     /// Compiles the code, producing an executable.
     pub fn compile(mut self) -> Result<Errors, CompilationError> {
-        self.token_stream.next().and_then(|token| {
-            self.lookahead = token;
-            Ok(())
-        })?;
+        self.translate()?;
+        Ok(self.errors)
+    }
 
-        self.program()?;
+    /// Checks the code for correctness like `check`, also returning
+    /// the types, the variables and the procedures declared globally.
+    pub fn check_with_symbols(
+        mut self
+    ) -> Result<(Errors, SymbolTable), CompilationError> {
+        self.wasm.silence();
+        self.translate()?;
+        let symbols = self.symbols.take().unwrap_or_default();
+        Ok((self.errors, symbols))
+    }
 
-        Ok(self.errors)
+    fn translate(&mut self) -> ParseResult {
+        let started = Instant::now();
+        let result = self.proceed().and_then(|_| self.program());
+        if self.stopped {
+            self.report_metrics(started, 0);
+            return Ok(());
+        }
+        if let Err(e) = result {
+            self.report_metrics(started, 1);
+            return Err(e);
+        }
+
+        if let Err(msg) = self.wasm.finish() {
+            let err = CompilationError::new(
+                CompilationErrorKind::InternalError,
+                self.token_stream.filepath(),
+                self.token_stream.pos(),
+                &format!("internal compiler error: invalid module: {}", msg)
+            );
+            self.report(err);
+        }
+
+        self.report_metrics(started, 0);
+        Ok(())
+    }
+
+    /// Passes the statistics of the compilation started
+    /// at `started` to the handler, if there is one.
+    fn report_metrics(&mut self, started: Instant, fatal_errors: usize) {
+        if let Some(handler) = &mut self.metrics_handler {
+            let generated = self.wasm.metrics();
+            let elapsed = started.elapsed();
+            let others = self.metrics.lexing
+                + generated.optimization
+                + generated.emission;
+            handler(Metrics {
+                lexing: self.metrics.lexing,
+                parsing: elapsed.saturating_sub(others),
+                tokens: self.metrics.tokens,
+                statements: self.metrics.statements,
+                errors: self.errors.error_count() + fatal_errors,
+                ..generated
+            });
+        }
     }
 
     /// Checks the code for correctness.
@@ -74,52 +327,58 @@ impl<T: Buffer> Code<T> {
         self.wasm.mod_start();
 
         if self.lookahead == Token::EOF {
-            println!("Input file empty, exiting.");
-            return Ok(());
+            return Err(self.syntax_error("the program is empty"));
         }
 
-        let procedures = self.scope.into_iter()
-            .filter(|(_, id)| {
-                if let Identifier::Procedure(_) = id {
-                    true
-                } else {
-                    false
-                }
-            })
+        let predefined = self.scope.scope(self.scope.current());
+        let mut procedures: Vec<_> = predefined.into_iter()
+            .filter(|(_, id)| matches!(id, Identifier::Procedure(_)))
             .map(|(name, id)| {
                 if let Identifier::Procedure(t) = id {
                     (name, t)
                 } else {
                     panic!("The list must contain only procedures");
                 }
-            });
+            })
+            .collect();
+        procedures.sort_by_key(|&(name, _)| name);
 
         for (name, types) in procedures {
             self.wasm.func_import(name, types)
         }
+        self.wasm.runtime_error_import();
+        self.wasm.standard_imports();
         
-        self.consume(Token::K(Keyword::Program)).and_then(|_| {
-            self.identifier()?;
-            self.wasm.func_start("program", true);
-            self.wasm.func_local(Self::R0, &Type::Integer);
-            self.consume(Token::P(Punctuation::Semicolon))
-        }).or_else(|_| {
-            self.panic(&[
-                Token::K(Keyword::Type),
-                Token::K(Keyword::Var)
-            ])
-        }).unwrap_or_default();
-        
-        self.scope = Scope::empty_with_outer(self.scope.to_owned());
-        self.block().or_else(|_| {
-            self.panic(&[Token::P(Punctuation::Dot)])
-        })?;
+        // The directives before the heading hold for the whole program
+        self.directives_applied();
+        let start = self.token_stream.token_range().start;
+        let heading = self.recover(
+            recovery::FOLLOW_PROGRAM_HEADING, None,
+            |c| c.program_heading().map(Some)
+        )?;
+
+        let export_name = self.wasm.target().export_name.clone();
+        self.wasm.func_start(&export_name, true);
+        self.wasm.func_local(FOR_LIMIT, &Type::Integer);
+
+        let program_scope = self.scope.push(Identifiers::new());
+        self.recover(recovery::FOLLOW_BLOCK, (), |c| c.block())?;
+
+        if self.lookahead != Token::EOF || !self.reported_at_end() {
+            self.recover(
+                recovery::FOLLOW_PROGRAM, (),
+                |c| c.consume(Token::P(Punctuation::Dot))
+            )?;
+        }
 
-        self.consume(Token::P(Punctuation::Dot)).or_else(|_| {
-            self.panic(&[Token::EOF])
-        }).expect("EOF not found in the stream");
+        self.symbols = Some(SymbolTable::of(&self.scope, program_scope));
+        self.scope.pop();
+        self.short_variables_reported();
 
-        self.scope = self.scope.clone().collapse().unwrap();
+        if let Some((name, range)) = heading {
+            let extent = start..self.last_end;
+            self.declared(&name, range, extent, SemanticKind::Program, None);
+        }
 
         self.wasm.func_end();
         self.wasm.mod_end();
@@ -127,25 +386,35 @@ impl<T: Buffer> Code<T> {
         Ok(())
     }
 
+    // <program heading> ::= program <identifier> ;
+    fn program_heading(
+        &mut self
+    ) -> Result<(String, Range<usize>), CompilationError> {
+        self.consume(Token::K(Keyword::Program))?;
+        let name = self.identifier()?;
+        let range = self.id_range.clone();
+        self.consume(Token::P(Punctuation::Semicolon))?;
+        Ok((name, range))
+    }
+
     // <block> ::=
         // <type definition part>
         // <variable declaration part> 
         // <statement part>
     fn block(&mut self) -> ParseResult {
-        self.type_definitions().or_else(|_| {
-            self.panic(&[
-                Token::K(Keyword::Var),
-                Token::K(Keyword::Begin),
-            ])
-        })?;
+        if let Token::K(Keyword::Type) = self.lookahead {
+            self.recover(
+                recovery::FOLLOW_TYPE_DEFINITION_PART, (),
+                |c| c.directed(|c| c.type_definitions())
+            )?;
+        }
+        self.pointed_types_resolved();
 
         if let Token::K(Keyword::Var) = self.lookahead {
-            self.variable_declarations().or_else(|_| {
-                self.panic(&[
-                    Token::K(Keyword::Begin),
-                    Token::P(Punctuation::Semicolon)
-                ])
-            })?;
+            self.recover(
+                recovery::FOLLOW_VARIABLE_DECLARATION_PART, (),
+                |c| c.directed(|c| c.variable_declarations())
+            )?;
         }
 
         self.statements()?;
@@ -162,14 +431,16 @@ impl<T: Buffer> Code<T> {
         }
         
         self.consume(Token::K(Keyword::Type))?;
-        self.type_definition()?;
+        // A pointer type may point to a type defined after it
+        self.pointed_types = Some(Vec::new());
+        self.directed(|c| c.type_definition())?;
         loop {
             if self.lookahead == Token::P(Punctuation::Semicolon) {
                 self.proceed()?;
                 if !matches!(self.lookahead, Token::Id(_)) {
                     break;
                 }
-                self.type_definition()?;
+                self.directed(|c| c.type_definition())?;
             } else {
                 break;
             }
@@ -180,19 +451,57 @@ impl<T: Buffer> Code<T> {
 
     // <type definition> ::= <identifier> = <type>
     fn type_definition(&mut self) -> ParseResult {
-        self.debug("Entering type definition");
         let id = self.identifier()?;
+        let range = self.id_range.clone();
+        let redefined = self.scope.declares(&id);
+        if redefined {
+            self.redefined_identifier(&id);
+        } else {
+            self.shadowing(&id);
+        }
         self.consume(Token::R(Relation::Eq))?;
         let t = self.type_()?;
+        let extent = range.start..self.last_end;
+        self.declared(&id, range.clone(), extent, SemanticKind::Type, Some(&t));
 
-        if let Err(e) = self.scope.put(id, Identifier::Type(t)) {
+        // The type may also collide with one of its own constants
+        let r = self.scope.put(id.clone(), Identifier::Type(t));
+        if let (Err(e), false) = (r, redefined) {
             self.redefined_identifier(e.id());
         }
+        self.scope.declare(&id, range);
 
-        self.debug("Exiting type definition");
         Ok(())
     }
 
+    /// Checks that the types the pointer types defined point to
+    /// are declared, now that all the types are.
+    fn pointed_types_resolved(&mut self) {
+        let pointed_types = match self.pointed_types.take() {
+            Some(pointed_types) => pointed_types,
+            None => return
+        };
+
+        for (name, range, pos) in pointed_types {
+            // Reported as if the name has just been read
+            self.id_range = range;
+            self.id_pos = pos;
+            match self.scope.get(&name) {
+                Some(Identifier::Type(t)) => {
+                    let t = t.clone();
+                    self.resolved(&name, SemanticKind::Type, Some(&t));
+                },
+                Some(Identifier::Unknown) => (),
+                Some(_) => {
+                    self.invalid_identifier("type", &name);
+                },
+                None => {
+                    self.undeclared_identifier(&name);
+                }
+            }
+        }
+    }
+
     // <variable declaration part> ::=
         // <empty>
         // | var <variable declaration> {; <variable declaration>} ;
@@ -202,12 +511,12 @@ impl<T: Buffer> Code<T> {
         }
 
         self.proceed()?;
-        self.variable_declaration()?;
+        self.directed(|c| c.variable_declaration())?;
 
         loop {
             self.consume(Token::P(Punctuation::Semicolon))?;
             if let Token::Id(_) = self.lookahead {
-                self.variable_declaration()?
+                self.directed(|c| c.variable_declaration())?
             } else {
                 break
             }
@@ -218,36 +527,39 @@ impl<T: Buffer> Code<T> {
 
     // <variable declaration> ::= <identifier> {,<identifier>} : <type>
     fn variable_declaration(&mut self) -> ParseResult {
-        let mut names = HashSet::new();
-        loop {
-            let maybe_name = self.identifier();
-            if let Ok(id) = maybe_name {
-                if names.contains(&id) {
-                    self.redefined_identifier(&id);
-                } else {
-                    names.insert(id);
-                }
-
-                match self.lookahead {
-                    Token::P(Punctuation::Comma) => self.proceed()?,
-                    Token::P(Punctuation::Colon) => break,
-                    _ => ()
-                }
-            } else {
-                self.panic(&[Token::P(Punctuation::Colon)])?
-            }
-        }
+        let names = self.recover(
+            recovery::FOLLOW_IDENTIFIER_LIST, Vec::new(),
+            |c| c.identifier_list(true)
+        )?;
 
         self.consume(Token::P(Punctuation::Colon))?;
 
+        let type_pos = self.token_stream.token_pos();
         let t = self.type_()?;
 
-        for name in &names {
-            self.wasm.func_local(name, &t.clone());
+        for (name, range) in names.iter().cloned() {
+            self.scope.declare(&name, range.clone());
+            let extent = range.start..self.last_end;
+            self.declared(
+                &name, range, extent, SemanticKind::Variable, Some(&t)
+            );
+            self.wasm.variable(&name, &t.clone());
+        }
+
+        let max_pages = self.wasm.target().memory.max_pages;
+        let max_pages = u64::from(max_pages.unwrap_or(MAX_PAGES));
+        if self.wasm.memory_pages() > max_pages && !self.memory_exceeded {
+            // Reported once, as the variables after it do not fit either
+            self.memory_exceeded = true;
+            self.error_at(
+                CompilationErrorKind::SemanticError,
+                type_pos,
+                "the variables do not fit in the memory"
+            );
         }
 
         let r = self.scope.extend(
-            names.drain().map(|name| (
+            names.into_iter().map(|(name, _)| (
                 name.clone(),
                 Identifier::Variable(name, t.clone())
             ))
@@ -260,19 +572,291 @@ impl<T: Buffer> Code<T> {
         Ok(())
     }
 
-    // <type> ::= <simple type> | <structured type>
+    // <identifier list> ::= <identifier> {, <identifier>}
+    /// Also reports `scoped` names, which go into the current scope,
+    /// if the scope already declares them.
+    fn identifier_list(
+        &mut self,
+        scoped: bool
+    ) -> Result<Vec<(String, Range<usize>)>, CompilationError> {
+        let mut ids: Vec<(String, Range<usize>)> = Vec::new();
+        loop {
+            let id = self.identifier()?;
+            let redefined = scoped && self.scope.declares(&id);
+            if redefined || ids.iter().any(|(other, _)| *other == id) {
+                self.redefined_identifier(&id);
+            } else {
+                if scoped {
+                    self.shadowing(&id);
+                }
+                ids.push((id, self.id_range.clone()));
+            }
+
+            if self.lookahead == Token::P(Punctuation::Comma) {
+                self.proceed()?;
+            } else {
+                return Ok(ids);
+            }
+        }
+    }
+
+    // <type> ::= <simple type> | <structured type> | <pointer type>
     fn type_(&mut self) -> Result<Type, CompilationError> {
         match self.lookahead {
-            Token::K(Keyword::Record) => self.structured_type(),
+            Token::K(Keyword::Record)
+            | Token::K(Keyword::Array)
+            | Token::K(Keyword::Packed) => self.structured_type(),
+            Token::P(Punctuation::Caret) => self.pointer_type(),
             _ => self.simple_type()
         }
     }
 
-    // <structured type> ::= <array type> | <record type> | <set type> | <file type>
+    // <pointer type> ::= ^ <type identifier>
+    /// Reads a pointer type. In the type definitions the type pointed
+    /// to may be defined later, so that a record can point to records
+    /// of its own type, and it is checked to be declared once all the
+    /// types are.
+    fn pointer_type(&mut self) -> Result<Type, CompilationError> {
+        self.consume(Token::P(Punctuation::Caret))?;
+        let name = self.identifier()?;
+        match (self.scope.get(&name), self.pointed_types.as_mut()) {
+            (Some(Identifier::Type(t)), _) => {
+                let t = t.clone();
+                self.resolved(&name, SemanticKind::Type, Some(&t));
+            },
+            (None, Some(pointed_types)) => {
+                let range = self.id_range.clone();
+                pointed_types.push((name.clone(), range, self.id_pos));
+            },
+            (Some(Identifier::Unknown), _) => return Ok(Type::Unknown),
+            (Some(_), _) => {
+                self.invalid_identifier("type", &name);
+                return Ok(Type::Unknown);
+            },
+            (None, None) => {
+                self.undeclared_identifier(&name);
+                return Ok(Type::Unknown);
+            }
+        }
+
+        Ok(Type::Pointer(name))
+    }
+
+    // <structured type> ::=
+        // [packed] <array type> | [packed] <record type>
+        // | <set type> | <file type>
     fn structured_type(&mut self) -> Result<Type, CompilationError> {
+        let packed = self.lookahead == Token::K(Keyword::Packed);
+        if packed {
+            self.proceed()?;
+        }
+
         match self.lookahead {
             Token::K(Keyword::Record) => self.record_type(),
-            _ => panic!("Only record structured types are supported")
+            Token::K(Keyword::Array) => self.array_type(packed),
+            _ => Err(self.syntax_error(&format!(
+                "expected array or record, found {:?}", self.lookahead
+            )))
+        }
+    }
+
+    // <array type> ::=
+        // array [ <index type> {, <index type>} ] of <component type>
+    /// Reads an array type. An array with several index types is an
+    /// array of arrays, so that `array [1..2, 1..3] of real` is
+    /// `array [1..2] of array [1..3] of real`, and is stored row by row.
+    fn array_type(&mut self, packed: bool) -> Result<Type, CompilationError> {
+        self.consume(Token::K(Keyword::Array))?;
+        self.consume(Token::P(Punctuation::Lsqbracket))?;
+        let mut indices = Vec::new();
+        loop {
+            let bounds_pos = self.token_stream.token_pos();
+            indices.push((self.index_type()?, bounds_pos));
+            if self.lookahead == Token::P(Punctuation::Comma) {
+                self.proceed()?;
+            } else {
+                break;
+            }
+        }
+        self.consume(Token::P(Punctuation::Rsqbracket))?;
+        self.consume(Token::K(Keyword::Of))?;
+        let element_pos = self.token_stream.token_pos();
+        let mut t = self.type_()?;
+
+        for (index, bounds_pos) in indices.into_iter().rev() {
+            t = match index {
+                Some(index) => {
+                    self.array_of(packed, index, t, (bounds_pos, element_pos))
+                },
+                None => Type::Unknown
+            };
+        }
+
+        Ok(t)
+    }
+
+    /// The array type with the index and the elements of the type
+    /// `element`, or an unknown type if there can be no such array.
+    /// The positions are the ones of the index and the element type.
+    fn array_of(
+        &mut self,
+        packed: bool,
+        index: (Type, i32, i32),
+        element: Type,
+        positions: (FilePosition, FilePosition)
+    ) -> Type {
+        let (index, low, high) = index;
+        let (bounds_pos, element_pos) = positions;
+        match element {
+            Type::Unknown => return Type::Unknown,
+            Type::Record(_) => {
+                self.error_at(
+                    CompilationErrorKind::SemanticError,
+                    element_pos,
+                    "arrays of records are not supported"
+                );
+                return Type::Unknown;
+            },
+            _ => ()
+        }
+
+        let t = Type::Array(Array {
+            packed,
+            index: Box::new(index),
+            low,
+            high,
+            element: Box::new(element),
+        });
+        if t.size().is_none() {
+            self.error_at(
+                CompilationErrorKind::SemanticError,
+                bounds_pos,
+                "the array is too large"
+            );
+            return Type::Unknown;
+        }
+
+        t
+    }
+
+    // <index type> ::= <constant> .. <constant> | <type identifier>
+    /// Reads the index type of an array, which is an enumeration or
+    /// `char` or a subrange of an ordinal type, returning the type of
+    /// the index and the ordinals of the bounds. Returns none if the
+    /// index type is wrong, which has been reported.
+    fn index_type(
+        &mut self
+    ) -> Result<Option<(Type, i32, i32)>, CompilationError> {
+        let pos = self.token_stream.token_pos();
+        if let Token::Id(name) = &self.lookahead {
+            if let Some(Identifier::Type(_)) = self.scope.get(name) {
+                let t = self.type_identifier()?;
+                return Ok(match t {
+                    Type::Scalar(_) | Type::Char => {
+                        t.range().map(|(low, high)| (t, low, high))
+                    },
+                    Type::Unknown => None,
+                    t => {
+                        self.error_at(
+                            CompilationErrorKind::SemanticError,
+                            pos,
+                            &format!("{} cannot index an array", t)
+                        );
+                        None
+                    }
+                });
+            }
+        }
+
+        let low = self.bound()?;
+        self.consume(Token::P(Punctuation::Range))?;
+        let high = self.bound()?;
+
+        let ((t, low), (high_type, high)) = match (low, high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => return Ok(None)
+        };
+        if t != high_type {
+            self.type_mismatch("the bounds of the index", &t, &high_type);
+            return Ok(None);
+        }
+        if low > high {
+            self.error_at(
+                CompilationErrorKind::SemanticError,
+                pos,
+                &format!(
+                    "the lower bound {} of the array exceeds \
+                    the upper bound {}",
+                    t.spell(low), t.spell(high)
+                )
+            );
+            return Ok(None);
+        }
+
+        Ok(Some((t, low, high)))
+    }
+
+    // <constant> ::=
+        // [<sign>] <unsigned integer> | <character> | <constant identifier>
+    /// Reads a bound of an index type, returning its type and its
+    /// ordinal, or none if it is wrong, which has been reported.
+    fn bound(&mut self) -> Result<Option<(Type, i32)>, CompilationError> {
+        let negative = self.lookahead == Token::O(Operator::Minus);
+        let signed = negative || self.lookahead == Token::O(Operator::Plus);
+        if signed {
+            self.proceed()?;
+        }
+
+        match self.lookahead.clone() {
+            Token::Number(v) if !v.contains(&['.', 'e'][..]) => {
+                let sign = if negative { "-" } else { "" };
+                let bound = format!("{}{}", sign, v).parse::<i32>().ok();
+                if bound.is_none() {
+                    self.semantic_error("integer constant out of range");
+                }
+                self.proceed()?;
+                Ok(bound.map(|b| (Type::Integer, b)))
+            },
+            Token::Literal(v) if !signed => {
+                let pos = self.token_stream.token_pos();
+                self.proceed()?;
+                let mut chars = v.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(Some((Type::Char, c as i32))),
+                    _ => {
+                        self.error_at(
+                            CompilationErrorKind::SemanticError,
+                            pos,
+                            "a bound must be a single character"
+                        );
+                        Ok(None)
+                    }
+                }
+            },
+            Token::Id(name) if !signed => {
+                self.identifier()?;
+                match self.scope.get(&name).cloned() {
+                    Some(Identifier::Constant(t)) => {
+                        self.resolved_member(
+                            &t, &name, SemanticKind::Constant, Some(&t)
+                        );
+                        let ordinal = t.ordinal(&name).unwrap_or_default();
+                        Ok(Some((t, ordinal as i32)))
+                    },
+                    Some(Identifier::Unknown) => Ok(None),
+                    Some(_) => {
+                        self.invalid_identifier("constant", &name);
+                        Ok(None)
+                    },
+                    None => {
+                        self.undeclared_identifier(&name);
+                        Ok(None)
+                    }
+                }
+            },
+            token => Err(self.syntax_error(&format!(
+                "expected a bound of the index, found {:?}", token
+            )))
         }
     }
 
@@ -291,14 +875,27 @@ impl<T: Buffer> Code<T> {
 
     // <subrange type> ::= <constant> .. <constant>
     fn subrange_type(&mut self) -> Result<Type, CompilationError> {
-        todo!("subrange_type");
+        self.proceed()?;
+        self.consume(Token::P(Punctuation::Range))?;
+        match self.lookahead {
+            Token::Number(_) => self.proceed()?,
+            _ => return Err(self.syntax_error(&format!(
+                "expected number, found {:?}", self.lookahead
+            ))),
+        }
+        self.semantic_error("subrange types are not supported");
+        Ok(Type::Unknown)
     }
 
     fn type_identifier(&mut self) -> Result<Type, CompilationError> {
         let name = self.identifier()?;
 
         match self.scope.get(&name) {
-            Some(Identifier::Type(t)) => Ok(t.to_owned()),
+            Some(Identifier::Type(t)) => {
+                let t = t.to_owned();
+                self.resolved(&name, SemanticKind::Type, Some(&t));
+                Ok(t)
+            },
             Some(_) => {
                 self.invalid_identifier("type", &name);
                 Ok(Type::Unknown)
@@ -314,19 +911,41 @@ impl<T: Buffer> Code<T> {
     fn scalar_type(&mut self) -> Result<Type, CompilationError> {
         self.consume(Token::P(Punctuation::Lbracket))?;
         let mut ids = Enumeration::new();
+        let mut ranges = Vec::new();
         loop {
             let id = self.identifier()?;
-            if ids.contains(&id) {
+            if ids.contains(&id) || self.scope.declares(&id) {
                 self.redefined_identifier(&id);
             } else {
-                ids.push_back(id);
+                self.shadowing(&id);
+                ids.push(id);
+                ranges.push(self.id_range.clone());
             }
 
             if self.lookahead == Token::P(Punctuation::Comma) {
                 self.proceed()?;
             } else {
                 self.consume(Token::P(Punctuation::Rbracket))?;
-                return Ok(Type::Scalar(ids));
+                let t = Type::Scalar(ids);
+                let mut spans = Spans::new();
+                if let Type::Scalar(ids) = &t {
+                    for (id, range) in ids.iter().zip(ranges) {
+                        // Checked for redefinition as it was read
+                        let constant = Identifier::Constant(t.clone());
+                        self.scope.put(id.clone(), constant).unwrap();
+                        self.scope.declare(id, range.clone());
+                        spans.insert(id.clone(), range.clone());
+                        self.declared(
+                            id,
+                            range.clone(),
+                            range,
+                            SemanticKind::Constant,
+                            Some(&t)
+                        );
+                    }
+                }
+                self.members.push((t.clone(), spans));
+                return Ok(t);
             }
         }
     }
@@ -334,32 +953,35 @@ impl<T: Buffer> Code<T> {
     // <record type> ::= record <field list> end
     fn record_type(&mut self) -> Result<Type, CompilationError> {
         self.consume(Token::K(Keyword::Record))?;
-        let fields = self.field_list().or_else(|_| {
-            self.panic(&[Token::K(Keyword::End)])?;
-            Ok(Fields::new())
-        })?;
+        let (fields, spans) = self.recover(
+            recovery::FOLLOW_FIELD_LIST, (Fields::new(), Spans::new()),
+            |c| c.field_list()
+        )?;
         self.consume(Token::K(Keyword::End))?;
 
-        Ok(Type::Record(fields))
+        let t = Type::Record(fields);
+        self.members.push((t.clone(), spans));
+        Ok(t)
     }
 
     // <field list> ::= <fixed part>
-    fn field_list(&mut self) -> Result<Fields, CompilationError> {
+    fn field_list(&mut self) -> Result<(Fields, Spans), CompilationError> {
         let mut table = Fields::new();
-        self.fixed_part(&mut table)?;
-        Ok(table)
+        let mut spans = Spans::new();
+        self.fixed_part(&mut table, &mut spans)?;
+        Ok((table, spans))
     }
 
     // <fixed part> ::= <record section> {;<record section>}
     fn fixed_part(
-        &mut self, table: &mut Fields
+        &mut self, table: &mut Fields, spans: &mut Spans
     ) -> ParseResult {
-        self.record_section(table)?;
+        self.record_section(table, spans)?;
 
         loop {
             if self.lookahead == Token::P(Punctuation::Semicolon) {
                 self.proceed()?;
-                self.record_section(table)?;
+                self.record_section(table, spans)?;
             } else {
                 break;
             }
@@ -372,78 +994,139 @@ impl<T: Buffer> Code<T> {
         // <field identifier> {, <field identifier>} : <type>
         // | <empty>
     fn record_section(
-        &mut self, table: &mut Fields
+        &mut self, table: &mut Fields, spans: &mut Spans
     ) -> ParseResult {
 
         if !matches!(self.lookahead, Token::Id(_)) {
             return Ok(())
         }
 
-        let mut ids = HashSet::new();
-        loop {
-            let id = self.identifier().or_else(|_| {
-                self.panic(&[Token::P(Punctuation::Colon)])?;
-                Ok(String::new())
-            })?;
-            
-            if ids.contains(&id) {
-                self.redefined_identifier(&id);
-            } else {
-                ids.insert(id);
-            }
+        let ids = self.recover(
+            recovery::FOLLOW_IDENTIFIER_LIST, Vec::new(),
+            |c| c.identifier_list(false)
+        )?;
 
-            if self.lookahead == Token::P(Punctuation::Comma) {
-                self.proceed()?;
-            } else {
-                break;
-            }
-        }
-        
-        
         self.consume(Token::P(Punctuation::Colon))?;
         
         let t = self.type_()?;
 
-        table.extend(ids.drain().map(|id| (id, t.to_owned())));
+        for (id, range) in ids {
+            let extent = range.start..self.last_end;
+            self.declared(
+                &id, range.clone(), extent, SemanticKind::Field, Some(&t)
+            );
+            spans.entry(id.clone()).or_insert(range);
+            table.insert(id, t.to_owned());
+        }
 
         Ok(())
     }
 
     // <statement part> ::= <compound statement>
+    /// Compiles the statements of the program. If they start with
+    /// a structured statement instead of `begin`, it is reported
+    /// missing and they are compiled as if it were there. An identifier
+    /// there is more likely a declaration missing its semicolon.
     fn statements(&mut self) -> ParseResult {
-        self.compound_statement()
+        self.directed(|c| {
+            let missing_begin = c.starts_statement()
+                && !matches!(
+                    c.lookahead,
+                    Token::Id(_) | Token::K(Keyword::Begin)
+                );
+            if !missing_begin {
+                return c.compound_statement();
+            }
+
+            let pos = c.token_stream.token_pos();
+            let message = "missing 'begin' before the statements \
+                of the program";
+            let fix = FixIt::insertion(pos, "begin ");
+            c.syntax_error_with_fix(pos, message, Some(fix));
+            c.statement_sequence(recovery::FOLLOW_COMPOUND_STATEMENT_PART)?;
+            c.consume(Token::K(Keyword::End))
+        })
     }
 
     // <compound statement> ::= begin <statement> {; <statement> } end;
     fn compound_statement(&mut self) -> ParseResult {
         self.consume(Token::K(Keyword::Begin))?;
-        self.statement()?;
-        loop {
-            if self.lookahead == Token::P(Punctuation::Semicolon) {
-                self.proceed()?;
+        self.statement_sequence(recovery::FOLLOW_COMPOUND_STATEMENT_PART)?;
+        self.consume(Token::K(Keyword::End))?;
 
-                if self.lookahead == Token::K(Keyword::End) {
-                    break;
-                }
+        Ok(())
+    }
 
-                self.statement()?;
-            } else {
-                break;
-            }
+    // <statement sequence> ::= <statement> {; <statement>}
+    /// Compiles the statements, reporting the first one that follows
+    /// a statement which never finishes, as it can never run. The
+    /// sequence never finishes if one of its statements does not.
+    fn statement_sequence(&mut self, follow: TokenSet) -> ParseResult {
+        self.recover(follow, (), |c| c.statement())?;
+        let mut terminates = self.terminates;
+        let mut reported = false;
+        while self.lookahead == Token::P(Punctuation::Semicolon) {
+            self.proceed()?;
+            let empty = matches!(
+                self.lookahead,
+                Token::P(Punctuation::Semicolon)
+                | Token::K(Keyword::End)
+                | Token::K(Keyword::Until)
+                | Token::K(Keyword::Else)
+            );
+            let report = terminates && !empty && !reported;
+            self.recover(follow, (), |c| c.directed(|c| {
+                if report {
+                    let pos = c.token_stream.token_pos();
+                    c.lint(Lint::UnreachableCode, pos, "unreachable statement");
+                }
+                c.statement()
+            }))?;
+            reported |= report;
+            terminates |= self.terminates;
         }
-
-        self.consume(Token::K(Keyword::End))?;
+        self.terminates = terminates;
 
         Ok(())
     }
 
-    // <statement> ::= <simple statement> | <structured statement>
+    /// Compiles a statement under the directives before it, dropping
+    /// its code if it has errors. The errors in a nested statement drop
+    /// only that one, unless they stop the enclosing statement from
+    /// being compiled to the end.
     fn statement(&mut self) -> ParseResult {
+        self.directed(|c| c.transactional_statement())
+    }
+
+    /// Compiles a statement, dropping its code if it has errors.
+    fn transactional_statement(&mut self) -> ParseResult {
+        let transaction = self.wasm.transaction();
+        self.transactions.push(false);
+        let result = self.unguarded_statement();
+        let failed = self.transactions.pop().unwrap_or_default();
+        if failed || result.is_err() {
+            self.wasm.rollback(transaction);
+        }
+
+        result
+    }
+
+    // <statement> ::= <simple statement> | <structured statement>
+    fn unguarded_statement(&mut self) -> ParseResult {
+        self.terminates = false;
         match self.lookahead.clone() {
             Token::P(Punctuation::Semicolon) => Ok(()),
             Token::K(Keyword::End) => Ok(()),
-            Token::K(_) => self.structured_statement(),
-            Token::Id(_) => self.simple_statement(),
+            Token::K(Keyword::Until) => Ok(()),
+            Token::K(Keyword::Else) => Ok(()),
+            Token::K(_) => {
+                self.metrics.statements += 1;
+                self.structured_statement()
+            },
+            Token::Id(_) => {
+                self.metrics.statements += 1;
+                self.simple_statement()
+            },
             t => Err(self.syntax_error(&format!(
                 "a statement cannot start with {:?}",
                 t
@@ -462,6 +1145,8 @@ impl<T: Buffer> Code<T> {
                             self.assignment_statement(),
                         Identifier::Procedure(types) =>
                             self.procedure_statement(&name, &types),
+                        Identifier::StandardProcedure(p) =>
+                            self.standard_procedure_statement(p),
                         _ => Err(self.semantic_error("illegal statement"))
                     }
                 }
@@ -474,23 +1159,73 @@ impl<T: Buffer> Code<T> {
 
     // <assignment statement> ::= <variable> := <expression>
     fn assignment_statement(&mut self) -> ParseResult {
-        let (name, variable_type) = self.variable()?;
-        self.consume(Token::O(Operator::Assign))?;
+        let (place, variable_type) = self.variable()?;
+        self.assignment_operator()?;
         let expression_type = self.expression(&variable_type)?;
+        let value = self.aggregate.take();
 
-        if variable_type != Type::Unknown
-            && expression_type != Type::Unknown {
+        if variable_type == Type::Unknown || expression_type == Type::Unknown {
+            return Ok(());
+        }
 
-            if variable_type == expression_type {
-                self.wasm.local_set(&name)
-            } else {
-                self.semantic_error("type mismatch in assignment");
+        match (&variable_type, &place, value) {
+            (
+                Type::Array(array),
+                Place::Memory(offset),
+                Some(Aggregate::Literal(text))
+            ) if array.holds_string(text.chars().count()) => {
+                self.wasm.store_text(&text, *offset);
+            },
+            (Type::Array(_), _, Some(Aggregate::Stored(_)))
+                if variable_type == expression_type => {
+                self.semantic_error("arrays cannot be assigned as a whole");
+            },
+            (Type::Array(_), _, _) => {
+                self.type_mismatch(
+                    "assignment", &variable_type, &expression_type
+                );
+            },
+            (
+                Type::Record(_),
+                Place::Memory(offset),
+                Some(Aggregate::Stored(source))
+            ) if variable_type == expression_type => {
+                self.wasm.copy(&variable_type, (*offset, source));
+            },
+            (Type::Record(_), _, _) => {
+                self.type_mismatch(
+                    "assignment", &variable_type, &expression_type
+                );
+            },
+            _ if self.assignable(&variable_type, &expression_type) => {
+                self.set(&place, &variable_type)
+            },
+            _ => {
+                self.type_mismatch(
+                    "assignment", &variable_type, &expression_type
+                );
             }
         }
 
         Ok(())
     }
 
+    /// Reads the value of the variable at the place.
+    fn get(&mut self, place: &Place, t: &Type) {
+        match place {
+            Place::Variable(name) => self.wasm.local_get(name),
+            Place::Memory(offset) => self.wasm.load(t, *offset)
+        }
+    }
+
+    /// Stores the value on top of the stack in the variable at the place.
+    fn set(&mut self, place: &Place, t: &Type) {
+        match place {
+            Place::Variable(name) => self.wasm.local_set(name),
+            Place::Memory(offset) => self.wasm.store(t, *offset)
+        }
+    }
+
     // <procedure statement> ::=
         // <procedure identifier>
         // | <procedure identifier> (<actual parameter>
@@ -501,78 +1236,318 @@ impl<T: Buffer> Code<T> {
         types: &Types
     ) -> ParseResult {
         self.identifier()?;
-        if types.len() > 0 {
-            self.consume(Token::P(Punctuation::Lbracket))?;
+        self.resolved(name, SemanticKind::Procedure, None);
+        let spelling = self.spelling(name);
 
-            for t in types {
-                let t_a = self.expression(t)?;
-                if t_a != *t {
-                    self.semantic_error(
-                        "type mismatch in procedure arguments"
-                    );
+        let mut found = 0;
+        if self.lookahead == Token::P(Punctuation::Lbracket) {
+            self.proceed()?;
+
+            if self.lookahead != Token::P(Punctuation::Rbracket) {
+                loop {
+                    let t = types.get(found).cloned().unwrap_or(Type::Unknown);
+                    let t_a = self.expression(&t)?;
+                    if found < types.len() && !self.assignable(&t, &t_a) {
+                        self.type_mismatch("procedure arguments", &t, &t_a);
+                    }
+                    found += 1;
+
+                    if self.lookahead == Token::P(Punctuation::Comma) {
+                        self.proceed()?;
+                    } else {
+                        break;
+                    }
                 }
             }
 
+            self.consume(Token::P(Punctuation::Rbracket))?;
+        }
+
+        if found == types.len() {
             self.wasm.call(name);
+        } else {
+            self.wrong_argument_count(&spelling, types.len(), found);
+        }
 
-            self.consume(Token::P(Punctuation::Rbracket))?;
+        Ok(())
+    }
+
+    fn standard_procedure_statement(
+        &mut self,
+        procedure: StandardProcedure
+    ) -> ParseResult {
+        let name = self.identifier()?;
+        self.resolved(&name, SemanticKind::Procedure, None);
+        match procedure {
+            // The program is the only routine,
+            // so leaving it ends the execution
+            StandardProcedure::Exit | StandardProcedure::Halt => {
+                self.wasm.return_();
+                self.terminates = true;
+            },
+            StandardProcedure::Randomize => self.wasm.randomize()
         }
 
         Ok(())
     }
 
-    // <variable> ::= <identifier> | <identifier> . <field_designator>
+    // <variable> ::=
+        // <identifier>
+        // | <identifier> . <field_designator>
+        // | <identifier> [ <expression> {, <expression>} ]
+            // { [ <expression> {, <expression>} ] }
+        // | <variable> ^
     fn variable(
         &mut self
-    ) -> Result<(String, Type), CompilationError> {
-        
-        let name = self.identifier()?;
-        let t = match self.scope.get(&name) {
-            Some(Identifier::Variable(_, t)) => Ok(t),
-            Some(_) => Err(self.invalid_identifier("variable", &name)),
-            None => Err(self.undeclared_identifier(&name))
-        }?.clone();
-
-        if let Token::P(Punctuation::Dot) = self.lookahead {
-            self.proceed()?;
-            if let Type::Record(fs) = t {
-                let t = self.field_designator(&fs)?;
-                Ok((name, t))
-            } else {
-                self.semantic_error(&format!(
-                    "attempt to access a field of a \
-                    non-record variable \"{}\"",
-                    name,
-                ));
-                let t = self.field_designator(&Fields::new())?;
-                Ok((name, t))
+    ) -> Result<(Place, Type), CompilationError> {
+        let (name, storage, t) = self.variable_identifier()?;
+        let address = self.wasm.address(&storage);
+        let (t, address) = self.selected_field(&name, t, address)?;
+        let (t, address) = self.referenced_variable(&name, t, address)?;
+
+        match (address, &self.lookahead) {
+            // The arrays and the records are kept in memory,
+            // as are their elements and fields
+            (Some(address), _) => self.indexed_variable(&name, address, t),
+            (None, Token::P(Punctuation::Lsqbracket)) => {
+                if t != Type::Unknown {
+                    let context = format!(
+                        "the indexing of \"{}\"", self.spelling(&name)
+                    );
+                    self.type_mismatch(&context, &"an array", &t);
+                }
+                self.indexed_variable(&name, 0, Type::Unknown)
+            },
+            (None, _) => Ok((Place::Variable(storage), t))
+        }
+    }
+
+    // <referenced variable> ::= <pointer variable> ^
+    /// Reads the arrow after a pointer variable of the type `t`, if
+    /// there is one, and the fields of the variable pointed to. The
+    /// memory pointers point to is not allocated yet, so they cannot
+    /// be followed and the variable read is of an unknown type.
+    fn referenced_variable(
+        &mut self,
+        name: &str,
+        t: Type,
+        address: Option<u32>
+    ) -> Result<(Type, Option<u32>), CompilationError> {
+        if self.lookahead != Token::P(Punctuation::Caret) {
+            return Ok((t, address));
+        }
+
+        let pos = self.token_stream.token_pos();
+        self.proceed()?;
+        match t {
+            Type::Pointer(_) => {
+                self.error_at(
+                    CompilationErrorKind::SemanticError,
+                    pos,
+                    "pointers cannot be followed yet"
+                );
+            },
+            Type::Unknown => (),
+            t => {
+                let context = format!(
+                    "the reference of \"{}\"", self.spelling(name)
+                );
+                self.type_mismatch(&context, &"a pointer", &t);
             }
+        }
+
+        let (t, address) = self.selected_field(name, Type::Unknown, None)?;
+        self.referenced_variable(name, t, address)
+    }
+
+    /// Reads the identifier of a variable, returning it along with the
+    /// name the variable is kept under and the type of the variable.
+    fn variable_identifier(
+        &mut self
+    ) -> Result<(String, String, Type), CompilationError> {
+        let name = self.identifier()?;
+        let (storage, t) = match self.scope.get(&name) {
+            Some(Identifier::Variable(storage, t)) => {
+                Ok((storage.clone(), t.clone()))
+            },
+            Some(_) => Err(self.invalid_identifier("variable", &name)),
+            None => Err(self.undeclared_identifier(&name))
+        }?;
+        self.resolved(&name, SemanticKind::Variable, Some(&t));
+
+        Ok((name, storage, t))
+    }
+
+    /// Reads the field designator after the variable of the type, if
+    /// there is one, returning the type of the field and its address
+    /// if the variable is kept at the address given.
+    fn selected_field(
+        &mut self,
+        name: &str,
+        t: Type,
+        address: Option<u32>
+    ) -> Result<(Type, Option<u32>), CompilationError> {
+        if self.lookahead != Token::P(Punctuation::Dot) {
+            return Ok((t, address));
+        }
+
+        self.proceed()?;
+        if let Type::Record(fs) = t {
+            let (t, offset) = self.field_designator(&fs)?;
+            Ok((t, address.map(|address| address + offset)))
+        } else if t == Type::Unknown {
+            let (t, _) = self.field_designator(&Fields::new())?;
+            Ok((t, None))
         } else {
-            Ok((name, t))
+            let context = format!(
+                "the field access of \"{}\"", self.spelling(name)
+            );
+            self.type_mismatch(&context, &"a record", &t);
+            let (t, _) = self.field_designator(&Fields::new())?;
+            Ok((t, address))
+        }
+    }
+
+    /// Reads the indices of an element of the array at the address,
+    /// computing the address of the element. The constant parts of
+    /// the address, such as the one of the array and the bias of
+    /// its lower bound, are left for the offset of the access.
+    fn indexed_variable(
+        &mut self,
+        name: &str,
+        address: u32,
+        mut t: Type
+    ) -> Result<(Place, Type), CompilationError> {
+        let mut offset = i64::from(address);
+        // Whether the part of the address depending
+        // on the indices is on the stack
+        let mut indexed = false;
+        // Whether the brackets are still open after a comma, as
+        // `a[i, j]` is the element `a[i][j]` of an array of arrays
+        let mut open = false;
+        while open || self.lookahead == Token::P(Punctuation::Lsqbracket) {
+            if !open {
+                self.proceed()?;
+            }
+            let array = match t {
+                Type::Array(array) => Some(array),
+                Type::Unknown => None,
+                other => {
+                    let context = format!(
+                        "the indexing of \"{}\"", self.spelling(name)
+                    );
+                    self.type_mismatch(&context, &"an array", &other);
+                    None
+                }
+            };
+
+            let line = self.token_stream.token_pos().line;
+            let pos = self.token_stream.token_pos();
+            let mark = self.wasm.mark();
+            let expected = array.as_ref()
+                .map_or(Type::Unknown, |a| (*a.index).clone());
+            let index_type = self.expression(&expected)?;
+            let constant = self.constant.take().and_then(Value::ordinal);
+            open = self.lookahead == Token::P(Punctuation::Comma);
+            if open {
+                self.proceed()?;
+            } else {
+                self.consume(Token::P(Punctuation::Rsqbracket))?;
+            }
+
+            let array = match array {
+                Some(array) => array,
+                None => {
+                    t = Type::Unknown;
+                    continue;
+                }
+            };
+            if index_type != Type::Unknown && index_type != *array.index {
+                let index = &*array.index;
+                self.type_mismatch("the array index", index, &index_type);
+                t = *array.element;
+                continue;
+            }
+
+            // The elements are checked to have a size when
+            // the array type is declared
+            let stride = array.element.size().unwrap_or_default();
+            let bias = i64::from(array.low) * i64::from(stride);
+            match constant {
+                Some(index) => {
+                    self.wasm.rollback(mark);
+                    if index < array.low || index > array.high {
+                        let spell = |o| array.index.spell(o);
+                        let message = format!(
+                            "the index {} is out of the bounds {}..{}",
+                            spell(index),
+                            spell(array.low),
+                            spell(array.high)
+                        );
+                        let kind = CompilationErrorKind::SemanticError;
+                        self.error_at(kind, pos, &message);
+                    }
+                    offset += i64::from(index) * i64::from(stride) - bias;
+                },
+                None => {
+                    self.wasm.index_check(array.low, array.high, line);
+                    // The index minus a negative bound is not negative,
+                    // so the address does not wrap around
+                    if array.low < 0 {
+                        let low = array.low.to_string();
+                        self.wasm.constant(&low, &Type::Integer);
+                        self.wasm.op(&Operator::Minus, &Type::Integer);
+                    } else {
+                        offset -= bias;
+                    }
+                    if stride != 1 {
+                        let stride = stride.to_string();
+                        self.wasm.constant(&stride, &Type::Integer);
+                        self.wasm.op(&Operator::Multiply, &Type::Integer);
+                    }
+                    if indexed {
+                        self.wasm.op(&Operator::Plus, &Type::Integer);
+                    }
+                    indexed = true;
+                }
+            }
+            t = *array.element;
+        }
+
+        if !indexed {
+            self.wasm.constant(&offset.to_string(), &Type::Integer);
+            offset = 0;
+        } else if offset < 0 || offset > i64::from(u32::MAX) {
+            self.wasm.constant(&offset.to_string(), &Type::Integer);
+            self.wasm.op(&Operator::Plus, &Type::Integer);
+            offset = 0;
         }
+
+        Ok((Place::Memory(offset as u32), t))
     }
 
     // <field_designator> ::= 
         // <field_identifier>
         // | <field_identifier> . <field_designator>
+    /// Returns the type of the field and its offset from the start
+    /// of the record.
     fn field_designator(
         &mut self,
         subscope: &Fields
-    ) -> Result<Type, CompilationError> {
-        let t = self.field_identifier(subscope)?;
+    ) -> Result<(Type, u32), CompilationError> {
+        let (t, offset) = self.field_identifier(subscope)?;
         
         if let Token::P(Punctuation::Dot) = self.lookahead {
             self.proceed()?;
-            if let Type::Record(fs) = t {
-                self.field_designator(&fs)
+            let (t, inner) = if let Type::Record(fs) = t {
+                self.field_designator(&fs)?
             } else {
-                self.semantic_error(
-                    "attempt to access a field of a non-record field",
-                );
-                self.field_designator(&Fields::new())
-            }
+                self.type_mismatch("the field access", &"a record", &t);
+                self.field_designator(&Fields::new())?
+            };
+            Ok((t, offset + inner))
         } else {
-            Ok(t)
+            Ok((t, offset))
         }
     }
 
@@ -580,17 +1555,20 @@ impl<T: Buffer> Code<T> {
     fn field_identifier(
         &mut self,
         subscope: &Fields
-    ) -> Result<Type, CompilationError> {
+    ) -> Result<(Type, u32), CompilationError> {
         let name = self.identifier()?;
         if subscope.is_empty() {
-            return Ok(Type::Unknown);
+            return Ok((Type::Unknown, 0));
         }
 
-        if let Some(t) = subscope.get(&name) {
-            Ok(t.clone())
+        let record = Type::Record(subscope.clone());
+        if let Some((offset, t)) = record.field(&name) {
+            let t = t.clone();
+            self.resolved_member(&record, &name, SemanticKind::Field, Some(&t));
+            Ok((t, offset))
         } else {
             self.semantic_error(&format!("undefined field {}", name));
-            Ok(Type::Unknown)
+            Ok((Type::Unknown, 0))
         }
     }
 
@@ -601,7 +1579,8 @@ impl<T: Buffer> Code<T> {
         // | <with statement>
     fn structured_statement(&mut self) -> ParseResult {
         match self.lookahead {
-            Token::K(Keyword::If) => self.conditional_statement(),
+            Token::K(Keyword::If)
+            | Token::K(Keyword::Case) => self.conditional_statement(),
             Token::K(Keyword::For)
             | Token::K(Keyword::While)
             | Token::K(Keyword::Repeat) => self.loop_statement(),
@@ -623,32 +1602,44 @@ impl<T: Buffer> Code<T> {
     }
 
 
-    // <conditional statement> ::= <if statement>
+    // <conditional statement> ::= <if statement> | <case statement>
     fn conditional_statement(&mut self) -> ParseResult {
-        self.if_statement()
+        match self.lookahead {
+            Token::K(Keyword::If) => self.if_statement(),
+            Token::K(Keyword::Case) => self.case_statement(),
+            _ => panic!("Keyword token that opens a condition was lost")
+        }
     }
 
     // <if statement> ::=
         // if <expression> then <statement>
         // | if <expression> then <statement> else <statement>
     fn if_statement(&mut self) -> ParseResult {
+        let start = self.token_stream.token_pos();
         self.consume(Token::K(Keyword::If))?;
-        
-        self.expression(&boolean())?;
+
+        let t = self.recover(
+            recovery::FOLLOW_IF_CONDITION, Type::Unknown,
+            |c| c.expression(&boolean())
+        )?;
+        if t != boolean() && t != Type::Unknown {
+            self.type_mismatch("the if condition", &boolean(), &t);
+        }
         self.wasm.if_start();
 
-        self.consume(Token::K(Keyword::Then))?;
+        self.header_end(Keyword::Then, "the if condition", start)?;
 
-        self.wasm.then_start();
         self.statement()?;
-        self.wasm.then_end();
+        let then_terminates = self.terminates;
 
         if self.lookahead == Token::K(Keyword::Else) {
             self.proceed()?;
 
             self.wasm.else_start();
             self.statement()?;
-            self.wasm.else_end();
+            self.terminates &= then_terminates;
+        } else {
+            self.terminates = false;
         }
 
         self.wasm.if_end();
@@ -656,6 +1647,205 @@ impl<T: Buffer> Code<T> {
         Ok(())
     }
     
+    // <case statement> ::=
+        // case <expression> of <case list element> {; <case list element>}
+        // [[;] else <statement> {; <statement>}] [;] end
+    /// Compiles a case statement as a chain of if statements, each
+    /// comparing the selector with the labels of an element. A label
+    /// may only be given once, and a case over an enumeration without
+    /// an else part should handle every constant of the enumeration.
+    fn case_statement(&mut self) -> ParseResult {
+        let pos = self.token_stream.token_pos();
+        self.consume(Token::K(Keyword::Case))?;
+
+        let mut t = self.recover(
+            recovery::FOLLOW_CASE_SELECTOR, Type::Unknown,
+            |c| c.expression(&Type::Unknown)
+        )?;
+        if t != Type::Unknown && t.range().is_none() {
+            self.type_mismatch("the case selector", &"an ordinal type", &t);
+            t = Type::Unknown;
+        }
+        let selector = self.wasm.case_selector();
+        self.wasm.local_set(selector);
+
+        self.expect(Token::K(Keyword::Of), recovery::FIRST_EXPRESSION)?;
+
+        let mut labels = Vec::new();
+        let mut elements = 0;
+        let mut terminates = true;
+        while !matches!(
+            self.lookahead,
+            Token::K(Keyword::End) | Token::K(Keyword::Else)
+        ) {
+            self.case_label_list(&t, &mut labels)?;
+            self.consume(Token::P(Punctuation::Colon))?;
+            self.wasm.if_start();
+            self.statement()?;
+            self.wasm.else_start();
+            elements += 1;
+            terminates &= self.terminates;
+
+            if self.lookahead == Token::P(Punctuation::Semicolon) {
+                self.proceed()?;
+            } else {
+                break;
+            }
+        }
+
+        let complete = self.lookahead == Token::K(Keyword::Else);
+        if complete {
+            self.proceed()?;
+            self.statement_sequence(recovery::FOLLOW_CASE_ELSE_PART)?;
+            terminates &= self.terminates;
+        }
+        for _ in 0..elements {
+            self.wasm.if_end();
+        }
+        self.consume(Token::K(Keyword::End))?;
+        let mut exhaustive = complete;
+        if !complete && matches!(t, Type::Scalar(_)) {
+            exhaustive = self.handles_constants(&t, &labels, pos);
+        }
+        self.terminates = exhaustive && terminates;
+
+        Ok(())
+    }
+
+    // <case label list> ::= <case label> {, <case label>}
+    // <case label> ::= <constant> [.. <constant>]
+    /// Reads the labels of an element of a case statement over the
+    /// type `selector`, leaving on the stack whether the selector has
+    /// the value of one of them. The ordinals of the labels are added
+    /// to those of the previous elements, which they must not repeat.
+    fn case_label_list(
+        &mut self,
+        selector: &Type,
+        labels: &mut Vec<(i32, i32)>
+    ) -> ParseResult {
+        let name = self.wasm.case_selector();
+        let mut first = true;
+        loop {
+            let pos = self.token_stream.token_pos();
+            let low = self.bound()?;
+            let high = if self.lookahead == Token::P(Punctuation::Range) {
+                self.proceed()?;
+                self.bound()?
+            } else {
+                low.clone()
+            };
+
+            let (low, high) = match (low, high) {
+                (Some(low), Some(high)) => (low, high),
+                _ => ((Type::Unknown, 0), (Type::Unknown, 0))
+            };
+            let range = (low.1, high.1);
+            let types = (&low.0, &high.0);
+            if self.case_label(selector, types, range, labels, pos) {
+                labels.push(range);
+            }
+
+            self.wasm.local_get(name);
+            self.wasm.constant(&range.0.to_string(), &Type::Integer);
+            if range.0 == range.1 {
+                self.wasm.relop(&Relation::Eq, &Type::Integer);
+            } else {
+                self.wasm.relop(&Relation::Ge, &Type::Integer);
+                self.wasm.local_get(name);
+                self.wasm.constant(&range.1.to_string(), &Type::Integer);
+                self.wasm.relop(&Relation::Le, &Type::Integer);
+                self.wasm.op(&Operator::And, &Type::Integer);
+            }
+            if !first {
+                self.wasm.op(&Operator::Or, &Type::Integer);
+            }
+            first = false;
+
+            if self.lookahead == Token::P(Punctuation::Comma) {
+                self.proceed()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks the label of a case statement over the type `selector`
+    /// with the bounds of the types and ordinals given, starting at the
+    /// position, against the labels found before. Reports what is wrong
+    /// with it, returning whether it is right.
+    fn case_label(
+        &mut self,
+        selector: &Type,
+        types: (&Type, &Type),
+        range: (i32, i32),
+        labels: &[(i32, i32)],
+        pos: FilePosition
+    ) -> bool {
+        let ((low_type, high_type), (low, high)) = (types, range);
+        if *selector == Type::Unknown || *low_type == Type::Unknown
+            || *high_type == Type::Unknown {
+            return false;
+        }
+        for t in [low_type, high_type] {
+            if t != selector {
+                self.type_mismatch("the case label", selector, t);
+                return false;
+            }
+        }
+
+        let kind = CompilationErrorKind::SemanticError;
+        let spell = |v| selector.spell(v);
+        let (least, greatest) = selector.range().unwrap_or_default();
+        let message = if low > high {
+            format!(
+                "the case label {}..{} has no values",
+                spell(low), spell(high)
+            )
+        } else if low < least || high > greatest {
+            let out = if low < least { low } else { high };
+            format!(
+                "the case label {} is out of the range {}..{}",
+                spell(out), spell(least), spell(greatest)
+            )
+        } else if let Some((other, _)) = labels.iter()
+            .find(|(l, h)| low <= *h && *l <= high) {
+            let repeated = low.max(*other);
+            format!("duplicate case label {}", spell(repeated))
+        } else {
+            return true;
+        };
+        self.error_at(kind, pos, &message);
+        false
+    }
+
+    /// Whether the labels of the case statement at the position have
+    /// every constant of the enumeration `selector`. Reports those that
+    /// none of the labels has.
+    fn handles_constants(
+        &mut self,
+        selector: &Type,
+        labels: &[(i32, i32)],
+        pos: FilePosition
+    ) -> bool {
+        let (least, greatest) = selector.range().unwrap_or_default();
+        let unhandled: Vec<_> = (least..=greatest)
+            .filter(|v| !labels.iter().any(|(l, h)| (l..=h).contains(&v)))
+            .map(|v| selector.spell(v))
+            .collect();
+        if unhandled.is_empty() {
+            return true;
+        }
+
+        let mut shown = unhandled[..unhandled.len().min(3)].join(", ");
+        if unhandled.len() > 3 {
+            shown += ", ...";
+        }
+        self.lint(Lint::IncompleteCase, pos, &format!(
+            "the case statement does not handle {}", shown
+        ));
+        false
+    }
+
     // <loop statement> ::=
         // <while statement>
         // | <repeat statemant>
@@ -671,29 +1861,29 @@ impl<T: Buffer> Code<T> {
 
     // <while statement> ::= while <expression> do <statement>
     fn while_statement(&mut self) -> ParseResult {
+        let start = self.token_stream.token_pos();
         self.consume(Token::K(Keyword::While))?;
 
-        self.wasm.loop_start(Self::CONTINUE, Self::END);
-        self.wasm.constant("1", &Type::Integer);
-        let t = self.expression(&boolean()).or_else(|_| {
-            self.panic(&[Token::K(Keyword::Do)])?;
-            Ok(Type::Unknown)
-        })?;
-        self.wasm.op(&Operator::Minus, &Type::Integer);
+        let labels = self.wasm.loop_start();
+        let t = self.recover(
+            recovery::FOLLOW_WHILE_CONDITION, Type::Unknown,
+            |c| c.expression(&boolean())
+        )?;
 
         if t == boolean() {
-            self.wasm.br_if(Self::END);
+            self.wasm.eqz(&t);
+            self.wasm.br_if(&labels.exit);
         } else if t != Type::Unknown {
-            self.semantic_error(
-                "the condition in a while statement must have boolean type"
-            );
+            self.type_mismatch("the while condition", &boolean(), &t);
         }
 
-        self.consume(Token::K(Keyword::Do))?;
+        self.header_end(Keyword::Do, "the while-loop condition", start)?;
         self.statement()?;
 
-        self.wasm.br(Self::CONTINUE);
+        self.wasm.br(&labels.next);
         self.wasm.loop_end();
+        // The body may never run
+        self.terminates = false;
 
         Ok(())
     }
@@ -701,25 +1891,17 @@ impl<T: Buffer> Code<T> {
     // <repeat statement> ::= repeat <statement> {; <statement>} until <expression>
     fn repeat_statement(&mut self) -> ParseResult {
         self.consume(Token::K(Keyword::Repeat))?;
-        self.wasm.loop_start(Self::CONTINUE, Self::END);
+        let labels = self.wasm.loop_start();
 
-        self.statement()?;
-        loop {
-            if self.lookahead == Token::P(Punctuation::Semicolon) {
-                self.proceed()?;
-                self.statement()?;
-            } else {
-                break;
-            }
-        }
+        self.statement_sequence(recovery::FOLLOW_REPEAT_STATEMENT_PART)?;
 
         self.consume(Token::K(Keyword::Until))?;
         let t = self.expression(&boolean())?;
         if t == boolean() {
-            self.wasm.br_if(Self::END);
-            self.wasm.br(Self::CONTINUE);
+            self.wasm.eqz(&t);
+            self.wasm.br_if(&labels.next);
         } else if t != Type::Unknown {
-            self.semantic_error("until expression must have boolean type");
+            self.type_mismatch("the until condition", &boolean(), &t);
         }
 
         self.wasm.loop_end();
@@ -729,35 +1911,42 @@ impl<T: Buffer> Code<T> {
     
     // <for statement> ::= for <control variable> := <for list> do <statement>
     fn for_statement(&mut self) -> ParseResult {
+        let start = self.token_stream.token_pos();
         self.consume(Token::K(Keyword::For))?;
-        self.wasm.local_get(Self::R0);
+        self.wasm.local_get(FOR_LIMIT);
 
-        let (n, t) = self.control_variable().or_else(|_| {
-            self.panic(&[Token::O(Operator::Assign)])?;
-            Ok(("".to_string(), Type::Unknown))
-        })?;
+        let (n, t) = self.recover(
+            recovery::FOLLOW_CONTROL_VARIABLE,
+            ("".to_string(), Type::Unknown),
+            |c| c.control_variable()
+        )?;
 
         if t != Type::Unknown && t != Type::Integer {
-            self.semantic_error(
-                "the for-loop control variable must have integer type"
+            self.type_mismatch(
+                "the for-loop control variable", &Type::Integer, &t
             );
         }
 
-        self.consume(Token::O(Operator::Assign))?;
+        if self.lookahead == Token::R(Relation::Eq) {
+            self.assignment_operator()?;
+        } else {
+            self.expect(
+                Token::O(Operator::Assign), recovery::FIRST_EXPRESSION
+            )?;
+        }
 
-        let direction = self.for_list(&n)
-            .or_else(|_| {
-                self.panic(&[Token::K(Keyword::Do)])?;
-                Ok(Token::Unknown)
-            })?;
+        let direction = self.recover(
+            recovery::FOLLOW_FOR_LIST, Token::Unknown,
+            |c| c.for_list(&n)
+        )?;
 
-        self.wasm.loop_start(Self::CONTINUE, Self::END);
-        self.wasm.local_get(Self::R0);
+        let labels = self.wasm.loop_start();
+        self.wasm.local_get(FOR_LIMIT);
         self.wasm.local_get(&n);
         self.wasm.relop(&Relation::Eq, &Type::Integer);
-        self.wasm.br_if(Self::END);
+        self.wasm.br_if(&labels.exit);
 
-        self.consume(Token::K(Keyword::Do))?;
+        self.header_end(Keyword::Do, "the for-loop header", start)?;
         self.statement()?;
 
         self.wasm.constant(
@@ -773,11 +1962,12 @@ impl<T: Buffer> Code<T> {
         self.wasm.op(&Operator::Plus, &Type::Integer);
         self.wasm.local_set(&n);
 
-        self.wasm.br(Self::CONTINUE);
+        self.wasm.br(&labels.next);
 
         self.wasm.loop_end();
+        self.terminates = false;
 
-        self.wasm.local_set(Self::R0);
+        self.wasm.local_set(FOR_LIMIT);
 
         Ok(())
     }
@@ -785,8 +1975,20 @@ impl<T: Buffer> Code<T> {
     // <control variable> ::= <identifier>
     fn control_variable(&mut self) -> Result<(String, Type), CompilationError> {
         let name = self.identifier()?;
+        self.short_variables.retain(|(short, ..)| short.to_lowercase() != name);
         match self.scope.get(&name) {
-            Some(Identifier::Variable(n, t)) => Ok((n.clone(), t.clone())),
+            Some(Identifier::Variable(n, t)) => {
+                let (n, t) = (n.clone(), t.clone());
+                self.resolved(&name, SemanticKind::Variable, Some(&t));
+                if self.wasm.address(&n).is_some() && !t.is_structured() {
+                    // A field named in a with statement
+                    self.semantic_error(
+                        "a field cannot be the control variable of a loop"
+                    );
+                    return Ok((n, Type::Unknown));
+                }
+                Ok((n, t))
+            },
             Some(_) => Err(self.invalid_identifier("variable", &name)),
             None => Err(self.undeclared_identifier(&name))
         }
@@ -797,7 +1999,7 @@ impl<T: Buffer> Code<T> {
         // | <initial value> downto <final value>
     fn for_list(&mut self, control_var_name: &str) -> Result<Token, CompilationError> {
         self.initial_value()?;
-        self.wasm.local_set(&control_var_name);
+        self.wasm.local_set(control_var_name);
 
         let direction = self.consume_any(&[
             Token::K(Keyword::To),
@@ -805,7 +2007,7 @@ impl<T: Buffer> Code<T> {
         ])?;
 
         self.final_value()?;
-        self.wasm.local_set(Self::R0);
+        self.wasm.local_set(FOR_LIMIT);
 
         Ok(direction)
     }
@@ -814,8 +2016,8 @@ impl<T: Buffer> Code<T> {
     fn initial_value(&mut self) -> Result<Type, CompilationError> {
         let t = self.expression(&Type::Integer)?;
         if t != Type::Integer {
-            self.semantic_error(
-                "the initial value in a for loop must have integer type"
+            self.type_mismatch(
+                "the initial value of the for loop", &Type::Integer, &t
             );
             Ok(Type::Unknown)
         } else {
@@ -827,8 +2029,8 @@ impl<T: Buffer> Code<T> {
     fn final_value(&mut self) -> Result<Type, CompilationError> {
         let t = self.expression(&Type::Integer)?;
         if t != Type::Integer {
-            self.semantic_error(
-                "the final value in a for loop must have integer type"
+            self.type_mismatch(
+                "the final value of the for loop", &Type::Integer, &t
             );
             Ok(Type::Unknown)
         } else {
@@ -838,24 +2040,69 @@ impl<T: Buffer> Code<T> {
 
     // <with statement> ::= with <record variable list> do <statement>
     fn with_statement(&mut self) -> ParseResult {
+        let start = self.token_stream.token_pos();
         self.consume(Token::K(Keyword::With))?;
-        let ids = self.record_variables()?;
-        self.scope = Scope::with_outer(self.scope.clone(), ids);
-        self.consume(Token::K(Keyword::Do))?;
-        self.statement()?;
-
-        Ok(())
+        let (ids, spans) = self.record_variables()?;
+        self.scope.push(ids);
+        for (name, range) in spans {
+            self.scope.declare(&name, range);
+        }
+        // The fields are visible only in the statement, even if
+        // it fails to parse and the error is recovered from above
+        let result = self
+            .header_end(Keyword::Do, "the records of the with statement", start)
+            .and_then(|_| self.statement());
+        self.scope.pop();
+
+        result
     }
 
     // <record variable list> ::= <record variable> {, <record variable>}
-    fn record_variables(&mut self) -> Result<Identifiers, CompilationError> {
-        let mut table = Fields::new();
+    fn record_variables(
+        &mut self
+    ) -> Result<(Identifiers, Spans), CompilationError> {
+        let mut ids = Identifiers::new();
+        let mut spans = Spans::new();
         loop {
-            let (_, t) = self.variable()?;
-            if let Type::Record(fs) = t {
-                table.extend(fs)
-            } else {
-                self.semantic_error("expected a variable of record type");
+            let pos = self.token_stream.token_pos();
+            let (name, storage, t) = self.variable_identifier()?;
+            let address = self.wasm.address(&storage);
+            let (t, address) = self.selected_field(&name, t, address)?;
+            let (mut t, address) =
+                self.referenced_variable(&name, t, address)?;
+            if self.lookahead == Token::P(Punctuation::Lsqbracket) {
+                // Records are never elements of arrays, so the element
+                // is only read to report that it is not a record
+                let mark = self.wasm.mark();
+                let address = address.unwrap_or_default();
+                t = self.indexed_variable(&name, address, t)?.1;
+                self.wasm.rollback(mark);
+            }
+
+            if let Some((_, s)) = self.members.iter().find(|(m, _)| *m == t) {
+                let s = s.clone();
+                self.hidden_names(&s, (&ids, &spans), pos);
+                spans.extend(s);
+            }
+            match (&t, address) {
+                (Type::Record(fs), Some(address)) => {
+                    for name in fs.keys() {
+                        let (offset, field) = match t.field(name) {
+                            Some((offset, field)) => (offset, field.clone()),
+                            None => continue
+                        };
+                        // The fields are known by the made-up names
+                        // of the memory they are kept in
+                        let storage = format!("with.{}", address + offset);
+                        self.wasm.alias(&storage, address + offset);
+                        let field = Identifier::Variable(storage, field);
+                        ids.insert(name.clone(), field);
+                    }
+                },
+                (Type::Record(_), None) | (Type::Unknown, _) => (),
+                _ => {
+                    self.type_mismatch("the with statement", &"a record", &t);
+                }
             }
 
             if let Token::P(Punctuation::Comma) = self.lookahead {
@@ -865,11 +2112,74 @@ impl<T: Buffer> Code<T> {
             }
         }
 
-        let ids = table.drain().map(
-            |(k, v)| (k.clone(), Identifier::Variable(k, v))
-        ).collect();
+        Ok((ids, spans))
+    }
+
+    /// Warns of the fields of a record named at the position in a with
+    /// statement, declared at the spans, that hide the fields of the
+    /// records named before them, at `before`, or the variables and
+    /// the fields visible outside the statement.
+    fn hidden_names(
+        &mut self,
+        fields: &Spans,
+        before: (&Identifiers, &Spans),
+        pos: FilePosition
+    ) {
+        if self.lints.level(Lint::ShadowedVariable) == LintLevel::Allow {
+            return;
+        }
+        let (ids, spans) = before;
+        let mut names: Vec<_> = fields.keys().collect();
+        names.sort();
+        for name in names {
+            let (kind, hidden) = if ids.contains_key(name) {
+                ("field", spans.get(name).cloned())
+            } else {
+                match self.scope.get(name) {
+                    Some(Identifier::Variable(storage, _)) => {
+                        let kind = if names::is_generated(storage) {
+                            "field"
+                        } else {
+                            "variable"
+                        };
+                        (kind, self.scope.declaration(name))
+                    },
+                    _ => continue,
+                }
+            };
+            let hidden = match hidden {
+                Some(hidden) => hidden,
+                None => continue,
+            };
+
+            let field = fields[name].clone();
+            let message = format!(
+                "the field \"{}\" declared at {} hides the {} \"{}\" \
+                declared at {}",
+                self.token_stream.text(field.clone()),
+                self.location(field.start),
+                kind,
+                self.token_stream.text(hidden.clone()),
+                self.location(hidden.start)
+            );
+            self.lint(Lint::ShadowedVariable, pos, &message);
+        }
+    }
+
+    /// The line and the column of the byte at the offset in the source
+    /// read, as `line:column`.
+    fn location(&self, offset: usize) -> String {
+        let pos = self.position(offset);
+        format!("{}:{}", pos.line, pos.col)
+    }
 
-        Ok(ids)
+    /// The position of the byte at the offset in the source read.
+    fn position(&self, offset: usize) -> FilePosition {
+        let before = self.token_stream.text(0..offset);
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        let col = before[line_start..].chars().count() + 1;
+        FilePosition::new(line, col)
     }
 
     // <expression> ::= 
@@ -882,37 +2192,226 @@ impl<T: Buffer> Code<T> {
         let type_a = self.simple_expression(expected_type)?;
         let mut type_r = type_a.clone();
 
-        if let Token::R(op) = self.lookahead {
+        // No expression is followed by `:=`, so it must have been
+        // meant as `=`
+        let relation = match self.lookahead {
+            Token::R(op) => Some(op),
+            Token::O(Operator::Assign) => {
+                self.mistaken_operator("=");
+                Some(Relation::Eq)
+            },
+            _ => None,
+        };
+        if let Some(op) = relation {
+            let left = self.aggregate.take();
+            let value = self.constant.take();
+            let spelling = match op {
+                Relation::Eq => "=".to_string(),
+                _ => self.lookahead_text(),
+            };
+            let pos = self.token_stream.token_pos();
             self.proceed()?;
-            let type_b = self.simple_expression(expected_type)?;
-
-            if type_a == type_b {
-                self.wasm.relop(&op, &type_a);
-                type_r = boolean();
+            let mark = self.wasm.mark();
+            // The right operand is compared with the left one, so
+            // a constant there belongs to the type of the left operand
+            let type_b = if type_a == Type::Unknown {
+                self.simple_expression(expected_type)?
             } else {
-                self.semantic_error(
-                    "values of different types cannot be compared"
-                );
-                type_r = Type::Unknown;
+                self.simple_expression(&type_a)?
+            };
+            let right = self.aggregate.take();
+            let constant = self.constant.take();
+
+            if type_a.is_structured() || type_b.is_structured() {
+                let operands = [(&type_a, left), (&type_b, right)];
+                let relation = (op, spelling.as_str(), pos);
+                return Ok(self.structured_comparison(relation, operands));
+            }
+
+            match self.unified(&type_a, &type_b, mark) {
+                Some(Type::Pointer(_))
+                    if !matches!(op, Relation::Eq | Relation::Ne) => {
+                    let message = format!(
+                        "pointers cannot be compared with {}", spelling
+                    );
+                    let kind = CompilationErrorKind::SemanticError;
+                    self.error_at(kind, pos, &message);
+                    type_r = Type::Unknown;
+                },
+                Some(t) => {
+                    self.wasm.relop(&op, &t);
+                    type_r = boolean();
+                    self.constant = value
+                        .zip(constant)
+                        .map(|(a, b)| evaluation::relation(op, a, b));
+                },
+                None => {
+                    self.type_mismatch("the comparison", &type_a, &type_b);
+                    type_r = Type::Unknown;
+                }
             }
         }
 
         Ok(type_r)
     }
 
+    /// Compares two operands at least one of which is structured with
+    /// the relation spelled as given at the position. Records of the
+    /// same type can be compared field by field, an array of characters
+    /// with a string literal of its length, and only for equality.
+    fn structured_comparison(
+        &mut self,
+        relation: (Relation, &str, FilePosition),
+        operands: [(&Type, Option<Aggregate>); 2]
+    ) -> Type {
+        let (op, spelling, pos) = relation;
+        let [(left, a), (right, b)] = operands;
+        if *left == Type::Unknown || *right == Type::Unknown {
+            return Type::Unknown;
+        }
+
+        let records = matches!(left, Type::Record(_))
+            || matches!(right, Type::Record(_));
+        let kind = if records { "records" } else { "arrays" };
+        let equal = match op {
+            Relation::Eq => true,
+            Relation::Ne => false,
+            _ => {
+                self.error_at(
+                    CompilationErrorKind::SemanticError,
+                    pos,
+                    &format!("{} cannot be compared with {}", kind, spelling)
+                );
+                return Type::Unknown;
+            }
+        };
+
+        match (left, a, right, b) {
+            (
+                Type::Array(array),
+                Some(Aggregate::Stored(offset)),
+                _,
+                Some(Aggregate::Literal(text))
+            )
+            | (
+                _,
+                Some(Aggregate::Literal(text)),
+                Type::Array(array),
+                Some(Aggregate::Stored(offset))
+            ) if array.holds_string(text.chars().count()) => {
+                self.wasm.compare_text(&text, offset, equal);
+                boolean()
+            },
+            (
+                Type::Record(_),
+                Some(Aggregate::Stored(a)),
+                Type::Record(_),
+                Some(Aggregate::Stored(b))
+            ) if left == right => {
+                self.wasm.compare(left, (a, b), equal);
+                boolean()
+            },
+            (
+                _, Some(Aggregate::Literal(x)), _, Some(Aggregate::Literal(y))
+            ) if left == right => {
+                let value = (x == y) == equal;
+                self.wasm.constant(
+                    &u8::from(value).to_string(), &Type::Integer
+                );
+                boolean()
+            },
+            _ if left == right && !records => {
+                let message =
+                    "arrays can only be compared with string literals";
+                let kind = CompilationErrorKind::SemanticError;
+                self.error_at(kind, pos, message);
+                Type::Unknown
+            },
+            _ => {
+                self.type_mismatch("the comparison", left, right);
+                Type::Unknown
+            }
+        }
+    }
+
+    /// Evaluates the operation on the constant `left` and the constant
+    /// compiled last, if both are constant and the operation has the
+    /// type given, so that what it yields is constant too. A division
+    /// of a variable operand by a constant zero is an error if constants
+    /// are folded, and a warning otherwise, since it stops the program
+    /// only when run.
+    fn fold(
+        &mut self,
+        op: Operator,
+        pos: FilePosition,
+        left: Option<Value>,
+        type_: &Type
+    ) {
+        let right = self.constant.take();
+        if *type_ == Type::Unknown {
+            return;
+        }
+        let division = matches!(
+            op,
+            Operator::Divide | Operator::IntegerDivide | Operator::Modulus
+        );
+        match (left, right) {
+            (Some(a), Some(b)) => {
+                self.evaluated(pos, Some(evaluation::binary(op, a, b)));
+            },
+            (None, Some(b)) if division && b.is_zero() => {
+                if self.wasm.optimizes() {
+                    let kind = CompilationErrorKind::SemanticError;
+                    self.error_at(kind, pos, "division by zero");
+                } else {
+                    self.warning(pos, "division by zero");
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Makes the result of an evaluation the constant compiled last,
+    /// reporting at the position of the operator what makes it fail.
+    fn evaluated(
+        &mut self,
+        pos: FilePosition,
+        result: Option<Result<Value, EvaluationError>>
+    ) {
+        self.constant = match result {
+            Some(Ok(value)) => Some(value),
+            Some(Err(e)) => {
+                let kind = CompilationErrorKind::SemanticError;
+                self.error_at(kind, pos, &e.to_string());
+                None
+            },
+            None => None,
+        };
+    }
+
     // <simple expression> ::=	<sign> <term> { <adding operator> <term> }
     fn simple_expression(
         &mut self,
         expected_type: &Type
     ) -> Result<Type, CompilationError> {
         let mut negative = false;
+        let signed = matches!(
+            self.lookahead,
+            Token::O(Operator::Plus) | Token::O(Operator::Minus)
+        );
+        let sign = if signed { self.lookahead_text() } else { String::new() };
+        let sign_pos = self.token_stream.token_pos();
         if let Token::O(op) = self.lookahead {
             match op {
-                Operator::Plus => negative = false,
-                Operator::Minus => negative = true,
+                Operator::Plus => self.proceed()?,
+                Operator::Minus => {
+                    negative = true;
+                    self.proceed()?
+                },
+                // A negation is read as a factor of the term
+                Operator::Not => (),
                 _ => return Err(self.syntax_error("expected plus or minus"))
             }
-            self.proceed()?;
         }
 
         if negative {
@@ -921,28 +2420,35 @@ impl<T: Buffer> Code<T> {
 
         let mut type_ = self.term(expected_type)?;
 
-        if negative {
+        if signed && !matches!(type_, Type::Integer | Type::Real) {
+            if type_ != Type::Unknown {
+                let context = format!("the operand of {}", sign);
+                self.type_mismatch(&context, &"a number", &type_);
+            }
+            type_ = Type::Unknown;
+            self.constant = None;
+        } else if negative {
             self.wasm.fill_nearest_unknown(&type_);
             self.wasm.op(&Operator::Minus, &type_);
+            let negated = self.constant.take().map(evaluation::negation);
+            self.evaluated(sign_pos, negated);
         }
 
-        loop {
-            if let Token::O(op) = self.lookahead {
-                if op.is_adding() {
-                    self.proceed()?;
-                    let next_type = self.term(expected_type)?;
-                    
-                    if next_type != type_ {
-                        type_ = Type::Unknown;
-                    }
-
-                    self.wasm.op(&op, &type_);
-                } else {
-                    break;
-                }
-            } else {
+        while let Token::O(op) = self.lookahead {
+            if !op.is_adding() {
                 break;
             }
+
+            let left = self.constant.take();
+            let spelling = self.lookahead_text();
+            let pos = self.token_stream.token_pos();
+            self.proceed()?;
+            let mark = self.wasm.mark();
+            let next_type = self.term(expected_type)?;
+            type_ = self.operation(&op, &spelling, &type_, &next_type, mark);
+            self.fold(op, pos, left, &type_);
+
+            self.wasm.op(&op, &type_);
         }
 
         
@@ -956,23 +2462,29 @@ impl<T: Buffer> Code<T> {
     ) -> Result<Type, CompilationError> {
         let mut type_ = self.factor(expected_type)?;
 
-        loop {
-            if let Token::O(op) = self.lookahead {
-                if op.is_multiplying() {
-                    self.proceed()?;
-                    let next_type = self.factor(expected_type)?;
-
-                    if type_ != next_type {
-                        type_ = Type::Unknown;
-                    }
-
-                    self.wasm.op(&op, &type_);
-                } else {
-                    break;
-                }
-            } else {
+        while let Token::O(op) = self.lookahead {
+            if !op.is_multiplying() {
                 break;
             }
+
+            let left = self.constant.take();
+            let pos = self.token_stream.token_pos();
+            let spelling = self.lookahead_text();
+            self.proceed()?;
+            let mark = self.wasm.mark();
+            let next_type = self.factor(expected_type)?;
+            type_ = self.operation(&op, &spelling, &type_, &next_type, mark);
+            self.fold(op, pos, left, &type_);
+
+            let division = matches!(
+                op,
+                Operator::IntegerDivide | Operator::Modulus
+            );
+            if division && type_ == Type::Integer {
+                self.wasm.divisor_check(pos.line);
+            }
+
+            self.wasm.op(&op, &type_);
         }
 
         
@@ -982,36 +2494,70 @@ impl<T: Buffer> Code<T> {
     // <factor> ::=
         // <variable>
         // | <constant>
+        // | nil
         // | ( <expression> )
         // | not <factor>
     fn factor(
         &mut self,
         expected_type: &Type
     ) -> Result<Type, CompilationError> {
+        self.constant = None;
+        self.aggregate = None;
         match self.lookahead.clone() {
             Token::Id(name) => {
-                let mut type_ = Type::Unknown;
-                if let Type::Scalar(vs) = expected_type {
-                    if let Some(p) = vs.iter().position(|n| n == &name) {
-                        type_ = expected_type.to_owned();
-                        self.wasm.constant(&p.to_string(), &Type::Integer);
-                        self.proceed()?;
-                    }
+                if let Some(Identifier::StandardFunction(f)) =
+                    self.scope.get(&name) {
+                    let f = *f;
+                    let t = self.standard_function(f);
+                    self.constant = None;
+                    return t;
                 }
 
-                if type_ == Type::Unknown {
-                    let (name, t) = self.variable()?;
-                    type_ = t;
-                    self.wasm.local_get(&name);
+                if let Some(Identifier::Constant(t)) = self.scope.get(&name) {
+                    let t = t.clone();
+                    let p = t.ordinal(&name).unwrap_or_default();
+                    self.wasm.constant(&p.to_string(), &Type::Integer);
+                    self.identifier()?;
+                    self.constant = Some(Value::Integer(p as i32));
+                    self.resolved_member(
+                        &t, &name, SemanticKind::Constant, Some(&t)
+                    );
+                    return Ok(t);
                 }
 
+                let (place, type_) = self.variable()?;
+                self.constant = None;
+                match place {
+                    Place::Memory(offset) if type_.is_structured() => {
+                        self.aggregate = Some(Aggregate::Stored(offset));
+                    },
+                    place => self.get(&place, &type_)
+                }
                 Ok(type_)
             },
             Token::Number(v) => self.number(&v),
             Token::Literal(v) => self.literal(&v),
+            Token::K(Keyword::Nil) => {
+                self.proceed()?;
+                self.wasm.constant("0", &Type::Integer);
+                Ok(Type::Pointer(String::new()))
+            },
             Token::O(Operator::Not) => {
                 self.proceed()?;
-                return self.factor(expected_type)
+                let type_ = self.factor(&boolean())?;
+                if type_ == boolean() {
+                    self.constant = self.constant.map(evaluation::not);
+                    self.wasm.eqz(&type_);
+                    Ok(type_)
+                } else {
+                    self.constant = None;
+                    if type_ != Type::Unknown {
+                        self.type_mismatch(
+                            "the operand of not", &boolean(), &type_
+                        );
+                    }
+                    Ok(Type::Unknown)
+                }
             },
             Token::P(Punctuation::Lbracket) => {
                 self.proceed()?;
@@ -1023,35 +2569,251 @@ impl<T: Buffer> Code<T> {
         }
     }
 
-    fn number(&mut self, value: &str) -> Result<Type, CompilationError> {
-        self.proceed()?;
-        let type_;
-        if value.contains('.') {
-            type_ = Type::Real
-        } else {
-            type_ = Type::Integer
-        }
-
-        self.wasm.constant(value, &type_);
-
-        Ok(type_)
-    }
-
-    fn literal(&mut self, value: &str) -> Result<Type, CompilationError> {
-        self.proceed()?;
-        if value.len() == 1 {
-            Ok(Type::Char)
-        } else {
-            unimplemented!(
-                "Character literals longer than 1 symbol are not supported"
-            );
-        }
-    }
-
-    fn identifier(&mut self) -> Result<String, CompilationError> {
+    // <function designator> ::= <function identifier> ( <actual parameter> )
+    fn standard_function(
+        &mut self,
+        function: StandardFunction
+    ) -> Result<Type, CompilationError> {
+        let name = self.identifier()?;
+        self.resolved(&name, SemanticKind::Procedure, None);
+        let spelling = self.spelling(&name);
+        self.consume(Token::P(Punctuation::Lbracket))?;
+        let t = self.expression(&Type::Unknown)?;
+        self.consume(Token::P(Punctuation::Rbracket))?;
+
+        let context = format!("the argument of {}", spelling);
+        let known = t != Type::Unknown;
+        match function {
+            StandardFunction::Trunc | StandardFunction::Round => {
+                if known && !self.assignable(&Type::Real, &t) {
+                    self.type_mismatch(&context, &Type::Real, &t);
+                }
+                if function == StandardFunction::Trunc {
+                    self.wasm.trunc();
+                } else {
+                    self.wasm.round();
+                }
+                Ok(Type::Integer)
+            },
+            StandardFunction::Odd => {
+                if t == Type::Integer {
+                    self.wasm.odd();
+                } else if known {
+                    self.type_mismatch(&context, &Type::Integer, &t);
+                }
+                Ok(boolean())
+            },
+            StandardFunction::Sqr | StandardFunction::Abs => {
+                if !matches!(t, Type::Integer | Type::Real) {
+                    if known {
+                        self.type_mismatch(&context, &"a number", &t);
+                    }
+                    return Ok(Type::Unknown);
+                }
+                if function == StandardFunction::Sqr {
+                    self.wasm.sqr(&t);
+                } else {
+                    self.wasm.abs(&t);
+                }
+                Ok(t)
+            },
+            StandardFunction::Random => {
+                if t == Type::Integer {
+                    self.wasm.random();
+                } else if known {
+                    self.type_mismatch(&context, &Type::Integer, &t);
+                }
+                Ok(Type::Integer)
+            },
+        }
+    }
+
+    /// The type of the result of an arithmetic operation on operands
+    /// of the types `left` and `right`, the code of the latter starting
+    /// at `mark`. `/` always divides reals, `div` and `mod` only take
+    /// integers, and the other operations widen an integer operand
+    /// to a real if the other one is real.
+    fn operation(
+        &mut self,
+        op: &Operator,
+        spelling: &str,
+        left: &Type,
+        right: &Type,
+        mark: usize
+    ) -> Type {
+        if *left == Type::Unknown || *right == Type::Unknown {
+            return Type::Unknown;
+        }
+
+        let context = format!("the operands of {}", spelling.to_lowercase());
+        let numeric = |t: &Type| matches!(t, Type::Integer | Type::Real);
+        // Pointers are only assigned and compared for equality
+        let no_operations = |t: &Type| {
+            t.is_structured() || matches!(t, Type::Pointer(_))
+        };
+        if no_operations(left) || no_operations(right) {
+            let found = if no_operations(left) { left } else { right };
+            match op {
+                Operator::And | Operator::Or | Operator::Xor => {
+                    self.type_mismatch(&context, &boolean(), found)
+                },
+                _ => self.type_mismatch(&context, &"a number", found)
+            };
+            return Type::Unknown;
+        }
+
+        // The logical operators work on the bits of booleans and integers
+        let logical = |t: &Type| *t == Type::Integer || *t == boolean();
+        match op {
+            Operator::And | Operator::Or | Operator::Xor
+                if !logical(left) || !logical(right) =>
+            {
+                let found = if logical(left) { right } else { left };
+                self.type_mismatch(&context, &"a boolean or an integer", found);
+                Type::Unknown
+            },
+            Operator::Divide if numeric(left) && numeric(right) => {
+                if *left == Type::Integer {
+                    self.wasm.widen_at(mark);
+                }
+                if *right == Type::Integer {
+                    self.wasm.widen();
+                }
+                Type::Real
+            },
+            Operator::Divide => {
+                let found = if numeric(left) { right } else { left };
+                self.type_mismatch(&context, &"a number", found);
+                Type::Unknown
+            },
+            Operator::IntegerDivide | Operator::Modulus => {
+                let found = if *left == Type::Integer { right } else { left };
+                if *found == Type::Integer {
+                    Type::Integer
+                } else {
+                    self.type_mismatch(&context, &Type::Integer, found);
+                    Type::Unknown
+                }
+            },
+            _ => match self.unified(left, right, mark) {
+                Some(t) => t,
+                None => {
+                    self.type_mismatch(&context, left, right);
+                    Type::Unknown
+                }
+            }
+        }
+    }
+
+    /// The common type of two operands of the types `left` and
+    /// `right`, the code of the latter starting at `mark`, widening
+    /// an integer operand to a real if the other one is real.
+    fn unified(
+        &mut self,
+        left: &Type,
+        right: &Type,
+        mark: usize
+    ) -> Option<Type> {
+        match (left, right) {
+            (Type::Integer, Type::Real) => {
+                self.wasm.widen_at(mark);
+                Some(Type::Real)
+            },
+            (Type::Real, Type::Integer) => {
+                self.wasm.widen();
+                Some(Type::Real)
+            },
+            (Type::Pointer(_), Type::Pointer(name)) if name.is_empty() => {
+                Some(left.clone())
+            },
+            (Type::Pointer(name), Type::Pointer(_)) if name.is_empty() => {
+                Some(right.clone())
+            },
+            _ if left == right => Some(left.clone()),
+            _ => None
+        }
+    }
+
+    /// Whether a value of the type `value` can be stored as `target`,
+    /// widening an integer value on top of the stack to a real.
+    fn assignable(&mut self, target: &Type, value: &Type) -> bool {
+        match (target, value) {
+            (Type::Real, Type::Integer) => {
+                self.wasm.widen();
+                true
+            },
+            // nil can be stored as any pointer
+            (Type::Pointer(_), Type::Pointer(name)) => {
+                name.is_empty() || target == value
+            },
+            _ => target == value
+        }
+    }
+
+    // <unsigned number> ::= <unsigned integer> | <unsigned real>
+    fn number(&mut self, value: &str) -> Result<Type, CompilationError> {
+        let pos = self.token_stream.token_pos();
+        self.proceed()?;
+        let kind = CompilationErrorKind::SemanticError;
+        // A number with a fraction or a scale factor is a real one
+        if value.contains(&['.', 'e'][..]) {
+            match value.parse::<f64>().map(|v| v as f32) {
+                Ok(v) if v.is_finite() => {
+                    self.wasm.constant(&format!("{:?}", v), &Type::Real);
+                    self.constant = Some(Value::Real(v));
+                },
+                _ => {
+                    self.error_at(kind, pos, "real constant out of range");
+                }
+            }
+            Ok(Type::Real)
+        } else {
+            match value.parse::<i32>() {
+                Ok(v) => {
+                    self.wasm.constant(&v.to_string(), &Type::Integer);
+                    self.constant = Some(Value::Integer(v));
+                },
+                Err(_) => {
+                    self.error_at(kind, pos, "integer constant out of range");
+                }
+            }
+            Ok(Type::Integer)
+        }
+    }
+
+    fn literal(&mut self, value: &str) -> Result<Type, CompilationError> {
+        let pos = self.token_stream.token_pos();
+        self.proceed()?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                self.wasm.constant(&(c as u32).to_string(), &Type::Char);
+                self.constant = Some(Value::Integer(c as i32));
+                Ok(Type::Char)
+            },
+            // A longer one is a value of a packed array of characters
+            (Some(_), Some(_)) => {
+                let length = value.chars().count();
+                self.aggregate = Some(Aggregate::Literal(value.to_string()));
+                Ok(Array::string(length))
+            },
+            _ => {
+                self.error_at(
+                    CompilationErrorKind::SemanticError,
+                    pos,
+                    "a string literal cannot be empty"
+                );
+                Ok(Type::Unknown)
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, CompilationError> {
         let lookahead = self.lookahead.to_owned();
         match lookahead {
             Token::Id(id) => {
+                self.id_range = self.token_stream.token_range();
+                self.id_pos = self.token_stream.token_pos();
                 self.proceed()?;
                 Ok(id)
             }
@@ -1068,14 +2830,85 @@ impl<T: Buffer> Code<T> {
         if self.lookahead == token {
             self.proceed()
         } else {
-            Err(self.syntax_error(
-                &format!(
-                    "expected {:?}, found {:?}",
-                    token,
-                    self.lookahead
-                )
-            ))
+            Err(self.unexpected(&token))
+        }
+    }
+
+    /// Reports that the lookahead is not the expected token, suggesting
+    /// a fix when the token is one that is easy to forget or mistype.
+    fn unexpected(&mut self, token: &Token) -> CompilationError {
+        let message = format!(
+            "expected {:?}, found {:?}",
+            token,
+            self.lookahead
+        );
+        let fix = self.fix_it(token);
+        let pos = self.token_stream.token_pos();
+        self.syntax_error_with_fix(pos, &message, fix)
+    }
+
+    /// Whether the lookahead starts a statement that is not empty.
+    fn starts_statement(&self) -> bool {
+        matches!(
+            self.lookahead,
+            Token::Id(_)
+            | Token::K(Keyword::Begin)
+            | Token::K(Keyword::If)
+            | Token::K(Keyword::Case)
+            | Token::K(Keyword::While)
+            | Token::K(Keyword::Repeat)
+            | Token::K(Keyword::For)
+            | Token::K(Keyword::With)
+        )
+    }
+
+    /// Consumes `:=`, taking `=` for it once the mistake is reported.
+    fn assignment_operator(&mut self) -> ParseResult {
+        if self.lookahead == Token::R(Relation::Eq) {
+            self.mistaken_operator(":=");
+            return self.proceed();
         }
+        self.consume(Token::O(Operator::Assign))
+    }
+
+    /// Reports the lookahead, `=` or `:=`, written where the `intended`
+    /// other one belongs, suggesting to replace it.
+    fn mistaken_operator(&mut self, intended: &str) {
+        let message = if intended == ":=" {
+            "'=' compares values, use ':=' to assign"
+        } else {
+            "':=' assigns a value, use '=' to compare"
+        };
+        let pos = self.token_stream.token_pos();
+        let fix = FixIt::replacement(pos, self.token_stream.pos(), intended);
+        self.syntax_error_with_fix(pos, message, Some(fix));
+    }
+
+    /// The fix for the lookahead found in place of the token: the
+    /// separator inserted after the token before it or `=` replaced
+    /// with `:=`. A statement found in place of `end` or `until`
+    /// is missing the semicolon before it.
+    fn fix_it(&self, token: &Token) -> Option<FixIt> {
+        let starts_statement = self.starts_statement();
+        let inserted = match token {
+            Token::P(Punctuation::Semicolon) => ";",
+            Token::K(Keyword::End) | Token::K(Keyword::Until)
+                if starts_statement => ";",
+            Token::K(Keyword::Then) => " then",
+            Token::K(Keyword::Do) => " do",
+            Token::K(Keyword::Of) => " of",
+            Token::O(Operator::Assign)
+                if self.lookahead == Token::R(Relation::Eq) => {
+                return Some(FixIt::replacement(
+                    self.token_stream.token_pos(),
+                    self.token_stream.pos(),
+                    ":="
+                ));
+            },
+            _ => return None,
+        };
+
+        Some(FixIt::insertion(self.position(self.last_end), inserted))
     }
 
     fn consume_any(
@@ -1084,9 +2917,10 @@ impl<T: Buffer> Code<T> {
 
         let search_result = tokens.iter()
             .find(|&t| self.lookahead == *t);
-        if search_result.is_some() {
+        if let Some(token) = search_result {
+            let token = token.to_owned();
             self.proceed()?;
-            Ok(search_result.unwrap().to_owned())
+            Ok(token)
         } else {
             Err(self.syntax_error(
                 &format!(
@@ -1099,63 +2933,223 @@ impl<T: Buffer> Code<T> {
     }
 
     fn proceed(&mut self) -> ParseResult {
-        self.lookahead = self.token_stream.next()?;
-        Ok(())
+        if self.stopped {
+            return Err(self.errors.iter().last().unwrap().clone());
+        }
+
+        self.last_end = self.token_stream.token_range().end;
+        loop {
+            let started = Instant::now();
+            let next = self.token_stream.next();
+            self.metrics.lexing += started.elapsed();
+            for err in self.token_stream.take_errors() {
+                self.report(err);
+            }
+
+            match next {
+                Ok(Token::Unknown) => continue,
+                Ok(token) => {
+                    if token != Token::EOF {
+                        self.metrics.tokens += 1;
+                    }
+                    self.lookahead = token;
+                    return Ok(());
+                }
+                Err(err) => self.report(err)
+            }
+        }
     }
 
-    fn panic(&mut self, until_tokens: &[Token]) -> ParseResult {
-        if self.token_stream.available(until_tokens)? {
-            self.proceed_until(until_tokens)?;
-        } else {
-            return Err(CompilationError::new(
-                CompilationErrorKind::SyntaxError,
-                self.token_stream.filepath(),
-                self.token_stream.prev_pos(),
-                &format!(
-                    "failed to recover, none of the \
-                    {:?} tokens are present in the stream",
-                    until_tokens
-                )
-            ))
+    /// Consumes `token` if it is the lookahead. Otherwise reports
+    /// it missing and skips to either `token` or a token from `resume`,
+    /// continuing as if `token` was present.
+    fn expect(&mut self, token: Token, resume: TokenSet) -> ParseResult {
+        if self.lookahead == token {
+            return self.proceed();
         }
 
-        Ok(())
+        let err = self.unexpected(&token);
+        self.resumed(token, resume, err)
     }
 
-    fn proceed_until(&mut self, tokens: &[Token]) -> ParseResult {
-        let token_set: HashSet<Token> = HashSet::from_iter(tokens.iter().cloned());
-        let mut token = self.token_stream.next()?;
-        while !token_set.contains(&token) && token != Token::EOF {
-            token = self.token_stream.next()?;
+    /// Consumes the keyword that ends the header of a statement
+    /// starting at `start`. A missing one is reported at the start
+    /// of the statement, and the parsing resumes as with `expect`.
+    fn header_end(
+        &mut self,
+        keyword: Keyword,
+        header: &str,
+        start: FilePosition
+    ) -> ParseResult {
+        let token = Token::K(keyword);
+        if self.lookahead == token {
+            return self.proceed();
         }
 
-        self.lookahead = token;
+        let message = format!(
+            "missing '{}' after {}",
+            format!("{:?}", keyword).to_lowercase(),
+            header
+        );
+        let fix = self.fix_it(&token);
+        let err = self.syntax_error_with_fix(start, &message, fix);
+        self.resumed(token, recovery::FIRST_STATEMENT, err)
+    }
 
-        Ok(())
+    /// Skips the tokens up to the expected one, which is consumed,
+    /// or one of the `resume` set, failing with `err` if a token
+    /// an enclosing rule synchronizes on comes first.
+    fn resumed(
+        &mut self,
+        token: Token,
+        resume: TokenSet,
+        err: CompilationError
+    ) -> ParseResult {
+        while self.lookahead != token
+            && !recovery::contains(resume, &self.lookahead)
+            && !self.at_sync_token() {
+
+            self.proceed()?;
+        }
+
+        if self.lookahead == token {
+            self.proceed()
+        } else if recovery::contains(resume, &self.lookahead) {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Parses `rule` with `follow` registered as a synchronization set.
+    /// When the rule fails, skips tokens up to one in `follow` and returns
+    /// `recovered`. If a token from the set of an enclosing rule is
+    /// reached first, the error is passed on to that rule.
+    fn recover<R>(
+        &mut self,
+        follow: TokenSet,
+        recovered: R,
+        rule: impl FnOnce(&mut Self) -> Result<R, CompilationError>
+    ) -> Result<R, CompilationError> {
+        self.sync_sets.push(follow);
+        let result = rule(self);
+        self.sync_sets.pop();
+
+        match result {
+            Ok(r) => Ok(r),
+            Err(err) => {
+                while !recovery::contains(follow, &self.lookahead)
+                    && !self.at_sync_token() {
+
+                    self.proceed()?;
+                }
+
+                if recovery::contains(follow, &self.lookahead) {
+                    Ok(recovered)
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    fn at_sync_token(&self) -> bool {
+        self.lookahead == Token::EOF || self.sync_sets.iter()
+            .any(|set| recovery::contains(set, &self.lookahead))
     }
 
     fn invalid_identifier(
         &mut self, expected_kind: &str, name: &str
     ) -> CompilationError {
-        self.semantic_error(
+        let (pos, spelling) = self.occurrence(name);
+        self.error_at(
+            CompilationErrorKind::SemanticError,
+            pos,
             &format!(
                 "invalid usage of {}, expected {} identifier",
-                name, expected_kind
+                spelling, expected_kind
             )
         )
     }
 
     fn undeclared_identifier(&mut self, name: &str) -> CompilationError {
         self.scope.put(name.to_string(), Identifier::Unknown).unwrap();
-        self.semantic_error(&format!("identifier not found \"{}\"", name))
+        let (pos, spelling) = self.occurrence(name);
+        self.error_at(
+            CompilationErrorKind::SemanticError,
+            pos,
+            &format!("identifier not found \"{}\"", spelling)
+        )
+    }
+
+    /// Warns of a declaration hiding the predefined identifier
+    /// of the same name, the identifier consumed last.
+    fn shadowing(&mut self, name: &str) {
+        let what = match self.scope.predefined(name) {
+            Some(Identifier::Type(_)) => "type",
+            Some(Identifier::Constant(_)) => "constant",
+            Some(Identifier::StandardFunction(_)) => "function",
+            Some(_) => "procedure",
+            None => return,
+        };
+        let (pos, spelling) = self.occurrence(name);
+        self.lint(Lint::ShadowedPredefined, pos, &format!(
+            "declaration of \"{}\" shadows the predefined {}",
+            spelling, what
+        ));
     }
 
     fn redefined_identifier(&mut self, name: &str) -> CompilationError {
+        let (pos, spelling) = self.occurrence(name);
+        self.error_at(
+            CompilationErrorKind::SemanticError,
+            pos,
+            &format!("duplicate identifier \"{}\"", spelling)
+        )
+    }
+
+    fn type_mismatch(
+        &mut self, context: &str, expected: &dyn Display, found: &Type
+    ) -> CompilationError {
+        self.semantic_error(&format!(
+            "type mismatch in {}: expected {}, found {}",
+            context, expected, found
+        ))
+    }
+
+    fn wrong_argument_count(
+        &mut self, spelling: &str, expected: usize, found: usize
+    ) -> CompilationError {
         self.semantic_error(&format!(
-            "duplicate identifier \"{}\"", name
+            "wrong number of arguments to \"{}\": expected {}, found {}",
+            spelling, expected, found
         ))
     }
 
+    /// The identifier as it is written in the source where it was
+    /// read last, as the tokens only keep the names lowercased.
+    fn spelling(&self, name: &str) -> String {
+        self.occurrence(name).1
+    }
+
+    /// Where the identifier was read last, either as the lookahead
+    /// or as the identifier consumed last, and how it is written there.
+    fn occurrence(&self, name: &str) -> (FilePosition, String) {
+        let occurrences = [
+            (self.token_stream.token_pos(), self.token_stream.token_range()),
+            (self.id_pos, self.id_range.clone()),
+        ];
+        occurrences.iter()
+            .map(|(pos, range)| (*pos, self.token_stream.text(range.clone())))
+            .find(|(_, text)| text.to_lowercase() == name)
+            .unwrap_or_else(|| (self.token_stream.token_pos(), name.to_string()))
+    }
+
+    /// The source text of the lookahead.
+    fn lookahead_text(&self) -> String {
+        self.token_stream.text(self.token_stream.token_range())
+    }
+
     fn semantic_error(&mut self, msg: &str) -> CompilationError {
         self.error(CompilationErrorKind::SemanticError, msg)
     }
@@ -1164,109 +3158,406 @@ impl<T: Buffer> Code<T> {
         self.error(CompilationErrorKind::SyntaxError, msg)
     }
 
+    /// Reports a syntax error at the position along with its fix.
+    fn syntax_error_with_fix(
+        &mut self,
+        pos: FilePosition,
+        msg: &str,
+        fix: Option<FixIt>
+    ) -> CompilationError {
+        let mut err = CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            self.token_stream.filepath(),
+            pos,
+            msg
+        );
+        if let Some(fix) = fix {
+            err = err.with_fix(fix);
+        }
+        self.report(err.clone());
+
+        err
+    }
+
+    /// Reports an error found at the lookahead, which it points at.
     fn error(
         &mut self,
         kind: CompilationErrorKind,
         message: &str
     ) -> CompilationError {
-        
+        let pos = self.token_stream.token_pos();
+        self.error_at(kind, pos, message)
+    }
+
+    fn error_at(
+        &mut self,
+        kind: CompilationErrorKind,
+        pos: FilePosition,
+        message: &str
+    ) -> CompilationError {
         let err = CompilationError::new(
             kind,
             self.token_stream.filepath(),
-            self.token_stream.prev_pos(),
+            pos,
             message
         );
 
-        self.wasm.silence();
-        self.errors.push(err.clone());
+        self.report(err.clone());
 
         err
     }
 
-    fn debug(&self, msg: &str) {
-        let pos = self.token_stream.pos();
-        println!(
-            "{}:{}:{}:{:?} => {}",
-            self.token_stream.filepath().as_ref().unwrap_or(&"~".to_string()),
-            pos.line, pos.col,
-            self.lookahead, msg
-        );
+    /// Tells the resolution handler what the identifier
+    /// consumed last refers to.
+    fn resolved(&mut self, name: &str, kind: SemanticKind, t: Option<&Type>) {
+        let declaration = self.scope.declaration(name);
+        self.used(name, kind, declaration, t);
     }
-}
 
-impl Operator {
-    fn is_adding(&self) -> bool {
-        match self {
-            Operator::Plus => true,
-            Operator::Minus => true,
-            Operator::Or => true,
-            _ => false
+    /// Tells the resolution handler that the identifier consumed
+    /// last names a field of the record or a constant of
+    /// the enumeration `owner`.
+    fn resolved_member(
+        &mut self,
+        owner: &Type,
+        name: &str,
+        kind: SemanticKind,
+        t: Option<&Type>
+    ) {
+        let declaration = self.members.iter()
+            .find(|(m, _)| m == owner)
+            .and_then(|(_, spans)| spans.get(name).cloned());
+        self.used(name, kind, declaration, t);
+    }
+
+    fn used(
+        &mut self,
+        name: &str,
+        kind: SemanticKind,
+        declaration: Option<Range<usize>>,
+        t: Option<&Type>
+    ) {
+        let range = self.id_range.clone();
+        if let Some(declaration) = declaration.clone() {
+            self.spelled_as_declared(range.clone(), declaration);
         }
+        self.notify(Resolution {
+            name: name.to_string(),
+            range: range.clone(),
+            extent: range,
+            kind,
+            declaration,
+            type_: t.cloned(),
+        });
     }
 
-    fn is_multiplying(&self) -> bool {
-        match self {
-            Operator::Multiply => true,
-            Operator::Divide => true,
-            Operator::IntegerDivide => true,
-            Operator::And => true,
-            _ => false,
+    /// Tells the resolution handler that the identifier
+    /// at `range` is declared by the source at `extent`.
+    fn declared(
+        &mut self,
+        name: &str,
+        range: Range<usize>,
+        extent: Range<usize>,
+        kind: SemanticKind,
+        t: Option<&Type>
+    ) {
+        self.declaration_style(name, range.clone(), kind);
+        self.notify(Resolution {
+            name: name.to_string(),
+            range: range.clone(),
+            extent,
+            kind,
+            declaration: Some(range),
+            type_: t.cloned(),
+        });
+    }
+
+    /// Warns of a declaration of the name at the range that is named
+    /// with a single letter, is spelled differently from another
+    /// declaration of the name, or names a field with a reserved word
+    /// of standard Pascal. The variables named with a single letter are
+    /// reported at the end of the program, unless they are the control
+    /// variables of loops by then.
+    fn declaration_style(
+        &mut self,
+        name: &str,
+        range: Range<usize>,
+        kind: SemanticKind
+    ) {
+        let enabled = |lint| self.lints.level(lint) != LintLevel::Allow;
+        let single_letter = enabled(Lint::SingleLetterName)
+            && name.chars().count() == 1
+            && kind != SemanticKind::Program;
+        let reserved = enabled(Lint::ReservedWordField)
+            && kind == SemanticKind::Field
+            && STANDARD_RESERVED_WORDS.contains(&name);
+        let cased = enabled(Lint::CaseMismatch);
+        if !(single_letter || reserved || cased) {
+            return;
+        }
+
+        let pos = self.position(range.start);
+        let spelling = self.token_stream.text(range);
+        if single_letter && kind == SemanticKind::Variable {
+            let level = self.lints.level(Lint::SingleLetterName);
+            self.short_variables.push((spelling.clone(), pos, level));
+        } else if single_letter {
+            self.lint(Lint::SingleLetterName, pos, &format!(
+                "\"{}\" is named with a single letter", spelling
+            ));
+        }
+        if reserved {
+            self.lint(Lint::ReservedWordField, pos, &format!(
+                "the field \"{}\" is named with a reserved word \
+                of standard Pascal",
+                spelling
+            ));
+        }
+        if cased {
+            match self.spellings.get(name) {
+                Some((other, at)) if *other != spelling => {
+                    let message = format!(
+                        "\"{}\" differs only in case from \"{}\" \
+                        declared at {}:{}",
+                        spelling, other, at.line, at.col
+                    );
+                    self.lint(Lint::CaseMismatch, pos, &message);
+                },
+                Some(_) => (),
+                None => {
+                    self.spellings.insert(name.to_string(), (spelling, pos));
+                }
+            }
         }
     }
 
-    fn is_sign(&self) -> bool {
-        match self {
-            Operator::Plus => true,
-            Operator::Minus => true,
-            _ => false,
+    /// Warns of the identifier at `range` if it is spelled differently
+    /// from its declaration at `declaration`.
+    fn spelled_as_declared(
+        &mut self,
+        range: Range<usize>,
+        declaration: Range<usize>
+    ) {
+        if self.lints.level(Lint::CaseMismatch) == LintLevel::Allow {
+            return;
+        }
+        let spelling = self.token_stream.text(range);
+        let declared = self.token_stream.text(declaration.clone());
+        if spelling != declared {
+            let message = format!(
+                "\"{}\" differs only in case from its declaration \
+                \"{}\" at {}",
+                spelling, declared, self.location(declaration.start)
+            );
+            self.lint(Lint::CaseMismatch, self.id_pos, &message);
         }
     }
-}
 
-#[cfg(test)]
-mod code_tests {
-    use std::io::stdout;
+    /// Warns of the variables named with a single letter
+    /// that are not the control variables of loops.
+    /// Whether an error has already been reported at the end of the file,
+    /// so that a missing `.` there would only repeat it.
+    fn reported_at_end(&self) -> bool {
+        let end = self.token_stream.token_pos();
+        self.errors.iter().any(|e| e.kind().is_error() && e.pos() == end)
+    }
 
-    use super::*;
-    use crate::tokenization::SimpleBuffer;
+    fn short_variables_reported(&mut self) {
+        let short_variables = std::mem::take(&mut self.short_variables);
+        for (spelling, pos, level) in short_variables {
+            self.lint_at_level(level, pos, &format!(
+                "\"{}\" is named with a single letter", spelling
+            ));
+        }
+    }
 
-    fn code(input: &str) -> Code<impl Buffer> {
-        let b = SimpleBuffer::new(input.as_bytes(), None);
-        let ts = TokenStream::new(b);
-        Code::new(ts, Box::new(stdout()))
+    fn notify(&mut self, resolution: Resolution) {
+        if let Some(handler) = self.resolution_handler.as_mut() {
+            handler(resolution);
+        }
     }
 
-    /******************************************/
-    /*                                        */
-    /*        Syntax analysis tests           */
-    /*                                        */
-    /******************************************/
+    /// Reports the error unless the analysis has stopped, stopping it
+    /// with a note when the policy gives up after this error.
+    fn report(&mut self, err: CompilationError) {
+        if self.stopped {
+            return;
+        }
 
-    #[test]
-    fn test_check_empty_program() {
-        let input =
-            " program Name;
-              begin
-              end.
-            ";
+        match self.transactions.last_mut() {
+            Some(failed) if self.partial => *failed = true,
+            _ => self.wasm.silence(),
+        }
+        let pos = err.pos();
+        self.diagnose(err);
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let errors = self.errors.error_count();
+        if self.policy.stops_after(errors) {
+            let note = CompilationError::new(
+                CompilationErrorKind::Note,
+                self.token_stream.filepath(),
+                pos,
+                &format!("too many errors, stopping after {}", errors)
+            );
+            self.diagnose(note);
+            self.stopped = true;
+        }
     }
 
-    #[test]
-    fn test_check_variables_block() {
-        let input =
-            " program Name;
-              var
-                a: Integer;
-              begin
-              end.
-            ";
+    /// Reports what a lint found at the position as its level says.
+    fn lint(&mut self, lint: Lint, pos: FilePosition, message: &str) {
+        self.lint_at_level(self.lints.level(lint), pos, message);
+    }
 
-        let c = code(input);
-        let errs = c.check().unwrap();
-        assert_eq!(errs.count(), 0);
+    /// Reports what a lint found at the position as the level
+    /// it had where the finding was made says.
+    fn lint_at_level(
+        &mut self,
+        level: LintLevel,
+        pos: FilePosition,
+        message: &str
+    ) {
+        match level {
+            LintLevel::Allow => (),
+            LintLevel::Warn => self.warning(pos, message),
+            LintLevel::Deny => {
+                self.error_at(CompilationErrorKind::SemanticError, pos, message);
+            },
+        }
+    }
+
+    /// Compiles a construct with the lint levels set by the directives
+    /// in the comments before it, restoring the levels after it.
+    fn directed(
+        &mut self,
+        compile: impl FnOnce(&mut Self) -> ParseResult
+    ) -> ParseResult {
+        let lints = self.lints.clone();
+        if !self.directives_applied() {
+            return compile(self);
+        }
+        let result = compile(self);
+        self.lints = lints;
+
+        result
+    }
+
+    /// Sets the lint levels as the directives in the comments before
+    /// the lookahead say, warning of the malformed ones. Returns
+    /// whether there were any directives.
+    fn directives_applied(&mut self) -> bool {
+        let directives = self.token_stream.take_directives();
+        for (comment, pos) in directives.iter() {
+            match directive(comment) {
+                Some(Ok((lint, level))) => self.lints.set(lint, level),
+                Some(Err(message)) => self.warning(*pos, &message),
+                None => (),
+            }
+        }
+
+        !directives.is_empty()
+    }
+
+    /// Reports a warning at the position, which does not stop
+    /// the compilation.
+    fn warning(&mut self, pos: FilePosition, message: &str) {
+        if !self.stopped {
+            let warning = CompilationError::new(
+                CompilationErrorKind::Warning,
+                self.token_stream.filepath(),
+                pos,
+                message
+            );
+            self.diagnose(warning);
+        }
+    }
+
+    /// Passes the diagnostic to the handler and collects it.
+    fn diagnose(&mut self, err: CompilationError) {
+        if let Some(handler) = self.handler.as_mut() {
+            handler.handle(err.clone());
+        }
+        self.errors.push(err);
+    }
+
+}
+
+impl Operator {
+    fn is_adding(&self) -> bool {
+        matches!(self, Operator::Plus | Operator::Minus | Operator::Or)
+    }
+
+    fn is_multiplying(&self) -> bool {
+        matches!(
+            self,
+            Operator::Multiply
+            | Operator::Divide
+            | Operator::IntegerDivide
+            | Operator::Modulus
+            | Operator::And
+        )
+    }
+
+    fn is_sign(&self) -> bool {
+        matches!(self, Operator::Plus | Operator::Minus)
+    }
+}
+
+/// Checks the program in `data`, which may be any bytes at all: the
+/// analysis reports what is wrong with them and never panics, which
+/// makes this the function to fuzz.
+pub fn check_bytes(data: &[u8]) -> Result<Errors, CompilationError> {
+    let ts = TokenStream::new(SimpleBuffer::new(data, None));
+    Code::new(ts, Box::new(std::io::sink())).check()
+}
+
+#[cfg(test)]
+mod code_tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::*;
+    use crate::{
+        tokenization::SimpleBuffer,
+        translation::{Memory, RandomSource, RuntimeErrors, Start}
+    };
+
+    fn code(input: &str) -> Code<impl Buffer> {
+        let b = SimpleBuffer::new(input.as_bytes(), None);
+        let ts = TokenStream::new(b);
+        Code::new(ts, Box::new(io::sink()))
+    }
+
+    /******************************************/
+    /*                                        */
+    /*        Syntax analysis tests           */
+    /*                                        */
+    /******************************************/
+
+    #[test]
+    fn test_check_empty_program() {
+        let input =
+            " program Name;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_variables_block() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        let errs = c.check().unwrap();
+        assert_eq!(errs.count(), 0);
     }
 
     #[test]
@@ -1380,7 +3671,7 @@ mod code_tests {
           var
             ix: integer;
           begin
-            for ix := 0 to 10
+            for ix := 0 to 10 begin
               writeln_int(ix)
             end
           end.
@@ -1397,7 +3688,7 @@ mod code_tests {
           var
             ix: integer;
           begin
-            for ix := 0 to do
+            for ix := 0 to do begin
               writeln_int(ix)
             end
           end.
@@ -1431,7 +3722,7 @@ mod code_tests {
           var
             ix: integer;
           begin
-            for ix 0 to 10 do
+            for ix 0 to 10 do begin
               writeln_int(ix)
             end
           end.
@@ -1504,6 +3795,156 @@ mod code_tests {
         assert_errors_count(c, 0);
     }
 
+    #[test]
+    fn test_check_with_statement_scope_ends() {
+        let input =
+            " program Name;
+              var
+                a: record
+                  f: Integer;
+                end;
+                b: integer;
+              begin
+                with a do
+                  f := 1;
+                with a do begin
+                  b := f +
+                end;
+                b := f
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 2, "{}", errs);
+        assert!(errs[1].msg().contains("\"f\""), "{}", errs);
+    }
+
+    #[test]
+    fn test_check_error_positions() {
+        let input =
+"program Name;
+var a: integer;
+begin
+  a := missing;
+  a := 1.5;
+  a := 1 +
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let positions: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col))
+            .collect();
+        assert_eq!(positions, vec![(4, 8), (5, 11), (7, 1)], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_constant_expressions() {
+        let input =
+"program Name;
+var a: integer; x: real;
+    v: array [1..2] of integer;
+begin
+  a := 2147483647 + 1;
+  a := -(-2147483647 - 1);
+  a := 10 mod (3 - 3);
+  x := 1.5 / (2 - 2);
+  a := 2147483647 + 1 - 1;
+  a := v[2 * 3 div 2];
+  a := v[-(1 - 2) - 1];
+  a := 2147483647 + a
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let overflow = "the constant expression overflows";
+        let division = "division by zero in a constant expression";
+        let messages: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        assert_eq!(messages, [
+            (5, 19, overflow),
+            (6, 8, overflow),
+            (7, 11, division),
+            (8, 12, division),
+            (9, 19, overflow),
+            (10, 10, "the index 3 is out of the bounds 1..2"),
+            (11, 10, "the index 0 is out of the bounds 1..2"),
+        ], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_division_by_constant_zero() {
+        let input =
+"program Name;
+var a: integer; x: real;
+begin
+  a := a div 0;
+  a := a mod (1 - 1);
+  x := x / 0.0;
+  x := a / 2;
+  a := 0 div a
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.kind(), e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        let warning = CompilationErrorKind::Warning;
+        assert_eq!(found, [
+            (warning, 4, 10, "division by zero"),
+            (warning, 5, 10, "division by zero"),
+            (warning, 6, 10, "division by zero"),
+        ], "{}", errs);
+        assert!(!errs.has_errors());
+
+        let errs = code(input).with_optimizations(true).check().unwrap();
+        assert_eq!(errs.error_count(), 3, "{}", errs);
+        assert!(errs.iter().all(|e| e.msg() == "division by zero"));
+    }
+
+    #[test]
+    fn test_check_procedure_argument_count() {
+        let input =
+            " program Name;
+              begin
+                writeln_int(1, 2);
+                Writeln_Int;
+                writeln_int();
+                writeln_int(1)
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 3, "{}", errs);
+        assert_eq!(
+            errs[0].msg(),
+            "wrong number of arguments to \"writeln_int\": expected 1, found 2"
+        );
+        assert!(errs[1].msg().contains("\"Writeln_Int\""), "{}", errs);
+        assert!(errs[1].msg().ends_with("found 0"), "{}", errs);
+        assert!(errs[2].msg().ends_with("found 0"), "{}", errs);
+    }
+
+    #[test]
+    fn test_check_identifier_spelling() {
+        let input =
+            " program Name;
+              var Total, TOTAL: integer;
+              begin
+                Total := Count;
+                Other := 1
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 3, "{}", errs);
+        assert!(errs[0].msg().contains("\"TOTAL\""), "{}", errs);
+        assert!(errs[1].msg().contains("\"Count\""), "{}", errs);
+        assert!(errs[2].msg().contains("\"Other\""), "{}", errs);
+    }
+
     #[test]
     fn test_check_long_correct() {
         let input =
@@ -1545,390 +3986,2251 @@ mod code_tests {
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_fix_its() {
+        let fixes = |statements: &str| {
+            let input = format!(
+                "program Name;\nvar a: integer;\nbegin\n{}\nend.\n",
+                statements
+            );
+            let errs = code(&input).check().unwrap();
+            errs.iter()
+                .flat_map(|e| e.fixes().to_vec())
+                .map(|fix| (
+                    (fix.start.line, fix.start.col),
+                    (fix.end.line, fix.end.col),
+                    fix.replacement
+                ))
+                .collect::<Vec<_>>()
+        };
+
+        let insertion = |line, col, text: &str| {
+            ((line, col), (line, col), text.to_string())
+        };
+        assert_eq!(fixes("a := 1\na := 2"), [insertion(4, 7, ";")]);
+        assert_eq!(
+            fixes("repeat a := 1 a := 2 until a = 2"),
+            [insertion(4, 14, ";")]
+        );
+        assert_eq!(fixes("while a > 0\n a := 0"), [insertion(4, 12, " do")]);
+        assert_eq!(fixes("if a > 0 a := 0"), [insertion(4, 9, " then")]);
+        assert_eq!(fixes("case a 1: end"), [insertion(4, 7, " of")]);
+        assert_eq!(
+            fixes("for a = 1 to 2 do"),
+            [((4, 7), (4, 8), ":=".to_string())]
+        );
+        assert_eq!(fixes("a := 1 +"), []);
+    }
+
+    #[test]
+    fn test_check_mistaken_assignment_operators() {
+        let input =
+"program Name;
+var a: integer; b: boolean;
+begin
+  a = 5;
+  if a := 5 then b := true;
+  for a = 1 to 2 do b := a := 2;
+  a := b
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        let assign = "'=' compares values, use ':=' to assign";
+        let compare = "':=' assigns a value, use '=' to compare";
+        // The statements are compiled on as if the right operator
+        // was used, so that the type error after them is found
+        assert_eq!(found, [
+            (4, 5, assign),
+            (5, 8, compare),
+            (6, 9, assign),
+            (6, 28, compare),
+            (
+                8, 1,
+                "type mismatch in assignment: expected integer, found boolean"
+            ),
+        ], "{}", errs);
+        let fix = &errs.iter().next().unwrap().fixes()[0];
+        assert_eq!(fix.replacement, ":=");
+        assert_eq!((fix.start.col, fix.end.col), (5, 6));
+    }
+
+    #[test]
+    fn test_check_missing_header_keywords() {
+        let input =
+"program Name;
+type r = record x: integer end;
+var a: integer; v: r;
+begin
+  while a > 0
+    a := a - 1;
+  for a := 1 to 2
+    a := 0;
+  if a > 0 a := 5;
+  with v x := 1;
+  a := true
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        // Each is reported at the start of its statement, and the
+        // statement is compiled on from its body
+        assert_eq!(found, [
+            (5, 3, "missing 'do' after the while-loop condition"),
+            (7, 3, "missing 'do' after the for-loop header"),
+            (9, 3, "missing 'then' after the if condition"),
+            (10, 3, "missing 'do' after the records of the with statement"),
+            (
+                12, 1,
+                "type mismatch in assignment: expected integer, found boolean"
+            ),
+        ], "{}", errs);
+        let fix = &errs.iter().next().unwrap().fixes()[0];
+        assert_eq!(fix.start, FilePosition::new(5, 14));
+        assert_eq!(fix.replacement, " do");
+
+        let input =
+"program Name;
+var a: integer;
+  if a = 1 then a := 2;
+  a := 3
+end.
+";
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        assert_eq!(found, [
+            (3, 3, "missing 'begin' before the statements of the program"),
+        ], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_error_recovery() {
+        let input =
+            " program Name;
+              var
+                r: record
+                  f:: Integer; { second ':' is unexpected but skipped }
+                end;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_recovery_is_local_to_statement() {
+        let input =
+            " program Name;
+              var
+                a, b: integer;
+              begin
+                a := ;
+                b := 1.5;
+                a := b
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 2);
+    }
+
+    #[test]
+    fn test_check_recovery_in_repeat_statement() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                repeat
+                  a := * 2;
+                  a := a + 1;
+                until a = 10
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_recovery_at_end_of_file() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a :=
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_missing_end_after_errors() {
+        let input = "program Name; begin a := 1";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 2);
+        assert_eq!(errs[0].kind(), CompilationErrorKind::SemanticError);
+        assert_eq!(errs[1].kind(), CompilationErrorKind::SyntaxError);
+        assert_eq!(errs[1].pos().col, 26);
+    }
+
+    #[test]
+    fn test_check_stray_characters() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a := 1 #;
+                a := a + @1;
+                a := 2.5
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 3);
+        assert_eq!(errs[0].kind(), CompilationErrorKind::LexicalError);
+        assert_eq!(errs[1].kind(), CompilationErrorKind::LexicalError);
+        assert_eq!(errs[2].kind(), CompilationErrorKind::SemanticError);
+    }
+
+    #[test]
+    fn test_check_unterminated_literal() {
+        let input =
+            " program Name;
+              var
+                c: char;
+              begin
+                c := 'a
+              end.";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs[0].kind(), CompilationErrorKind::LexicalError);
+        assert_eq!(errs[0].pos().line, 5);
+    }
+
+    #[test]
+    fn test_check_empty_file() {
+        let input = "  { nothing here }\n";
+
+        let err = code(input).check().unwrap_err();
+        assert_eq!(err.kind(), CompilationErrorKind::SyntaxError);
+        assert_eq!(err.msg(), "the program is empty");
+    }
+
+    /******************************************/
+    /*                                        */
+    /*        Semantic analysis tests         */
+    /*                                        */
+    /******************************************/
+
+    #[test]
+    fn test_check_var_redefinition_global() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+                a: Boolean;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_var_redefinition_line() {
+        let input =
+            " program Name;
+              var
+                a, a: Integer;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_type_redefinition() {
+        let input =
+            " program Name;
+              type
+                a = Integer;
+                a = record end;
+              begin
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_type_mismatch_with_redefined_type() {
+        let input =
+            " program Name;
+              type
+                real = integer;
+              var
+                x: integer;
+              begin
+                x := 67.786
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_type_outer_redefintion() {
+        let input =
+            " program Name;
+              type
+                integer = real;
+              begin
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+        assert!(!errs.has_errors());
+        assert_eq!(errs[0].kind(), CompilationErrorKind::Warning);
+        assert_eq!(
+            errs[0].msg(),
+            "declaration of \"integer\" shadows the predefined type"
+        );
+        assert_eq!(errs[0].pos().line, 3);
+    }
+
+    #[test]
+    fn test_check_shadowing_lint_levels() {
+        let input =
+            " program Name;
+              type
+                Boolean = (no, yes);
+              var
+                halt: integer;
+                b: (false, maybe);
+              begin
+                halt := 1
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.kind(), e.msg().to_string()))
+            .collect();
+        let warning = |name: &str, what: &str| (
+            CompilationErrorKind::Warning,
+            format!("declaration of \"{}\" shadows the predefined {}", name, what)
+        );
+        assert_eq!(found, [
+            warning("Boolean", "type"),
+            warning("halt", "procedure"),
+            warning("false", "constant"),
+        ]);
+
+        let mut lints = Lints::default();
+        lints.set(Lint::ShadowedPredefined, LintLevel::Deny);
+        let errs = code(input).with_lints(lints).check().unwrap();
+        assert_eq!(errs.error_count(), 3, "{}", errs);
+
+        let mut lints = Lints::default();
+        lints.set(Lint::ShadowedPredefined, LintLevel::Allow);
+        let errs = code(input).with_lints(lints).compile().unwrap();
+        assert!(errs.is_empty(), "{}", errs);
+    }
+
+    #[test]
+    fn test_check_case_labels() {
+        let input =
+"program Name;
+type color = (red, green, blue, cyan, magenta);
+var c: color; i: integer; ch: char; x: real;
+begin
+  case c of
+    red: i := 1;
+    blue..red: i := 2;
+    green, red: i := 3
+  end;
+  case ch of 'a'..'z': i := 1; 'q': i := 2; 'ж': i := 3; 'A': end;
+  case i of 1: i := 1; 0..2: i := 2 else i := 0 end;
+  case x of 1: i := 1 end
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        assert_eq!(found, [
+            (7, 5, "the case label blue..red has no values"),
+            (8, 12, "duplicate case label red"),
+            (5, 3, "the case statement does not handle blue, cyan, magenta"),
+            (10, 32, "duplicate case label 'q'"),
+            (10, 45, "the case label 'ж' is out of the range chr(0)..'ÿ'"),
+            (11, 24, "duplicate case label 1"),
+            (
+                12, 10,
+                "type mismatch in the case selector: \
+                expected an ordinal type, found real"
+            ),
+        ], "{}", errs);
+        assert_eq!(errs[2].kind(), CompilationErrorKind::Warning);
+    }
+
+    #[test]
+    fn test_check_incomplete_case_lint() {
+        let input =
+            " program Name;
+              type color = (red, green, blue);
+              var c: color; b: boolean;
+              begin
+                case c of red, green: ; blue: end;
+                case c of red..green: else end;
+                case b of true: c := red end
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+        assert_eq!(
+            errs[0].msg(),
+            "the case statement does not handle false"
+        );
+
+        let mut lints = Lints::default();
+        lints.set(Lint::IncompleteCase, LintLevel::Allow);
+        let errs = code(input).with_lints(lints).check().unwrap();
+        assert!(errs.is_empty(), "{}", errs);
+    }
+
+    #[test]
+    fn test_check_unreachable_code() {
+        let input =
+"program Name;
+type color = (red, green);
+var a: integer; c: color;
+begin
+  if a > 0 then halt else begin a := 1; exit end;
+  a := 2;
+  a := 3;
+  begin repeat a := 1; halt; until a = 1; a := 2 end;
+  begin while a > 0 do halt; a := 2 end;
+  begin case c of red: halt; green: exit end; a := 2 end;
+  begin if a > 0 then halt; a := 2 end;
+  begin case c of red: halt else a := 1 end; a := 2 end;
+  begin for a := 1 to 2 do begin exit; end; a := 2 end;
+  halt;
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.kind(), e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        let unreachable = |line, col| (
+            CompilationErrorKind::Warning, line, col, "unreachable statement"
+        );
+        assert_eq!(found, [
+            unreachable(6, 3),
+            unreachable(8, 43),
+            unreachable(10, 47),
+        ], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_invalid_field_access() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+              begin
+                a.b := 0;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_non_existent_field_access() {
+        let input =
+        " program Name;
+          var
+            a: record
+              a: Integer;
+            end;
+          begin
+            a.b := 0;
+          end.
+        ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_non_existent_field_field_access() {
+        let input =
+        " program Name;
+          var
+            a: record
+              a: Integer;
+            end;
+          begin
+            a.b.c := 0;
+          end.
+        ";
+
+        let c = code(input);
+        assert_errors_count(c, 2);
+    }
+
+    #[test]
+    fn test_check_field_field_access() {
+        let input =
+        " program Name;
+          var
+            a: record
+              b: record
+                c: Integer;
+              end;
+            end;
+          begin
+            a.b.c := 0;
+          end.
+        ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_bad_assignment() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+                b: Boolean;
+              begin
+                a := b;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);       
+    }
+
+    #[test]
+    fn test_check_deep_assignment() {
+        let input =
+            " program Name;
+              var
+                a: record
+                  b: record
+                    c: Integer;
+                  end;
+                end;
+
+                b: record
+                  c: Integer;
+                end;
+              begin
+                a.b.c := b.c;
+                b.c := a.b.c;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);      
+    }
+
+    #[test]
+    fn test_check_alias_assignment() {
+        let input =
+            " program Name;
+              type
+                t_a = integer;
+                t_b = integer;
+              var
+                a: t_a;
+                b: t_b;
+              begin
+                a := b;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);   
+    }
+
+    #[test]
+    fn test_check_deep_alias_assignment() {
+        let input =
+            " program Name;
+              type
+                t_a = integer;
+                t_b = t_a;
+              var
+                a: t_a;
+                b: t_b;
+              begin
+                a := b;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_deep_incorrect_alias_assignment() {
+        let input =
+            " program Name;
+              type
+                t_a = integer;
+                t_b = t_a;
+                t_c = boolean;
+              var
+                a: t_b;
+                b: t_c;
+              begin
+                a := b;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_boolean_assignment() {
+        let input =
+            " program Name;
+              var
+                a: boolean;
+              begin
+                a := true;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0); 
+    }
+
+    #[test]
+    fn test_check_boolean_relations() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+                c: char;
+                f: boolean;
+              begin
+                f := not (a < 1) and (c <> 'x');
+                f := f = (c < 'y');
+                f := not a
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+        assert_eq!(
+            errs[0].msg(),
+            "type mismatch in the operand of not: expected boolean, found integer"
+        );
+    }
+
+    #[test]
+    fn test_check_scalar_constants_in_any_context() {
+        let input =
+            " program Name;
+              type
+                fruit = (apple, banana);
+              var
+                a: fruit;
+                f: boolean;
+                colour: (red, green, banana);
+              begin
+                if apple = a then
+                  a := banana;
+                f := (apple < banana) = true;
+                a := red
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 2, "{}", errs);
+        assert_eq!(errs[0].msg(), "duplicate identifier \"banana\"");
+        assert_eq!(errs[0].pos().line, 7);
+        assert_eq!(errs[0].pos().col, 38);
+        assert_eq!(
+            errs[1].msg(),
+            "type mismatch in assignment: \
+            expected (apple, banana), found (red, green)"
+        );
+    }
+
+    #[test]
+    fn test_check_number_range() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+                x: real;
+              begin
+                a := 2147483647;
+                a := 2147483648;
+                x := 3.4e38;
+                x := 1e39;
+                a := 1e2
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 3, "{}", errs);
+        assert_eq!(errs[0].msg(), "integer constant out of range");
+        assert_eq!(errs[0].pos(), FilePosition::new(7, 22));
+        assert_eq!(errs[1].msg(), "real constant out of range");
+        assert_eq!(errs[1].pos(), FilePosition::new(9, 22));
+        assert_eq!(
+            errs[2].msg(),
+            "type mismatch in assignment: expected integer, found real"
+        );
+    }
+
+    #[test]
+    fn test_check_type_mismatch_names_types() {
+        let input =
+            " program Name;
+              type point = record x, y: real end;
+              var
+                p: point;
+                a: integer;
+                c: (red, green, blue, cyan);
+              begin
+                a := p;
+                if c = 1 then a := 2;
+                while a do a := 1
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in assignment: \
+            expected integer, found record x: real; y: real end",
+            "type mismatch in the comparison: \
+            expected (red, green, blue, ...), found integer",
+            "type mismatch in the while condition: \
+            expected boolean, found integer",
+        ]);
+    }
+
+    #[test]
+    fn test_check_scalar_type() {
+        let input =
+            " program Name;
+              var
+                a: (Apple, Banana, Grape);
+              begin
+                a := apple;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_expression() {
+        let input =
+            " program Name;
+              var
+                result: integer;
+              begin
+                result := -2 + 5*10;
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_expression_with_negative_number_in_if() {
+        let input =
+            " program Name;
+              var
+                result: integer;
+              begin
+                if -2 < -4 then
+                begin
+                    result := -2;
+                end else begin
+                    result := 0;
+                end
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 0);
+    }
+
+    #[test]
+    fn test_check_with_statement_undefined_field() {
+        let input =
+            " program Name;
+              var
+                a: record
+                  f: integer
+                end;
+              begin
+                with a do begin
+                  f_u := 0
+                end
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_with_statement_shadowed_field_leading_to_type_mismatch() {
+        let input =
+            " program Name;
+              var
+                a: record
+                  f: real
+                end;
+                b: record
+                  f: integer
+                end;
+              begin
+                with a, b do begin
+                  f := 0.5
+                end
+              end.
+            ";
+
+        let c = code(input);
+        assert_errors_count(c, 1);
+    }
+
+    #[test]
+    fn test_check_with_statement_shadowing_warnings() {
+        let input =
+"program Name;
+var F: integer;
+  a: record f: real end;
+  b: record F: integer; g: real end;
+begin
+  with a, b do f := 1;
+  with a do with b do g := 1.5;
+  with b do g := 0.5
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        assert!(!errs.has_errors(), "{}", errs);
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        let hides_variable = "the field \"f\" declared at 3:13 hides \
+            the variable \"F\" declared at 2:5";
+        let hides_field = "the field \"F\" declared at 4:13 hides \
+            the field \"f\" declared at 3:13";
+        assert_eq!(found, [
+            (6, 8, hides_variable),
+            (6, 11, hides_field),
+            (7, 8, hides_variable),
+            (7, 18, hides_field),
+            (
+                8, 8,
+                "the field \"F\" declared at 4:13 hides \
+                the variable \"F\" declared at 2:5"
+            ),
+        ], "{}", errs);
+
+        let mut lints = Lints::default();
+        lints.set(Lint::ShadowedVariable, LintLevel::Allow);
+        let errs = code(input).with_lints(lints).check().unwrap();
+        assert!(errs.is_empty(), "{}", errs);
+    }
+
+    #[test]
+    fn test_check_directives() {
+        let input =
+"program Directives;
+{$WARN single_letter_name ON}
+var n: integer;
+  {%suppress single-letter-name} m: integer;
+begin
+  n := 1;
+  {$WARN unreachable_code OFF}
+  if n > 0 then begin exit; n := 2 end;
+  exit;
+  {$WARN unused_variable OFF} m := 3;
+  begin halt; {$WARN unreachable_code ERROR} n := 4 end
+end.
+";
+
+        let errs = code(input).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.kind(), e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        let warning = CompilationErrorKind::Warning;
+        assert_eq!(found, [
+            (
+                warning, 10, 3,
+                "unknown lint \"unused-variable\" in the directive"
+            ),
+            (warning, 10, 31, "unreachable statement"),
+            (
+                CompilationErrorKind::SemanticError, 11, 46,
+                "unreachable statement"
+            ),
+            (warning, 3, 5, "\"n\" is named with a single letter"),
+        ], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_style_lints() {
+        let input =
+"program Style;
+type Point = record x, Size: real; set: integer end;
+var i, n: integer;
+  Count: integer;
+  p: point;
+  r: record count: integer end;
+begin
+  for i := 1 to 3 do count := Count + i;
+  p.size := 1.5
+end.
+";
+
+        // The style lints are only run when asked for
+        let errs = code(input).check().unwrap();
+        assert!(errs.is_empty(), "{}", errs);
+
+        let mut lints = Lints::default();
+        for lint in [
+            Lint::SingleLetterName,
+            Lint::CaseMismatch,
+            Lint::ReservedWordField,
+        ] {
+            lints.set(lint, LintLevel::Warn);
+        }
+        let errs = code(input).with_lints(lints).check().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.pos().line, e.pos().col, e.msg()))
+            .collect();
+        assert_eq!(found, [
+            (2, 21, "\"x\" is named with a single letter"),
+            (
+                2, 36,
+                "the field \"set\" is named with a reserved word \
+                of standard Pascal"
+            ),
+            (
+                5, 6,
+                "\"point\" differs only in case from its declaration \
+                \"Point\" at 2:6"
+            ),
+            (
+                6, 13,
+                "\"count\" differs only in case from \"Count\" \
+                declared at 4:3"
+            ),
+            (
+                8, 22,
+                "\"count\" differs only in case from its declaration \
+                \"Count\" at 4:3"
+            ),
+            (
+                9, 5,
+                "\"size\" differs only in case from its declaration \
+                \"Size\" at 2:24"
+            ),
+            (3, 8, "\"n\" is named with a single letter"),
+            (5, 3, "\"p\" is named with a single letter"),
+            (6, 3, "\"r\" is named with a single letter"),
+        ], "{}", errs);
+    }
+
+    #[test]
+    fn test_check_errors_iteration() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+                a: Boolean;
+              begin
+                a := 1.5;
+              end.
+            ";
+
+        let errs = code(input).check().unwrap();
+        assert_eq!(errs.count(), 2);
+        assert_eq!(errs[0].kind(), CompilationErrorKind::SemanticError);
+        assert_eq!(errs.iter().count(), 2);
+
+        let semantic = errs.into_iter()
+            .filter(|e| e.kind() == CompilationErrorKind::SemanticError)
+            .count();
+        assert_eq!(semantic, 2);
+    }
+
+    #[test]
+    fn test_check_diagnostic_handler() {
+        let input =
+            " program Name;
+              var
+                a: Integer;
+                a: Boolean;
+              begin
+                a := 1.5;
+              end.
+            ";
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&reported);
+        let c = code(input).with_diagnostic_handler(
+            move |e: CompilationError| sink.borrow_mut().push(e)
+        );
+
+        let errs = c.check().unwrap();
+        assert_eq!(reported.borrow().len(), 2);
+        assert_eq!(reported.borrow()[1].pos(), errs[1].pos());
+    }
+
+    #[test]
+    fn test_check_error_policy() {
+        let input =
+            " program Name;
+              begin
+                a := 1;
+                b := 2;
+                c := 3
+              end.
+            ";
+
+        assert_errors_count(code(input), 3);
+
+        let errs = code(input)
+            .with_error_policy(ErrorPolicy::max_errors(2))
+            .check()
+            .unwrap();
+        assert_eq!(errs.count(), 3);
+        assert!(errs[1].msg().contains("\"b\""));
+        assert_eq!(errs[2].kind(), CompilationErrorKind::Note);
+        assert_eq!(errs[2].msg(), "too many errors, stopping after 2");
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&reported);
+        let errs = code(input)
+            .with_error_policy(ErrorPolicy::fail_fast())
+            .with_diagnostic_handler(
+                move |e: CompilationError| sink.borrow_mut().push(e)
+            )
+            .compile()
+            .unwrap();
+        assert_eq!(errs.count(), 2);
+        assert_eq!(reported.borrow().len(), 2);
+        assert_eq!(errs[1].kind(), CompilationErrorKind::Note);
+    }
+
+    #[test]
+    fn test_check_with_symbols() {
+        let input =
+            " program Name;
+              type
+                point = record x, y: real end;
+                count = integer;
+              var
+                p: point;
+                n: count;
+                b: boolean;
+              begin
+                with p do x := y
+              end.
+            ";
+
+        let (errs, symbols) = code(input).check_with_symbols().unwrap();
+        assert!(errs.is_empty(), "{}", errs);
+
+        let names = |list: &[(String, Type)]| -> Vec<_> {
+            list.iter().map(|(name, _)| name.clone()).collect()
+        };
+        assert_eq!(names(&symbols.types), ["point", "count"]);
+        assert_eq!(names(&symbols.variables), ["p", "n", "b"]);
+        assert_eq!(symbols.types[1].1, Type::Integer);
+        assert_eq!(symbols.variables[2].1, boolean());
+        assert!(matches!(
+            &symbols.variables[0].1,
+            Type::Record(fields) if fields["x"] == Type::Real
+        ));
+        assert_eq!(symbols.procedures, [
+            ("writeln_int".to_string(), vec![Type::Integer]),
+            ("writeln_real".to_string(), vec![Type::Real]),
+        ]);
+    }
+
+    #[test]
+    fn test_check_enumerated_type_constants() {
+        let input =
+            " program Name;
+              type
+                Color = (Red, Green, Blue);
+                green = integer;
+                Shade = (Light, Dark, Color);
+              var
+                c: Color;
+                blue: integer;
+              begin
+                c := Green
+              end.
+            ";
+
+        let (errs, symbols) = code(input).check_with_symbols().unwrap();
+        let found: Vec<_> = errs.iter()
+            .map(|e| (e.msg().to_string(), e.pos().line, e.pos().col))
+            .collect();
+        assert_eq!(found, [
+            ("duplicate identifier \"green\"".to_string(), 4, 17),
+            ("duplicate identifier \"Color\"".to_string(), 5, 39),
+            ("duplicate identifier \"blue\"".to_string(), 8, 17),
+        ]);
+
+        let color = Type::Scalar(
+            ["red", "green", "blue"].iter().map(|c| c.to_string()).collect()
+        );
+        let constants: Vec<_> = symbols.constants.iter()
+            .map(|(name, t)| (name.as_str(), t == &color))
+            .collect();
+        assert_eq!(constants, [
+            ("red", true),
+            ("green", true),
+            ("blue", true),
+            ("light", false),
+            ("dark", false),
+        ]);
+        assert_eq!(symbols.variables, [("c".to_string(), color)]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_check_serde() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a := 'c'
+              end.
+            ";
+
+        let (errs, symbols) = code(input).check_with_symbols().unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+
+        let json = serde_json::to_value(&errs[0]).unwrap();
+        assert_eq!(json["kind"], "SemanticError");
+        assert_eq!(json["pos"]["line"], errs[0].pos().line);
+        let back: CompilationError = serde_json::from_value(json).unwrap();
+        assert_eq!(back.to_string(), errs[0].to_string());
+
+        let json = serde_json::to_string(&symbols).unwrap();
+        assert_eq!(serde_json::from_str::<SymbolTable>(&json).unwrap(), symbols);
+    }
+
+    #[test]
+    fn test_compile_metrics() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a := 1;
+                if a > 0 then writeln_int(a)
+              end.
+            ";
+
+        let reported = Rc::new(RefCell::new(None));
+        let sink = Rc::clone(&reported);
+        let text = compile_with(input, |c| c.with_metrics_handler(
+            move |m| *sink.borrow_mut() = Some(m)
+        ));
+        let metrics = reported.take().unwrap();
+
+        assert_eq!(metrics.tokens, 24);
+        assert_eq!(metrics.statements, 3);
+        assert_eq!(metrics.errors, 0);
+        assert!(metrics.instructions > 0);
+        assert_eq!(metrics.output_size, text.len());
+
+        let sink = Rc::clone(&reported);
+        code("program Name; begin a := 1 end.")
+            .with_metrics_handler(move |m| *sink.borrow_mut() = Some(m))
+            .check()
+            .unwrap();
+        let metrics = reported.take().unwrap();
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.output_size, 0);
+    }
+
+    #[test]
+    fn test_compile_output_is_deterministic() {
+        let input =
+            " program Name;
+              var
+                d, c, b, a: integer;
+                z: real;
+              begin
+                a := 1;
+                writeln_int(a)
+              end.
+            ";
+
+        let first = compile(input, false);
+        for _ in 0..8 {
+            assert_eq!(first, compile(input, false));
+        }
+
+        let imports = first.find("$writeln_int").unwrap()
+            < first.find("$writeln_real").unwrap();
+        assert!(imports);
+        assert!(first.contains(
+            "(local $for.limit i32) (local $d i32) (local $c i32) \
+            (local $b i32) (local $a i32) (local $z f32)"
+        ));
+    }
+
+    #[test]
+    fn test_compile_empty_file() {
+        assert!(code("").compile().is_err());
+    }
+
+    #[test]
+    fn test_compile_folds_constants() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a := 2 + 5*(2-2) + 2;
+                if 3 < 2 then
+                  a := 1
+                else
+                  writeln_int(a)
+              end.
+            ";
+
+        let plain = compile(input, false);
+        assert!(plain.contains("i32.mul"));
+        assert!(plain.contains("(if"));
+
+        let optimized = compile(input, true);
+        assert!(optimized.contains("i32.const 4\n"));
+        assert!(!optimized.contains("i32.mul"));
+        assert!(!optimized.contains("(if"));
+        assert!(!optimized.contains("i32.const 1\n"));
+        assert!(optimized.contains("call $writeln_int"));
+    }
+
+    #[test]
+    fn test_compile_eliminates_dead_code() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+              begin
+                a := 1;
+                while a < 10 do
+                begin
+                  writeln_int(a);
+                  halt;
+                  a := a + 1
+                end;
+                exit;
+                a := 2
+              end.
+            ";
+        let mut lints = Lints::default();
+        lints.set(Lint::UnreachableCode, LintLevel::Allow);
+        let compile = |optimize| compile_with(input, |c| {
+            c.with_optimizations(optimize).with_lints(lints.clone())
+        });
+
+        let plain = compile(false);
+        assert!(plain.contains("$writeln_real"));
+        assert!(plain.contains("i32.const 2"));
+
+        let optimized = compile(true);
+        assert!(optimized.contains("$writeln_int"));
+        assert!(!optimized.contains("$writeln_real"));
+        assert!(!optimized.contains("i32.add"));
+        assert!(!optimized.contains("i32.const 2"));
+        assert_eq!(optimized.matches("return").count(), 2);
+    }
+
+    #[test]
+    fn test_compile_target() {
+        let input =
+            " program Name;
+              begin
+                writeln_int(1);
+                writeln_real(1.0)
+              end.
+            ";
+
+        let mut target = Target {
+            import_module: "env".to_string(),
+            export_name: "main".to_string(),
+            ..Target::default()
+        };
+        target.procedure_modules.insert(
+            "writeln_real".to_string(), "io".to_string()
+        );
+
+        let output = compile_with(input, |c| c.with_target(target));
+        assert!(output.contains("(import \"env\" \"writeln_int\")"));
+        assert!(output.contains("(import \"io\" \"writeln_real\")"));
+        assert!(output.contains("(export \"main\")"));
+
+        // The names are any text, which the module quotes
+        let target = Target {
+            import_module: "a\\b".to_string(),
+            export_name: "run \"main\"\n".to_string(),
+            ..Target::default()
+        };
+        let output = compile_with(input, |c| c.with_target(target));
+        assert!(output.contains("(import \"a\\\\b\" \"writeln_int\")"));
+        assert!(output.contains("(export \"run \\\"main\\\"\\0a\")"));
+        wat::parse_str(&output).unwrap();
+    }
+
+    #[test]
+    fn test_compile_globals() {
+        let input =
+            " program Name;
+              var
+                a: integer;
+                b: real;
+              begin
+                for a := 1 to 3 do
+                  b := 2.5
+              end.
+            ";
+
+        let target = Target {
+            globals: true,
+            ..Target::default()
+        };
+
+        let output = compile_with(input, |c| c.with_target(target));
+        assert!(output.contains(
+            "(global $a (export \"a\") (mut i32) (i32.const 0))"
+        ));
+        assert!(output.contains(
+            "(global $b (export \"b\") (mut f32) (f32.const 0))"
+        ));
+        assert!(output.contains("(local $for.limit i32)\n"));
+        assert!(output.contains("global.set $b"));
+        assert!(!output.contains("local.get $a"));
+    }
+
+    #[test]
+    fn test_compile_names_do_not_collide() {
+        let input =
+            " program Name;
+              var r0, continue: integer;
+              begin
+                r0 := 0;
+                for continue := 1 to 3 do
+                  r0 := r0 + continue;
+                writeln_int(r0)
+              end.
+            ";
+        let target = Target {
+            globals: true,
+            ..Target::default()
+        };
+        let run = |input: &str| {
+            let output = compile_with(input, |c| c.with_target(target.clone()));
+            let printed = SharedBuffer::default();
+            let binary = wat::parse_str(&output).unwrap();
+            crate::runtime::run(&binary, &target, Box::new(printed.clone()))
+                .unwrap();
+            let printed = printed.0.borrow().clone();
+            (output, printed)
+        };
+
+        let (output, printed) = run(input);
+        assert!(output.contains("(global $r0 (export \"r0\")"), "{}", output);
+        let renamed = input.replace("r0", "s").replace("continue", "i");
+        assert_eq!(printed, run(&renamed).1);
+    }
+
+    #[test]
+    fn test_compile_case_statement() {
+        let input =
+            " program Name;
+              var i: integer; c: char;
+              begin
+                for i := 0 to 8 do
+                  case i of
+                    0: writeln_int(10);
+                    1, 2: writeln_int(20);
+                    3..4, 6:
+                      case i mod 2 of
+                        0: writeln_int(-2);
+                        1: writeln_int(-1)
+                      end
+                  else
+                    writeln_int(0);
+                    writeln_int(i)
+                  end;
+                c := 'q';
+                case c of 'a'..'m': writeln_int(1); 'n'..'z': writeln_int(2) end
+              end.
+            ";
+
+        let output = compile(input, false);
+        assert!(output.contains("(local $case.selector i32)"), "{}", output);
+        let target = Target::default();
+        let printed = SharedBuffer::default();
+        let binary = wat::parse_str(&output).unwrap();
+        crate::runtime::run(&binary, &target, Box::new(printed.clone()))
+            .unwrap();
+        let printed = String::from_utf8(printed.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = printed.lines().collect();
+        assert_eq!(lines, [
+            "10", "20", "20", "-1", "-2", "0", "5", "-2", "0", "7", "2"
+        ]);
+    }
+
+    #[test]
+    fn test_compile_nested_loops_have_own_labels() {
+        let input =
+            " program Name;
+              var a, b: integer;
+              begin
+                a := 0;
+                while a < 3 do begin
+                  b := 0;
+                  repeat
+                    b := b + 1
+                  until b > a;
+                  a := a + 1
+                end
+              end.
+            ";
+
+        let output = compile(input, false);
+        for code in &[
+            "(block $loop.end.0", "(loop $loop.continue.0",
+            "br_if $loop.end.0", "br $loop.continue.0",
+            "(block $loop.end.1", "(loop $loop.continue.1",
+            "br_if $loop.continue.1",
+        ] {
+            assert_eq!(
+                output.matches(&format!("{}\n", code)).count(), 1,
+                "{}\n{}", code, output
+            );
+        }
+        assert_eq!(output.matches("i32.eqz").count(), 2, "{}", output);
+        assert!(!output.contains("i32.sub"), "{}", output);
+    }
+
+    #[test]
+    fn test_compile_partial_output() {
+        let input =
+            " program Name;
+              var a: integer;
+              begin
+                a := 1;
+                writeln_int(a);
+                a := b;
+                while a < 3 do begin
+                  a := a + 1;
+                  writeln_int(1.5)
+                end;
+                if a = 3 then
+                  writeln_int(a, a);
+                writeln_int(a)
+              end.
+            ";
+
+        let output = SharedBuffer::default();
+        let b = SimpleBuffer::new(input.as_bytes(), None);
+        let errs = Code::new(TokenStream::new(b), Box::new(output.clone()))
+            .with_partial_output(true)
+            .compile()
+            .unwrap();
+        assert_eq!(errs.count(), 3, "{}", errs);
+
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let binary = wat::parse_str(&text).unwrap();
+        let printed = SharedBuffer::default();
+        crate::runtime::run(
+            &binary, &Target::default(), Box::new(printed.clone())
+        ).unwrap();
+        assert_eq!(printed.0.borrow().as_slice(), b"1\n3\n");
     }
 
     #[test]
-    fn test_check_error_recovery() {
+    fn test_compile_partial_output_needs_declarations() {
         let input =
             " program Name;
-              var
-                r: record
-                  f:: Integer; { second ':' is unexpected but skipped }
-                end;
+              var a: Missing;
               begin
+                writeln_int(1)
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let output = SharedBuffer::default();
+        let b = SimpleBuffer::new(input.as_bytes(), None);
+        let errs = Code::new(TokenStream::new(b), Box::new(output.clone()))
+            .with_partial_output(true)
+            .compile()
+            .unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+        assert!(output.0.borrow().is_empty());
     }
 
     #[test]
-    fn test_check_empty_file() {
-        let input = "";
+    fn test_compile_real_constants() {
+        let input =
+            " program Name;
+              var x: real;
+              begin
+                x := 1.10e+30;
+                x := 1E5;
+                x := 25e-3;
+                x := 1.50;
+                x := 0.0000001
+              end.
+            ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let output = compile(input, false);
+        for constant in &["1.1e30", "100000.0", "0.025", "1.5", "1e-7"] {
+            assert!(
+                output.contains(&format!("f32.const {}\n", constant)),
+                "{}\n{}", constant, output
+            );
+        }
     }
 
-    /******************************************/
-    /*                                        */
-    /*        Semantic analysis tests         */
-    /*                                        */
-    /******************************************/
-
     #[test]
-    fn test_check_var_redefinition_global() {
+    fn test_compile_widening() {
         let input =
             " program Name;
               var
-                a: Integer;
-                a: Boolean;
+                i: integer;
+                x: real;
               begin
+                x := i - x;
+                x := i / 2;
+                i := round(x * i)
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let output = compile(input, false);
+        let lines: Vec<_> = output.lines().map(str::trim).collect();
+        let code = lines.join("\n");
+        for expected in &[
+            "local.get $i\nf32.convert_i32_s\nlocal.get $x\nf32.sub",
+            "local.get $i\nf32.convert_i32_s\n\
+            i32.const 2\nf32.convert_i32_s\nf32.div",
+            "local.get $x\nlocal.get $i\nf32.convert_i32_s\nf32.mul\n\
+            f32.nearest\ni32.trunc_f32_s\nlocal.set $i",
+        ] {
+            assert!(code.contains(expected), "{}\n{}", expected, output);
+        }
     }
 
     #[test]
-    fn test_check_var_redefinition_line() {
+    fn test_compile_number_functions_without_imports() {
         let input =
             " program Name;
               var
-                a, a: Integer;
+                i: integer;
+                x: real;
+                b: boolean;
               begin
+                b := odd(i);
+                i := sqr(i) + abs(i - 3);
+                x := abs(sqr(x))
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let output = compile(input, true);
+        assert!(!output.contains("import"), "{}", output);
+        for expected in &["i32.and", "select", "f32.abs", "f32.mul"] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
+
+        let errs = code(
+            " program Name;
+              var x: real; c: char; b: boolean;
+              begin
+                b := odd(x);
+                x := sqr(c) + abs(b)
+              end.
+            "
+        ).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in the argument of odd: expected integer, found real",
+            "type mismatch in the argument of sqr: expected a number, found char",
+            "type mismatch in the argument of abs: \
+            expected a number, found boolean",
+        ]);
     }
 
     #[test]
-    fn test_check_type_redefinition() {
+    fn test_compile_random() {
         let input =
             " program Name;
-              type
-                a = Integer;
-                a = record end;
+              var i: integer;
               begin
+                randomize;
+                i := random(6) + 1
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let imported = compile(input, false);
+        assert!(imported.contains("(import \"imports\" \"random_int\")"));
+        assert!(imported.contains("call $randomize"), "{}", imported);
+
+        let defined = compile_with(input, |c| c.with_target(Target {
+            random: RandomSource::Xorshift,
+            ..Target::default()
+        }));
+        assert!(!defined.contains("random_int"), "{}", defined);
+        for expected in &[
+            "(import \"imports\" \"current_time\")",
+            "(func $rt.random (param $rt.bound i32) (result i32)",
+            "(global $rt.random_state (mut i32)",
+            "i32.rem_u",
+        ] {
+            assert!(defined.contains(expected), "{}\n{}", expected, defined);
+        }
+
+        // The imports are only declared when they are called
+        let output = compile("program Name; begin end.", false);
+        assert!(!output.contains("random"), "{}", output);
+
+        let errs = code(
+            " program Name;
+              var i: integer;
+              begin
+                i := random(1.5)
+              end.
+            "
+        ).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in the argument of random: \
+            expected integer, found real",
+        ]);
     }
 
     #[test]
-    fn test_check_type_mismatch_with_redefined_type() {
+    fn test_check_conversions() {
         let input =
             " program Name;
-              type
-                real = integer;
               var
-                x: integer;
+                i: integer;
+                x: real;
+                c: char;
               begin
-                x := 67.786
+                i := x;
+                i := 4 / 2;
+                i := x div 2;
+                i := trunc(c);
+                i := c + 1;
+                i := trunc(i) + round(i * 1.5)
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in assignment: expected integer, found real",
+            "type mismatch in assignment: expected integer, found real",
+            "type mismatch in the operands of div: \
+            expected integer, found real",
+            "type mismatch in the argument of trunc: expected real, found char",
+            "type mismatch in the operands of +: expected char, found integer",
+        ]);
     }
 
     #[test]
-    fn test_check_type_outer_redefintion() {
+    fn test_check_logical_operands() {
         let input =
             " program Name;
-              type
-                integer = real;
+              var
+                i: integer;
+                x: real;
+                b: boolean;
+                c: char;
               begin
+                x := 1.5 and 2.0;
+                x := x or x;
+                b := b and (i > 0);
+                i := i and 3;
+                i := c or i
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in the operands of and: \
+            expected a boolean or an integer, found real",
+            "type mismatch in the operands of or: \
+            expected a boolean or an integer, found real",
+            "type mismatch in the operands of or: \
+            expected a boolean or an integer, found char",
+        ]);
     }
 
     #[test]
-    fn test_check_invalid_field_access() {
+    fn test_compile_text_arrays() {
         let input =
             " program Name;
               var
-                a: Integer;
+                name: packed array [1..3] of char;
+                xs: array [-1..1] of integer;
+                i: integer;
               begin
-                a.b := 0;
+                name := 'aBc';
+                if name <> 'abd' then xs[i] := 2;
+                i := xs[1]
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
-    }
-
-    #[test]
-    fn test_check_non_existent_field_access() {
-        let input =
-        " program Name;
-          var
-            a: record
-              a: Integer;
-            end;
-          begin
-            a.b := 0;
-          end.
-        ";
+        let output = compile(input, false);
+        for expected in &[
+            "(memory (export \"memory\") 1)",
+            "(local $rt.index i32)",
+            "i32.const 66\n    i32.store offset=4",
+            "i32.load offset=8\n    i32.const 100\n    i32.eq",
+            "i32.const -1\n        i32.sub",
+            "i32.store offset=12",
+            "i32.const 20\n    i32.load\n",
+        ] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let target = Target {
+            runtime_errors: RuntimeErrors::Callback,
+            ..Target::default()
+        };
+        let output = compile_with(input, |c| c.with_target(target));
+        assert!(output.contains("i32.const 201\n"), "{}", output);
     }
 
     #[test]
-    fn test_check_non_existent_field_field_access() {
+    fn test_check_arrays() {
         let input =
-        " program Name;
-          var
-            a: record
-              a: Integer;
-            end;
-          begin
-            a.b.c := 0;
-          end.
-        ";
+            " program Name;
+              var
+                s, t: packed array [1..3] of char;
+                empty: array [5..1] of integer;
+                xs: array [0..9] of integer;
+                i: integer;
+              begin
+                s := 'abcd';
+                s := t;
+                if s < 'abc' then i := 1;
+                if s = t then i := 1;
+                i := xs[10];
+                i := xs['a'];
+                i := i[1]
+              end.
+            ";
 
-        let c = code(input);
-        assert_errors_count(c, 2);
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "the lower bound 5 of the array exceeds the upper bound 1",
+            "type mismatch in assignment: expected packed array [1..3] \
+            of char, found packed array [1..4] of char",
+            "arrays cannot be assigned as a whole",
+            "arrays cannot be compared with <",
+            "arrays can only be compared with string literals",
+            "the index 10 is out of the bounds 0..9",
+            "type mismatch in the array index: \
+            expected integer, found char",
+            "type mismatch in the indexing of \"i\": \
+            expected an array, found integer",
+        ]);
+
+        let errs = code(
+            " program Name;
+              var a, b: array [1..1000000000] of integer;
+              begin end.
+            "
+        ).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, ["the variables do not fit in the memory"]);
     }
 
     #[test]
-    fn test_check_field_field_access() {
+    fn test_compile_ordinal_indices() {
         let input =
-        " program Name;
-          var
-            a: record
-              b: record
-                c: Integer;
-              end;
-            end;
-          begin
-            a.b.c := 0;
-          end.
-        ";
+            " program Name;
+              type color = (red, green, blue);
+              var
+                counts: array ['a'..'z'] of integer;
+                shades: array [color] of real;
+                window: array [5..10] of real;
+                c: char;
+              begin
+                counts[c] := 1;
+                counts['b'] := 2;
+                shades[blue] := 0.5;
+                window[6] := shades[green]
+              end.
+            ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let output = compile(input, false);
+        for expected in &[
+            // The bias of 'a' goes into the address, the array is at 0
+            "i32.const 97\n    i32.lt_s",
+            "i32.const 4\n    i32.mul\n    i32.const -388\n    i32.add",
+            "i32.const 4\n    i32.const 2\n    i32.store\n",
+            "i32.const 112\n    f32.const 0.5\n    f32.store\n",
+            "i32.const 120\n    i32.const 108\n    f32.load\n",
+        ] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
     }
 
     #[test]
-    fn test_check_bad_assignment() {
+    fn test_check_ordinal_indices() {
         let input =
             " program Name;
+              type color = (red, green, blue);
               var
-                a: Integer;
-                b: Boolean;
+                a: array ['z'..'a'] of integer;
+                b: array ['a'..5] of integer;
+                c: array [integer] of integer;
+                d: array [blue..red] of integer;
+                h: array ['a'..'c'] of integer;
+                i: integer;
               begin
-                a := b;
+                i := h['d'];
+                i := h[red]
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);       
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "the lower bound 'z' of the array exceeds the upper bound 'a'",
+            "type mismatch in the bounds of the index: \
+            expected char, found integer",
+            "integer cannot index an array",
+            "the lower bound blue of the array exceeds the upper bound red",
+            "the index 'd' is out of the bounds 'a'..'c'",
+            "type mismatch in the array index: \
+            expected char, found (red, green, blue)",
+        ]);
     }
 
     #[test]
-    fn test_check_deep_assignment() {
+    fn test_compile_matrices() {
         let input =
             " program Name;
               var
-                a: record
-                  b: record
-                    c: Integer;
-                  end;
-                end;
-
-                b: record
-                  c: Integer;
-                end;
+                m: array [1..2, 1..3] of integer;
+                i: integer;
               begin
-                a.b.c := b.c;
-                b.c := a.b.c;
+                m[2, 3] := 7;
+                m[2][3] := 8;
+                m[i, 2] := 9
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);      
+        let output = compile(input, false);
+        for expected in &[
+            // Rows of three elements follow each other
+            "i32.const 20\n    i32.const 7\n    i32.store\n",
+            "i32.const 20\n    i32.const 8\n    i32.store\n",
+            // The bias of the row and the constant column fold together
+            "i32.const 12\n    i32.mul\n    i32.const -8\n    i32.add",
+        ] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
     }
 
     #[test]
-    fn test_check_alias_assignment() {
+    fn test_check_matrices() {
         let input =
             " program Name;
-              type
-                t_a = integer;
-                t_b = integer;
               var
-                a: t_a;
-                b: t_b;
+                m: array [1..2, 1..3] of integer;
+                i: integer;
               begin
-                a := b;
+                i := m[1, 2, 3];
+                i := m[1][2][3];
+                m[3, 1] := 0
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);   
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        let indexing = "type mismatch in the indexing of \"m\": \
+            expected an array, found integer";
+        assert_eq!(messages, [
+            indexing,
+            indexing,
+            "the index 3 is out of the bounds 1..2",
+        ]);
     }
 
     #[test]
-    fn test_check_deep_alias_assignment() {
+    fn test_compile_records() {
         let input =
             " program Name;
-              type
-                t_a = integer;
-                t_b = t_a;
+              type sample = record at: integer; value: real end;
               var
-                a: t_a;
-                b: t_b;
+                a, b: sample;
+                s: record first, last: sample end;
               begin
-                a := b;
+                b := a;
+                s.last := b;
+                if a = s.last then with s do first.value := 1.5
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let output = compile(input, false);
+        for expected in &[
+            // Copied field by field, the real one as a real
+            "i32.const 8\n    i32.const 0\n    i32.load\n    i32.store\n",
+            "i32.const 8\n    i32.const 0\n    \
+            f32.load offset=4\n    f32.store offset=4\n",
+            "i32.const 24\n    i32.const 8\n    i32.load\n    i32.store\n",
+            "i32.const 0\n    f32.load offset=4\n    \
+            i32.const 24\n    f32.load offset=4\n    f32.eq\n    i32.and",
+            "i32.const 20\n        f32.const 1.5\n        f32.store)",
+        ] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
     }
 
     #[test]
-    fn test_check_deep_incorrect_alias_assignment() {
+    fn test_check_records() {
         let input =
             " program Name;
-              type
-                t_a = integer;
-                t_b = t_a;
-                t_c = boolean;
               var
-                a: t_b;
-                b: t_c;
+                a, c: record x: integer end;
+                b: record y: integer end;
+                i: integer;
               begin
                 a := b;
+                a := c;
+                if a < c then i := 1;
+                a := 1;
+                with a do for x := 1 to 2 do i := x
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "type mismatch in assignment: \
+            expected record x: integer end, found record y: integer end",
+            "records cannot be compared with <",
+            "type mismatch in assignment: \
+            expected record x: integer end, found integer",
+            "a field cannot be the control variable of a loop",
+        ]);
     }
 
     #[test]
-    fn test_check_boolean_assignment() {
+    fn test_compile_pointers() {
         let input =
             " program Name;
+              type
+                link = ^node;
+                node = record value: integer; next: link end;
               var
-                a: boolean;
+                head: link;
+                n: node;
               begin
-                a := true;
+                n.next := nil;
+                head := n.next;
+                if head <> nil then n.value := 1
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0); 
+        let output = compile(input, false);
+        for expected in &[
+            "(local $head i32)",
+            // The field next comes before value
+            "i32.const 0\n    i32.const 0\n    i32.store\n",
+            "i32.const 0\n    i32.load\n    local.set $head\n",
+            "local.get $head\n    i32.const 0\n    i32.ne\n",
+        ] {
+            assert!(output.contains(expected), "{}\n{}", expected, output);
+        }
     }
 
     #[test]
-    fn test_check_scalar_type() {
+    fn test_check_pointers() {
         let input =
             " program Name;
+              type
+                point = record x, y: real end;
+                segment = record start, finish: point end;
+                link = ^node;
+                lost = ^missing;
+                node = record at: segment; next: link end;
               var
-                a: (Apple, Banana, Grape);
+                a: link;
+                b: ^integer;
+                c: ^later;
+                n: node;
+                i: integer;
               begin
-                a := apple;
+                n.at.finish.y := 1.5;
+                a := n.next;
+                a := b;
+                if a < nil then i := 1;
+                i := a^.next
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let errs = code(input).check().unwrap();
+        let messages: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+        assert_eq!(messages, [
+            "identifier not found \"missing\"",
+            "identifier not found \"later\"",
+            "type mismatch in assignment: expected ^node, found ^integer",
+            "pointers cannot be compared with <",
+            "pointers cannot be followed yet",
+        ]);
     }
 
     #[test]
-    fn test_check_expression() {
+    fn test_compile_parameterless_call() {
         let input =
             " program Name;
-              var
-                result: integer;
               begin
-                result := -2 + 5*10;
+                tick;
+                Tick();
+                if true then tick
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let output = compile_with(input, |mut c| {
+            c.scope.put("tick".to_string(), Identifier::Procedure(vec![]))
+                .unwrap();
+            c
+        });
+        assert!(
+            output.contains("(func $tick (import \"imports\" \"tick\") )"),
+            "{}", output
+        );
+        assert_eq!(output.matches("call $tick").count(), 3, "{}", output);
     }
 
     #[test]
-    fn test_check_expression_with_negative_number_in_if() {
+    fn test_compile_memory_import() {
         let input =
             " program Name;
-              var
-                result: integer;
               begin
-                if -2 < -4 then
-                begin
-                    result := -2;
-                end else begin
-                    result := 0;
-                end
+                writeln_int(1)
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 0);
+        let plain = compile(input, false);
+        assert!(!plain.contains("memory"));
+
+        let target = Target {
+            memory: Memory {
+                import: true,
+                ..Memory::default()
+            },
+            ..Target::default()
+        };
+
+        let output = compile_with(input, |c| c.with_target(target));
+        let memory = output.find("(import \"imports\" \"memory\" (memory 1))");
+        assert!(memory.unwrap() < output.find("$writeln_int").unwrap());
     }
 
     #[test]
-    fn test_check_with_statement_undefined_field() {
+    fn test_compile_runtime_error_callback() {
         let input =
             " program Name;
               var
-                a: record
-                  f: integer
-                end;
+                a, b: integer;
               begin
-                with a do begin
-                  f_u := 0
-                end
+                a := 7;
+                b := a mod 2;
+                a := a div b
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let plain = compile(input, false);
+        assert!(!plain.contains("runtime_error"));
+
+        let target = Target {
+            runtime_errors: RuntimeErrors::Callback,
+            ..Target::default()
+        };
+
+        let output = compile_with(input, |c| c.with_target(target));
+        assert!(output.contains(
+            "(import \"imports\" \"runtime_error\") (param i32)(param i32))"
+        ));
+        assert!(output.contains("(local $rt.divisor i32)"));
+        assert_eq!(output.matches("call $runtime_error").count(), 1);
+        assert!(output.contains("i32.const 200\n"));
+        assert!(output.contains("i32.const 7\n"));
+        assert!(output.contains("unreachable"));
     }
 
     #[test]
-    fn test_check_with_statement_shadowed_field_leading_to_type_mismatch() {
+    fn test_compile_start() {
         let input =
             " program Name;
-              var
-                a: record
-                  f: integer
-                end;
-                b: record
-                  f: real
-                end;
               begin
-                with a, b do begin
-                  f := 0
-                end
+                writeln_int(1)
               end.
             ";
 
-        let c = code(input);
-        assert_errors_count(c, 1);
+        let plain = compile(input, false);
+        assert!(plain.contains("(func (export \"program\")"));
+        assert!(!plain.contains("start"));
+
+        let section = compile_with(input, |c| c.with_target(Target {
+            start: Start::Section,
+            ..Target::default()
+        }));
+        assert!(section.contains("(start $program)"));
+        assert!(section.contains("(func $program (export \"program\")"));
+
+        let initialize = compile_with(input, |c| c.with_target(Target {
+            start: Start::Initialize,
+            ..Target::default()
+        }));
+        assert!(initialize.contains("(export \"_initialize\" (func $program))"));
+    }
+
+    #[test]
+    fn test_compile_streamed() {
+        let input = "program P; var a, b: integer; \
+            begin a := 1; b := a * 2 end.";
+
+        for configure in [
+            |c: Code<SimpleBuffer>| c.with_target(Target {
+                globals: true,
+                start: Start::Section,
+                ..Target::default()
+            }),
+            |c: Code<SimpleBuffer>| c.with_optimizations(true),
+        ] {
+            let validated = compile_with(input, configure);
+            let streamed = compile_with(input, |c| {
+                configure(c).with_validation(false)
+            });
+            assert_eq!(streamed, validated);
+            assert!(validated.ends_with(")\n"));
+        }
+    }
+
+    #[test]
+    fn test_check_bytes_no_panic() {
+        let inputs: [&[u8]; 6] = [
+            b"program a\xcd\n",
+            b"program P; var a: integer; begin a := 0. end.",
+            b"program P; var a: 1..5; begin end.",
+            b"program P; var c: char; begin c := 'ab' end.",
+            b"program P; var c: char; begin c := 'a' end.",
+            b"program P; type r = record c: char end; var a: r; begin end.",
+        ];
+
+        // Reporting errors is all that is expected of these
+        for input in inputs.iter() {
+            check_bytes(input).ok();
+            let b = SimpleBuffer::new(input, None);
+            Code::new(TokenStream::new(b), Box::new(std::io::sink()))
+                .compile()
+                .ok();
+        }
+
+        let errs = check_bytes(inputs[3]).unwrap();
+        assert_eq!(errs.count(), 1, "{}", errs);
+        let char_var = compile(std::str::from_utf8(inputs[4]).unwrap(), false);
+        assert!(char_var.contains("i32.const 97"), "{}", char_var);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn compile(input: &str, optimize: bool) -> String {
+        compile_with(input, |c| c.with_optimizations(optimize))
+    }
+
+    fn compile_with(
+        input: &str,
+        configure: impl FnOnce(Code<SimpleBuffer>) -> Code<SimpleBuffer>
+    ) -> String {
+        let output = SharedBuffer::default();
+        let b = SimpleBuffer::new(input.as_bytes(), None);
+        let c = configure(
+            Code::new(TokenStream::new(b), Box::new(output.clone()))
+        );
+        let errs = c.compile().unwrap();
+        assert_eq!(errs.count(), 0, "{}", errs);
+
+        let text = output.0.borrow().clone();
+        String::from_utf8(text).unwrap()
     }
 
     fn assert_errors_count(code: Code<impl Buffer>, count: usize) {
         let errs = code.check().unwrap();
         println!("{}", errs);
-        assert_eq!(count, errs.count()); 
+        assert_eq!(count, errs.error_count());
     }
 }
@@ -0,0 +1,181 @@
+use std::{cell::RefCell, collections::HashMap, ops::Range, rc::Rc};
+
+use crate::{
+    position::FilePosition,
+    tokenization::{Operator, SimpleBuffer, Token, TokenStream, Trivia},
+};
+use super::{code::Code, resolution::Resolution};
+
+/// What a piece of source text is, for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticKind {
+    Keyword,
+    /// The name of the program
+    Program,
+    Type,
+    Variable,
+    /// A constant of an enumerated type
+    Constant,
+    Procedure,
+    Field,
+    Literal,
+    Comment,
+}
+
+/// A classified span of source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    /// The byte offsets of the text
+    pub range: Range<usize>,
+    /// Where the text starts
+    pub pos: FilePosition,
+    pub kind: SemanticKind,
+}
+
+/// Classifies the keywords, literals and comments of a program by
+/// the lexer, and its identifiers by what they resolve to.
+/// The identifiers that resolve to nothing are left out, as are
+/// operators and punctuation. The tokens are in source order.
+pub fn semantic_tokens(data: &[u8]) -> Vec<SemanticToken> {
+    let resolved = Rc::new(RefCell::new(HashMap::new()));
+    let sink = Rc::clone(&resolved);
+    let ts = TokenStream::new(SimpleBuffer::new(data, None));
+    let code = Code::new(ts, Box::new(std::io::sink()))
+        .with_resolution_handler(move |resolution: Resolution| {
+            sink.borrow_mut().insert(resolution.range.start, resolution.kind);
+        });
+    // Errors only leave some identifiers unresolved
+    let _ = code.check();
+    let resolved = resolved.borrow();
+
+    let buf = SimpleBuffer::new(data, None);
+    let source_map = buf.source_map();
+    let mut ts = TokenStream::new(buf).with_trivia();
+    let mut tokens = Vec::new();
+    let mut push = |range: Range<usize>, kind| {
+        let pos = source_map.position(range.start);
+        tokens.push(SemanticToken { range, pos, kind });
+    };
+
+    loop {
+        let token = match ts.next_with_trivia() {
+            Ok(token) => token,
+            Err(_) => continue,
+        };
+
+        let range = ts.token_range();
+        let trivia_len: usize = token.leading.iter()
+            .map(|t| t.text().len())
+            .sum();
+        let mut offset = range.start - trivia_len;
+        for t in &token.leading {
+            let end = offset + t.text().len();
+            if let Trivia::Comment(_) = t {
+                push(offset..end, SemanticKind::Comment);
+            }
+            offset = end;
+        }
+
+        let kind = match token.token {
+            Token::EOF => break,
+            Token::K(_) | Token::O(
+                Operator::And | Operator::Or | Operator::Xor | Operator::Not
+                | Operator::IntegerDivide | Operator::Modulus
+            ) => Some(SemanticKind::Keyword),
+            Token::Literal(_) | Token::Number(_) => Some(SemanticKind::Literal),
+            Token::Id(_) => resolved.get(&range.start).copied(),
+            _ => None
+        };
+        if let Some(kind) = kind {
+            push(range, kind);
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod highlighting_tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<(String, SemanticKind)> {
+        semantic_tokens(input.as_bytes()).into_iter()
+            .map(|t| (input[t.range].to_string(), t.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let input = "program P;\n\
+            type\n  \
+              point = record x: integer end;\n  \
+              color = (red, green);\n\
+            var\n  \
+              p: point;\n  \
+              c: color;\n\
+            begin { set up }\n  \
+              p.x := 1;\n  \
+              c := red;\n  \
+              writeln_int(p.x)\n\
+            end.";
+
+        use SemanticKind::*;
+        let expected = [
+            ("program", Keyword),
+            ("P", Program),
+            ("type", Keyword),
+            ("point", Type),
+            ("record", Keyword),
+            ("x", Field),
+            ("integer", Type),
+            ("end", Keyword),
+            ("color", Type),
+            ("red", Constant),
+            ("green", Constant),
+            ("var", Keyword),
+            ("p", Variable),
+            ("point", Type),
+            ("c", Variable),
+            ("color", Type),
+            ("begin", Keyword),
+            ("{ set up }", Comment),
+            ("p", Variable),
+            ("x", Field),
+            ("1", Literal),
+            ("c", Variable),
+            ("red", Constant),
+            ("writeln_int", Procedure),
+            ("p", Variable),
+            ("x", Field),
+            ("end", Keyword),
+        ];
+        let expected: Vec<_> = expected.iter()
+            .map(|&(text, kind)| (text.to_string(), kind))
+            .collect();
+
+        assert_eq!(kinds(input), expected);
+    }
+
+    #[test]
+    fn test_semantic_tokens_positions() {
+        let tokens = semantic_tokens(b"program P;\n{ c }\nbegin end.");
+
+        assert_eq!(tokens[2].kind, SemanticKind::Comment);
+        assert_eq!(tokens[2].pos, FilePosition::new(2, 1));
+        assert_eq!(tokens[3].pos, FilePosition::new(3, 1));
+    }
+
+    #[test]
+    fn test_semantic_tokens_unresolved() {
+        assert_eq!(
+            kinds("program P; begin a := 1 end."),
+            vec![
+                ("program".to_string(), SemanticKind::Keyword),
+                ("P".to_string(), SemanticKind::Program),
+                ("begin".to_string(), SemanticKind::Keyword),
+                ("1".to_string(), SemanticKind::Literal),
+                ("end".to_string(), SemanticKind::Keyword),
+            ]
+        );
+    }
+}
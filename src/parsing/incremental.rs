@@ -0,0 +1,419 @@
+use std::{
+    cell::RefCell,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use crate::{
+    error::{CompilationError, CompilationErrorKind},
+    position::START_POSITION,
+    source::SourceMap,
+    tokenization::{SimpleBuffer, Token, TokenStream},
+};
+use super::{code::Code, resolution::DefUseMap};
+
+/// A token and the byte offsets of its text
+type Lexeme = (Token, Range<usize>);
+
+/// The analysis of a program being edited. It keeps the tokens of the
+/// text, so an edit only reads again the tokens from the one before it
+/// up to where the tokens fall back in step with the old ones. The
+/// program is only checked again when its tokens change; an edit of
+/// the whitespace or the comments just moves the known errors and
+/// identifiers, unless it touches a directive comment or comes after
+/// the last token, where the errors at the end of the file are. The
+/// check itself is the usual single pass over the whole program, as
+/// its declarations all depend on the ones before.
+#[derive(Debug, Clone)]
+pub struct IncrementalAnalysis {
+    text: String,
+    source_map: SourceMap,
+    tokens: Vec<Lexeme>,
+    /// Whether the tokens reach the end of the text
+    /// rather than stop at an error
+    complete: bool,
+    errors: Vec<CompilationError>,
+    identifiers: DefUseMap,
+}
+
+/// How much of the analysis an edit reused
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EditStats {
+    /// The tokens read again
+    pub relexed: usize,
+    /// The tokens kept from before the edit
+    pub reused: usize,
+    /// Whether the program was checked again
+    pub rechecked: bool,
+}
+
+impl IncrementalAnalysis {
+    /// Reads and checks the whole text.
+    pub fn new(text: &str) -> Self {
+        let (tokens, complete) = lex(text, 0, |_| false);
+        let mut analysis = Self {
+            text: text.to_string(),
+            source_map: SourceMap::new(text.as_bytes(), None),
+            tokens,
+            complete,
+            errors: Vec::new(),
+            identifiers: DefUseMap::default(),
+        };
+        analysis.check();
+        analysis
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn errors(&self) -> &[CompilationError] {
+        &self.errors
+    }
+
+    pub fn identifiers(&self) -> &DefUseMap {
+        &self.identifiers
+    }
+
+    /// Replaces the bytes in `range` with `new_text` and brings
+    /// the analysis up to date. The range is cut to the text. A range
+    /// that splits a character is an error, which leaves the text as is.
+    pub fn edit(
+        &mut self,
+        range: Range<usize>,
+        new_text: &str
+    ) -> Result<EditStats, String> {
+        let start = range.start.min(self.text.len());
+        let range = start..range.end.clamp(start, self.text.len());
+        for offset in [range.start, range.end] {
+            if !self.text.is_char_boundary(offset) {
+                return Err(format!(
+                    "the edit splits the character at byte {}", offset
+                ));
+            }
+        }
+        // The trivia around the edit, from the end of the token before
+        // it to the start of the token after it
+        let around = self.tokens.partition_point(|(_, r)| r.end <= start)
+            .checked_sub(1)
+            .map_or(0, |i| self.tokens[i].1.end);
+        let after = self.tokens.iter()
+            .find(|(_, r)| r.start >= range.end)
+            .map_or(self.text.len(), |(_, r)| r.start);
+        let old_directives = has_directives(&self.text[around..after]);
+        // The errors at the end of the file point at its last character,
+        // which an edit after the last token moves on its own
+        let at_end = after == self.text.len();
+        let old_source_map = std::mem::replace(&mut self.source_map, {
+            self.text.replace_range(range.clone(), new_text);
+            SourceMap::new(self.text.as_bytes(), None)
+        });
+        let shift = |offset: usize| if offset >= range.end {
+            offset + new_text.len() - range.len()
+        } else {
+            offset
+        };
+
+        if !self.complete {
+            let (tokens, complete) = lex(&self.text, 0, |_| false);
+            let stats = EditStats {
+                relexed: tokens.len(),
+                reused: 0,
+                rechecked: true,
+            };
+            self.tokens = tokens;
+            self.complete = complete;
+            self.check();
+            return Ok(stats);
+        }
+
+        // The token before the first one the edit touches may run
+        // into the edited text, so the reading starts from there
+        let first = self.tokens.partition_point(|(_, r)| r.end < range.start)
+            .saturating_sub(1);
+        let from = match first {
+            0 => 0,
+            _ => self.tokens[first].1.start,
+        };
+        // The tokens after the edit, where the reading may fall in step
+        let mut old = self.tokens.partition_point(|(_, r)| r.start < range.end);
+        let tokens = &self.tokens;
+        let mut synced = None;
+        let (relexed, complete) = lex(&self.text, from, |(token, r)| {
+            while old < tokens.len() && shift(tokens[old].1.start) < r.start {
+                old += 1;
+            }
+            let in_step = tokens.get(old).is_some_and(|(t, old_range)| {
+                shift(old_range.start) == r.start && t == token
+            });
+            if in_step {
+                synced = Some(old);
+            }
+            in_step
+        });
+
+        let unchanged = match synced {
+            Some(old) => relexed.iter().map(|(t, _)| t)
+                .eq(self.tokens[first..old].iter().map(|(t, _)| t)),
+            None => complete && relexed.iter().map(|(t, _)| t)
+                .eq(self.tokens[first..].iter().map(|(t, _)| t)),
+        };
+        let tail: Vec<_> = match synced {
+            Some(old) => self.tokens[old..].iter()
+                .map(|(t, r)| (t.clone(), shift(r.start)..shift(r.end)))
+                .collect(),
+            None => Vec::new(),
+        };
+        // Directives live in the comments, so editing one
+        // changes the check even if the tokens stay the same
+        let unchanged = unchanged && !old_directives && !at_end
+            && !has_directives(&self.text[around..shift(after)]);
+        let stats = EditStats {
+            relexed: relexed.len(),
+            reused: first + tail.len(),
+            rechecked: !unchanged,
+        };
+        self.tokens.truncate(first);
+        self.tokens.extend(relexed);
+        self.tokens.extend(tail);
+        self.complete = complete || synced.is_some();
+
+        if unchanged {
+            self.move_results(&old_source_map, shift);
+        } else {
+            self.check();
+        }
+        Ok(stats)
+    }
+
+    /// Moves the errors and the identifiers found before an edit
+    /// that left the tokens as they were.
+    fn move_results(
+        &mut self,
+        old_source_map: &SourceMap,
+        shift: impl Fn(usize) -> usize
+    ) {
+        for e in &mut self.errors {
+            if let Some(offset) = old_source_map.offset(e.pos()) {
+                *e = e.clone().at(self.source_map.position(shift(offset)));
+            }
+        }
+
+        let moved = |r: &Range<usize>| shift(r.start)..shift(r.end);
+        self.identifiers = self.identifiers.identifiers().iter()
+            .map(|r| {
+                let mut r = r.clone();
+                r.range = moved(&r.range);
+                r.extent = moved(&r.extent);
+                r.declaration = r.declaration.as_ref().map(moved);
+                r
+            })
+            .collect();
+    }
+
+    /// Checks the whole program again.
+    fn check(&mut self) {
+        let resolutions = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&resolutions);
+        let data = self.text.as_bytes();
+
+        // A bug in the analysis must not lose the program being edited
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let buf = SimpleBuffer::new(data, None);
+            Code::new(TokenStream::new(buf), Box::new(std::io::sink()))
+                .with_resolution_handler(move |r| sink.borrow_mut().push(r))
+                .check()
+        }));
+        self.errors = match result {
+            Ok(Ok(errors)) => errors.iter().cloned().collect(),
+            Ok(Err(e)) => vec![e],
+            Err(_) => vec![CompilationError::new(
+                CompilationErrorKind::InternalError,
+                &None,
+                START_POSITION,
+                "internal compiler error: the analysis panicked"
+            )],
+        };
+        self.identifiers = resolutions.take().into_iter().collect();
+    }
+}
+
+/// Reads the tokens of the text from the byte offset `from`, which
+/// must be where a token or the trivia before it starts, until the end
+/// or a token for which `stop` holds, which is left out. Also tells
+/// whether the reading got to the end or to such a token.
+fn lex(
+    text: &str,
+    from: usize,
+    mut stop: impl FnMut(&Lexeme) -> bool
+) -> (Vec<Lexeme>, bool) {
+    let buf = SimpleBuffer::new(&text.as_bytes()[from..], None);
+    let mut ts = TokenStream::new(buf);
+    let mut tokens = Vec::new();
+    loop {
+        match ts.next() {
+            Ok(Token::EOF) => return (tokens, true),
+            Ok(token) => {
+                let range = ts.token_range();
+                let lexeme = (token, from + range.start..from + range.end);
+                if stop(&lexeme) {
+                    return (tokens, true);
+                }
+                tokens.push(lexeme);
+            },
+            Err(_) => return (tokens, false),
+        }
+    }
+}
+
+/// Whether the text has a directive comment.
+fn has_directives(text: &str) -> bool {
+    let mut ts = TokenStream::new(SimpleBuffer::new(text.as_bytes(), None));
+    loop {
+        let token = ts.next();
+        if !ts.take_directives().is_empty() {
+            return true;
+        }
+        match token {
+            Ok(Token::EOF) | Err(_) => return false,
+            Ok(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod incremental_tests {
+    use super::*;
+    use crate::position::FilePosition;
+
+    const PROGRAM: &str = "program P;\n\
+        var a, b: integer;\n\
+        begin\n  \
+          { the sum }\n  \
+          a := 1;\n  \
+          b := a + c\n\
+        end.";
+
+    /// Applies the edit and checks the analysis against a fresh one.
+    fn edit(
+        analysis: &mut IncrementalAnalysis,
+        old: &str,
+        new: &str
+    ) -> EditStats {
+        let start = analysis.text().find(old).unwrap();
+        let stats = analysis.edit(start..start + old.len(), new).unwrap();
+
+        let fresh = IncrementalAnalysis::new(analysis.text());
+        assert_eq!(analysis.tokens, fresh.tokens);
+        assert_eq!(
+            analysis.identifiers().identifiers(),
+            fresh.identifiers().identifiers()
+        );
+        let errors = |a: &IncrementalAnalysis| -> Vec<_> {
+            a.errors().iter().map(|e| (e.pos(), e.to_string())).collect()
+        };
+        assert_eq!(errors(analysis), errors(&fresh));
+        stats
+    }
+
+    #[test]
+    fn test_incremental_trivia() {
+        let mut analysis = IncrementalAnalysis::new(PROGRAM);
+        assert_eq!(analysis.errors().len(), 1);
+        let pos = analysis.errors()[0].pos();
+
+        let stats =
+            edit(&mut analysis, "{ the sum }", "{ the sum\n of a and c }");
+        assert!(!stats.rechecked);
+        assert!(stats.relexed <= 2, "{:?}", stats);
+        let moved = FilePosition::new(pos.line + 1, pos.col);
+        assert_eq!(analysis.errors()[0].pos(), moved);
+
+        let stats = edit(&mut analysis, "a := 1", "a  :=  1");
+        assert!(!stats.rechecked);
+        for r in analysis.identifiers().identifiers() {
+            let text = &analysis.text()[r.range.clone()];
+            assert!(text.eq_ignore_ascii_case(&r.name), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_incremental_tokens() {
+        let mut analysis = IncrementalAnalysis::new(PROGRAM);
+
+        let stats = edit(&mut analysis, "a + c", "a + b");
+        assert!(stats.rechecked);
+        assert!(stats.relexed <= 4, "{:?}", stats);
+        assert!(stats.reused > 10, "{:?}", stats);
+        assert!(analysis.errors().is_empty());
+
+        // The identifier grows into the edit
+        let stats = edit(&mut analysis, "a + b", "a + bb");
+        assert!(stats.rechecked);
+        assert_eq!(analysis.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_incremental_directives() {
+        let mut analysis = IncrementalAnalysis::new(
+            "program P;\n\
+            var n: integer;\n\
+            begin\n  \
+              if n > 0 then begin\n    \
+                exit;\n    \
+                n := 1\n  \
+              end\n\
+            end."
+        );
+        assert_eq!(analysis.errors().len(), 1);
+
+        let stats = edit(
+            &mut analysis,
+            "begin\n  if",
+            "{$WARN unreachable-code OFF}\nbegin\n  if"
+        );
+        assert!(stats.rechecked);
+        assert!(analysis.errors().is_empty());
+
+        let stats = edit(&mut analysis, "code OFF", "code ON");
+        assert!(stats.rechecked);
+        assert_eq!(analysis.errors().len(), 1);
+
+        // Turning a comment into a directive
+        let stats = edit(&mut analysis, "exit;", "exit; { unreachable-code }");
+        assert!(!stats.rechecked);
+        let stats =
+            edit(&mut analysis, "{ unreachable", "{%suppress unreachable");
+        assert!(stats.rechecked);
+        assert!(analysis.errors().is_empty());
+    }
+
+    #[test]
+    fn test_incremental_comments() {
+        let mut analysis = IncrementalAnalysis::new(PROGRAM);
+
+        // Opening a comment swallows the tokens up to its end
+        let stats = edit(&mut analysis, "a := 1;", "{ a := 1;");
+        assert!(stats.rechecked);
+        edit(&mut analysis, "{ the sum }", "the sum }");
+        edit(&mut analysis, "end.", "");
+        edit(&mut analysis, "program", "");
+        edit(&mut analysis, "", "program ");
+    }
+
+    #[test]
+    fn test_incremental_char_boundary() {
+        let mut analysis = IncrementalAnalysis::new(PROGRAM);
+        edit(&mut analysis, "{ the sum }", "{ the süm }");
+
+        // The edit splits the two bytes of 'ü'
+        let start = analysis.text().find('ü').unwrap() + 1;
+        assert!(analysis.edit(start..start + 1, "u").is_err());
+        assert!(analysis.text().contains("{ the süm }"));
+    }
+}
@@ -1 +1,11 @@
+pub mod ast;
 pub mod code;
+pub mod highlighting;
+pub mod incremental;
+pub mod outline;
+pub mod parser;
+pub mod printer;
+pub mod rename;
+pub mod resolution;
+pub mod visit;
+mod recovery;
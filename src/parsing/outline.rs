@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use crate::semantics::Type;
+use super::{
+    highlighting::SemanticKind,
+    resolution::{def_use_map, DefUseMap, Resolution},
+};
+
+/// A declaration in the outline of a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    /// The name as the declaration spells it
+    pub name: String,
+    /// The name in lowercase, which the symbol is looked up by
+    pub key: String,
+    pub kind: SemanticKind,
+    /// The byte offsets of the whole declaration
+    pub range: Range<usize>,
+    /// The byte offsets of the declared identifier
+    pub name_range: Range<usize>,
+    /// The type of the variable, constant, field or type declared
+    pub type_: Option<Type>,
+    /// The declarations within this one, in source order
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    fn new(declaration: &Resolution, source: &[u8]) -> Self {
+        let name = source.get(declaration.range.clone())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .unwrap_or_else(|| declaration.name.clone());
+        Self {
+            name,
+            key: declaration.name.clone(),
+            kind: declaration.kind,
+            range: declaration.extent.clone(),
+            name_range: declaration.range.clone(),
+            type_: declaration.type_.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Whether the declaration belongs within this one.
+    fn contains(&self, declaration: &Resolution) -> bool {
+        // The identifiers of one declaration end together and are
+        // siblings, even though the first one spans the rest
+        let within = self.range.start <= declaration.extent.start
+            && declaration.extent.end < self.range.end;
+        let nests = match (self.kind, declaration.kind) {
+            (SemanticKind::Program | SemanticKind::Procedure, _) => true,
+            (SemanticKind::Constant, _) => false,
+            (_, SemanticKind::Field | SemanticKind::Constant) => true,
+            _ => false,
+        };
+        within && nests
+    }
+}
+
+impl DefUseMap {
+    /// The declarations nested in the program, the procedures,
+    /// the records and the enumerations they belong to, named
+    /// as they are spelled in the source the map was made of.
+    pub fn outline(&self, source: &[u8]) -> Vec<Symbol> {
+        let mut roots = Vec::new();
+        let mut open: Vec<Symbol> = Vec::new();
+
+        for declaration in self.declarations() {
+            while let Some(symbol) = open.last() {
+                if symbol.contains(declaration) {
+                    break;
+                }
+                let symbol = open.pop().unwrap();
+                close(&mut open, &mut roots, symbol);
+            }
+            open.push(Symbol::new(declaration, source));
+        }
+
+        while let Some(symbol) = open.pop() {
+            close(&mut open, &mut roots, symbol);
+        }
+
+        roots
+    }
+}
+
+fn close(open: &mut [Symbol], roots: &mut Vec<Symbol>, symbol: Symbol) {
+    match open.last_mut() {
+        Some(parent) => parent.children.push(symbol),
+        None => roots.push(symbol),
+    }
+}
+
+/// Checks a program and lists its declarations, nested in the
+/// program, the procedures, the records and the enumerations
+/// they belong to, for editor outlines.
+pub fn outline(data: &[u8]) -> Vec<Symbol> {
+    def_use_map(data).outline(data)
+}
+
+#[cfg(test)]
+mod outline_tests {
+    use super::*;
+
+    /// The names and kinds of the symbols, indented by depth.
+    fn render(symbols: &[Symbol], depth: usize, out: &mut Vec<String>) {
+        for s in symbols {
+            out.push(format!("{}{} {:?}", "  ".repeat(depth), s.name, s.kind));
+            render(&s.children, depth + 1, out);
+        }
+    }
+
+    #[test]
+    fn test_outline_nesting() {
+        let input = "program P;\n\
+            type\n  \
+              point = record x, y: integer end;\n  \
+              color = (red, green);\n\
+            var\n  \
+              a, b: record\n    \
+                c: color;\n    \
+                p: record z: real end\n  \
+              end;\n  \
+              i: integer;\n\
+            begin\n\
+            end.";
+
+        let mut lines = Vec::new();
+        render(&outline(input.as_bytes()), 0, &mut lines);
+
+        assert_eq!(lines, [
+            "P Program",
+            "  point Type",
+            "    x Field",
+            "    y Field",
+            "  color Type",
+            "    red Constant",
+            "    green Constant",
+            "  a Variable",
+            "  b Variable",
+            "    c Field",
+            "    p Field",
+            "      z Field",
+            "  i Variable",
+        ]);
+    }
+
+    #[test]
+    fn test_outline_ranges() {
+        let input = "program P;\ntype t = integer;\nbegin end.";
+        let symbols = outline(input.as_bytes());
+
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(&input[symbols[0].range.clone()], input);
+        assert_eq!(symbols[0].name, "P");
+        assert_eq!(symbols[0].key, "p");
+
+        let t = &symbols[0].children[0];
+        assert_eq!(&input[t.range.clone()], "t = integer");
+        assert_eq!(&input[t.name_range.clone()], "t");
+        assert_eq!(t.type_, Some(Type::Integer));
+    }
+}
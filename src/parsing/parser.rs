@@ -0,0 +1,941 @@
+use std::ops::Range;
+
+use crate::{
+    error::{CompilationError, CompilationErrorKind, Errors},
+    tokenization::{
+        Buffer,
+        Keyword,
+        Operator,
+        Punctuation,
+        Relation,
+        SimpleBuffer,
+        Token,
+        TokenStream,
+    },
+};
+use super::ast::{
+    Block,
+    CaseElement,
+    CaseLabel,
+    Direction,
+    Expression,
+    ExpressionKind,
+    Name,
+    Program,
+    Selector,
+    Statement,
+    StatementKind,
+    TypeDefinition,
+    TypeExpression,
+    Variable,
+    VariableDeclaration,
+};
+
+type ParseResult<T> = Result<T, CompilationError>;
+
+/// Parses a program into its syntax tree without checking what the
+/// names in it refer to or generating any code. Parsing stops at the
+/// first syntax error, which is returned along with the lexical ones.
+/// The names and the numbers in the tree are spelled as in the source.
+pub fn parse(data: &[u8]) -> Result<Program, Errors> {
+    parse_stream(TokenStream::new(SimpleBuffer::new(data, None)))
+}
+
+/// Parses the program read from the token stream like `parse` does.
+pub fn parse_stream<T: Buffer>(
+    ts: TokenStream<T>
+) -> Result<Program, Errors> {
+    let mut parser = Parser {
+        token_stream: ts,
+        lookahead: Token::EOF,
+        last_end: 0,
+    };
+    let result = parser.proceed().and_then(|_| parser.program());
+
+    let mut errors = Errors::new();
+    for e in parser.token_stream.take_errors() {
+        errors.push(e);
+    }
+    match result {
+        Ok(program) if errors.is_empty() => Ok(program),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.push(e);
+            Err(errors)
+        }
+    }
+}
+
+struct Parser<T: Buffer> {
+    token_stream: TokenStream<T>,
+    lookahead: Token,
+    /// Where the token before the lookahead ends
+    last_end: usize,
+}
+
+impl<T: Buffer> Parser<T> {
+    // <program> ::= program <identifier> ; <block> .
+    fn program(&mut self) -> ParseResult<Program> {
+        self.consume(Token::K(Keyword::Program))?;
+        let name = self.identifier()?;
+        self.consume(Token::P(Punctuation::Semicolon))?;
+        let block = self.block()?;
+        self.consume(Token::P(Punctuation::Dot))?;
+        self.consume(Token::EOF)?;
+
+        Ok(Program { name, block })
+    }
+
+    // <block> ::=
+        // <type definition part>
+        // <variable declaration part>
+        // <statement part>
+    fn block(&mut self) -> ParseResult<Block> {
+        let mut types = Vec::new();
+        if self.lookahead == Token::K(Keyword::Type) {
+            self.proceed()?;
+            types.push(self.type_definition()?);
+            while self.lookahead == Token::P(Punctuation::Semicolon) {
+                self.proceed()?;
+                if !matches!(self.lookahead, Token::Id(_)) {
+                    break;
+                }
+                types.push(self.type_definition()?);
+            }
+        }
+
+        let mut variables = Vec::new();
+        if self.lookahead == Token::K(Keyword::Var) {
+            self.proceed()?;
+            loop {
+                variables.push(self.variable_declaration()?);
+                self.consume(Token::P(Punctuation::Semicolon))?;
+                if !matches!(self.lookahead, Token::Id(_)) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(Token::K(Keyword::Begin))?;
+        let statements = self.statement_sequence()?;
+        self.consume(Token::K(Keyword::End))?;
+
+        Ok(Block { types, variables, statements })
+    }
+
+    // <type definition> ::= <identifier> = <type>
+    fn type_definition(&mut self) -> ParseResult<TypeDefinition> {
+        let name = self.identifier()?;
+        self.consume(Token::R(Relation::Eq))?;
+        let type_ = self.type_()?;
+
+        Ok(TypeDefinition { name, type_ })
+    }
+
+    // <variable declaration> ::= <identifier> {,<identifier>} : <type>
+    fn variable_declaration(&mut self) -> ParseResult<VariableDeclaration> {
+        let names = self.identifier_list()?;
+        self.consume(Token::P(Punctuation::Colon))?;
+        let type_ = self.type_()?;
+
+        Ok(VariableDeclaration { names, type_ })
+    }
+
+    // <identifier list> ::= <identifier> {, <identifier>}
+    fn identifier_list(&mut self) -> ParseResult<Vec<Name>> {
+        let mut names = vec![self.identifier()?];
+        while self.lookahead == Token::P(Punctuation::Comma) {
+            self.proceed()?;
+            names.push(self.identifier()?);
+        }
+
+        Ok(names)
+    }
+
+    // <type> ::= <simple type> | <structured type> | <pointer type>
+    fn type_(&mut self) -> ParseResult<TypeExpression> {
+        let packed = self.lookahead == Token::K(Keyword::Packed);
+        if packed {
+            self.proceed()?;
+        }
+
+        match self.lookahead {
+            Token::K(Keyword::Array) => self.array_type(packed),
+            Token::K(Keyword::Record) => self.record_type(packed),
+            _ if packed => Err(self.syntax_error(&format!(
+                "expected array or record, found {:?}", self.lookahead
+            ))),
+            Token::P(Punctuation::Caret) => {
+                self.proceed()?;
+                Ok(TypeExpression::Pointer(self.identifier()?))
+            },
+            Token::P(Punctuation::Lbracket) => {
+                self.proceed()?;
+                let names = self.identifier_list()?;
+                self.consume(Token::P(Punctuation::Rbracket))?;
+                Ok(TypeExpression::Enumeration(names))
+            },
+            _ => self.simple_type()
+        }
+    }
+
+    // <array type> ::=
+        // array [ <index type> {, <index type>} ] of <component type>
+    fn array_type(&mut self, packed: bool) -> ParseResult<TypeExpression> {
+        self.consume(Token::K(Keyword::Array))?;
+        self.consume(Token::P(Punctuation::Lsqbracket))?;
+        let mut indices = vec![self.simple_type()?];
+        while self.lookahead == Token::P(Punctuation::Comma) {
+            self.proceed()?;
+            indices.push(self.simple_type()?);
+        }
+        self.consume(Token::P(Punctuation::Rsqbracket))?;
+        self.consume(Token::K(Keyword::Of))?;
+        let element = Box::new(self.type_()?);
+
+        Ok(TypeExpression::Array { packed, indices, element })
+    }
+
+    // <record type> ::= record <field list> end
+    // <record section> ::=
+        // <field identifier> {, <field identifier>} : <type>
+        // | <empty>
+    fn record_type(&mut self, packed: bool) -> ParseResult<TypeExpression> {
+        self.consume(Token::K(Keyword::Record))?;
+        let mut fields = Vec::new();
+        loop {
+            if let Token::Id(_) = self.lookahead {
+                fields.push(self.variable_declaration()?);
+            }
+            if self.lookahead == Token::P(Punctuation::Semicolon) {
+                self.proceed()?;
+            } else {
+                break;
+            }
+        }
+        self.consume(Token::K(Keyword::End))?;
+
+        Ok(TypeExpression::Record { packed, fields })
+    }
+
+    // <simple type> ::= <subrange type> | <type identifier>
+    // <subrange type> ::= <constant> .. <constant>
+    fn simple_type(&mut self) -> ParseResult<TypeExpression> {
+        let low = match self.lookahead {
+            Token::Id(_) => {
+                let name = self.name();
+                self.proceed()?;
+                if self.lookahead != Token::P(Punctuation::Range) {
+                    return Ok(TypeExpression::Named(name));
+                }
+                identifier_constant(name)
+            },
+            _ => self.constant()?
+        };
+        self.consume(Token::P(Punctuation::Range))?;
+        let high = self.constant()?;
+
+        Ok(TypeExpression::Subrange(Box::new(low), Box::new(high)))
+    }
+
+    // <constant> ::=
+        // [<sign>] <unsigned number> | <character> | <constant identifier>
+    fn constant(&mut self) -> ParseResult<Expression> {
+        let start = self.start();
+        let sign = match self.lookahead {
+            Token::O(op @ (Operator::Plus | Operator::Minus)) => {
+                self.proceed()?;
+                Some(op)
+            },
+            _ => None
+        };
+
+        let constant = match self.lookahead.clone() {
+            Token::Number(_) => self.number(),
+            Token::Literal(v) if sign.is_none() => self.literal(v),
+            Token::Id(_) if sign.is_none() => identifier_constant(self.name()),
+            token => return Err(self.syntax_error(&format!(
+                "expected a constant, found {:?}", token
+            )))
+        };
+        self.proceed()?;
+
+        Ok(match sign {
+            Some(operator) => Expression {
+                kind: ExpressionKind::Unary {
+                    operator,
+                    operand: Box::new(constant),
+                },
+                range: self.range_from(start),
+            },
+            None => constant
+        })
+    }
+
+    // <statement sequence> ::= <statement> {; <statement>}
+    fn statement_sequence(&mut self) -> ParseResult<Vec<Statement>> {
+        let mut statements = vec![self.statement()?];
+        while self.lookahead == Token::P(Punctuation::Semicolon) {
+            self.proceed()?;
+            statements.push(self.statement()?);
+        }
+
+        Ok(statements)
+    }
+
+    // <statement> ::= <simple statement> | <structured statement>
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let start = self.start();
+        let kind = match self.lookahead {
+            Token::P(Punctuation::Semicolon)
+            | Token::K(Keyword::End)
+            | Token::K(Keyword::Until)
+            | Token::K(Keyword::Else) => return Ok(Statement {
+                kind: StatementKind::Empty,
+                range: start..start,
+            }),
+            Token::Id(_) => self.simple_statement()?,
+            Token::K(Keyword::Begin) => {
+                self.proceed()?;
+                let statements = self.statement_sequence()?;
+                self.consume(Token::K(Keyword::End))?;
+                StatementKind::Compound(statements)
+            },
+            Token::K(Keyword::If) => self.if_statement()?,
+            Token::K(Keyword::Case) => self.case_statement()?,
+            Token::K(Keyword::While) => {
+                self.proceed()?;
+                let condition = self.expression()?;
+                self.consume(Token::K(Keyword::Do))?;
+                let body = Box::new(self.statement()?);
+                StatementKind::While { condition, body }
+            },
+            Token::K(Keyword::Repeat) => {
+                self.proceed()?;
+                let body = self.statement_sequence()?;
+                self.consume(Token::K(Keyword::Until))?;
+                let condition = self.expression()?;
+                StatementKind::Repeat { body, condition }
+            },
+            Token::K(Keyword::For) => self.for_statement()?,
+            Token::K(Keyword::With) => {
+                self.proceed()?;
+                let mut records = vec![self.variable()?];
+                while self.lookahead == Token::P(Punctuation::Comma) {
+                    self.proceed()?;
+                    records.push(self.variable()?);
+                }
+                self.consume(Token::K(Keyword::Do))?;
+                let body = Box::new(self.statement()?);
+                StatementKind::With { records, body }
+            },
+            ref t => return Err(self.syntax_error(&format!(
+                "a statement cannot start with {:?}",
+                t
+            )))
+        };
+
+        Ok(Statement { kind, range: self.range_from(start) })
+    }
+
+    // <simple statement> ::=
+        // <assignment statement> | <procedure statement>
+    fn simple_statement(&mut self) -> ParseResult<StatementKind> {
+        let target = self.variable()?;
+        if self.lookahead == Token::O(Operator::Assign) {
+            self.proceed()?;
+            let value = self.expression()?;
+            return Ok(StatementKind::Assignment { target, value });
+        }
+
+        if !target.selectors.is_empty() {
+            return Err(self.unexpected(&Token::O(Operator::Assign)));
+        }
+        let arguments = self.arguments()?;
+        Ok(StatementKind::Call { name: target.name, arguments })
+    }
+
+    // <if statement> ::=
+        // if <expression> then <statement>
+        // | if <expression> then <statement> else <statement>
+    fn if_statement(&mut self) -> ParseResult<StatementKind> {
+        self.consume(Token::K(Keyword::If))?;
+        let condition = self.expression()?;
+        self.consume(Token::K(Keyword::Then))?;
+        let then = Box::new(self.statement()?);
+        let else_ = if self.lookahead == Token::K(Keyword::Else) {
+            self.proceed()?;
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(StatementKind::If { condition, then, else_ })
+    }
+
+    // <case statement> ::=
+        // case <expression> of <case list element> {; <case list element>}
+        // [[;] else <statement> {; <statement>}] [;] end
+    fn case_statement(&mut self) -> ParseResult<StatementKind> {
+        self.consume(Token::K(Keyword::Case))?;
+        let selector = self.expression()?;
+        self.consume(Token::K(Keyword::Of))?;
+
+        let mut elements = Vec::new();
+        while !matches!(
+            self.lookahead,
+            Token::K(Keyword::End) | Token::K(Keyword::Else)
+        ) {
+            let labels = self.case_label_list()?;
+            self.consume(Token::P(Punctuation::Colon))?;
+            let statement = self.statement()?;
+            elements.push(CaseElement { labels, statement });
+
+            if self.lookahead == Token::P(Punctuation::Semicolon) {
+                self.proceed()?;
+            } else {
+                break;
+            }
+        }
+
+        let else_ = if self.lookahead == Token::K(Keyword::Else) {
+            self.proceed()?;
+            Some(self.statement_sequence()?)
+        } else {
+            None
+        };
+        self.consume(Token::K(Keyword::End))?;
+
+        Ok(StatementKind::Case { selector, elements, else_ })
+    }
+
+    // <case label list> ::= <case label> {, <case label>}
+    // <case label> ::= <constant> [.. <constant>]
+    fn case_label_list(&mut self) -> ParseResult<Vec<CaseLabel>> {
+        let mut labels = Vec::new();
+        loop {
+            let low = self.constant()?;
+            let high = if self.lookahead == Token::P(Punctuation::Range) {
+                self.proceed()?;
+                Some(self.constant()?)
+            } else {
+                None
+            };
+            labels.push(CaseLabel { low, high });
+
+            if self.lookahead == Token::P(Punctuation::Comma) {
+                self.proceed()?;
+            } else {
+                return Ok(labels);
+            }
+        }
+    }
+
+    // <for statement> ::= for <control variable> := <for list> do <statement>
+    fn for_statement(&mut self) -> ParseResult<StatementKind> {
+        self.consume(Token::K(Keyword::For))?;
+        let variable = self.identifier()?;
+        self.consume(Token::O(Operator::Assign))?;
+        let initial = self.expression()?;
+        let direction = match self.lookahead {
+            Token::K(Keyword::To) => Direction::To,
+            Token::K(Keyword::Downto) => Direction::Downto,
+            _ => return Err(self.syntax_error(&format!(
+                "expected to or downto, found {:?}", self.lookahead
+            )))
+        };
+        self.proceed()?;
+        let final_ = self.expression()?;
+        self.consume(Token::K(Keyword::Do))?;
+        let body = Box::new(self.statement()?);
+
+        Ok(StatementKind::For { variable, initial, direction, final_, body })
+    }
+
+    // <variable> ::=
+        // <identifier> { [ <expression> {, <expression>} ]
+        // | . <field identifier> | ^ }
+    fn variable(&mut self) -> ParseResult<Variable> {
+        let name = self.identifier()?;
+        let mut selectors = Vec::new();
+        loop {
+            match self.lookahead {
+                Token::P(Punctuation::Lsqbracket) => {
+                    self.proceed()?;
+                    let mut indices = vec![self.expression()?];
+                    while self.lookahead == Token::P(Punctuation::Comma) {
+                        self.proceed()?;
+                        indices.push(self.expression()?);
+                    }
+                    self.consume(Token::P(Punctuation::Rsqbracket))?;
+                    selectors.push(Selector::Index(indices));
+                },
+                Token::P(Punctuation::Dot) => {
+                    self.proceed()?;
+                    selectors.push(Selector::Field(self.identifier()?));
+                },
+                Token::P(Punctuation::Caret) => {
+                    self.proceed()?;
+                    selectors.push(Selector::Dereference);
+                },
+                _ => return Ok(Variable { name, selectors })
+            }
+        }
+    }
+
+    // <actual parameters> ::= [ ( <expression> {, <expression>} ) ]
+    fn arguments(&mut self) -> ParseResult<Vec<Expression>> {
+        let mut arguments = Vec::new();
+        if self.lookahead != Token::P(Punctuation::Lbracket) {
+            return Ok(arguments);
+        }
+
+        self.proceed()?;
+        if self.lookahead != Token::P(Punctuation::Rbracket) {
+            arguments.push(self.expression()?);
+            while self.lookahead == Token::P(Punctuation::Comma) {
+                self.proceed()?;
+                arguments.push(self.expression()?);
+            }
+        }
+        self.consume(Token::P(Punctuation::Rbracket))?;
+
+        Ok(arguments)
+    }
+
+    // <expression> ::=
+        // <simple expression>
+        // | <simple expression> <relational operator> <simple expression>
+    fn expression(&mut self) -> ParseResult<Expression> {
+        let start = self.start();
+        let left = self.simple_expression()?;
+        if let Token::R(relation) = self.lookahead {
+            self.proceed()?;
+            let right = self.simple_expression()?;
+            return Ok(Expression {
+                kind: ExpressionKind::Relation {
+                    relation,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                range: self.range_from(start),
+            });
+        }
+
+        Ok(left)
+    }
+
+    // <simple expression> ::= [<sign>] <term> { <adding operator> <term> }
+    fn simple_expression(&mut self) -> ParseResult<Expression> {
+        let start = self.start();
+        let mut left = match self.lookahead {
+            Token::O(operator @ (Operator::Plus | Operator::Minus)) => {
+                self.proceed()?;
+                let operand = Box::new(self.term()?);
+                Expression {
+                    kind: ExpressionKind::Unary { operator, operand },
+                    range: self.range_from(start),
+                }
+            },
+            _ => self.term()?
+        };
+
+        while let Token::O(
+            operator @ (
+                Operator::Plus | Operator::Minus | Operator::Or | Operator::Xor
+            )
+        ) = self.lookahead {
+            self.proceed()?;
+            let right = Box::new(self.term()?);
+            left = Expression {
+                kind: ExpressionKind::Binary {
+                    operator,
+                    left: Box::new(left),
+                    right,
+                },
+                range: self.range_from(start),
+            };
+        }
+
+        Ok(left)
+    }
+
+    // <term> ::= <factor> { <multiplying operator> <factor> }
+    fn term(&mut self) -> ParseResult<Expression> {
+        let start = self.start();
+        let mut left = self.factor()?;
+        while let Token::O(
+            operator @ (
+                Operator::Multiply | Operator::Divide
+                | Operator::IntegerDivide | Operator::Modulus | Operator::And
+            )
+        ) = self.lookahead {
+            self.proceed()?;
+            let right = Box::new(self.factor()?);
+            left = Expression {
+                kind: ExpressionKind::Binary {
+                    operator,
+                    left: Box::new(left),
+                    right,
+                },
+                range: self.range_from(start),
+            };
+        }
+
+        Ok(left)
+    }
+
+    // <factor> ::=
+        // <variable>
+        // | <function designator>
+        // | <unsigned constant>
+        // | nil
+        // | ( <expression> )
+        // | not <factor>
+    fn factor(&mut self) -> ParseResult<Expression> {
+        let start = self.start();
+        let kind = match self.lookahead.clone() {
+            Token::Id(_) => {
+                let variable = self.variable()?;
+                let call = variable.selectors.is_empty()
+                    && self.lookahead == Token::P(Punctuation::Lbracket);
+                if call {
+                    let arguments = self.arguments()?;
+                    ExpressionKind::Call { name: variable.name, arguments }
+                } else {
+                    ExpressionKind::Variable(variable)
+                }
+            },
+            Token::Number(_) => {
+                let number = self.number();
+                self.proceed()?;
+                return Ok(number);
+            },
+            Token::Literal(v) => {
+                let literal = self.literal(v);
+                self.proceed()?;
+                return Ok(literal);
+            },
+            Token::K(Keyword::Nil) => {
+                self.proceed()?;
+                ExpressionKind::Nil
+            },
+            Token::O(Operator::Not) => {
+                self.proceed()?;
+                ExpressionKind::Unary {
+                    operator: Operator::Not,
+                    operand: Box::new(self.factor()?),
+                }
+            },
+            Token::P(Punctuation::Lbracket) => {
+                self.proceed()?;
+                let expression = self.expression()?;
+                self.consume(Token::P(Punctuation::Rbracket))?;
+                ExpressionKind::Parenthesized(Box::new(expression))
+            },
+            _ => return Err(self.syntax_error("illegal expression"))
+        };
+
+        Ok(Expression { kind, range: self.range_from(start) })
+    }
+
+    fn identifier(&mut self) -> ParseResult<Name> {
+        match self.lookahead {
+            Token::Id(_) => {
+                let name = self.name();
+                self.proceed()?;
+                Ok(name)
+            },
+            _ => Err(self.unexpected(&Token::Id(String::new())))
+        }
+    }
+
+    /// The identifier in the lookahead as it is spelled in the source.
+    fn name(&self) -> Name {
+        let range = self.token_stream.token_range();
+        Name { text: self.token_stream.text(range.clone()), range }
+    }
+
+    /// The number in the lookahead as it is written.
+    fn number(&self) -> Expression {
+        let range = self.token_stream.token_range();
+        let text = self.token_stream.text(range.clone());
+        Expression { kind: ExpressionKind::Number(text), range }
+    }
+
+    /// The literal in the lookahead with the text of its token.
+    fn literal(&self, value: String) -> Expression {
+        Expression {
+            kind: ExpressionKind::Literal(value),
+            range: self.token_stream.token_range(),
+        }
+    }
+
+    /// Where the lookahead starts.
+    fn start(&self) -> usize {
+        self.token_stream.token_range().start
+    }
+
+    /// The text from `start` to the end of the token before the lookahead.
+    fn range_from(&self, start: usize) -> Range<usize> {
+        start..self.last_end
+    }
+
+    fn consume(&mut self, token: Token) -> ParseResult<()> {
+        if self.lookahead == token {
+            self.proceed()
+        } else {
+            Err(self.unexpected(&token))
+        }
+    }
+
+    fn proceed(&mut self) -> ParseResult<()> {
+        self.last_end = self.token_stream.token_range().end;
+        loop {
+            match self.token_stream.next()? {
+                // Already reported by the token stream
+                Token::Unknown => continue,
+                token => {
+                    self.lookahead = token;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn unexpected(&self, token: &Token) -> CompilationError {
+        self.syntax_error(&format!(
+            "expected {:?}, found {:?}",
+            token,
+            self.lookahead
+        ))
+    }
+
+    fn syntax_error(&self, message: &str) -> CompilationError {
+        CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            self.token_stream.filepath(),
+            self.token_stream.token_pos(),
+            message
+        )
+    }
+}
+
+/// An identifier used as a constant.
+fn identifier_constant(name: Name) -> Expression {
+    Expression {
+        range: name.range.clone(),
+        kind: ExpressionKind::Variable(Variable {
+            name,
+            selectors: Vec::new(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use super::*;
+    use crate::parsing::visit::forget_ranges;
+
+    fn parse_str(input: &str) -> Result<Program, Errors> {
+        parse(input.as_bytes())
+    }
+
+    /// The tree of the program without the ranges.
+    fn structure(input: &str) -> Program {
+        let mut program = parse_str(input).unwrap();
+        forget_ranges(&mut program);
+        program
+    }
+
+    fn name(text: &str) -> Name {
+        Name::new(text)
+    }
+
+    fn variable(text: &str) -> Variable {
+        Variable { name: name(text), selectors: Vec::new() }
+    }
+
+    fn number(n: &str) -> Box<Expression> {
+        Box::new(Expression::new(ExpressionKind::Number(n.to_string())))
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let program = structure(
+            "program p; var a: array [1..2] of integer; \
+            begin a[1] := -(2 + x) * 3; write(a[1]); writeln end."
+        );
+
+        assert_eq!(program.block.variables, vec![VariableDeclaration {
+            names: vec![name("a")],
+            type_: TypeExpression::Array {
+                packed: false,
+                indices: vec![TypeExpression::Subrange(
+                    number("1"),
+                    number("2")
+                )],
+                element: Box::new(TypeExpression::Named(name("integer"))),
+            },
+        }]);
+
+        let element = Variable {
+            name: name("a"),
+            selectors: vec![Selector::Index(vec![*number("1")])],
+        };
+        let sum = ExpressionKind::Binary {
+            operator: Operator::Plus,
+            left: number("2"),
+            right: Box::new(Expression::new(
+                ExpressionKind::Variable(variable("x"))
+            )),
+        };
+        let product = ExpressionKind::Binary {
+            operator: Operator::Multiply,
+            left: Box::new(Expression::new(ExpressionKind::Parenthesized(
+                Box::new(Expression::new(sum))
+            ))),
+            right: number("3"),
+        };
+        assert_eq!(program.block.statements, vec![
+            Statement::new(StatementKind::Assignment {
+                target: element.clone(),
+                value: Expression::new(ExpressionKind::Unary {
+                    operator: Operator::Minus,
+                    operand: Box::new(Expression::new(product)),
+                }),
+            }),
+            Statement::new(StatementKind::Call {
+                name: name("write"),
+                arguments: vec![Expression::new(
+                    ExpressionKind::Variable(element)
+                )],
+            }),
+            Statement::new(StatementKind::Call {
+                name: name("writeln"),
+                arguments: Vec::new(),
+            }),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_subrange_of_constants() {
+        let program = structure(
+            "program p; type small = low..high; letters = 'a'..'z'; \
+            begin end."
+        );
+
+        let constant = |text: &str| Box::new(Expression::new(
+            ExpressionKind::Variable(variable(text))
+        ));
+        let literal = |v: &str| Box::new(Expression::new(
+            ExpressionKind::Literal(v.to_string())
+        ));
+        assert_eq!(program.block.types, vec![
+            TypeDefinition {
+                name: name("small"),
+                type_: TypeExpression::Subrange(
+                    constant("low"),
+                    constant("high")
+                ),
+            },
+            TypeDefinition {
+                name: name("letters"),
+                type_: TypeExpression::Subrange(literal("a"), literal("z")),
+            },
+        ]);
+        assert_eq!(
+            program.block.statements,
+            vec![Statement::new(StatementKind::Empty)]
+        );
+    }
+
+    #[test]
+    fn test_parse_keeps_spelling_and_ranges() {
+        let input = "program Lit; var Word: char; \
+            begin Word := 'HeLLo'; WriteLn(-1E3 + Word) end.";
+        let program = parse_str(input).unwrap();
+        let text = |range: &Range<usize>| &input[range.clone()];
+
+        assert_eq!(program.name.text, "Lit");
+        assert_eq!(text(&program.name.range), "Lit");
+        let statements = &program.block.statements;
+        assert_eq!(text(&statements[0].range), "Word := 'HeLLo'");
+        let value = match &statements[0].kind {
+            StatementKind::Assignment { target, value } => {
+                assert_eq!(target.name.text, "Word");
+                assert_eq!(text(&target.name.range), "Word");
+                value
+            },
+            kind => panic!("{:?}", kind),
+        };
+        assert_eq!(value.kind, ExpressionKind::Literal("HeLLo".to_string()));
+        assert_eq!(text(&value.range), "'HeLLo'");
+
+        assert_eq!(text(&statements[1].range), "WriteLn(-1E3 + Word)");
+        let argument = match &statements[1].kind {
+            StatementKind::Call { name, arguments } => {
+                assert_eq!(name.text, "WriteLn");
+                &arguments[0]
+            },
+            kind => panic!("{:?}", kind),
+        };
+        assert_eq!(text(&argument.range), "-1E3 + Word");
+        match &argument.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                assert_eq!(text(&left.range), "-1E3");
+                assert_eq!(text(&right.range), "Word");
+            },
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parse_empty_statement_range() {
+        let input = "program p; begin a := (1); end.";
+        let program = parse_str(input).unwrap();
+
+        let statements = &program.block.statements;
+        assert_eq!(&input[statements[0].range.clone()], "a := (1)");
+        assert_eq!(statements[1].range, 27..27);
+        assert!(statements[1].is_empty());
+    }
+
+    #[test]
+    fn test_parse_stops_at_first_error() {
+        let errors = parse_str(
+            "program p; begin a := 1 b := 2; c = 3 end."
+        ).unwrap_err();
+
+        assert_eq!(errors.count(), 1);
+        let err = errors.get(0).unwrap();
+        assert_eq!(err.kind(), CompilationErrorKind::SyntaxError);
+        assert_eq!(err.msg(), "expected K(End), found Id(\"b\")");
+    }
+
+    #[test]
+    fn test_parse_reports_lexical_errors() {
+        let errors = parse_str("program p; begin a := 1 # end.")
+            .unwrap_err();
+
+        assert_eq!(errors.count(), 1);
+        let err = errors.get(0).unwrap();
+        assert_eq!(err.kind(), CompilationErrorKind::LexicalError);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_parse_serde() {
+        let program = parse_str(
+            "program p; type r = record x: real end; var v: ^r; \
+            begin if v <> nil then v^.x := 1.5 end."
+        ).unwrap();
+
+        let json = serde_json::to_value(&program).unwrap();
+        assert_eq!(json["name"]["text"], "p");
+        assert_eq!(json["name"]["range"]["start"], 8);
+        assert_eq!(json["block"]["types"][0]["name"]["text"], "r");
+        let back: Program = serde_json::from_value(json).unwrap();
+        assert_eq!(back, program);
+    }
+}
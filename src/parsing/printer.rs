@@ -0,0 +1,679 @@
+use crate::{
+    formatting::FormatOptions,
+    tokenization::{Operator, Relation},
+};
+use super::ast::{
+    Block,
+    CaseLabel,
+    Direction,
+    Expression,
+    ExpressionKind,
+    Name,
+    Program,
+    Selector,
+    Statement,
+    StatementKind,
+    TypeExpression,
+    Variable,
+    VariableDeclaration,
+};
+
+/// Prints the source of a program laid out the way `rupc fmt` lays
+/// programs out: one statement per line, the statements of a header
+/// indented a level deeper and `begin` on the line of the header.
+/// Brackets are added where the tree has no `Parenthesized` node but
+/// the operator precedence demands them, so the printed program
+/// parses back into the same tree when the tree came from the parser,
+/// except for the ranges. The names are printed as they are spelled.
+pub fn print(program: &Program, options: &FormatOptions) -> String {
+    let mut printer = Printer { options, out: String::new(), level: 0 };
+    printer.program(program);
+    printer.out
+}
+
+struct Printer<'a> {
+    options: &'a FormatOptions,
+    out: String,
+    /// The level of the current line
+    level: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn program(&mut self, program: &Program) {
+        self.word("program");
+        self.out += &format!(" {};", program.name.text);
+        self.block(&program.block);
+        self.out += ".\n";
+    }
+
+    fn block(&mut self, block: &Block) {
+        if !block.types.is_empty() {
+            self.new_line();
+            self.word("type");
+            self.level += 1;
+            for definition in &block.types {
+                self.new_line();
+                let type_ = self.type_(&definition.type_);
+                self.out += &format!(
+                    "{} = {};",
+                    definition.name.text,
+                    type_
+                );
+            }
+            self.level -= 1;
+        }
+
+        if !block.variables.is_empty() {
+            self.new_line();
+            self.word("var");
+            self.level += 1;
+            for declaration in &block.variables {
+                self.new_line();
+                self.out += &self.declaration(declaration);
+                self.out.push(';');
+            }
+            self.level -= 1;
+        }
+
+        self.new_line();
+        self.compound(&block.statements);
+    }
+
+    /// Prints `begin`, the statements a level deeper and `end`
+    /// on the level of the current line.
+    fn compound(&mut self, statements: &[Statement]) {
+        self.word("begin");
+        self.level += 1;
+        self.sequence(statements);
+        self.level -= 1;
+        self.new_line();
+        self.word("end");
+    }
+
+    /// Prints the statements on their own lines, separated with
+    /// semicolons. An empty statement gets a line of its own unless
+    /// it is the last one, which the closing keyword stands for.
+    fn sequence(&mut self, statements: &[Statement]) {
+        for (i, statement) in statements.iter().enumerate() {
+            let last = i + 1 == statements.len();
+            if last && statement.is_empty() {
+                break;
+            }
+
+            self.new_line();
+            self.statement(statement);
+            if !last {
+                self.out.push(';');
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Empty => (),
+            StatementKind::Assignment { target, value } => {
+                self.out += &format!(
+                    "{} := {}",
+                    self.variable(target),
+                    self.expression(value)
+                );
+            },
+            StatementKind::Call { name, arguments } => {
+                self.out += &name.text;
+                if !arguments.is_empty() {
+                    self.out += &self.arguments(arguments);
+                }
+            },
+            StatementKind::Compound(statements) => self.compound(statements),
+            StatementKind::If { condition, then, else_ } => {
+                self.word("if");
+                self.out += &format!(" {} ", self.expression(condition));
+                self.word("then");
+                match else_ {
+                    // The else part would go to the inner if otherwise
+                    Some(_) if dangles(then) => {
+                        self.out.push(' ');
+                        self.compound(std::slice::from_ref(then));
+                    },
+                    _ => self.body(then),
+                }
+
+                if let Some(else_) = else_ {
+                    self.new_line();
+                    self.word("else");
+                    if let StatementKind::If { .. } = else_.kind {
+                        // An else if chain stays on the level of its if
+                        self.out.push(' ');
+                        self.statement(else_);
+                    } else {
+                        self.body(else_);
+                    }
+                }
+            },
+            StatementKind::Case { selector, elements, else_ } => {
+                self.word("case");
+                self.out += &format!(" {} ", self.expression(selector));
+                self.word("of");
+                self.level += 1;
+                for (i, element) in elements.iter().enumerate() {
+                    self.new_line();
+                    let labels: Vec<_> = element.labels.iter()
+                        .map(|l| self.case_label(l))
+                        .collect();
+                    self.out += &labels.join(", ");
+                    self.out.push(':');
+                    if !element.statement.is_empty() {
+                        self.out.push(' ');
+                        self.statement(&element.statement);
+                    }
+                    // Before else, so that it is not taken for the else
+                    // part of an if statement
+                    if i + 1 < elements.len() || else_.is_some() {
+                        self.out.push(';');
+                    }
+                }
+                self.level -= 1;
+
+                if let Some(statements) = else_ {
+                    self.new_line();
+                    self.word("else");
+                    self.level += 1;
+                    self.sequence(statements);
+                    self.level -= 1;
+                }
+                self.new_line();
+                self.word("end");
+            },
+            StatementKind::While { condition, body } => {
+                self.word("while");
+                self.out += &format!(" {} ", self.expression(condition));
+                self.word("do");
+                self.body(body);
+            },
+            StatementKind::Repeat { body, condition } => {
+                self.word("repeat");
+                self.level += 1;
+                self.sequence(body);
+                self.level -= 1;
+                self.new_line();
+                self.word("until");
+                self.out += &format!(" {}", self.expression(condition));
+            },
+            StatementKind::For {
+                variable, initial, direction, final_, body
+            } => {
+                self.word("for");
+                self.out += &format!(
+                    " {} := {} ",
+                    variable.text,
+                    self.expression(initial)
+                );
+                self.word(match direction {
+                    Direction::To => "to",
+                    Direction::Downto => "downto",
+                });
+                self.out += &format!(" {} ", self.expression(final_));
+                self.word("do");
+                self.body(body);
+            },
+            StatementKind::With { records, body } => {
+                self.word("with");
+                let records: Vec<_> = records.iter()
+                    .map(|r| self.variable(r))
+                    .collect();
+                self.out += &format!(" {} ", records.join(", "));
+                self.word("do");
+                self.body(body);
+            },
+        }
+    }
+
+    /// Prints the statement of a `then`, `else` or `do` header:
+    /// a compound statement starts on the line of the header
+    /// and any other statement on the next line, a level deeper.
+    fn body(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Empty => (),
+            StatementKind::Compound(statements) => {
+                self.out.push(' ');
+                self.compound(statements);
+            },
+            _ => {
+                self.level += 1;
+                self.new_line();
+                self.statement(statement);
+                self.level -= 1;
+            }
+        }
+    }
+
+    fn case_label(&self, label: &CaseLabel) -> String {
+        match &label.high {
+            Some(high) => format!(
+                "{}..{}",
+                self.expression(&label.low),
+                self.expression(high)
+            ),
+            None => self.expression(&label.low),
+        }
+    }
+
+    fn declaration(&self, declaration: &VariableDeclaration) -> String {
+        format!(
+            "{}: {}",
+            names(&declaration.names),
+            self.type_(&declaration.type_)
+        )
+    }
+
+    fn type_(&self, type_: &TypeExpression) -> String {
+        match type_ {
+            TypeExpression::Named(name) => name.text.clone(),
+            TypeExpression::Enumeration(names) => {
+                format!("({})", self::names(names))
+            },
+            TypeExpression::Subrange(low, high) => format!(
+                "{}..{}",
+                self.expression(low),
+                self.expression(high)
+            ),
+            TypeExpression::Array { packed, indices, element } => {
+                let indices: Vec<_> = indices.iter()
+                    .map(|i| self.type_(i))
+                    .collect();
+                format!(
+                    "{}{} [{}] {} {}",
+                    self.packed(*packed),
+                    self.keyword("array"),
+                    indices.join(", "),
+                    self.keyword("of"),
+                    self.type_(element)
+                )
+            },
+            TypeExpression::Record { packed, fields } => {
+                let mut record = self.packed(*packed);
+                record += &self.keyword("record");
+                let fields: Vec<_> = fields.iter()
+                    .map(|f| self.declaration(f))
+                    .collect();
+                if !fields.is_empty() {
+                    record += &format!(" {}", fields.join("; "));
+                }
+                record + " " + &self.keyword("end")
+            },
+            TypeExpression::Pointer(name) => format!("^{}", name.text),
+        }
+    }
+
+    fn packed(&self, packed: bool) -> String {
+        if packed {
+            self.keyword("packed") + " "
+        } else {
+            String::new()
+        }
+    }
+
+    fn variable(&self, variable: &Variable) -> String {
+        let mut text = variable.name.text.clone();
+        for selector in &variable.selectors {
+            match selector {
+                Selector::Index(indices) => {
+                    let indices: Vec<_> = indices.iter()
+                        .map(|i| self.expression(i))
+                        .collect();
+                    text += &format!("[{}]", indices.join(", "));
+                },
+                Selector::Field(field) => {
+                    text += &format!(".{}", field.text);
+                },
+                Selector::Dereference => text.push('^'),
+            }
+        }
+
+        text
+    }
+
+    fn arguments(&self, arguments: &[Expression]) -> String {
+        let arguments: Vec<_> = arguments.iter()
+            .map(|a| self.expression(a))
+            .collect();
+        format!("({})", arguments.join(", "))
+    }
+
+    fn expression(&self, expression: &Expression) -> String {
+        match &expression.kind {
+            ExpressionKind::Variable(variable) => self.variable(variable),
+            ExpressionKind::Number(number) => number.clone(),
+            ExpressionKind::Literal(value) => format!("'{}'", value),
+            ExpressionKind::Nil => self.keyword("nil"),
+            ExpressionKind::Call { name, arguments } => {
+                format!("{}{}", name.text, self.arguments(arguments))
+            },
+            ExpressionKind::Unary { operator, operand } => {
+                // A sign applies to a term and not to a factor
+                let binds = match operator {
+                    Operator::Not => 3,
+                    _ => 2,
+                };
+                let operand = self.operand(operand, binds);
+                match operator {
+                    Operator::Not => {
+                        format!("{} {}", self.keyword("not"), operand)
+                    },
+                    _ => format!("{}{}", self.operator(*operator), operand),
+                }
+            },
+            ExpressionKind::Binary { operator, left, right } => {
+                let precedence = expression.precedence();
+                format!(
+                    "{} {} {}",
+                    self.operand(left, precedence),
+                    self.operator(*operator),
+                    self.operand(right, precedence + 1)
+                )
+            },
+            ExpressionKind::Relation { relation, left, right } => format!(
+                "{} {} {}",
+                self.operand(left, 1),
+                relation_text(*relation),
+                self.operand(right, 1)
+            ),
+            ExpressionKind::Parenthesized(inner) => {
+                format!("({})", self.expression(inner))
+            },
+        }
+    }
+
+    /// Prints the operand in brackets if it binds less tightly
+    /// than `precedence`.
+    fn operand(&self, operand: &Expression, precedence: u8) -> String {
+        if operand.precedence() < precedence {
+            format!("({})", self.expression(operand))
+        } else {
+            self.expression(operand)
+        }
+    }
+
+    fn operator(&self, operator: Operator) -> String {
+        match operator {
+            Operator::Plus => "+".to_string(),
+            Operator::Minus => "-".to_string(),
+            Operator::Multiply => "*".to_string(),
+            Operator::Divide => "/".to_string(),
+            Operator::IntegerDivide => self.keyword("div"),
+            Operator::Modulus => self.keyword("mod"),
+            Operator::And => self.keyword("and"),
+            Operator::Or => self.keyword("or"),
+            Operator::Xor => self.keyword("xor"),
+            Operator::Not => self.keyword("not"),
+            Operator::Assign => ":=".to_string(),
+        }
+    }
+
+    fn keyword(&self, word: &str) -> String {
+        self.options.keyword_case.apply(word)
+    }
+
+    fn word(&mut self, word: &str) {
+        self.out += &self.keyword(word);
+    }
+
+    fn new_line(&mut self) {
+        self.out.push('\n');
+        self.out += &" ".repeat(self.level * self.options.indent);
+    }
+}
+
+fn names(names: &[Name]) -> String {
+    let names: Vec<_> = names.iter().map(|n| n.text.as_str()).collect();
+    names.join(", ")
+}
+
+fn relation_text(relation: Relation) -> &'static str {
+    match relation {
+        Relation::Eq => "=",
+        Relation::Ne => "<>",
+        Relation::Gt => ">",
+        Relation::Lt => "<",
+        Relation::Ge => ">=",
+        Relation::Le => "<=",
+    }
+}
+
+/// Whether the statement ends with an if statement without
+/// an else part, which would take an else written after it.
+fn dangles(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::If { else_: None, .. } => true,
+        StatementKind::If { else_: Some(s), .. } => dangles(s),
+        StatementKind::While { body, .. }
+        | StatementKind::For { body, .. }
+        | StatementKind::With { body, .. } => dangles(body),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod printer_tests {
+    use super::*;
+    use crate::{
+        formatting::KeywordCase,
+        parsing::{parser::parse, visit::forget_ranges},
+    };
+
+    fn parse_str(input: &str) -> Program {
+        parse(input.as_bytes()).unwrap()
+    }
+
+    fn assert_round_trip(input: &str) -> String {
+        let mut program = parse_str(input);
+        let printed = print(&program, &FormatOptions::default());
+        let mut reparsed = parse_str(&printed);
+        forget_ranges(&mut program);
+        forget_ranges(&mut reparsed);
+        assert_eq!(reparsed, program, "{}", printed);
+        printed
+    }
+
+    #[test]
+    fn test_print_program() {
+        let input = "PROGRAM Shapes; TYPE color = (red, green); \
+            link = ^node; node = packed record value: integer; \
+            next: link end; grid = array [1..3, color] of real; \
+            VAR a, b: integer; g: grid; n: link; \
+            BEGIN a := -1 + 2 * (b - 3); \
+            IF a > 0 THEN BEGIN writeln_int(a); a := 0 END \
+            ELSE IF a < 0 THEN a := 1 ELSE; \
+            CASE a OF 1, 2..3: b := 0; -1: ELSE b := 1; a := 2 END; \
+            while not (a >= b) do a := a + 1; \
+            repeat a := a - 1; until a = 0; \
+            for a := 1 to 10 do g[a, red] := 0.5; \
+            with n^ do next := nil \
+            END.";
+        let expected = "program Shapes;\n\
+            type\n  \
+              color = (red, green);\n  \
+              link = ^node;\n  \
+              node = packed record value: integer; next: link end;\n  \
+              grid = array [1..3, color] of real;\n\
+            var\n  \
+              a, b: integer;\n  \
+              g: grid;\n  \
+              n: link;\n\
+            begin\n  \
+              a := -1 + 2 * (b - 3);\n  \
+              if a > 0 then begin\n    \
+                writeln_int(a);\n    \
+                a := 0\n  \
+              end\n  \
+              else if a < 0 then\n    \
+                a := 1\n  \
+              else;\n  \
+              case a of\n    \
+                1, 2..3: b := 0;\n    \
+                -1:;\n  \
+              else\n    \
+                b := 1;\n    \
+                a := 2\n  \
+              end;\n  \
+              while not (a >= b) do\n    \
+                a := a + 1;\n  \
+              repeat\n    \
+                a := a - 1;\n  \
+              until a = 0;\n  \
+              for a := 1 to 10 do\n    \
+                g[a, red] := 0.5;\n  \
+              with n^ do\n    \
+                next := nil\n\
+            end.\n";
+
+        assert_eq!(assert_round_trip(input), expected);
+    }
+
+    #[test]
+    fn test_print_round_trips_correct_programs() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data/correct");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "pas") {
+                let source = std::fs::read_to_string(&path).unwrap();
+                let printed = assert_round_trip(&source);
+                assert_eq!(assert_round_trip(&printed), printed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_keeps_spelling() {
+        let printed = assert_round_trip(
+            "program Lit; var Word: char; \
+            begin Word := 'HeLLo'; WriteLn(Word, 1E3) end."
+        );
+
+        assert_eq!(
+            printed,
+            "program Lit;\n\
+            var\n  \
+              Word: char;\n\
+            begin\n  \
+              Word := 'HeLLo';\n  \
+              WriteLn(Word, 1E3)\n\
+            end.\n"
+        );
+    }
+
+    #[test]
+    fn test_print_adds_brackets() {
+        let number = |n: &str| Box::new(Expression::new(
+            ExpressionKind::Number(n.to_string())
+        ));
+        let sum = Expression::new(ExpressionKind::Binary {
+            operator: Operator::Plus,
+            left: number("1"),
+            right: number("2"),
+        });
+        let program = Program {
+            name: Name::new("p"),
+            block: Block {
+                types: Vec::new(),
+                variables: Vec::new(),
+                statements: vec![Statement::new(StatementKind::Call {
+                    name: Name::new("writeln_int"),
+                    arguments: vec![
+                        Expression::new(ExpressionKind::Binary {
+                            operator: Operator::Multiply,
+                            left: Box::new(sum.clone()),
+                            right: number("3"),
+                        }),
+                        Expression::new(ExpressionKind::Binary {
+                            operator: Operator::Minus,
+                            left: number("4"),
+                            right: Box::new(sum.clone()),
+                        }),
+                        Expression::new(ExpressionKind::Unary {
+                            operator: Operator::Minus,
+                            operand: Box::new(sum.clone()),
+                        }),
+                    ],
+                })],
+            },
+        };
+
+        let printed = print(&program, &FormatOptions::default());
+        assert_eq!(
+            printed,
+            "program p;\n\
+            begin\n  \
+              writeln_int((1 + 2) * 3, 4 - (1 + 2), -(1 + 2))\n\
+            end.\n"
+        );
+    }
+
+    #[test]
+    fn test_print_dangling_else() {
+        let condition = |name: &str| Expression::new(
+            ExpressionKind::Variable(Variable {
+                name: Name::new(name),
+                selectors: Vec::new(),
+            })
+        );
+        let call = Statement::new(StatementKind::Call {
+            name: Name::new("x"),
+            arguments: Vec::new(),
+        });
+        let program = Program {
+            name: Name::new("p"),
+            block: Block {
+                types: Vec::new(),
+                variables: Vec::new(),
+                statements: vec![Statement::new(StatementKind::If {
+                    condition: condition("a"),
+                    then: Box::new(Statement::new(StatementKind::If {
+                        condition: condition("b"),
+                        then: Box::new(call.clone()),
+                        else_: None,
+                    })),
+                    else_: Some(Box::new(call)),
+                })],
+            },
+        };
+
+        let printed = print(&program, &FormatOptions::default());
+        assert_eq!(
+            printed,
+            "program p;\n\
+            begin\n  \
+              if a then begin\n    \
+                if b then\n      \
+                  x\n  \
+              end\n  \
+              else\n    \
+                x\n\
+            end.\n"
+        );
+    }
+
+    #[test]
+    fn test_print_with_options() {
+        let program = parse_str(
+            "program p; var a: integer; begin \
+            while a > 0 do a := a div 2 end."
+        );
+        let options = FormatOptions {
+            indent: 4,
+            keyword_case: KeywordCase::Upper,
+        };
+
+        assert_eq!(
+            print(&program, &options),
+            "PROGRAM p;\n\
+            VAR\n    \
+                a: integer;\n\
+            BEGIN\n    \
+                WHILE a > 0 DO\n        \
+                    a := a DIV 2\n\
+            END.\n"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use crate::tokenization::{Keyword, Operator, Punctuation, Token};
+
+/// A set of tokens at which the parser may resume after a syntax error.
+/// An identifier, number or literal with an empty value
+/// stands for any token of that kind.
+pub type TokenSet = &'static [Token];
+
+pub fn contains(set: &[Token], token: &Token) -> bool {
+    set.iter().any(|t| match (t, token) {
+        (Token::Id(v), Token::Id(_))
+        | (Token::Number(v), Token::Number(_))
+        | (Token::Literal(v), Token::Literal(_)) if v.is_empty() => true,
+        _ => t == token
+    })
+}
+
+// FOLLOW(<program heading>) = FIRST(<block>)
+pub const FOLLOW_PROGRAM_HEADING: TokenSet = &[
+    Token::K(Keyword::Type),
+    Token::K(Keyword::Var),
+    Token::K(Keyword::Begin),
+];
+
+pub const FOLLOW_BLOCK: TokenSet = &[
+    Token::P(Punctuation::Dot),
+    Token::EOF,
+];
+
+pub const FOLLOW_PROGRAM: TokenSet = &[Token::EOF];
+
+pub const FOLLOW_TYPE_DEFINITION_PART: TokenSet = &[
+    Token::K(Keyword::Var),
+    Token::K(Keyword::Begin),
+];
+
+pub const FOLLOW_VARIABLE_DECLARATION_PART: TokenSet = &[
+    Token::K(Keyword::Begin),
+];
+
+pub const FOLLOW_IDENTIFIER_LIST: TokenSet = &[Token::P(Punctuation::Colon)];
+
+pub const FOLLOW_FIELD_LIST: TokenSet = &[Token::K(Keyword::End)];
+
+// FOLLOW(<statement>) inside a compound statement
+pub const FOLLOW_COMPOUND_STATEMENT_PART: TokenSet = &[
+    Token::P(Punctuation::Semicolon),
+    Token::K(Keyword::End),
+];
+
+// FOLLOW(<statement>) inside a repeat statement
+pub const FOLLOW_REPEAT_STATEMENT_PART: TokenSet = &[
+    Token::P(Punctuation::Semicolon),
+    Token::K(Keyword::Until),
+];
+
+pub const FOLLOW_IF_CONDITION: TokenSet = &[Token::K(Keyword::Then)];
+
+pub const FOLLOW_WHILE_CONDITION: TokenSet = &[Token::K(Keyword::Do)];
+
+pub const FOLLOW_CONTROL_VARIABLE: TokenSet = &[Token::O(Operator::Assign)];
+
+pub const FOLLOW_FOR_LIST: TokenSet = &[Token::K(Keyword::Do)];
+
+pub const FOLLOW_CASE_SELECTOR: TokenSet = &[Token::K(Keyword::Of)];
+
+// FOLLOW(<statement>) inside the else part of a case statement
+pub const FOLLOW_CASE_ELSE_PART: TokenSet = &[
+    Token::P(Punctuation::Semicolon),
+    Token::K(Keyword::End),
+];
+
+pub const FIRST_EXPRESSION: TokenSet = &[
+    Token::Id(String::new()),
+    Token::Number(String::new()),
+    Token::Literal(String::new()),
+    Token::P(Punctuation::Lbracket),
+    Token::O(Operator::Plus),
+    Token::O(Operator::Minus),
+    Token::O(Operator::Not),
+    Token::K(Keyword::Nil),
+];
+
+// Includes the tokens following an empty statement
+pub const FIRST_STATEMENT: TokenSet = &[
+    Token::Id(String::new()),
+    Token::K(Keyword::Begin),
+    Token::K(Keyword::If),
+    Token::K(Keyword::Case),
+    Token::K(Keyword::While),
+    Token::K(Keyword::Repeat),
+    Token::K(Keyword::For),
+    Token::K(Keyword::With),
+    Token::P(Punctuation::Semicolon),
+    Token::K(Keyword::End),
+    Token::K(Keyword::Else),
+];
@@ -0,0 +1,212 @@
+use std::{error::Error, fmt::Display, ops::Range};
+
+use crate::tokenization::{SimpleBuffer, Token, TokenStream};
+use super::resolution::{analyze, DefUseMap};
+
+/// A replacement of a piece of source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// The byte offsets of the text to replace
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameError {
+    /// There is no resolved identifier at the offset
+    NoIdentifier,
+    /// The identifier is predefined rather than declared in the program
+    Predefined(String),
+    InvalidName(String),
+    /// The new name would change what some identifiers refer to
+    Collision { old: String, new: String },
+}
+
+impl Error for RenameError {}
+
+impl Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RenameError::NoIdentifier => {
+                write!(f, "there is no identifier to rename")
+            },
+            RenameError::Predefined(name) => {
+                write!(f, "\"{}\" is predefined and cannot be renamed", name)
+            },
+            RenameError::InvalidName(name) => {
+                write!(f, "\"{}\" is not a valid identifier", name)
+            },
+            RenameError::Collision { old, new } => write!(
+                f,
+                "renaming \"{}\" to \"{}\" would clash with another \"{}\"",
+                old, new, new
+            ),
+        }
+    }
+}
+
+/// Computes the edits that rename the identifier at the byte offset,
+/// its declaration and all its uses. Refuses when under the new name
+/// any identifier of the program would refer to something else,
+/// shadowing or being shadowed by another declaration.
+/// The edits are in source order.
+pub fn rename(
+    data: &[u8],
+    offset: usize,
+    new_name: &str
+) -> Result<Vec<TextEdit>, RenameError> {
+    if !is_identifier(new_name) {
+        return Err(RenameError::InvalidName(new_name.to_string()));
+    }
+
+    let (map, errors) = analyze(data);
+    let id = map.at(offset).ok_or(RenameError::NoIdentifier)?;
+    let declaration = map.declaration(id)
+        .ok_or_else(|| RenameError::Predefined(id.name.clone()))?;
+
+    let mut edits: Vec<_> = std::iter::once(declaration)
+        .chain(map.uses(declaration))
+        .map(|r| TextEdit {
+            range: r.range.clone(),
+            text: new_name.to_string(),
+        })
+        .collect();
+    edits.sort_by_key(|e| e.range.start);
+
+    // Every identifier must keep referring to the same declaration
+    let (renamed, renamed_errors) = analyze(&apply_edits(data, &edits));
+    let shift = |offset: usize| shift(&edits, offset);
+    let expected: Vec<_> = links(&map).into_iter()
+        .map(|(at, to)| (shift(at), to.map(shift)))
+        .collect();
+    if links(&renamed) != expected || renamed_errors > errors {
+        return Err(RenameError::Collision {
+            old: declaration.name.clone(),
+            new: new_name.to_string(),
+        });
+    }
+
+    Ok(edits)
+}
+
+/// Applies the edits, which must be in source order and not overlap.
+pub fn apply_edits(data: &[u8], edits: &[TextEdit]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut copied = 0;
+    for edit in edits {
+        out.extend_from_slice(&data[copied..edit.range.start]);
+        out.extend_from_slice(edit.text.as_bytes());
+        copied = edit.range.end;
+    }
+    out.extend_from_slice(&data[copied..]);
+    out
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut ts = TokenStream::new(SimpleBuffer::new(name.as_bytes(), None));
+    matches!(ts.next(), Ok(Token::Id(_)))
+        && matches!(ts.next(), Ok(Token::EOF))
+        && ts.take_errors().is_empty()
+}
+
+/// Where the identifiers start and where their declarations start.
+fn links(map: &DefUseMap) -> Vec<(usize, Option<usize>)> {
+    map.identifiers().iter()
+        .map(|r| (r.range.start, r.declaration.as_ref().map(|d| d.start)))
+        .collect()
+}
+
+/// Where the text at the offset moves once the edits are applied.
+fn shift(edits: &[TextEdit], offset: usize) -> usize {
+    edits.iter()
+        .take_while(|e| e.range.end <= offset)
+        .fold(offset, |offset, e| offset - e.range.len() + e.text.len())
+}
+
+#[cfg(test)]
+mod rename_tests {
+    use super::*;
+
+    fn rename_at(
+        input: &str,
+        at: &str,
+        new_name: &str
+    ) -> Result<String, RenameError> {
+        let offset = input.find(at).unwrap();
+        let edits = rename(input.as_bytes(), offset, new_name)?;
+        Ok(String::from_utf8(apply_edits(input.as_bytes(), &edits)).unwrap())
+    }
+
+    #[test]
+    fn test_rename() {
+        let input = "program P;\n\
+            type point = record x, y: integer end;\n\
+            var a: point; b: integer;\n\
+            begin\n  \
+              a.x := 1;\n  \
+              with a do x := y\n\
+            end.";
+
+        assert_eq!(
+            rename_at(input, "a.x", "origin").unwrap(),
+            input.replace("a.x", "origin.x").replace("a:", "origin:")
+                .replace("with a", "with origin")
+        );
+        assert_eq!(
+            rename_at(input, "x, y", "Left").unwrap(),
+            input.replace("x,", "Left,").replace("a.x", "a.Left")
+                .replace("x :=", "Left :=")
+        );
+        assert_eq!(
+            rename_at(input, "point;", "pt").unwrap(),
+            input.replace("point", "pt")
+        );
+    }
+
+    #[test]
+    fn test_rename_collisions() {
+        let input = "program P;\n\
+            type point = record x, y: integer end;\n\
+            var a: point; b: integer;\n\
+            begin\n  \
+              b := 1;\n  \
+              with a do x := b\n\
+            end.";
+
+        let collision = |old: &str, new: &str| Err(RenameError::Collision {
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+        // Redeclared in the same scope
+        assert_eq!(rename_at(input, "b :=", "a"), collision("b", "a"));
+        // Shadowed by a field in the with statement
+        assert_eq!(rename_at(input, "b :=", "y"), collision("b", "y"));
+        // Shadowing a predefined type used later
+        assert_eq!(
+            rename_at(input, "a:", "integer"),
+            collision("a", "integer")
+        );
+        assert!(rename_at(input, "b :=", "integer").is_ok());
+        assert!(rename_at(input, "b :=", "c").is_ok());
+    }
+
+    #[test]
+    fn test_rename_refused() {
+        let input = "program P; var a: integer; begin a := 1 end.";
+
+        assert_eq!(
+            rename_at(input, "integer", "int"),
+            Err(RenameError::Predefined("integer".to_string()))
+        );
+        assert_eq!(
+            rename_at(input, "begin", "b"),
+            Err(RenameError::NoIdentifier)
+        );
+        for name in ["end", "1a", "a b", ""] {
+            assert_eq!(
+                rename_at(input, "a :=", name),
+                Err(RenameError::InvalidName(name.to_string()))
+            );
+        }
+    }
+}
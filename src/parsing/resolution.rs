@@ -0,0 +1,186 @@
+use std::{cell::RefCell, iter::FromIterator, ops::Range, rc::Rc};
+
+use crate::{
+    semantics::Type,
+    tokenization::{SimpleBuffer, TokenStream},
+};
+use super::{code::Code, highlighting::SemanticKind};
+
+/// An identifier that `Code` has declared or resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub name: String,
+    /// The byte offsets of the identifier in the source
+    pub range: Range<usize>,
+    /// The byte offsets of the whole declaration,
+    /// the identifier itself for the uses
+    pub extent: Range<usize>,
+    pub kind: SemanticKind,
+    /// The byte offsets of the identifier that declares this one,
+    /// none for predefined identifiers
+    pub declaration: Option<Range<usize>>,
+    /// The type of the variable, constant, field or type named
+    pub type_: Option<Type>,
+}
+
+impl Resolution {
+    /// Whether the identifier is declared here rather than used.
+    pub fn is_declaration(&self) -> bool {
+        self.declaration.as_ref() == Some(&self.range)
+    }
+}
+
+/// Where the identifiers of a program are declared and used
+#[derive(Debug, Clone, Default)]
+pub struct DefUseMap {
+    /// In source order
+    identifiers: Vec<Resolution>,
+}
+
+impl DefUseMap {
+    /// The declarations and uses of all the identifiers, in source order.
+    pub fn identifiers(&self) -> &[Resolution] {
+        &self.identifiers
+    }
+
+    /// The declarations, in source order.
+    pub fn declarations(&self) -> impl Iterator<Item=&Resolution> {
+        self.identifiers.iter().filter(|r| r.is_declaration())
+    }
+
+    /// The identifier that covers the byte offset, or ends right at it.
+    pub fn at(&self, offset: usize) -> Option<&Resolution> {
+        let i = self.identifiers.partition_point(|r| r.range.end < offset);
+        self.identifiers.get(i).filter(|r| r.range.start <= offset)
+    }
+
+    /// The declaration of the identifier, none for
+    /// predefined and undeclared identifiers.
+    pub fn declaration(&self, of: &Resolution) -> Option<&Resolution> {
+        let range = of.declaration.as_ref()?;
+        let i = self.identifiers.partition_point(|r| r.range.start < range.start);
+        self.identifiers.get(i).filter(|r| r.range == *range)
+    }
+
+    /// The uses of the identifier declared by `declaration`,
+    /// in source order.
+    pub fn uses<'a>(
+        &'a self,
+        declaration: &'a Resolution
+    ) -> impl Iterator<Item=&'a Resolution> {
+        self.identifiers.iter().filter(move |r| {
+            !r.is_declaration() && r.declaration == declaration.declaration
+        })
+    }
+}
+
+impl FromIterator<Resolution> for DefUseMap {
+    fn from_iter<I: IntoIterator<Item=Resolution>>(iter: I) -> Self {
+        let mut identifiers: Vec<_> = iter.into_iter().collect();
+        // Declarations are reported once their types are known
+        identifiers.sort_by_key(|r| r.range.start);
+        identifiers.dedup_by_key(|r| r.range.start);
+        Self { identifiers }
+    }
+}
+
+/// Checks a program and maps the declarations of its
+/// identifiers to their uses. The identifiers that
+/// resolve to nothing are left out.
+pub fn def_use_map(data: &[u8]) -> DefUseMap {
+    analyze(data).0
+}
+
+/// Checks a program, mapping its identifiers and
+/// counting the errors found.
+pub(crate) fn analyze(data: &[u8]) -> (DefUseMap, usize) {
+    let resolutions = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&resolutions);
+    let ts = TokenStream::new(SimpleBuffer::new(data, None));
+    let code = Code::new(ts, Box::new(std::io::sink()))
+        .with_resolution_handler(move |r| sink.borrow_mut().push(r));
+    // Errors only leave some identifiers unresolved
+    let errors = match code.check() {
+        Ok(errors) => errors.error_count(),
+        Err(_) => usize::MAX,
+    };
+
+    let resolutions = resolutions.take();
+    (resolutions.into_iter().collect(), errors)
+}
+
+#[cfg(test)]
+mod resolution_tests {
+    use super::*;
+
+    const PROGRAM: &str = "program P;\n\
+        type\n  \
+          point = record x, y: integer end;\n  \
+          size = record x: integer end;\n  \
+          color = (red, green);\n\
+        var\n  \
+          p: point;\n  \
+          s: size;\n  \
+          c: color;\n\
+        begin\n  \
+          p.x := 1;\n  \
+          s.x := p.x;\n  \
+          c := red;\n  \
+          with p do y := x\n\
+        end.";
+
+    fn offset(text: &str, n: usize) -> usize {
+        PROGRAM.match_indices(text).nth(n).unwrap().0
+    }
+
+    /// Where the identifier at the offset is declared.
+    fn declaration_of(map: &DefUseMap, offset: usize) -> Option<usize> {
+        map.declaration(map.at(offset)?).map(|d| d.range.start)
+    }
+
+    #[test]
+    fn test_def_use_declarations() {
+        let map = def_use_map(PROGRAM.as_bytes());
+        let names: Vec<_> = map.declarations()
+            .map(|r| r.name.as_str())
+            .collect();
+
+        assert_eq!(
+            names,
+            ["p", "point", "x", "y", "size", "x", "color", "red", "green", "p", "s", "c"]
+        );
+    }
+
+    #[test]
+    fn test_def_use_links_uses() {
+        let map = def_use_map(PROGRAM.as_bytes());
+
+        let point_x = Some(offset("x, y", 0));
+        let size_x = Some(offset("x: integer", 0));
+        let with = offset("y := x", 0);
+
+        assert_eq!(declaration_of(&map, offset("p.x", 0)), Some(offset("p:", 0)));
+        // The fields of different records are told apart
+        assert_eq!(declaration_of(&map, offset("p.x", 0) + 2), point_x);
+        assert_eq!(declaration_of(&map, offset("s.x", 0) + 2), size_x);
+        assert_eq!(declaration_of(&map, offset("p.x", 1) + 2), point_x);
+        assert_eq!(declaration_of(&map, with), Some(offset("y:", 0)));
+        assert_eq!(declaration_of(&map, with + 5), point_x);
+        assert_eq!(declaration_of(&map, offset("red;", 0)), Some(offset("red", 0)));
+        assert_eq!(declaration_of(&map, offset("point;", 0)), Some(offset("point", 0)));
+        assert_eq!(declaration_of(&map, offset("integer", 0)), None);
+    }
+
+    #[test]
+    fn test_def_use_uses() {
+        let map = def_use_map(PROGRAM.as_bytes());
+        let p = map.at(offset("p:", 0)).unwrap();
+        assert!(p.is_declaration());
+
+        let uses: Vec<_> = map.uses(p).map(|r| r.range.start).collect();
+        assert_eq!(
+            uses,
+            [offset("p.x", 0), offset("p.x", 1), offset("p do", 0)]
+        );
+    }
+}
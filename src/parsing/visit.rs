@@ -0,0 +1,670 @@
+//! Traversal of the syntax tree. A `Visitor` gets every node of the
+//! tree in the order it is written in, and a `VisitorMut` also gets to
+//! change the nodes. Each method walks the children of its node by
+//! default, so a visitor overrides only the methods of the nodes it
+//! is interested in and calls the `walk_` function of the node to go
+//! on into its children. The names are visited too, as the leaves
+//! of the tree, wherever they are written.
+
+use super::ast::{
+    Block,
+    CaseElement,
+    CaseLabel,
+    Expression,
+    ExpressionKind,
+    Name,
+    Program,
+    Selector,
+    Statement,
+    StatementKind,
+    TypeDefinition,
+    TypeExpression,
+    Variable,
+    VariableDeclaration,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_type_definition(&mut self, definition: &TypeDefinition) {
+        walk_type_definition(self, definition);
+    }
+
+    /// Visits the declaration of variables or of the fields of a record.
+    fn visit_variable_declaration(
+        &mut self,
+        declaration: &VariableDeclaration
+    ) {
+        walk_variable_declaration(self, declaration);
+    }
+
+    fn visit_type(&mut self, type_: &TypeExpression) {
+        walk_type(self, type_);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_case_element(&mut self, element: &CaseElement) {
+        walk_case_element(self, element);
+    }
+
+    fn visit_case_label(&mut self, label: &CaseLabel) {
+        walk_case_label(self, label);
+    }
+
+    fn visit_variable(&mut self, variable: &Variable) {
+        walk_variable(self, variable);
+    }
+
+    fn visit_selector(&mut self, selector: &Selector) {
+        walk_selector(self, selector);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    /// Visits a name that is declared or used.
+    fn visit_name(&mut self, _name: &Name) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(v: &mut V, program: &Program) {
+    v.visit_name(&program.name);
+    v.visit_block(&program.block);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(v: &mut V, block: &Block) {
+    for definition in &block.types {
+        v.visit_type_definition(definition);
+    }
+    for declaration in &block.variables {
+        v.visit_variable_declaration(declaration);
+    }
+    for statement in &block.statements {
+        v.visit_statement(statement);
+    }
+}
+
+pub fn walk_type_definition<V: Visitor + ?Sized>(
+    v: &mut V,
+    definition: &TypeDefinition
+) {
+    v.visit_name(&definition.name);
+    v.visit_type(&definition.type_);
+}
+
+pub fn walk_variable_declaration<V: Visitor + ?Sized>(
+    v: &mut V,
+    declaration: &VariableDeclaration
+) {
+    for name in &declaration.names {
+        v.visit_name(name);
+    }
+    v.visit_type(&declaration.type_);
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(v: &mut V, type_: &TypeExpression) {
+    match type_ {
+        TypeExpression::Named(name) | TypeExpression::Pointer(name) => {
+            v.visit_name(name);
+        },
+        TypeExpression::Enumeration(names) => {
+            for name in names {
+                v.visit_name(name);
+            }
+        },
+        TypeExpression::Subrange(low, high) => {
+            v.visit_expression(low);
+            v.visit_expression(high);
+        },
+        TypeExpression::Array { indices, element, .. } => {
+            for index in indices {
+                v.visit_type(index);
+            }
+            v.visit_type(element);
+        },
+        TypeExpression::Record { fields, .. } => {
+            for field in fields {
+                v.visit_variable_declaration(field);
+            }
+        },
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(v: &mut V, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::Empty => (),
+        StatementKind::Assignment { target, value } => {
+            v.visit_variable(target);
+            v.visit_expression(value);
+        },
+        StatementKind::Call { name, arguments } => {
+            v.visit_name(name);
+            for argument in arguments {
+                v.visit_expression(argument);
+            }
+        },
+        StatementKind::Compound(statements) => {
+            for statement in statements {
+                v.visit_statement(statement);
+            }
+        },
+        StatementKind::If { condition, then, else_ } => {
+            v.visit_expression(condition);
+            v.visit_statement(then);
+            if let Some(else_) = else_ {
+                v.visit_statement(else_);
+            }
+        },
+        StatementKind::Case { selector, elements, else_ } => {
+            v.visit_expression(selector);
+            for element in elements {
+                v.visit_case_element(element);
+            }
+            for statement in else_.iter().flatten() {
+                v.visit_statement(statement);
+            }
+        },
+        StatementKind::While { condition, body } => {
+            v.visit_expression(condition);
+            v.visit_statement(body);
+        },
+        StatementKind::Repeat { body, condition } => {
+            for statement in body {
+                v.visit_statement(statement);
+            }
+            v.visit_expression(condition);
+        },
+        StatementKind::For { variable, initial, final_, body, .. } => {
+            v.visit_name(variable);
+            v.visit_expression(initial);
+            v.visit_expression(final_);
+            v.visit_statement(body);
+        },
+        StatementKind::With { records, body } => {
+            for record in records {
+                v.visit_variable(record);
+            }
+            v.visit_statement(body);
+        },
+    }
+}
+
+pub fn walk_case_element<V: Visitor + ?Sized>(
+    v: &mut V,
+    element: &CaseElement
+) {
+    for label in &element.labels {
+        v.visit_case_label(label);
+    }
+    v.visit_statement(&element.statement);
+}
+
+pub fn walk_case_label<V: Visitor + ?Sized>(v: &mut V, label: &CaseLabel) {
+    v.visit_expression(&label.low);
+    if let Some(high) = &label.high {
+        v.visit_expression(high);
+    }
+}
+
+pub fn walk_variable<V: Visitor + ?Sized>(v: &mut V, variable: &Variable) {
+    v.visit_name(&variable.name);
+    for selector in &variable.selectors {
+        v.visit_selector(selector);
+    }
+}
+
+pub fn walk_selector<V: Visitor + ?Sized>(v: &mut V, selector: &Selector) {
+    match selector {
+        Selector::Index(indices) => {
+            for index in indices {
+                v.visit_expression(index);
+            }
+        },
+        Selector::Field(field) => v.visit_name(field),
+        Selector::Dereference => (),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(
+    v: &mut V,
+    expression: &Expression
+) {
+    match &expression.kind {
+        ExpressionKind::Variable(variable) => v.visit_variable(variable),
+        ExpressionKind::Number(_)
+        | ExpressionKind::Literal(_)
+        | ExpressionKind::Nil => (),
+        ExpressionKind::Call { name, arguments } => {
+            v.visit_name(name);
+            for argument in arguments {
+                v.visit_expression(argument);
+            }
+        },
+        ExpressionKind::Unary { operand, .. } => v.visit_expression(operand),
+        ExpressionKind::Binary { left, right, .. }
+        | ExpressionKind::Relation { left, right, .. } => {
+            v.visit_expression(left);
+            v.visit_expression(right);
+        },
+        ExpressionKind::Parenthesized(inner) => v.visit_expression(inner),
+    }
+}
+
+/// A `Visitor` that may change the nodes it visits.
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_type_definition_mut(&mut self, definition: &mut TypeDefinition) {
+        walk_type_definition_mut(self, definition);
+    }
+
+    fn visit_variable_declaration_mut(
+        &mut self,
+        declaration: &mut VariableDeclaration
+    ) {
+        walk_variable_declaration_mut(self, declaration);
+    }
+
+    fn visit_type_mut(&mut self, type_: &mut TypeExpression) {
+        walk_type_mut(self, type_);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_case_element_mut(&mut self, element: &mut CaseElement) {
+        walk_case_element_mut(self, element);
+    }
+
+    fn visit_case_label_mut(&mut self, label: &mut CaseLabel) {
+        walk_case_label_mut(self, label);
+    }
+
+    fn visit_variable_mut(&mut self, variable: &mut Variable) {
+        walk_variable_mut(self, variable);
+    }
+
+    fn visit_selector_mut(&mut self, selector: &mut Selector) {
+        walk_selector_mut(self, selector);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_name_mut(&mut self, _name: &mut Name) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    program: &mut Program
+) {
+    v.visit_name_mut(&mut program.name);
+    v.visit_block_mut(&mut program.block);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(v: &mut V, block: &mut Block) {
+    for definition in &mut block.types {
+        v.visit_type_definition_mut(definition);
+    }
+    for declaration in &mut block.variables {
+        v.visit_variable_declaration_mut(declaration);
+    }
+    for statement in &mut block.statements {
+        v.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_type_definition_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    definition: &mut TypeDefinition
+) {
+    v.visit_name_mut(&mut definition.name);
+    v.visit_type_mut(&mut definition.type_);
+}
+
+pub fn walk_variable_declaration_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    declaration: &mut VariableDeclaration
+) {
+    for name in &mut declaration.names {
+        v.visit_name_mut(name);
+    }
+    v.visit_type_mut(&mut declaration.type_);
+}
+
+pub fn walk_type_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    type_: &mut TypeExpression
+) {
+    match type_ {
+        TypeExpression::Named(name) | TypeExpression::Pointer(name) => {
+            v.visit_name_mut(name);
+        },
+        TypeExpression::Enumeration(names) => {
+            for name in names {
+                v.visit_name_mut(name);
+            }
+        },
+        TypeExpression::Subrange(low, high) => {
+            v.visit_expression_mut(low);
+            v.visit_expression_mut(high);
+        },
+        TypeExpression::Array { indices, element, .. } => {
+            for index in indices {
+                v.visit_type_mut(index);
+            }
+            v.visit_type_mut(element);
+        },
+        TypeExpression::Record { fields, .. } => {
+            for field in fields {
+                v.visit_variable_declaration_mut(field);
+            }
+        },
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    statement: &mut Statement
+) {
+    match &mut statement.kind {
+        StatementKind::Empty => (),
+        StatementKind::Assignment { target, value } => {
+            v.visit_variable_mut(target);
+            v.visit_expression_mut(value);
+        },
+        StatementKind::Call { name, arguments } => {
+            v.visit_name_mut(name);
+            for argument in arguments {
+                v.visit_expression_mut(argument);
+            }
+        },
+        StatementKind::Compound(statements) => {
+            for statement in statements {
+                v.visit_statement_mut(statement);
+            }
+        },
+        StatementKind::If { condition, then, else_ } => {
+            v.visit_expression_mut(condition);
+            v.visit_statement_mut(then);
+            if let Some(else_) = else_ {
+                v.visit_statement_mut(else_);
+            }
+        },
+        StatementKind::Case { selector, elements, else_ } => {
+            v.visit_expression_mut(selector);
+            for element in elements {
+                v.visit_case_element_mut(element);
+            }
+            for statement in else_.iter_mut().flatten() {
+                v.visit_statement_mut(statement);
+            }
+        },
+        StatementKind::While { condition, body } => {
+            v.visit_expression_mut(condition);
+            v.visit_statement_mut(body);
+        },
+        StatementKind::Repeat { body, condition } => {
+            for statement in body {
+                v.visit_statement_mut(statement);
+            }
+            v.visit_expression_mut(condition);
+        },
+        StatementKind::For { variable, initial, final_, body, .. } => {
+            v.visit_name_mut(variable);
+            v.visit_expression_mut(initial);
+            v.visit_expression_mut(final_);
+            v.visit_statement_mut(body);
+        },
+        StatementKind::With { records, body } => {
+            for record in records {
+                v.visit_variable_mut(record);
+            }
+            v.visit_statement_mut(body);
+        },
+    }
+}
+
+pub fn walk_case_element_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    element: &mut CaseElement
+) {
+    for label in &mut element.labels {
+        v.visit_case_label_mut(label);
+    }
+    v.visit_statement_mut(&mut element.statement);
+}
+
+pub fn walk_case_label_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    label: &mut CaseLabel
+) {
+    v.visit_expression_mut(&mut label.low);
+    if let Some(high) = &mut label.high {
+        v.visit_expression_mut(high);
+    }
+}
+
+pub fn walk_variable_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    variable: &mut Variable
+) {
+    v.visit_name_mut(&mut variable.name);
+    for selector in &mut variable.selectors {
+        v.visit_selector_mut(selector);
+    }
+}
+
+pub fn walk_selector_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    selector: &mut Selector
+) {
+    match selector {
+        Selector::Index(indices) => {
+            for index in indices {
+                v.visit_expression_mut(index);
+            }
+        },
+        Selector::Field(field) => v.visit_name_mut(field),
+        Selector::Dereference => (),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    expression: &mut Expression
+) {
+    match &mut expression.kind {
+        ExpressionKind::Variable(variable) => v.visit_variable_mut(variable),
+        ExpressionKind::Number(_)
+        | ExpressionKind::Literal(_)
+        | ExpressionKind::Nil => (),
+        ExpressionKind::Call { name, arguments } => {
+            v.visit_name_mut(name);
+            for argument in arguments {
+                v.visit_expression_mut(argument);
+            }
+        },
+        ExpressionKind::Unary { operand, .. } => {
+            v.visit_expression_mut(operand);
+        },
+        ExpressionKind::Binary { left, right, .. }
+        | ExpressionKind::Relation { left, right, .. } => {
+            v.visit_expression_mut(left);
+            v.visit_expression_mut(right);
+        },
+        ExpressionKind::Parenthesized(inner) => v.visit_expression_mut(inner),
+    }
+}
+
+/// Empties the ranges in the tree, so that the trees of two sources
+/// with the same structure compare equal.
+#[cfg(test)]
+pub(crate) fn forget_ranges(program: &mut Program) {
+    struct Forget;
+
+    impl VisitorMut for Forget {
+        fn visit_statement_mut(&mut self, statement: &mut Statement) {
+            statement.range = 0..0;
+            walk_statement_mut(self, statement);
+        }
+
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            expression.range = 0..0;
+            walk_expression_mut(self, expression);
+        }
+
+        fn visit_name_mut(&mut self, name: &mut Name) {
+            name.range = 0..0;
+        }
+    }
+
+    Forget.visit_program_mut(program);
+}
+
+#[cfg(test)]
+mod visit_tests {
+    use super::*;
+    use crate::{
+        formatting::FormatOptions,
+        parsing::{parser::parse, printer::print},
+        tokenization::Operator,
+    };
+
+    fn parse_str(input: &str) -> Program {
+        parse(input.as_bytes()).unwrap()
+    }
+
+    /// Collects the names of the variables in the order they are used.
+    #[derive(Default)]
+    struct Uses {
+        names: Vec<String>,
+        statements: usize,
+    }
+
+    impl Visitor for Uses {
+        fn visit_variable(&mut self, variable: &Variable) {
+            self.names.push(variable.name.text.clone());
+            walk_variable(self, variable);
+        }
+
+        fn visit_statement(&mut self, statement: &Statement) {
+            if !statement.is_empty() {
+                self.statements += 1;
+            }
+            walk_statement(self, statement);
+        }
+    }
+
+    #[test]
+    fn test_visit_in_source_order() {
+        let program = parse_str(
+            "program p; type r = array [low..high] of integer; \
+            var a: r; begin \
+            for i := 1 to n do a[i] := b + c[j]; \
+            case k of one: x := 1; two..three: begin end end; \
+            with v^ do repeat f(y) until z \
+            end."
+        );
+
+        let mut uses = Uses::default();
+        uses.visit_program(&program);
+        assert_eq!(uses.names, vec![
+            "low", "high", "n", "a", "i", "b", "c", "j",
+            "k", "one", "x", "two", "three", "v", "y", "z",
+        ]);
+        assert_eq!(uses.statements, 8);
+    }
+
+    /// Collects every name with its text in the source.
+    struct Names<'a> {
+        source: &'a str,
+        names: Vec<(String, &'a str)>,
+    }
+
+    impl Visitor for Names<'_> {
+        fn visit_name(&mut self, name: &Name) {
+            let text = &self.source[name.range.clone()];
+            self.names.push((name.text.clone(), text));
+        }
+    }
+
+    #[test]
+    fn test_visit_names() {
+        let source = "program P; type T = ^R; var a: T; \
+            begin A^.f := G(a, B) end.";
+        let mut names = Names { source, names: Vec::new() };
+        names.visit_program(&parse_str(source));
+
+        let expected = ["P", "T", "R", "a", "T", "A", "f", "G", "a", "B"];
+        let found: Vec<_> = names.names.iter()
+            .map(|(name, text)| {
+                assert_eq!(name, text);
+                text.to_string()
+            })
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    /// Renames a variable and replaces each `not not e` with `e`.
+    struct Simplify;
+
+    impl VisitorMut for Simplify {
+        fn visit_variable_mut(&mut self, variable: &mut Variable) {
+            if variable.name.text == "old" {
+                variable.name.text = "new".to_string();
+            }
+            walk_variable_mut(self, variable);
+        }
+
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            walk_expression_mut(self, expression);
+            if let ExpressionKind::Unary {
+                operator: Operator::Not,
+                operand,
+            } = &expression.kind {
+                if let ExpressionKind::Unary {
+                    operator: Operator::Not,
+                    operand: inner,
+                } = &operand.kind {
+                    *expression = (**inner).clone();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit_mut_changes_nodes() {
+        let mut program = parse_str(
+            "program p; begin \
+            if not not (old > 0) then old := old[not not b] end."
+        );
+
+        Simplify.visit_program_mut(&mut program);
+        assert_eq!(
+            print(&program, &FormatOptions::default()),
+            "program p;\n\
+            begin\n  \
+              if (new > 0) then\n    \
+                new := new[b]\n\
+            end.\n"
+        );
+    }
+}
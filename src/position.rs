@@ -1,6 +1,7 @@
 pub const START_POSITION: FilePosition = FilePosition { line: 1, col: 1 };
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FilePosition {
     pub line: usize,
     pub col: usize,
@@ -8,6 +9,6 @@ pub struct FilePosition {
 
 impl FilePosition {
     pub fn new(line: usize, col: usize) -> Self {
-        FilePosition { line: line, col: col }
+        FilePosition { line, col }
     }
 }
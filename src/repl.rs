@@ -0,0 +1,323 @@
+use std::io::{self, BufRead, Write};
+
+use wasmi::Val;
+
+use crate::{
+    error::{CompilationError, CompilationErrorKind},
+    parsing::code::Code,
+    runtime::{self, State},
+    semantics::{boolean, SymbolTable, Type},
+    session::CompilerSession,
+    tokenization::{SimpleBuffer, TokenStream},
+    translation::Target,
+    SharedBuffer,
+};
+
+const PROMPT: &str = "> ";
+/// The variable an expression entered on its own is assigned to,
+/// which its value is printed from
+const VALUE: &str = "repl_value";
+
+/// Reads declarations, statements and expressions from standard input
+/// line by line and runs them, printing what they print and the values
+/// of the expressions, until the input ends or `:quit` is entered.
+pub fn run() -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut session = Session::default();
+
+    loop {
+        print!("{}", PROMPT);
+        stdout.flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        let read = stdin.lock().read_line(&mut line)
+            .map_err(|e| format!("Error reading standard input: {}", e))?;
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => (),
+            ":quit" => return Ok(()),
+            ":reset" => session = Session::default(),
+            entry => match session.eval(entry) {
+                Ok(printed) => print!("{}", printed),
+                Err(e) => eprint!("{}", e),
+            }
+        }
+    }
+}
+
+enum EvalError {
+    Compilation(Vec<CompilationError>),
+    /// The program failed after printing `output`
+    Runtime { output: String, error: String },
+}
+
+/// The declarations entered so far and the values the entries have
+/// left in the variables. Every entry runs on its own, starting from
+/// the values the entries before it left.
+#[derive(Clone, Default)]
+struct Session {
+    types: Vec<String>,
+    variables: Vec<String>,
+    state: State,
+}
+
+impl Session {
+    /// Runs an entry, returning what it prints or the errors it causes.
+    /// The entries that fail are forgotten.
+    fn eval(&mut self, entry: &str) -> Result<String, String> {
+        let entry = entry.trim_end_matches(';').trim_end();
+
+        let mut session = self.clone();
+        let mut statement = None;
+        if let Some(types) = after_keyword(entry, "type") {
+            session.types.push(format!("{};", types));
+        } else if let Some(variables) = after_keyword(entry, "var") {
+            session.variables.push(format!("{};", variables));
+        } else {
+            statement = Some(entry);
+        }
+
+        let statement_errors = match session.execute(None, statement) {
+            Ok((output, state)) => {
+                session.state = state;
+                *self = session;
+                return Ok(output);
+            },
+            Err(EvalError::Runtime { output, error }) => {
+                return Err(format!("{}{}\n", output, error));
+            },
+            Err(EvalError::Compilation(errors)) => errors,
+        };
+
+        // Not a statement, perhaps an expression to print
+        let mut expression_errors = None;
+        for (declaration, variable, type_) in self.values() {
+            let statement = format!("{} := {}", variable, entry);
+            match self.execute(declaration.as_deref(), Some(&statement)) {
+                Ok((_, state)) => {
+                    let value = state.global(&variable)
+                        .map(|v| show(v, &type_));
+                    // The variables of the session keep their values
+                    if declaration.is_some() {
+                        self.state = state;
+                    }
+                    return Ok(value.unwrap_or_default() + "\n");
+                },
+                Err(EvalError::Runtime { error, .. }) => {
+                    return Err(format!("{}\n", error));
+                },
+                Err(EvalError::Compilation(errors)) => {
+                    expression_errors.get_or_insert(errors);
+                }
+            }
+        }
+
+        // Report the errors of what the entry looks like
+        let errors = match expression_errors {
+            Some(e) if !syntax_errors(&e) && syntax_errors(&statement_errors) => {
+                e
+            },
+            _ => statement_errors,
+        };
+        Err(errors.iter()
+            .map(|e| format!("{:?}: {}\n", e.kind(), e.msg()))
+            .collect())
+    }
+
+    /// The variables the value of an expression can be assigned to
+    /// in order to print it, with their declarations and types: a new
+    /// one of every standard and declared enumerated type, or one of
+    /// the variables if its enumerated type has no name.
+    fn values(&self) -> Vec<(Option<String>, String, Type)> {
+        let symbols = self.symbols();
+        let types = vec![
+            ("integer".to_string(), Type::Integer),
+            ("real".to_string(), Type::Real),
+            ("boolean".to_string(), boolean()),
+            ("char".to_string(), Type::Char),
+        ];
+        let declared = symbols.types.into_iter()
+            .filter(|(_, t)| matches!(t, Type::Scalar(_)));
+
+        let mut values: Vec<_> = types.into_iter()
+            .chain(declared)
+            .map(|(name, t)| {
+                let declaration = format!("{}: {};", VALUE, name);
+                (Some(declaration), VALUE.to_string(), t)
+            })
+            .collect();
+        for (name, t) in symbols.variables {
+            let named = values.iter().any(|(_, _, v)| *v == t);
+            if matches!(t, Type::Scalar(_)) && !named {
+                values.push((None, name, t));
+            }
+        }
+        values
+    }
+
+    /// The types and the variables the session declares.
+    fn symbols(&self) -> SymbolTable {
+        let program = self.program(None, None);
+        let buf = SimpleBuffer::new(program.as_bytes(), None);
+        Code::new(TokenStream::new(buf), Box::new(io::sink()))
+            .check_with_symbols()
+            .map(|(_, symbols)| symbols)
+            .unwrap_or_default()
+    }
+
+    /// Compiles and runs the statement with the declarations of the
+    /// session and the one given, starting from the values the session
+    /// has left, and returns what the statement prints and leaves.
+    fn execute(
+        &self,
+        declaration: Option<&str>,
+        statement: Option<&str>
+    ) -> Result<(String, State), EvalError> {
+        let program = self.program(declaration, statement);
+        let target = Target { globals: true, ..Target::default() };
+        let compilation = CompilerSession::new()
+            .with_target(target.clone())
+            .compile(None, program.as_bytes());
+        if !compilation.succeeded() {
+            return Err(EvalError::Compilation(compilation.all_errors()));
+        }
+
+        let output = SharedBuffer::default();
+        let mut state = self.state.clone();
+        let result = wat::parse_bytes(&compilation.text)
+            .map_err(|e| format!("internal compiler error: {}", e))
+            .and_then(|binary| runtime::resume(
+                &binary, &target, Box::new(output.clone()), &mut state
+            ));
+        let output = String::from_utf8_lossy(&output.0.take()).into_owned();
+        match result {
+            Ok(()) => Ok((output, state)),
+            Err(error) => Err(EvalError::Runtime { output, error }),
+        }
+    }
+
+    fn program(
+        &self,
+        declaration: Option<&str>,
+        statement: Option<&str>
+    ) -> String {
+        let mut program = String::from("program repl;\n");
+        let variables: Vec<_> = self.variables.iter()
+            .map(String::as_str)
+            .chain(declaration)
+            .collect();
+        for (keyword, declarations) in [
+            ("type", self.types.iter().map(String::as_str).collect()),
+            ("var", variables)
+        ] {
+            if !declarations.is_empty() {
+                program += &format!(
+                    "{}\n  {}\n", keyword, declarations.join("\n  ")
+                );
+            }
+        }
+
+        program += &format!("begin\n  {}\nend.\n", statement.unwrap_or(""));
+        program
+    }
+}
+
+/// How a value of the type is printed.
+fn show(value: &Val, type_: &Type) -> String {
+    match (value, type_) {
+        (Val::F32(value), _) => value.to_float().to_string(),
+        (Val::I32(value), Type::Char) => char::from(*value as u8).to_string(),
+        (Val::I32(value), Type::Scalar(names)) => names.get(*value as usize)
+            .cloned()
+            .unwrap_or_else(|| value.to_string()),
+        (Val::I32(value), _) => value.to_string(),
+        (value, _) => format!("{:?}", value),
+    }
+}
+
+/// The rest of the entry if it starts with the keyword.
+fn after_keyword<'a>(entry: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = entry.get(keyword.len()..)?;
+    let starts = entry[..keyword.len()].eq_ignore_ascii_case(keyword)
+        && rest.starts_with(char::is_whitespace);
+    starts.then(|| rest.trim())
+}
+
+fn syntax_errors(errors: &[CompilationError]) -> bool {
+    errors.iter().any(|e| e.kind() == CompilationErrorKind::SyntaxError)
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_session() {
+        let mut session = Session::default();
+        let mut eval = |entry| session.eval(entry);
+
+        assert_eq!(eval("var a: integer"), Ok(String::new()));
+        assert_eq!(eval("a := 2;"), Ok(String::new()));
+        assert_eq!(eval("writeln_int(a)"), Ok("2\n".to_string()));
+        // Earlier statements do not print again
+        assert_eq!(eval("a * 3"), Ok("6\n".to_string()));
+        assert_eq!(eval("var r: real"), Ok(String::new()));
+        assert_eq!(eval("r := 1.5"), Ok(String::new()));
+        assert_eq!(eval("r / 2.0"), Ok("0.75\n".to_string()));
+        assert_eq!(eval("a > 1"), Ok("true\n".to_string()));
+
+        assert_eq!(eval("type color = (red, green)"), Ok(String::new()));
+        assert_eq!(eval("var c: color"), Ok(String::new()));
+        assert_eq!(eval("c := green"), Ok(String::new()));
+        assert_eq!(eval("a < 0"), Ok("false\n".to_string()));
+        assert_eq!(eval("c"), Ok("green\n".to_string()));
+        assert_eq!(eval("'c'"), Ok("c\n".to_string()));
+    }
+
+    #[test]
+    fn test_repl_state() {
+        let mut session = Session::default();
+        let mut eval = |entry| session.eval(entry);
+
+        assert_eq!(eval("var d: (north, south)"), Ok(String::new()));
+        assert_eq!(eval("d := south"), Ok(String::new()));
+        assert_eq!(eval("d"), Ok("south\n".to_string()));
+
+        assert_eq!(eval("var v: array [1..3] of integer"), Ok(String::new()));
+        assert_eq!(eval("v[2] := 5"), Ok(String::new()));
+        assert_eq!(eval("var i: integer"), Ok(String::new()));
+        assert_eq!(eval("v[2] + 1"), Ok("6\n".to_string()));
+
+        // The statements run once, reseeding does not shift the output
+        assert_eq!(eval("randomize"), Ok(String::new()));
+        assert_eq!(eval("i := random(20)"), Ok(String::new()));
+        assert_eq!(eval("writeln_int(7)"), Ok("7\n".to_string()));
+        assert!(eval("i").unwrap().trim().parse::<i32>().is_ok());
+    }
+
+    #[test]
+    fn test_repl_errors() {
+        let mut session = Session::default();
+        session.eval("var a: integer").unwrap();
+
+        let error = session.eval("b := 1").unwrap_err();
+        assert!(error.contains("identifier not found \"b\""), "{}", error);
+        assert!(session.eval("var a: real").is_err());
+
+        let error = session.eval("a := 1 div a").unwrap_err();
+        assert!(error.starts_with("Runtime error"), "{}", error);
+
+        // A constant divisor is checked before the entry runs
+        let error = session.eval("a := 1 div (2 - 2)").unwrap_err();
+        let message = "division by zero in a constant expression";
+        assert!(error.contains(message), "{}", error);
+
+        // The failed entries are forgotten
+        assert_eq!(session.eval("a"), Ok("0\n".to_string()));
+    }
+}
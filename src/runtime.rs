@@ -0,0 +1,337 @@
+use std::{io::Write, time::{SystemTime, UNIX_EPOCH}};
+
+use wasmi::{
+    core::Pages, Caller, Engine, Error, ExternType, Instance, Linker,
+    Memory, Module, Store, Val,
+};
+
+use crate::translation::{Start, Target};
+
+const INITIALIZE: &str = "_initialize";
+const PAGE_SIZE: usize = 65536;
+// The seed of the random numbers until the program reseeds them
+const RANDOM_SEED: u32 = 2463534242;
+
+/// The state the imported procedures work with.
+struct Host {
+    out: Box<dyn Write>,
+    /// The state of the xorshift generator behind `random_int`
+    random: u32,
+}
+
+/// What a program leaves behind for the next one to start from: the
+/// values of its exported globals and memory and the random numbers.
+#[derive(Clone)]
+pub struct State {
+    globals: Vec<(String, Val)>,
+    memory: Vec<u8>,
+    random: u32,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self { globals: Vec::new(), memory: Vec::new(), random: RANDOM_SEED }
+    }
+}
+
+impl State {
+    /// The value of the exported global, if the program had it.
+    pub fn global(&self, name: &str) -> Option<&Val> {
+        self.globals.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+/// Runs a compiled program in the embedded WebAssembly interpreter,
+/// which provides the procedures the program imports.
+/// What the program prints is written to `out`.
+pub fn run(
+    binary: &[u8],
+    target: &Target,
+    out: Box<dyn Write>
+) -> Result<(), String> {
+    resume(binary, target, out, &mut State::default())
+}
+
+/// Runs a compiled program like `run`, starting from the state an
+/// earlier one left. The globals the program exports under the names
+/// and with the types the earlier one did get their values back, so
+/// does its memory, and the state is replaced with what the program
+/// leaves if it succeeds.
+pub fn resume(
+    binary: &[u8],
+    target: &Target,
+    out: Box<dyn Write>,
+    state: &mut State
+) -> Result<(), String> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, binary).map_err(|e| e.to_string())?;
+    let host = Host { out, random: state.random };
+    let mut store = Store::new(&engine, host);
+    let mut linker = Linker::<Host>::new(&engine);
+
+    for import in module.imports() {
+        let (module_name, name) = (import.module(), import.name());
+        let defined = match (name, import.ty()) {
+            ("writeln_int", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |mut caller: Caller<'_, Host>, value: i32| {
+                    writeln(&mut caller, value)
+                }
+            ),
+            ("writeln_real", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |mut caller: Caller<'_, Host>, value: f32| {
+                    writeln(&mut caller, value)
+                }
+            ),
+            ("runtime_error", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |_: Caller<'_, Host>, code: i32, line: i32| -> Result<(), Error> {
+                    Err(Error::new(format!(
+                        "Runtime error {} at line {}", code, line
+                    )))
+                }
+            ),
+            ("random_int", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |mut caller: Caller<'_, Host>, bound: i32| -> i32 {
+                    let host = caller.data_mut();
+                    host.random ^= host.random << 13;
+                    host.random ^= host.random >> 17;
+                    host.random ^= host.random << 5;
+                    if bound > 0 {
+                        (host.random % bound as u32) as i32
+                    } else {
+                        0
+                    }
+                }
+            ),
+            ("randomize", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |mut caller: Caller<'_, Host>| {
+                    caller.data_mut().random = current_time() as u32 | 1;
+                }
+            ),
+            ("current_time", ExternType::Func(_)) => linker.func_wrap(
+                module_name, name,
+                |_: Caller<'_, Host>| -> i32 { current_time() }
+            ),
+            (_, ExternType::Memory(t)) => {
+                let memory = Memory::new(&mut store, *t)
+                    .map_err(|e| e.to_string())?;
+                linker.define(module_name, name, memory)
+            },
+            _ => return Err(format!(
+                "the program imports \"{}\" from \"{}\", \
+                which the runtime does not provide",
+                name, module_name
+            ))
+        };
+        defined.map_err(|e| e.to_string())?;
+    }
+
+    let instance = linker.instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(describe)?;
+    restore(&instance, &mut store, state)?;
+
+    let entry = match target.start {
+        Start::Export => Some(target.export_name.as_str()),
+        Start::Initialize => Some(INITIALIZE),
+        Start::Section => None,
+    };
+    if let Some(name) = entry {
+        instance.get_typed_func::<(), ()>(&store, name)
+            .map_err(|e| e.to_string())?
+            .call(&mut store, ())
+            .map_err(describe)?;
+    }
+
+    store.data_mut().out.flush().map_err(|e| e.to_string())?;
+    *state = save(&instance, &store);
+    Ok(())
+}
+
+/// Gives the exported globals and memory the values in the state.
+fn restore(
+    instance: &Instance,
+    store: &mut Store<Host>,
+    state: &State
+) -> Result<(), String> {
+    for (name, value) in &state.globals {
+        let global = instance.get_global(&*store, name)
+            .filter(|g| g.ty(&*store).content() == value.ty());
+        if let Some(global) = global {
+            global.set(&mut *store, value.clone())
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    if let Some(memory) = instance.get_memory(&*store, "memory") {
+        let missing = state.memory.len()
+            .saturating_sub(memory.data(&*store).len());
+        if missing > 0 {
+            let pages = Pages::new(missing.div_ceil(PAGE_SIZE) as u32)
+                .ok_or("the saved memory does not fit")?;
+            memory.grow(&mut *store, pages).map_err(|e| e.to_string())?;
+        }
+        memory.write(&mut *store, 0, &state.memory)
+            .map_err(|e| e.to_string())?;
+    }
+    store.data_mut().random = state.random;
+    Ok(())
+}
+
+/// Takes the values of the exported globals and memory.
+fn save(instance: &Instance, store: &Store<Host>) -> State {
+    let globals = instance.exports(store)
+        .filter_map(|export| {
+            let name = export.name().to_string();
+            export.into_global().map(|global| (name, global))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|(name, global)| (name, global.get(store)))
+        .collect();
+    let memory = instance.get_memory(store, "memory")
+        .map(|memory| memory.data(store).to_vec())
+        .unwrap_or_default();
+    State { globals, memory, random: store.data().random }
+}
+
+fn writeln(
+    caller: &mut Caller<'_, Host>,
+    value: impl std::fmt::Display
+) -> Result<(), Error> {
+    writeln!(caller.data_mut().out, "{}", value)
+        .map_err(|e| Error::new(e.to_string()))
+}
+
+/// The seconds since the Unix epoch, wrapped to fit.
+fn current_time() -> i32 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i32)
+        .unwrap_or_default()
+}
+
+fn describe(e: Error) -> String {
+    match e.as_trap_code() {
+        Some(code) => format!("Runtime error: {}", code),
+        None => e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod runtime_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_text(text: &str, target: &Target) -> (Result<(), String>, String) {
+        let out = SharedBuffer::default();
+        let binary = wat::parse_str(text).unwrap();
+        let result = run(&binary, target, Box::new(out.clone()));
+        let printed = String::from_utf8(out.0.borrow().clone()).unwrap();
+        (result, printed)
+    }
+
+    #[test]
+    fn test_run_prints() {
+        let text = "(module
+            (func $writeln_int (import \"imports\" \"writeln_int\") (param i32))
+            (func $writeln_real (import \"imports\" \"writeln_real\") (param f32))
+            (func (export \"program\")
+                i32.const 42
+                call $writeln_int
+                f32.const 0.5
+                call $writeln_real))";
+
+        let (result, printed) = run_text(text, &Target::default());
+        assert_eq!(result, Ok(()));
+        assert_eq!(printed, "42\n0.5\n");
+    }
+
+    #[test]
+    fn test_run_start_section() {
+        let text = "(module
+            (func $writeln_int (import \"imports\" \"writeln_int\") (param i32))
+            (start $program)
+            (func $program (export \"program\")
+                i32.const 1
+                call $writeln_int))";
+        let target = Target { start: Start::Section, ..Target::default() };
+
+        let (result, printed) = run_text(text, &target);
+        assert_eq!(result, Ok(()));
+        assert_eq!(printed, "1\n");
+    }
+
+    #[test]
+    fn test_run_runtime_errors() {
+        let text = "(module
+            (func $runtime_error (import \"imports\" \"runtime_error\")
+                (param i32 i32))
+            (func (export \"program\")
+                i32.const 200
+                i32.const 3
+                call $runtime_error
+                unreachable))";
+
+        let (result, _) = run_text(text, &Target::default());
+        assert_eq!(result, Err("Runtime error 200 at line 3".to_string()));
+
+        let text = "(module (func (export \"program\")
+            i32.const 1
+            i32.const 0
+            i32.div_s
+            drop))";
+
+        let (result, _) = run_text(text, &Target::default());
+        assert!(result.unwrap_err().starts_with("Runtime error: "));
+    }
+
+    #[test]
+    fn test_run_random() {
+        let text = "(module
+            (func $writeln_int (import \"imports\" \"writeln_int\") (param i32))
+            (func $random_int (import \"imports\" \"random_int\")
+                (param i32) (result i32))
+            (func (export \"program\")
+                i32.const 10
+                call $random_int
+                call $writeln_int
+                i32.const 0
+                call $random_int
+                call $writeln_int))";
+
+        let (result, printed) = run_text(text, &Target::default());
+        assert_eq!(result, Ok(()));
+        let numbers: Vec<i32> = printed.lines()
+            .map(|l| l.parse().unwrap())
+            .collect();
+        assert!((0..10).contains(&numbers[0]), "{}", printed);
+        assert_eq!(numbers[1], 0);
+    }
+
+    #[test]
+    fn test_run_unknown_import() {
+        let text = "(module
+            (func $read (import \"imports\" \"read\") (result i32)))";
+
+        let (result, _) = run_text(text, &Target::default());
+        assert!(result.unwrap_err().contains("\"read\""));
+    }
+}
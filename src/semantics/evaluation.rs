@@ -0,0 +1,213 @@
+//! The evaluation of constant expressions at compile time. The values
+//! are computed the way the generated code computes them at run time,
+//! except that what would overflow or divide by zero there is an error.
+
+use std::fmt::{self, Display};
+
+use crate::tokenization::{Operator, Relation};
+
+/// The value of a constant expression. A value of an ordinal type,
+/// such as a character or a constant of an enumeration, is its ordinal,
+/// and a boolean is 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    Real(f32),
+}
+
+impl Value {
+    /// The ordinal of the value, if it is not a real.
+    pub fn ordinal(self) -> Option<i32> {
+        match self {
+            Value::Integer(v) => Some(v),
+            Value::Real(_) => None,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.real() == 0.0
+    }
+
+    fn real(self) -> f32 {
+        match self {
+            Value::Integer(v) => v as f32,
+            Value::Real(v) => v,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Real(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// The result does not fit in an integer or a real
+    Overflow,
+    DivisionByZero,
+    /// The operator does not apply to the values
+    Undefined(Operator),
+}
+
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvaluationError::Overflow => {
+                write!(f, "the constant expression overflows")
+            },
+            EvaluationError::DivisionByZero => {
+                write!(f, "division by zero in a constant expression")
+            },
+            EvaluationError::Undefined(op) => {
+                write!(f, "{:?} does not apply to the constants", op)
+            },
+        }
+    }
+}
+
+type Evaluation = Result<Value, EvaluationError>;
+
+/// The result of the operator on the values. An integer operand of
+/// an operation with a real one is converted to a real, and `/`
+/// always gives a real. The logical operators work on the bits of
+/// the operands, so that they work on booleans as 0 and 1.
+pub fn binary(op: Operator, left: Value, right: Value) -> Evaluation {
+    use EvaluationError::*;
+
+    if let (Value::Integer(a), Value::Integer(b)) = (left, right) {
+        let value = match op {
+            Operator::Plus => a.checked_add(b),
+            Operator::Minus => a.checked_sub(b),
+            Operator::Multiply => a.checked_mul(b),
+            Operator::IntegerDivide if b == 0 => return Err(DivisionByZero),
+            Operator::IntegerDivide => a.checked_div(b),
+            Operator::Modulus if b == 0 => return Err(DivisionByZero),
+            // The remainder has the sign of the dividend, as `i32.rem_s`
+            Operator::Modulus => Some(a.wrapping_rem(b)),
+            Operator::And => Some(a & b),
+            Operator::Or => Some(a | b),
+            Operator::Xor => Some(a ^ b),
+            Operator::Divide => return binary(op, left, Value::Real(b as f32)),
+            Operator::Not | Operator::Assign => return Err(Undefined(op)),
+        };
+        return value.map(Value::Integer).ok_or(Overflow);
+    }
+
+    let (a, b) = (left.real(), right.real());
+    let value = match op {
+        Operator::Plus => a + b,
+        Operator::Minus => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide if b == 0.0 => return Err(DivisionByZero),
+        Operator::Divide => a / b,
+        _ => return Err(Undefined(op))
+    };
+    if value.is_finite() {
+        Ok(Value::Real(value))
+    } else {
+        Err(Overflow)
+    }
+}
+
+/// Whether the values are in the relation, as 1 if they are and 0 if
+/// they are not.
+pub fn relation(relation: Relation, left: Value, right: Value) -> Value {
+    let ordering = match (left, right) {
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(&b)),
+        _ => left.real().partial_cmp(&right.real()),
+    };
+    let holds = ordering.is_some_and(|o| match relation {
+        Relation::Eq => o.is_eq(),
+        Relation::Ne => o.is_ne(),
+        Relation::Lt => o.is_lt(),
+        Relation::Le => o.is_le(),
+        Relation::Gt => o.is_gt(),
+        Relation::Ge => o.is_ge(),
+    });
+    Value::Integer(i32::from(holds))
+}
+
+/// The value with the opposite sign.
+pub fn negation(value: Value) -> Evaluation {
+    match value {
+        Value::Integer(v) => {
+            let negated = v.checked_neg().map(Value::Integer);
+            negated.ok_or(EvaluationError::Overflow)
+        },
+        Value::Real(v) => Ok(Value::Real(-v)),
+    }
+}
+
+/// The negation of a boolean.
+pub fn not(value: Value) -> Value {
+    Value::Integer(i32::from(value == Value::Integer(0)))
+}
+
+#[cfg(test)]
+mod evaluation_tests {
+    use super::*;
+
+    use Value::*;
+
+    #[test]
+    fn test_evaluate_integers() {
+        assert_eq!(
+            binary(Operator::Plus, Integer(2), Integer(3)),
+            Ok(Integer(5))
+        );
+        assert_eq!(
+            binary(Operator::Modulus, Integer(-7), Integer(2)),
+            Ok(Integer(-1))
+        );
+        assert_eq!(
+            binary(Operator::Divide, Integer(3), Integer(2)),
+            Ok(Real(1.5))
+        );
+        assert_eq!(relation(Relation::Lt, Integer(1), Real(1.5)), Integer(1));
+        assert_eq!(not(Integer(0)), Integer(1));
+    }
+
+    #[test]
+    fn test_evaluation_errors() {
+        assert_eq!(
+            binary(Operator::Plus, Integer(i32::MAX), Integer(1)),
+            Err(EvaluationError::Overflow)
+        );
+        assert_eq!(
+            binary(Operator::IntegerDivide, Integer(i32::MIN), Integer(-1)),
+            Err(EvaluationError::Overflow)
+        );
+        assert_eq!(negation(Integer(i32::MIN)), Err(EvaluationError::Overflow));
+        assert_eq!(
+            binary(Operator::Multiply, Real(f32::MAX), Integer(2)),
+            Err(EvaluationError::Overflow)
+        );
+        for (op, divisor) in [
+            (Operator::IntegerDivide, Integer(0)),
+            (Operator::Modulus, Integer(0)),
+            (Operator::Divide, Integer(0)),
+            (Operator::Divide, Real(0.0)),
+        ] {
+            assert_eq!(
+                binary(op, Integer(1), divisor),
+                Err(EvaluationError::DivisionByZero),
+                "{:?}", op
+            );
+        }
+        for op in [Operator::And, Operator::Or, Operator::Not] {
+            assert_eq!(
+                binary(op, Real(1.5), Real(2.0)),
+                Err(EvaluationError::Undefined(op))
+            );
+        }
+        assert_eq!(
+            binary(Operator::Assign, Integer(1), Integer(2)),
+            Err(EvaluationError::Undefined(Operator::Assign))
+        );
+    }
+}
@@ -7,6 +7,36 @@ pub type Fields = HashMap<String, Type>;
 pub enum Identifier {
     Variable(String, Type),
     Type(Type),
+    /// A constant of a scalar type, standing for its ordinal
+    Constant(Type),
     Procedure(Types),
+    StandardProcedure(StandardProcedure),
+    StandardFunction(StandardFunction),
     Unknown
 }
+
+/// Procedures the compiler knows, which are not declared as imports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardProcedure {
+    Exit,
+    Halt,
+    /// Seeds the random numbers with the current time
+    Randomize,
+}
+
+/// Functions the compiler knows, which are not declared as imports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardFunction {
+    /// The integer part of a real
+    Trunc,
+    /// The integer nearest to a real
+    Round,
+    /// Whether an integer is odd
+    Odd,
+    /// The square of a number
+    Sqr,
+    /// The absolute value of a number
+    Abs,
+    /// A random integer from 0 below a bound
+    Random,
+}
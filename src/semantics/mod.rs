@@ -1,7 +1,15 @@
 mod scope;
 mod type_;
 mod identifier;
+mod symbols;
+pub mod evaluation;
 
-pub use scope::{Scope, Identifiers};
-pub use identifier::{Identifier, Fields};
-pub use type_::{Type, Types, Enumeration, boolean};
+pub use scope::{Scopes, Identifiers, Spans};
+pub use identifier::{
+    Identifier,
+    Fields,
+    StandardFunction,
+    StandardProcedure
+};
+pub use symbols::SymbolTable;
+pub use type_::{Type, Types, Array, Enumeration, boolean};
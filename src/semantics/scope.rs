@@ -1,62 +1,112 @@
-use std::{boxed::Box, collections::{HashMap}, error::Error, fmt::Display};
-
-use crate::semantics::{Identifier, Type, boolean};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    ops::Range,
+};
+
+use crate::semantics::{
+    Identifier,
+    StandardFunction,
+    StandardProcedure,
+    Type,
+    boolean
+};
 
 pub type Identifiers = HashMap<String, Identifier>;
+/// Where in the source the identifiers are declared
+pub type Spans = HashMap<String, Range<usize>>;
 
+/// The identifiers declared in one block, with statement
+/// or the predefined ones.
 #[derive(Clone)]
 pub struct Scope {
-    outer_scope: Option<Box<Scope>>,
+    /// The index of the scope this one is nested in
+    parent: Option<usize>,
     identifiers: Identifiers,
+    spans: Spans,
 }
 
-impl Default for Scope {
-    fn default() -> Self {
-        Self::new(
-            [
-                ("char".to_string(), Identifier::Type(Type::Char)),
-                ("integer".to_string(), Identifier::Type(Type::Integer)),
-                ("real".to_string(), Identifier::Type(Type::Real)),
-                ("boolean".to_string(), Identifier::Type(boolean())),
-                ("writeln_int".to_string(), Identifier::Procedure(
-                    [
-                        Type::Integer
-                    ].iter().cloned().collect()
-                )),
-                ("writeln_real".to_string(), Identifier::Procedure(
-                    [
-                        Type::Real
-                    ].iter().cloned().collect()
-                ))
-            ].iter().cloned().collect(),
-        )
+impl Scope {
+    pub fn new(parent: Option<usize>, identifiers: Identifiers) -> Self {
+        Scope {
+            parent,
+            identifiers,
+            spans: Spans::new(),
+        }
+    }
+
+    /// The index of the scope this one is nested in.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// Where the identifier of this scope is declared,
+    /// if it is declared in the source.
+    pub fn declaration(&self, name: &str) -> Option<Range<usize>> {
+        self.spans.get(name).cloned()
     }
 }
 
-impl Scope {
-    pub fn new(table: Identifiers) -> Self {
-        Scope {
-            outer_scope: None,
-            identifiers: table,
+impl<'a> IntoIterator for &'a Scope {
+    type Item = (&'a String, &'a Identifier);
+
+    type IntoIter = std::collections::hash_map::Iter<'a, String, Identifier>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.identifiers.iter()
+    }
+}
+
+/// All the scopes of a program, each referring to the one it is
+/// nested in by index. A closed scope stays in the arena, so the
+/// declarations of a block can still be read after it ends, but
+/// its identifiers are no longer visible.
+#[derive(Clone)]
+pub struct Scopes {
+    arena: Vec<Scope>,
+    /// The open scopes, the innermost last
+    stack: Vec<usize>,
+}
+
+impl Default for Scopes {
+    /// The predefined scope alone.
+    fn default() -> Self {
+        Self {
+            arena: vec![Scope::new(None, predefined())],
+            stack: vec![0],
         }
     }
+}
 
-    pub fn with_outer(
-        scope: Box<Scope>,
-        identifiers: Identifiers
-    ) -> Box<Self> {
-        Box::new(Scope {
-            outer_scope: Some(scope),
-            identifiers,
-        })
+impl Scopes {
+    /// The index of the innermost open scope.
+    pub fn current(&self) -> usize {
+        *self.stack.last().expect("the predefined scope is never closed")
     }
 
-    pub fn empty_with_outer(scope: Box<Scope>) -> Box<Self> {
-        Self::with_outer(scope, Identifiers::new())
+    /// Opens a scope nested in the current one, returning its index.
+    pub fn push(&mut self, identifiers: Identifiers) -> usize {
+        let index = self.arena.len();
+        self.arena.push(Scope::new(Some(self.current()), identifiers));
+        self.stack.push(index);
+        index
     }
 
-    pub fn collapse(self) -> Option<Box<Self>> {
-        self.outer_scope
+    /// Closes the current scope, except for the predefined one.
+    pub fn pop(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    pub fn scope(&self, index: usize) -> &Scope {
+        &self.arena[index]
+    }
+
+    fn current_mut(&mut self) -> &mut Scope {
+        let index = self.current();
+        &mut self.arena[index]
     }
 
     pub fn put(
@@ -64,11 +114,12 @@ impl Scope {
         name: String,
         id: Identifier
     ) -> Result<(), ScopeError> {
-        if self.identifiers.contains_key(&name) {
+        let scope = self.current_mut();
+        if scope.identifiers.contains_key(&name) {
             return Err(ScopeError::new(name));
         }
 
-        self.identifiers.insert(name, id);
+        scope.identifiers.insert(name, id);
 
         Ok(())
     }
@@ -84,26 +135,93 @@ impl Scope {
         Ok(())
     }
 
-    pub fn get(&self, name: &str) -> Option<&Identifier> {
-        let maybe_id = self.identifiers.get(name);
-        if maybe_id.is_none() && self.outer_scope.is_some() {
-            return self.outer_scope.as_ref().unwrap().get(name);
-        }
+    /// Whether the current scope itself declares the name.
+    pub fn declares(&self, name: &str) -> bool {
+        self.arena[self.current()].identifiers.contains_key(name)
+    }
 
-        maybe_id
+    /// The predefined identifier of the name, if there is one.
+    pub fn predefined(&self, name: &str) -> Option<&Identifier> {
+        self.arena[0].identifiers.get(name)
     }
-}
 
-impl<'a> IntoIterator for &'a Scope {
-    type Item = (&'a String, &'a Identifier);
+    /// Records where an identifier of the current scope is declared,
+    /// keeping the first declaration of a redefined identifier.
+    pub fn declare(&mut self, name: &str, range: Range<usize>) {
+        let spans = &mut self.current_mut().spans;
+        if !spans.contains_key(name) {
+            spans.insert(name.to_string(), range);
+        }
+    }
 
-    type IntoIter = std::collections::hash_map::Iter<'a, String, Identifier>;
+    /// The innermost open scope declaring the name.
+    fn visible(&self, name: &str) -> Option<&Scope> {
+        let mut index = Some(self.current());
+        while let Some(i) = index {
+            let scope = &self.arena[i];
+            if scope.identifiers.contains_key(name) {
+                return Some(scope);
+            }
+            index = scope.parent;
+        }
+        None
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.identifiers.iter()
+    /// Where the identifier visible under the name is declared,
+    /// if it is declared in the source.
+    pub fn declaration(&self, name: &str) -> Option<Range<usize>> {
+        self.visible(name).and_then(|scope| scope.declaration(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Identifier> {
+        self.visible(name).and_then(|scope| scope.identifiers.get(name))
     }
 }
 
+fn predefined() -> Identifiers {
+    [
+        ("char".to_string(), Identifier::Type(Type::Char)),
+        ("integer".to_string(), Identifier::Type(Type::Integer)),
+        ("real".to_string(), Identifier::Type(Type::Real)),
+        ("boolean".to_string(), Identifier::Type(boolean())),
+        ("false".to_string(), Identifier::Constant(boolean())),
+        ("true".to_string(), Identifier::Constant(boolean())),
+        ("writeln_int".to_string(), Identifier::Procedure(
+            vec![Type::Integer]
+        )),
+        ("writeln_real".to_string(), Identifier::Procedure(
+            vec![Type::Real]
+        )),
+        ("exit".to_string(), Identifier::StandardProcedure(
+            StandardProcedure::Exit
+        )),
+        ("halt".to_string(), Identifier::StandardProcedure(
+            StandardProcedure::Halt
+        )),
+        ("trunc".to_string(), Identifier::StandardFunction(
+            StandardFunction::Trunc
+        )),
+        ("round".to_string(), Identifier::StandardFunction(
+            StandardFunction::Round
+        )),
+        ("odd".to_string(), Identifier::StandardFunction(
+            StandardFunction::Odd
+        )),
+        ("sqr".to_string(), Identifier::StandardFunction(
+            StandardFunction::Sqr
+        )),
+        ("abs".to_string(), Identifier::StandardFunction(
+            StandardFunction::Abs
+        )),
+        ("random".to_string(), Identifier::StandardFunction(
+            StandardFunction::Random
+        )),
+        ("randomize".to_string(), Identifier::StandardProcedure(
+            StandardProcedure::Randomize
+        ))
+    ].iter().cloned().collect()
+}
+
 #[derive(Debug)]
 pub struct ScopeError {
     id: String
@@ -112,7 +230,7 @@ pub struct ScopeError {
 impl ScopeError {
     pub fn new(id: String) -> Self {
         Self {
-            id: id
+            id
         }
     }
 
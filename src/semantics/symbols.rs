@@ -0,0 +1,62 @@
+use crate::semantics::{Identifier, Scopes, Type};
+
+/// The global declarations of a checked program
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SymbolTable {
+    /// The types the program declares, in source order
+    pub types: Vec<(String, Type)>,
+    /// The variables of the program and their types, in source order
+    pub variables: Vec<(String, Type)>,
+    /// The constants of the enumerated types the program declares
+    /// and the types they belong to, in source order
+    pub constants: Vec<(String, Type)>,
+    /// The procedures imported from the host and the types
+    /// of their parameters, sorted by name
+    pub procedures: Vec<(String, Vec<Type>)>,
+}
+
+impl SymbolTable {
+    /// Collects the declarations of the program scope, the one
+    /// at `index`, and the procedures of the scopes around it.
+    pub fn of(scopes: &Scopes, index: usize) -> Self {
+        let mut table = Self::default();
+        let scope = scopes.scope(index);
+        let mut declared: Vec<_> = scope.into_iter()
+            .map(|(name, id)| (scope.declaration(name), name, id))
+            .collect();
+        declared.sort_by_key(|(span, name, _)| {
+            (span.as_ref().map(|s| s.start), name.to_string())
+        });
+
+        for (_, name, id) in declared {
+            match id {
+                Identifier::Type(t) => {
+                    table.types.push((name.clone(), t.clone()))
+                },
+                Identifier::Variable(_, t) => {
+                    table.variables.push((name.clone(), t.clone()))
+                },
+                Identifier::Constant(t) => {
+                    table.constants.push((name.clone(), t.clone()))
+                },
+                _ => (),
+            }
+        }
+
+        let mut outer = scope.parent();
+        while let Some(index) = outer {
+            let scope = scopes.scope(index);
+            for (name, id) in scope {
+                if let Identifier::Procedure(types) = id {
+                    let types = types.to_vec();
+                    table.procedures.push((name.clone(), types));
+                }
+            }
+            outer = scope.parent();
+        }
+        table.procedures.sort_by(|a, b| a.0.cmp(&b.0));
+
+        table
+    }
+}
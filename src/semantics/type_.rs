@@ -1,13 +1,26 @@
-use std::{collections::LinkedList, fmt::Debug};
+use std::{convert::TryFrom, fmt::{self, Debug, Display}};
 
 use crate::semantics::Fields;
 
-pub type Enumeration = LinkedList<String>;
-pub type Types = LinkedList<Type>;
+/// How many constants of a scalar type or fields of a record
+/// its name shows before the rest are left out
+const SHOWN: usize = 3;
+/// The greatest character an array indexed by `char` has an element for
+const MAX_CHAR: i32 = 255;
+
+/// The constants of a scalar type in the order of their values
+pub type Enumeration = Vec<String>;
+pub type Types = Vec<Type>;
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Record(Fields),
+    Array(Array),
+    /// A pointer to the values of the type named, which may be
+    /// declared after the pointer type. The type of `nil`, which
+    /// points to nothing, has no name.
+    Pointer(String),
     Scalar(Enumeration),
     Integer,
     Real,
@@ -15,17 +28,181 @@ pub enum Type {
     Unknown
 }
 
+/// An array type, indexed by the values of the ordinal type `index`
+/// from `low` to `high`, which are kept as their ordinals
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Array {
+    pub packed: bool,
+    pub index: Box<Type>,
+    pub low: i32,
+    pub high: i32,
+    pub element: Box<Type>,
+}
+
+impl Array {
+    /// The number of the elements.
+    pub fn length(&self) -> u32 {
+        (i64::from(self.high) - i64::from(self.low) + 1) as u32
+    }
+
+    /// The type of a string literal of the length, which
+    /// is `packed array [1..length] of char` in Pascal.
+    pub fn string(length: usize) -> Type {
+        Type::Array(Array {
+            packed: true,
+            index: Box::new(Type::Integer),
+            low: 1,
+            high: length as i32,
+            element: Box::new(Type::Char),
+        })
+    }
+
+    /// Whether a string literal of the length can be stored in the
+    /// array, which takes as many characters as the literal holds.
+    pub fn holds_string(&self, length: usize) -> bool {
+        *self.element == Type::Char && self.length() as usize == length
+    }
+}
+
 pub fn boolean() -> Type{
-    Type::Scalar([ 
+    Type::Scalar(vec![
         "false".to_string(),
         "true".to_string()
-    ].iter().cloned().collect())
+    ])
+}
+
+impl Type {
+    /// The value of the constant `name` of a scalar type.
+    pub fn ordinal(&self, name: &str) -> Option<usize> {
+        match self {
+            Type::Scalar(constants) => constants.iter().position(|c| c == name),
+            _ => None,
+        }
+    }
+
+    /// The ordinals of the least and the greatest value of an ordinal
+    /// type, or none if the type is not ordinal.
+    pub fn range(&self) -> Option<(i32, i32)> {
+        match self {
+            Type::Integer => Some((i32::MIN, i32::MAX)),
+            Type::Char => Some((0, MAX_CHAR)),
+            Type::Scalar(constants) => {
+                Some((0, constants.len() as i32 - 1))
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the type has no value of its own, only its elements
+    /// or fields do, so its values are not kept on the stack.
+    pub fn is_structured(&self) -> bool {
+        matches!(self, Type::Record(_) | Type::Array(_))
+    }
+
+    /// How many bytes a value of the type takes in memory, if it can be
+    /// kept there. Every simple value takes four bytes, a character too.
+    pub fn size(&self) -> Option<u32> {
+        match self {
+            Type::Integer
+            | Type::Real
+            | Type::Char
+            | Type::Scalar(_)
+            | Type::Pointer(_) => Some(4),
+            Type::Array(array) => {
+                array.element.size()?.checked_mul(array.length())
+            },
+            Type::Record(fields) => fields.values()
+                .try_fold(0u32, |size, t| size.checked_add(t.size()?)),
+            Type::Unknown => None,
+        }
+    }
+
+    /// The offset of the field of a record from the start of the record
+    /// and the type of the field. The fields are kept in memory in the
+    /// order of their names, as the record does not keep the order in
+    /// which they are declared.
+    pub fn field(&self, name: &str) -> Option<(u32, &Type)> {
+        let fields = match self {
+            Type::Record(fields) => fields,
+            _ => return None
+        };
+        let t = fields.get(name)?;
+        let offset = fields.iter()
+            .filter(|(other, _)| other.as_str() < name)
+            .map(|(_, t)| t.size().unwrap_or(0))
+            .sum();
+        Some((offset, t))
+    }
+
+    /// The simple values a value of the type consists of in memory,
+    /// with their offsets from its start: the value itself if it is
+    /// simple, or the elements or the fields of a structured one.
+    pub fn leaves(&self) -> Vec<(u32, &Type)> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(0, &mut leaves);
+        leaves
+    }
+
+    fn collect_leaves<'a>(
+        &'a self,
+        offset: u32,
+        leaves: &mut Vec<(u32, &'a Type)>
+    ) {
+        match self {
+            Type::Record(fields) => {
+                let mut names: Vec<_> = fields.keys().collect();
+                names.sort();
+                let mut offset = offset;
+                for name in names {
+                    let t = &fields[name];
+                    t.collect_leaves(offset, leaves);
+                    offset += t.size().unwrap_or(0);
+                }
+            },
+            Type::Array(array) => {
+                let size = array.element.size().unwrap_or(0);
+                for i in 0..array.length() {
+                    array.element.collect_leaves(offset + i * size, leaves);
+                }
+            },
+            Type::Unknown => (),
+            t => leaves.push((offset, t)),
+        }
+    }
+
+    /// The value of an ordinal type with the ordinal as it is written
+    /// in Pascal: a number, a character literal or a constant name.
+    pub fn spell(&self, ordinal: i32) -> String {
+        let character = std::char::from_u32(ordinal as u32)
+            .filter(|c| !c.is_control() && *c != '\'');
+        match (self, character) {
+            (Type::Char, Some(c)) => format!("'{}'", c),
+            (Type::Char, None) => format!("chr({})", ordinal),
+            (Type::Scalar(_), _) => usize::try_from(ordinal).ok()
+                .and_then(|o| self.constant(o))
+                .map_or_else(|| ordinal.to_string(), |c| c.to_string()),
+            _ => ordinal.to_string(),
+        }
+    }
+
+    /// The constant of a scalar type with the value `ordinal`.
+    pub fn constant(&self, ordinal: usize) -> Option<&str> {
+        match self {
+            Type::Scalar(constants) => {
+                constants.get(ordinal).map(|c| c.as_str())
+            },
+            _ => None,
+        }
+    }
 }
 
 impl Debug for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let t = match self {
             Type::Record(_) => "Record",
+            Type::Array(_) => "Array",
+            Type::Pointer(_) => "Pointer",
             Type::Scalar(_) => "Scalar",
             Type::Integer => "Integer",
             Type::Real => "Real",
@@ -36,3 +213,159 @@ impl Debug for Type {
         write!(f, "{}", t)
     }
 }
+
+/// The type as it would be written in Pascal, such as `integer`,
+/// `(red, green, ...)`, `record x: real; y: real end`, `^node` or
+/// `array [1..10] of integer`, with the long lists of constants
+/// and fields shortened.
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Record(fields) => {
+                let mut names: Vec<_> = fields.keys().collect();
+                names.sort();
+                write!(f, "record ")?;
+                for (i, name) in names.iter().take(SHOWN).enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", name, fields[*name])?;
+                }
+                if names.len() > SHOWN {
+                    write!(f, "; ...")?;
+                }
+                write!(f, " end")
+            },
+            Type::Array(array) => {
+                if array.packed {
+                    write!(f, "packed ")?;
+                }
+                write!(
+                    f, "array [{}..{}] of {}",
+                    array.index.spell(array.low),
+                    array.index.spell(array.high),
+                    array.element
+                )
+            },
+            Type::Pointer(name) if name.is_empty() => write!(f, "nil"),
+            Type::Pointer(name) => write!(f, "^{}", name),
+            t if *t == boolean() => write!(f, "boolean"),
+            Type::Scalar(constants) => {
+                let shown = constants.iter().take(SHOWN);
+                write!(f, "(")?;
+                for (i, constant) in shown.enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", constant)?;
+                }
+                if constants.len() > SHOWN {
+                    write!(f, ", ...")?;
+                }
+                write!(f, ")")
+            },
+            Type::Integer => write!(f, "integer"),
+            Type::Real => write!(f, "real"),
+            Type::Char => write!(f, "char"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod type_tests {
+    use crate::semantics::Fields;
+
+    use super::*;
+
+    fn scalar(constants: &[&str]) -> Type {
+        Type::Scalar(constants.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn test_display_simple_types() {
+        assert_eq!(Type::Integer.to_string(), "integer");
+        assert_eq!(Type::Real.to_string(), "real");
+        assert_eq!(Type::Char.to_string(), "char");
+        assert_eq!(boolean().to_string(), "boolean");
+        assert_eq!(Type::Pointer("node".to_string()).to_string(), "^node");
+        assert_eq!(Type::Pointer(String::new()).to_string(), "nil");
+    }
+
+    #[test]
+    fn test_display_scalar_abbreviated() {
+        assert_eq!(scalar(&["red", "green"]).to_string(), "(red, green)");
+        assert_eq!(
+            scalar(&["a", "b", "c", "d", "e"]).to_string(),
+            "(a, b, c, ...)"
+        );
+    }
+
+    #[test]
+    fn test_display_array() {
+        let array = Type::Array(Array {
+            packed: false,
+            index: Box::new(Type::Integer),
+            low: -1,
+            high: 1,
+            element: Box::new(Type::Real),
+        });
+        assert_eq!(array.to_string(), "array [-1..1] of real");
+        assert_eq!(array.size(), Some(12));
+
+        assert_eq!(Array::string(5).to_string(), "packed array [1..5] of char");
+
+        let array = Type::Array(Array {
+            packed: false,
+            index: Box::new(Type::Char),
+            low: 'a' as i32,
+            high: 'z' as i32,
+            element: Box::new(Type::Integer),
+        });
+        assert_eq!(array.to_string(), "array ['a'..'z'] of integer");
+
+        let array = Type::Array(Array {
+            packed: false,
+            index: Box::new(scalar(&["red", "green", "blue"])),
+            low: 1,
+            high: 2,
+            element: Box::new(boolean()),
+        });
+        assert_eq!(array.to_string(), "array [green..blue] of boolean");
+        assert_eq!(Type::Char.spell(0), "chr(0)");
+    }
+
+    #[test]
+    fn test_display_record() {
+        let mut inner = Fields::new();
+        inner.insert("y".to_string(), Type::Real);
+        inner.insert("x".to_string(), Type::Real);
+        let mut fields = Fields::new();
+        fields.insert("at".to_string(), Type::Record(inner));
+        fields.insert("name".to_string(), Type::Char);
+
+        let record = Type::Record(fields);
+        assert_eq!(
+            record.to_string(),
+            "record at: record x: real; y: real end; name: char end"
+        );
+        assert_eq!(record.size(), Some(12));
+        assert_eq!(record.field("name"), Some((8, &Type::Char)));
+        let leaves: Vec<_> = record.leaves().into_iter()
+            .map(|(offset, t)| (offset, t.clone()))
+            .collect();
+        assert_eq!(leaves, [
+            (0, Type::Real),
+            (4, Type::Real),
+            (8, Type::Char),
+        ]);
+
+        let many = ["a", "b", "c", "d"].iter()
+            .map(|n| (n.to_string(), Type::Integer))
+            .collect();
+        assert_eq!(
+            Type::Record(many).to_string(),
+            "record a: integer; b: integer; c: integer; ... end"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::{
+    error::{CompilationError, ErrorPolicy, Errors, Lints},
+    metrics::Metrics,
+    parsing::code::Code,
+    source::SourceMap,
+    tokenization::{SimpleBuffer, TokenStream},
+    translation::Target,
+};
+
+/// The settings compilations run with. A session keeps no state
+/// between compilations and shares none with other sessions, so it
+/// can be sent to other threads and compile sources on all of them
+/// at once. Everything a compilation produces is in its `Compilation`.
+#[derive(Debug, Clone, Default)]
+pub struct CompilerSession {
+    target: Target,
+    optimize: bool,
+    policy: ErrorPolicy,
+    lints: Lints,
+}
+
+/// What compiling a source produced
+#[derive(Debug, Clone)]
+pub struct Compilation {
+    /// The module text, complete only if the compilation succeeded
+    pub text: Vec<u8>,
+    /// The errors in the order they were reported,
+    /// including the one that stopped the compilation
+    pub errors: Errors,
+    /// The error that stopped the compilation, if one did,
+    /// which is also the last of `errors`
+    pub fatal: Option<CompilationError>,
+    /// The source the positions of the errors point into
+    pub source_map: SourceMap,
+    pub metrics: Metrics,
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CompilerSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn with_optimizations(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_lints(mut self, lints: Lints) -> Self {
+        self.lints = lints;
+        self
+    }
+
+    /// Compiles the source, named `name` in the diagnostics.
+    pub fn compile(&self, name: Option<&str>, source: &[u8]) -> Compilation {
+        self.run(name, source, Code::compile)
+    }
+
+    /// Checks the source for correctness without generating the module.
+    pub fn check(&self, name: Option<&str>, source: &[u8]) -> Compilation {
+        self.run(name, source, Code::check)
+    }
+
+    fn run(
+        &self,
+        name: Option<&str>,
+        source: &[u8],
+        analyze: fn(Code<SimpleBuffer>) -> Result<Errors, CompilationError>
+    ) -> Compilation {
+        // Everything shared with the handlers lives only as long as
+        // this call and on this thread
+        let output = SharedBuffer::default();
+        let errors = Rc::new(RefCell::new(Errors::new()));
+        let metrics = Rc::new(RefCell::new(Metrics::default()));
+        let buf = SimpleBuffer::new(source, name.map(str::to_string));
+        let source_map = buf.source_map();
+
+        let (reported, measured) = (errors.clone(), metrics.clone());
+        let code = Code::new(TokenStream::new(buf), Box::new(output.clone()))
+            .with_target(self.target.clone())
+            .with_optimizations(self.optimize)
+            .with_error_policy(self.policy)
+            .with_lints(self.lints.clone())
+            .with_diagnostic_handler(move |e| reported.borrow_mut().push(e))
+            .with_metrics_handler(move |m| *measured.borrow_mut() = m);
+        let fatal = analyze(code).err();
+
+        let text = output.0.take();
+        let mut errors = errors.take();
+        // `Code` reports the error it stops at before returning it
+        if let Some(e) = &fatal {
+            let reported = errors.iter().last().is_some_and(|r| same(r, e));
+            if !reported {
+                errors.push(e.clone());
+            }
+        }
+        let metrics = metrics.take();
+        Compilation { text, errors, fatal, source_map, metrics }
+    }
+}
+
+/// Whether the errors are one reported twice: of the same kind,
+/// at the same position and with the same message.
+fn same(a: &CompilationError, b: &CompilationError) -> bool {
+    a.kind() == b.kind() && a.pos() == b.pos() && a.msg() == b.msg()
+}
+
+impl Compilation {
+    /// Whether the source compiled without errors.
+    pub fn succeeded(&self) -> bool {
+        self.fatal.is_none() && !self.errors.has_errors()
+    }
+
+    /// The reported errors, the one that stopped
+    /// the compilation among them.
+    pub fn all_errors(&self) -> Vec<CompilationError> {
+        self.errors.iter().cloned().collect()
+    }
+
+    /// The outcome in the form `Code::compile` returns it.
+    pub fn result(&self) -> Result<Errors, CompilationError> {
+        match &self.fatal {
+            Some(e) => Err(e.clone()),
+            None => Ok(self.errors.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+    use crate::error::{Lint, LintLevel};
+
+    const PROGRAMS: [&str; 3] = [
+        "program A; var a: integer; begin a := 1; writeln_int(a) end.",
+        "program B; var r: real; begin r := 2.5; writeln_real(r) end.",
+        "program C; var c: integer; begin c := x end.",
+    ];
+
+    #[test]
+    fn test_session_is_send() {
+        fn send_and_sync<T: Send + Sync>() {}
+        send_and_sync::<CompilerSession>();
+        send_and_sync::<Compilation>();
+    }
+
+    #[test]
+    fn test_session_compile() {
+        let session = CompilerSession::new();
+
+        let compiled = session.compile(Some("a.pas"), PROGRAMS[0].as_bytes());
+        assert!(compiled.succeeded(), "{:?}", compiled.all_errors());
+        assert!(compiled.text.starts_with(b"(module"));
+        assert!(compiled.metrics.tokens > 0);
+
+        let failed = session.check(Some("c.pas"), PROGRAMS[2].as_bytes());
+        assert!(!failed.succeeded());
+        assert!(failed.text.is_empty());
+        let rendered = failed.source_map.render(&failed.all_errors()[0]);
+        assert!(rendered.contains("c.pas:1:39"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_session_fatal_error() {
+        let failed = CompilerSession::new().check(None, b"program");
+
+        let fatal = failed.fatal.as_ref().unwrap();
+        assert_eq!(failed.errors.count(), 1);
+        let errors = failed.all_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), fatal.to_string());
+
+        let empty = CompilerSession::new().compile(Some("empty.pas"), b"");
+        assert!(empty.fatal.is_some());
+        assert_eq!(empty.all_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_session_unfinished_program() {
+        let program = b"program a; begin x := 1";
+        let failed = CompilerSession::new().check(None, program);
+
+        assert!(!failed.succeeded());
+        let errors = failed.all_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].pos().col, 23);
+    }
+
+    #[test]
+    fn test_session_warnings() {
+        let program = b"program D; var real: integer; begin real := 1 end.";
+
+        let compiled = CompilerSession::new().compile(None, program);
+        assert!(compiled.succeeded(), "{:?}", compiled.all_errors());
+        assert_eq!(compiled.errors.count(), 1);
+        assert!(compiled.text.starts_with(b"(module"));
+
+        let mut lints = Lints::default();
+        lints.set(Lint::ShadowedPredefined, LintLevel::Deny);
+        let denied = CompilerSession::new().with_lints(lints);
+        assert!(!denied.compile(None, program).succeeded());
+    }
+
+    #[test]
+    fn test_session_concurrent() {
+        let session = CompilerSession::new().with_optimizations(true);
+        let sequential: Vec<_> = PROGRAMS.iter()
+            .map(|p| session.compile(None, p.as_bytes()))
+            .collect();
+
+        let concurrent: Vec<_> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let session = session.clone();
+                    s.spawn(move || {
+                        let program = PROGRAMS[i % PROGRAMS.len()];
+                        session.compile(None, program.as_bytes())
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        for (i, compiled) in concurrent.iter().enumerate() {
+            let expected = &sequential[i % PROGRAMS.len()];
+            assert_eq!(compiled.text, expected.text);
+            let errors = compiled.all_errors().len();
+            assert_eq!(errors, expected.all_errors().len());
+        }
+    }
+}
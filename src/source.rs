@@ -0,0 +1,259 @@
+use crate::{
+    error::{CompilationError, CompilationErrorKind},
+    position::FilePosition,
+    tokenization::is_continuation
+};
+
+// The terminal colors of the messages and of the line numbers
+const ERROR: &str = "1;31";
+const WARNING: &str = "1;33";
+const NOTE: &str = "1;36";
+const GUTTER: &str = "1;34";
+const HELP: &str = "1;32";
+
+/// The source text of a compiled file together with the
+/// offsets of its lines, used to show diagnostics in context.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    file: Option<String>,
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(data: &[u8], file: Option<String>) -> Self {
+        let text = String::from_utf8_lossy(data).into_owned();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            file,
+            text,
+            line_starts,
+        }
+    }
+
+    pub fn file(&self) -> &Option<String> {
+        &self.file
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the text of the line with the given 1-based number,
+    /// without the line terminator.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        if line == 0 || line > self.line_starts.len() {
+            return None;
+        }
+
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line)
+            .map(|&e| e - 1)
+            .unwrap_or_else(|| self.text.len());
+
+        Some(self.text[start..end].trim_end_matches('\r'))
+    }
+
+    /// Returns the byte offset of `pos` in the source text.
+    /// The columns count characters rather than bytes.
+    pub fn offset(&self, pos: FilePosition) -> Option<usize> {
+        let line = self.line(pos.line)?;
+        let start = self.line_starts[pos.line - 1];
+        let column = line.char_indices()
+            .nth(pos.col.saturating_sub(1))
+            .map_or(line.len(), |(i, _)| i);
+        Some(start + column)
+    }
+
+    /// Returns the position of the byte at `offset` in the source text.
+    pub fn position(&self, offset: usize) -> FilePosition {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        let end = offset.clamp(start, self.text.len());
+        let column = self.text.as_bytes()[start..end].iter()
+            .filter(|&&b| !is_continuation(b))
+            .count();
+        FilePosition::new(line + 1, column + offset.saturating_sub(end) + 1)
+    }
+
+    /// Renders the error message followed by the offending line
+    /// with a marker under the reported column and the fixes
+    /// suggested for it, each under the line it changes unless
+    /// it is where the marker is.
+    pub fn render(&self, err: &CompilationError) -> String {
+        self.render_styled(err, false)
+    }
+
+    /// Renders the error like `render`, coloring it
+    /// with terminal escape codes if `colored` is set.
+    pub fn render_styled(
+        &self,
+        err: &CompilationError,
+        colored: bool
+    ) -> String {
+        let paint = |code: &str, text: &str| if colored {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        };
+
+        let color = match err.kind() {
+            CompilationErrorKind::InternalError => {
+                return paint(ERROR, &err.to_string())
+            },
+            CompilationErrorKind::Note => return paint(NOTE, &err.to_string()),
+            CompilationErrorKind::Warning => WARNING,
+            _ => ERROR,
+        };
+
+        let pos = err.pos();
+        let line = match self.line(pos.line) {
+            Some(line) => line,
+            None => return paint(color, &err.to_string()),
+        };
+        let widest = err.fixes().iter()
+            .map(|fix| fix.start.line)
+            .fold(pos.line, usize::max);
+        let gutter = " ".repeat(widest.to_string().len());
+        let snippet = |number: usize, line: &str, col: usize| format!(
+            "\n{} {}\n{}{}",
+            paint(GUTTER, &format!("{:>1$} |", number, gutter.len())), line,
+            paint(GUTTER, &format!("{} | ", gutter)),
+            " ".repeat(col.saturating_sub(1))
+        );
+
+        let mut rendered = format!(
+            "{}\n{}{}{}",
+            paint(color, &err.to_string()),
+            paint(GUTTER, &format!("{} |", gutter)),
+            snippet(pos.line, line, pos.col),
+            paint(color, "^")
+        );
+        for fix in err.fixes() {
+            let help = format!(" help: {}", fix.description());
+            // A fix where the error is goes next to its marker
+            if fix.start == pos {
+                rendered += &paint(HELP, &help);
+                continue;
+            }
+            let line = self.line(fix.start.line).unwrap_or_default();
+            rendered += "\n";
+            rendered += &paint(GUTTER, &format!("{} |", gutter));
+            rendered += &snippet(fix.start.line, line, fix.start.col);
+            rendered += &paint(HELP, &format!("^{}", help));
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod source_map_tests {
+    use super::*;
+    use crate::error::FixIt;
+
+    #[test]
+    fn test_line() {
+        let map = SourceMap::new(b"program a;\r\nbegin\nend.", None);
+
+        assert_eq!(map.line_count(), 3);
+        assert_eq!(map.line(1), Some("program a;"));
+        assert_eq!(map.line(2), Some("begin"));
+        assert_eq!(map.line(3), Some("end."));
+        assert_eq!(map.line(4), None);
+        assert_eq!(map.line(0), None);
+    }
+
+    #[test]
+    fn test_offset() {
+        let map = SourceMap::new(b"ab\ncd", None);
+
+        assert_eq!(map.offset(FilePosition::new(2, 2)), Some(4));
+        assert_eq!(map.offset(FilePosition::new(3, 1)), None);
+    }
+
+    #[test]
+    fn test_position() {
+        let map = SourceMap::new(b"ab\ncd", None);
+
+        assert_eq!(map.position(0), FilePosition::new(1, 1));
+        assert_eq!(map.position(3), FilePosition::new(2, 1));
+        assert_eq!(map.position(4), FilePosition::new(2, 2));
+    }
+
+    #[test]
+    fn test_columns_count_characters() {
+        let map = SourceMap::new("{ é } a\nb".as_bytes(), None);
+
+        assert_eq!(map.position(7), FilePosition::new(1, 7));
+        assert_eq!(map.offset(FilePosition::new(1, 7)), Some(7));
+        assert_eq!(map.offset(FilePosition::new(1, 20)), Some(8));
+        assert_eq!(map.position(9), FilePosition::new(2, 1));
+    }
+
+    #[test]
+    fn test_render() {
+        let map = SourceMap::new(b"begin\n  a := ;\nend.", None);
+        let err = CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            &None,
+            FilePosition::new(2, 8),
+            "illegal expression"
+        );
+
+        let expected = "SyntaxError at ~:2:8: illegal expression\n  \
+            |\n2 |   a := ;\n  |        ^";
+        assert_eq!(map.render(&err), expected);
+
+        let styled = map.render_styled(&err, true);
+        assert!(styled.starts_with("\x1b[1;31mSyntaxError"), "{:?}", styled);
+        assert!(
+            styled.contains("\x1b[1;34m2 |\x1b[0m   a := ;"),
+            "{:?}", styled
+        );
+        let plain = styled.replace("\x1b[1;31m", "")
+            .replace("\x1b[1;34m", "")
+            .replace("\x1b[0m", "");
+        assert_eq!(plain, expected);
+    }
+
+    #[test]
+    fn test_render_fix_its() {
+        let map = SourceMap::new(b"begin\n  a := 1\n  b := 2\nend.", None);
+        let err = CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            &None,
+            FilePosition::new(3, 3),
+            "expected K(End), found Id(\"b\")"
+        ).with_fix(FixIt::insertion(FilePosition::new(2, 9), ";"));
+
+        let expected = "SyntaxError at ~:3:3: \
+            expected K(End), found Id(\"b\")\n  \
+            |\n3 |   b := 2\n  |   ^\n  \
+            |\n2 |   a := 1\n  |         ^ help: insert ';' here";
+        assert_eq!(map.render(&err), expected);
+
+        let err = CompilationError::new(
+            CompilationErrorKind::SyntaxError,
+            &None,
+            FilePosition::new(2, 5),
+            "'=' compares values, use ':=' to assign"
+        ).with_fix(FixIt::replacement(
+            FilePosition::new(2, 5), FilePosition::new(2, 6), ":="
+        ));
+        let expected = "SyntaxError at ~:2:5: \
+            '=' compares values, use ':=' to assign\n  \
+            |\n2 |   a := 1\n  |     ^ help: replace with ':='";
+        assert_eq!(map.render(&err), expected);
+    }
+}
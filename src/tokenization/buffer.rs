@@ -1,24 +1,30 @@
 use std::{fs::File, io::Read};
-use crate::position::{START_POSITION, FilePosition};
+use crate::{position::{START_POSITION, FilePosition}, source::SourceMap};
 
+/// The bytes of a source, read as UTF-8: the columns of the positions
+/// count characters, so the continuation bytes of a character that
+/// takes several bytes do not move the position.
 pub trait Buffer {
     fn next(&mut self) -> std::io::Result<u8>;
     fn back(&mut self, count: usize);
-    fn range(&self, start: usize, end: usize) -> Vec<u8>;
+    /// The bytes from `start` up to `end`, clipped to the data.
+    fn range(&self, start: usize, end: usize) -> &[u8];
     fn file(&self) -> &Option<String>;
     fn shift(&self) -> usize;
     fn pos(&self) -> FilePosition;
     fn prev_pos(&self) -> FilePosition;
-    fn save_pos(&mut self);
-    fn restore_pos(&mut self);
+}
+
+/// Whether the byte continues a character that takes several bytes
+/// in UTF-8 rather than starts one.
+pub(crate) fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
 }
 
 pub struct SimpleBuffer {
     storage: Vec<u8>,
     pos: usize,
-    saved_pos: Option<usize>,
     file_pos: FilePosition,
-    saved_file_pos: Option<FilePosition>,
     prev_file_pos: FilePosition,
     file: Option<String>
 }
@@ -28,11 +34,9 @@ impl SimpleBuffer {
         Self {
             storage: Vec::from(data),
             pos: 0,
-            saved_pos: None,
             file_pos: START_POSITION,
-            saved_file_pos: None,
             prev_file_pos: START_POSITION,
-            file: file
+            file
         }
     }
 
@@ -42,6 +46,32 @@ impl SimpleBuffer {
         file.read_to_end(&mut data)?;
         Ok(Self::new(&data, Some(filepath)))
     }
+
+    pub fn source_map(&self) -> SourceMap {
+        SourceMap::new(&self.storage, self.file.clone())
+    }
+
+    /// The position of the character before the offset, which is
+    /// at the current position.
+    fn position_before(&self, offset: usize) -> FilePosition {
+        let FilePosition { line, col } = self.file_pos;
+        if col > 1 {
+            return FilePosition::new(line, col - 1);
+        }
+
+        // The character before is the line break ending the line above
+        let end = match offset.checked_sub(1) {
+            Some(end) => end,
+            None => return START_POSITION,
+        };
+        let start = self.storage[..end].iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let col = self.storage[start..end].iter()
+            .filter(|&&b| !is_continuation(b))
+            .count() + 1;
+        FilePosition::new(line - 1, col)
+    }
 }
 
 impl Buffer for SimpleBuffer {
@@ -51,12 +81,14 @@ impl Buffer for SimpleBuffer {
         if self.pos >= self.storage.len() {
             result = Ok(0);
         } else {
-            result = Ok(self.storage[self.pos]);
-            self.prev_file_pos = self.file_pos.clone();
-            if self.storage[self.pos] == b'\n' {
+            let byte = self.storage[self.pos];
+            result = Ok(byte);
+            if byte == b'\n' {
+                self.prev_file_pos = self.file_pos;
                 self.file_pos.line += 1;
                 self.file_pos.col = 1;
-            } else {
+            } else if !is_continuation(byte) {
+                self.prev_file_pos = self.file_pos;
                 self.file_pos.col += 1;
             }
         }
@@ -69,22 +101,19 @@ impl Buffer for SimpleBuffer {
     fn back(&mut self, count: usize) {
         for _ in 0..count {
             self.pos -= 1;
-            if self.pos < self.storage.len() {
-                if self.storage[self.pos] != b'\n' {
-                    self.file_pos.col -= 1;
-                } else {
-                    self.file_pos.line -= 1;
-                }
+            match self.storage.get(self.pos) {
+                Some(&byte) if !is_continuation(byte) => {
+                    self.file_pos = self.prev_file_pos;
+                    self.prev_file_pos = self.position_before(self.pos);
+                },
+                _ => (),
             }
         }
     }
 
-    fn range(&self, start: usize, end: usize) -> Vec<u8> {
-        let mut actual_end = end;
-        if end > self.storage.len() {
-            actual_end = self.storage.len();
-        }
-        Vec::from(&self.storage[start..actual_end])
+    fn range(&self, start: usize, end: usize) -> &[u8] {
+        let end = end.min(self.storage.len());
+        &self.storage[start.min(end)..end]
     }
 
     fn shift(&self) -> usize {
@@ -92,24 +121,11 @@ impl Buffer for SimpleBuffer {
     }
 
     fn pos(&self) -> FilePosition {
-        self.file_pos.clone()
+        self.file_pos
     }
 
     fn prev_pos(&self) -> FilePosition {
-        self.prev_file_pos.clone()
-    }
-
-    fn save_pos(&mut self) {
-        self.saved_pos = Some(self.pos);
-        self.saved_file_pos = Some(self.file_pos);
-    }
-
-    fn restore_pos(&mut self) {
-        if self.saved_pos.is_some() {
-            self.pos = self.saved_pos.unwrap();
-            self.saved_pos = None;
-            self.saved_file_pos = None;
-        }
+        self.prev_file_pos
     }
 
     fn file(&self) -> &Option<String> {
@@ -9,5 +9,7 @@ pub use token::{
     Operator,
     Punctuation,
     Relation,
+    Trivia,
 };
 pub use buffer::{Buffer, SimpleBuffer};
+pub(crate) use buffer::is_continuation;
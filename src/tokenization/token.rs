@@ -2,6 +2,7 @@
 /// "greater than", "less than", "greater or equal",
 /// "less or equal". 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Relation {
     Eq,
     Ne,
@@ -13,6 +14,7 @@ pub enum Relation {
 
 /// Operators
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Operator {
     Plus,
     Minus,
@@ -29,6 +31,7 @@ pub enum Operator {
 
 /// Keywords
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Keyword {
     If,
     Then,
@@ -48,12 +51,16 @@ pub enum Keyword {
     For,
     To,
     Downto,
+    Case,
     Record,
     Type,
+    Packed,
+    Nil,
 }
 
 /// Punctuation symbols
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Punctuation {
     Lbracket,
     Rbracket,
@@ -64,9 +71,16 @@ pub enum Punctuation {
     Semicolon,
     Colon,
     Range,
+    /// `^`, which makes a pointer type
+    Caret,
 }
 
+/// A token of the source. Names, numbers and literals own their text,
+/// the one copy made of the lexeme: tokens outlive the buffer they are
+/// read from, as in the recovery sets and the incremental analysis.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     O(Operator),
     R(Relation),
@@ -78,3 +92,59 @@ pub enum Token {
     EOF,
     Unknown,
 }
+
+impl Token {
+    /// The group the token belongs to, as named in token dumps.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Token::O(_) => "operator",
+            Token::R(_) => "relation",
+            Token::K(_) => "keyword",
+            Token::P(_) => "punctuation",
+            Token::Literal(_) => "literal",
+            Token::Id(_) => "identifier",
+            Token::Number(_) => "number",
+            Token::EOF => "eof",
+            Token::Unknown => "unknown",
+        }
+    }
+}
+
+/// Text between tokens that carries no meaning:
+/// whitespace and comments
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trivia {
+    Whitespace(String),
+    Comment(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(text) | Trivia::Comment(text) => text,
+        }
+    }
+}
+
+/// A token with the text it is written as and the trivia before it.
+/// The texts of all the tokens of a source, up to and including `EOF`,
+/// add up to the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenWithTrivia {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub text: String,
+}
+
+impl TokenWithTrivia {
+    /// The text of the trivia followed by the text of the token.
+    pub fn source(&self) -> String {
+        let mut source: String = self.leading.iter()
+            .map(Trivia::text)
+            .collect();
+        source += &self.text;
+        source
+    }
+}
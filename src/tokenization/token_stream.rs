@@ -1,91 +1,266 @@
-use std::{collections::HashSet};
-use std::collections::HashMap;
-use std::iter::FromIterator;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ops::Range;
 use crate::position::{FilePosition, START_POSITION};
 use crate::error::{CompilationError, CompilationErrorKind};
 use crate::tokenization::{
     token::*,
-    buffer::{Buffer}
+    buffer::{Buffer, is_continuation}
 };
 
 type TokenizationResult = std::result::Result<Token, CompilationError>;
 
+/// The length of the longest reserved word
+const MAX_RESERVED_WORD_LEN: usize = 9;
+
+/// The reserved word written as `lexeme` in any case. The lexeme
+/// is lowercased on the stack and matched against the words, so
+/// the identifiers that are not reserved words are the only ones
+/// allocated.
+fn reserved_word(lexeme: &str) -> Option<Token> {
+    let bytes = lexeme.as_bytes();
+    if bytes.len() > MAX_RESERVED_WORD_LEN {
+        return None;
+    }
+
+    let mut lowered = [0; MAX_RESERVED_WORD_LEN];
+    for (l, b) in lowered.iter_mut().zip(bytes) {
+        *l = b.to_ascii_lowercase();
+    }
+    let token = match &lowered[..bytes.len()] {
+        b"program" => Token::K(Keyword::Program),
+        b"procedure" => Token::K(Keyword::Procedure),
+        b"div" => Token::O(Operator::IntegerDivide),
+        b"mod" => Token::O(Operator::Modulus),
+        b"record" => Token::K(Keyword::Record),
+        b"xor" => Token::O(Operator::Xor),
+        b"or" => Token::O(Operator::Or),
+        b"and" => Token::O(Operator::And),
+        b"not" => Token::O(Operator::Not),
+        b"if" => Token::K(Keyword::If),
+        b"then" => Token::K(Keyword::Then),
+        b"else" => Token::K(Keyword::Else),
+        b"of" => Token::K(Keyword::Of),
+        b"while" => Token::K(Keyword::While),
+        b"do" => Token::K(Keyword::Do),
+        b"begin" => Token::K(Keyword::Begin),
+        b"end" => Token::K(Keyword::End),
+        b"var" => Token::K(Keyword::Var),
+        b"type" => Token::K(Keyword::Type),
+        b"array" => Token::K(Keyword::Array),
+        b"packed" => Token::K(Keyword::Packed),
+        b"nil" => Token::K(Keyword::Nil),
+        b"for" => Token::K(Keyword::For),
+        b"repeat" => Token::K(Keyword::Repeat),
+        b"with" => Token::K(Keyword::With),
+        b"until" => Token::K(Keyword::Until),
+        b"to" => Token::K(Keyword::To),
+        b"downto" => Token::K(Keyword::Downto),
+        b"case" => Token::K(Keyword::Case),
+        _ => return None,
+    };
+    Some(token)
+}
+
+/// A token read from the buffer and what reading it left behind
+struct Lexed {
+    /// The byte offsets of the token
+    range: Range<usize>,
+    /// Where the token starts
+    start_pos: FilePosition,
+    /// The position of the last character of the token
+    prev_pos: FilePosition,
+    /// The position of the character after the token
+    end_pos: FilePosition,
+    trivia: Vec<Trivia>,
+    /// The directives in the comments before the token
+    directives: Vec<(String, FilePosition)>,
+    errors: Vec<CompilationError>,
+}
+
 /// A stream of tokens
 pub struct TokenStream<T: Buffer> {
-    prev_pos: FilePosition,
     buffer: T,
-    reserved_words: HashMap<String, Token>,
     lexeme_start: usize,
-    state: i32
+    token_start: usize,
+    token_pos: FilePosition,
+    literal_pos: FilePosition,
+    state: i32,
+    /// The errors of the tokens read, not yet taken
+    errors: Vec<CompilationError>,
+    /// The errors and trivia of the token being lexed
+    lexer_errors: Vec<CompilationError>,
+    trivia: Vec<Trivia>,
+    keep_trivia: bool,
+    /// The directives of the token being lexed
+    directives: Vec<(String, FilePosition)>,
+    /// The token read last
+    current: Lexed,
+    /// The tokens lexed ahead by `available`, the next one first
+    lookahead: VecDeque<(TokenizationResult, Lexed)>,
 }
 
 impl<T: Buffer> TokenStream<T> {
     /// Creates a new TokenStream based on the provided stream.
     pub fn new(buffer: T) -> TokenStream<T> {
         TokenStream {
-            prev_pos: START_POSITION,
-            buffer: buffer,
+            buffer,
             state: 1,
-            reserved_words: [
-                ("program".to_string(), Token::K(Keyword::Program)),
-                ("procedure".to_string(), Token::K(Keyword::Procedure)),
-                ("div".to_string(), Token::O(Operator::IntegerDivide)),
-                ("record".to_string(), Token::K(Keyword::Record)),
-                ("xor".to_string(), Token::O(Operator::Xor)),
-                ("or".to_string(), Token::O(Operator::Or)),
-                ("and".to_string(), Token::O(Operator::And)),
-                ("not".to_string(), Token::O(Operator::Not)),
-                ("if".to_string(), Token::K(Keyword::If)),
-                ("then".to_string(), Token::K(Keyword::Then)),
-                ("else".to_string(), Token::K(Keyword::Else)),
-                ("of".to_string(), Token::K(Keyword::Of)),
-                ("while".to_string(), Token::K(Keyword::While)),
-                ("do".to_string(), Token::K(Keyword::Do)),
-                ("begin".to_string(), Token::K(Keyword::Begin)),
-                ("end".to_string(), Token::K(Keyword::End)),
-                ("var".to_string(), Token::K(Keyword::Var)),
-                ("type".to_string(), Token::K(Keyword::Type)),
-                ("array".to_string(), Token::K(Keyword::Array)),
-                ("for".to_string(), Token::K(Keyword::For)),
-                ("repeat".to_string(), Token::K(Keyword::Repeat)),
-                ("with".to_string(), Token::K(Keyword::With)),
-                ("until".to_string(), Token::K(Keyword::Until)),
-                ("to".to_string(), Token::K(Keyword::To)),
-                ("downto".to_string(), Token::K(Keyword::Downto))
-            ].iter().cloned().collect(),
             lexeme_start: 0,
+            token_start: 0,
+            token_pos: START_POSITION,
+            literal_pos: START_POSITION,
+            errors: Vec::new(),
+            lexer_errors: Vec::new(),
+            trivia: Vec::new(),
+            keep_trivia: false,
+            directives: Vec::new(),
+            current: Lexed {
+                range: 0..0,
+                start_pos: START_POSITION,
+                prev_pos: START_POSITION,
+                end_pos: START_POSITION,
+                trivia: Vec::new(),
+                directives: Vec::new(),
+                errors: Vec::new(),
+            },
+            lookahead: VecDeque::new(),
         }
     }
 
+    /// Makes the stream keep the whitespace and comments,
+    /// which `next_with_trivia` attaches to the tokens after them.
+    pub fn with_trivia(mut self) -> Self {
+        self.keep_trivia = true;
+        self
+    }
+
     pub fn filepath(&self) -> &Option<String> {
         self.buffer.file()
     }
 
+    /// The position of the character after the token read last.
     pub fn pos(&self) -> FilePosition {
-        self.buffer.pos()
+        self.current.end_pos
     }
 
+    /// The position of the last character of the token read last.
     pub fn prev_pos(&self) -> FilePosition {
-        self.buffer.prev_pos()
+        self.current.prev_pos
+    }
+
+    /// Takes the errors recovered from since the last call.
+    /// An unexpected character is reported here and
+    /// read as `Token::Unknown`.
+    pub fn take_errors(&mut self) -> Vec<CompilationError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Reads a token along with its text and the whitespace and
+    /// comments before it, which are only kept by a stream
+    /// made `with_trivia`.
+    pub fn next_with_trivia(
+        &mut self
+    ) -> Result<TokenWithTrivia, CompilationError> {
+        let token = self.next()?;
+        let text = if token == Token::EOF {
+            String::new()
+        } else {
+            self.token_text()
+        };
+
+        Ok(TokenWithTrivia {
+            leading: std::mem::take(&mut self.current.trivia),
+            token,
+            text,
+        })
+    }
+
+    /// The token read last as it is written in the source.
+    pub fn token_text(&self) -> String {
+        self.text(self.current.range.clone())
+    }
+
+    /// The source text at the byte offsets, as it is written.
+    pub fn text(&self, range: Range<usize>) -> String {
+        let text = self.buffer.range(range.start, range.end);
+        String::from_utf8_lossy(text).into_owned()
+    }
+
+    /// Takes the directives in the comments before the token read
+    /// last, with where they start, so that they are only taken once.
+    pub fn take_directives(&mut self) -> Vec<(String, FilePosition)> {
+        std::mem::take(&mut self.current.directives)
+    }
+
+    /// Where the token read last starts.
+    pub fn token_pos(&self) -> FilePosition {
+        self.current.start_pos
+    }
+
+    /// The byte offsets of the token read last in the source.
+    pub fn token_range(&self) -> Range<usize> {
+        self.current.range.clone()
     }
 
     /// Reads a token from the `stream`.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> TokenizationResult {
+        let (result, lexed) = match self.lookahead.pop_front() {
+            Some(lexed) => lexed,
+            None => self.read(),
+        };
+        self.current = lexed;
+        self.errors.append(&mut self.current.errors);
+        result
+    }
+
+    /// Lexes a token from the buffer.
+    fn read(&mut self) -> (TokenizationResult, Lexed) {
+        let result = self.lex();
+        let text = self.buffer.range(self.token_start, self.buffer.shift());
+        let lexed = Lexed {
+            range: self.token_start..self.token_start + text.len(),
+            start_pos: self.token_pos,
+            prev_pos: self.buffer.prev_pos(),
+            end_pos: self.buffer.pos(),
+            trivia: std::mem::take(&mut self.trivia),
+            directives: std::mem::take(&mut self.directives),
+            errors: std::mem::take(&mut self.lexer_errors),
+        };
+        (result, lexed)
+    }
+
+    fn lex(&mut self) -> TokenizationResult {
         loop {
             let pos = self.buffer.shift();
             let c = self.buffer.next().unwrap() as char;
 
             match self.state {
                 1 => {
-                    if c.is_whitespace() {
+                    if c.is_ascii_whitespace() {
                         self.skip_whitespace();
+                        if self.keep_trivia {
+                            let text = self.text_from(pos);
+                            self.trivia.push(Trivia::Whitespace(text));
+                        }
                     } else if c == '{' {
+                        let comment_pos = self.buffer.prev_pos();
                         self.skip_comment();
+                        let text = self.text_from(pos);
+                        if text.starts_with("{$") || text.starts_with("{%") {
+                            self.directives.push((text.clone(), comment_pos));
+                        }
+                        if self.keep_trivia {
+                            self.trivia.push(Trivia::Comment(text));
+                        }
                     } else {
                         self.lexeme_start = pos;
-                        if c.is_numeric() {
+                        self.token_start = pos;
+                        self.token_pos = self.buffer.prev_pos();
+                        if c.is_ascii_digit() {
                             self.state = 4;
-                        } else if c.is_alphabetic() {
+                        } else if c.is_ascii_alphabetic() {
                             self.state = 2;
                         } else {
                             match c {
@@ -93,6 +268,7 @@ impl<T: Buffer> TokenStream<T> {
                                 ':' => self.state = 20,
                                 '\'' => {
                                     self.lexeme_start = self.buffer.shift();
+                                    self.literal_pos = self.buffer.prev_pos();
                                     self.state = 13;
                                 },
                                 '<' => {
@@ -152,12 +328,27 @@ impl<T: Buffer> TokenStream<T> {
                                         )
                                     )
                                 },
+                                '^' => {
+                                    self.state = 1;
+                                    return Ok(
+                                        Token::P(Punctuation::Caret)
+                                    )
+                                },
                                 '\0' => return Ok(Token::EOF),
+                                _ if !c.is_ascii() => {
+                                    self.state = 1;
+                                    let err = self.non_ascii(pos);
+                                    self.lexer_errors.push(err);
+                                    return Ok(Token::Unknown)
+                                },
                                 _ => {
                                     self.state = 1;
-                                    return Err(self.error(
-                                        "Unexpected character"
-                                    ))
+                                    let err = self.error(&format!(
+                                        "unexpected character '{}'",
+                                        c.escape_default()
+                                    ));
+                                    self.lexer_errors.push(err);
+                                    return Ok(Token::Unknown)
                                 }
                             }
                         }
@@ -165,7 +356,7 @@ impl<T: Buffer> TokenStream<T> {
 
                 },
                 2 => {
-                    if !c.is_alphanumeric() && c != '_' {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
                         self.buffer.back(1);
                         self.state = 1;
                         return Ok(self.identifier());
@@ -174,7 +365,9 @@ impl<T: Buffer> TokenStream<T> {
                 4 => {
                     if c == '.' {
                         self.state = 5;
-                    } else if !c.is_numeric() {
+                    } else if c.eq_ignore_ascii_case(&'e') {
+                        self.state = 7;
+                    } else if !c.is_ascii_digit() {
                         self.buffer.back(1);
                         let number = self.number();
                         self.state = 1;
@@ -182,10 +375,11 @@ impl<T: Buffer> TokenStream<T> {
                     }
                 },
                 5 => {
-                    if c.is_numeric() {
+                    if c.is_ascii_digit() {
                         self.state = 6;   
-                    }
-                    else if c == '.' {
+                    } else {
+                        // The dot is not a decimal point but the start
+                        // of a range or a dot of its own
                         self.buffer.back(2);
                         let number = self.number();
                         self.state = 1;
@@ -193,9 +387,9 @@ impl<T: Buffer> TokenStream<T> {
                     }
                 },
                 6 => {
-                    if c.to_ascii_lowercase() == 'e' {
+                    if c.eq_ignore_ascii_case(&'e') {
                         self.state = 7;
-                    } else if !c.is_numeric() {
+                    } else if !c.is_ascii_digit() {
                         self.buffer.back(1);
                         let number = self.number();
                         self.state = 1;
@@ -203,14 +397,21 @@ impl<T: Buffer> TokenStream<T> {
                     }
                 },
                 7 => {
-                    if c.is_numeric() {
+                    if c.is_ascii_digit() {
                         self.state = 9;
                     } else if c == '+' || c == '-' {
                         self.state = 8;
+                    } else {
+                        self.buffer.back(1);
+                        self.state = 1;
+                        return Err(self.error(
+                            "Exponent in scientific notation \
+                            must be a number"
+                        ))
                     }
                 },
                 8 => {
-                    if c.is_numeric() {
+                    if c.is_ascii_digit() {
                         self.state = 9;
                     } else {
                         self.state = 1;
@@ -221,19 +422,35 @@ impl<T: Buffer> TokenStream<T> {
                     }
                 },
                 9 => {
-                    if !c.is_numeric() {
+                    if !c.is_ascii_digit() {
+                        self.buffer.back(1);
                         let number = self.number();
+                        self.state = 1;
                         return Ok(number);
                     }
                 }
                 13 => {
                     if c == '\'' {
                         self.state = 1;
+                        let literal = self.buffer
+                            .range(self.lexeme_start, self.buffer.shift());
+                        if std::str::from_utf8(literal).is_err() {
+                            let err = self.error_at(
+                                self.literal_pos,
+                                "string literal is not valid UTF-8"
+                            );
+                            self.lexer_errors.push(err);
+                        }
                         return Ok(self.literal());
-                    } else if c == '\n' {
+                    } else if c == '\n' || c == '\0' {
+                        if c == '\0' {
+                            self.buffer.back(1);
+                        }
                         self.state = 1;
-                        return Err(self.error(
-                            "string literal cannot span multiple lines"
+                        return Err(self.error_at(
+                            self.literal_pos,
+                            "unterminated string literal, \
+                            a literal must be closed on the line it starts"
                         ))
                     }
                 },
@@ -285,97 +502,138 @@ impl<T: Buffer> TokenStream<T> {
     }
 
     /// Reports whether some `token` in `tokens`
-    /// is present further in the stream.
+    /// is present further in the stream. The tokens read on the way
+    /// are kept for `next`, so the stream is read only once however
+    /// many times it is searched.
     pub fn available(
         &mut self, tokens: &[Token]
     ) -> Result<bool, CompilationError> {
-
-        let token_set: HashSet<Token> = HashSet::from_iter(
-            tokens.iter().cloned()
-        );
-
-        self.buffer.save_pos();
-        let result;
+        let mut i = 0;
         loop {
-            let token = self.next()?;
-
-            if token == Token::EOF {
-                if token_set.contains(&Token::EOF) {
-                    result = true
-                } else {
-                    result = false;
-                }
-                break;
+            if i == self.lookahead.len() {
+                let lexed = self.read();
+                self.lookahead.push_back(lexed);
             }
-    
-            if token_set.contains(&token) {
-                result = true;
-                break;
+
+            match &self.lookahead[i].0 {
+                Ok(Token::EOF) => return Ok(tokens.contains(&Token::EOF)),
+                Ok(token) if tokens.contains(token) => return Ok(true),
+                Ok(_) => i += 1,
+                Err(e) => return Err(e.clone()),
             }
         }
-
-        self.buffer.restore_pos();
-        Ok(result)
     }
 
     fn skip_whitespace(&mut self) {
         loop {
             let c = self.buffer.next().unwrap() as char;
-            if !c.is_whitespace() {
+            if !c.is_ascii_whitespace() {
                 self.buffer.back(1);
                 break;
             }
         }
     }
 
+    /// Skips a comment whose opening brace has been read.
+    /// Comments nest, as in Free Pascal.
     fn skip_comment(&mut self) {
+        let mut depth = 1;
         loop {
-            let c = self.buffer.next().unwrap() as char;
-            if c == '}' || c == '\0' {
-                self.buffer.next().unwrap();
-                break;
+            match self.buffer.next().unwrap() as char {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                '\0' => break,
+                _ => ()
             }
         }
     }
 
+    /// Reads the rest of a character outside ASCII whose first byte is
+    /// at `start` and reports it, as only comments and literals may
+    /// have such characters. A byte that does not start a character
+    /// in UTF-8 is reported on its own.
+    fn non_ascii(&mut self, start: usize) -> CompilationError {
+        let lead = self.buffer.range(start, start + 1)[0];
+        let width = match lead {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => 1,
+        };
+        for _ in 1..width {
+            match self.buffer.next() {
+                Ok(byte) if is_continuation(byte) => (),
+                _ => {
+                    self.buffer.back(1);
+                    break;
+                }
+            }
+        }
+
+        let bytes = self.buffer.range(start, self.buffer.shift());
+        let msg = match std::str::from_utf8(bytes) {
+            Ok(c) => format!(
+                "unexpected character '{}', only comments and \
+                literals may have characters outside ASCII",
+                c
+            ),
+            Err(_) => format!("invalid UTF-8 byte 0x{:02x}", lead),
+        };
+        self.error_at(self.token_pos, &msg)
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        let text = self.buffer.range(start, self.buffer.shift());
+        String::from_utf8_lossy(text).into_owned()
+    }
+
     fn number(&self) -> Token {
-        let lexeme = self.lexeme();
-        Token::Number(lexeme)
+        Token::Number(self.lexeme().to_lowercase())
     }
 
     fn identifier(&self) -> Token {
         let lexeme = self.lexeme();
-        if self.reserved_words.contains_key(&lexeme) {
-            self.reserved_words.get(&lexeme).unwrap().clone()
-        } else {
-            Token::Id(lexeme)
+        match reserved_word(&lexeme) {
+            Some(token) => token,
+            None => Token::Id(lexeme.to_lowercase()),
         }
     }
 
     fn literal(&self) -> Token {
-        let lexeme = self.lexeme().strip_suffix('\'').unwrap().to_string();
-        Token::Literal(lexeme)
+        let lexeme = self.lexeme();
+        let value = lexeme.strip_suffix('\'').unwrap_or(&lexeme);
+        Token::Literal(value.to_string())
     }
 
-    fn lexeme(&self) -> String {
-        let range = self.buffer.range(
-            self.lexeme_start,
-            self.buffer.shift()
-        );
-        String::from_utf8(range).unwrap().to_lowercase()
+    /// The text of the lexeme read last, borrowed from the buffer
+    /// unless it has bytes that are not UTF-8, which are replaced.
+    fn lexeme(&self) -> Cow<'_, str> {
+        let lexeme = self.buffer.range(self.lexeme_start, self.buffer.shift());
+        String::from_utf8_lossy(lexeme)
     }
 
+    /// An error in the token being read, which it points at.
     fn error(&self, msg: &str) -> CompilationError {
+        self.error_at(self.token_pos, msg)
+    }
+
+    fn error_at(&self, pos: FilePosition, msg: &str) -> CompilationError {
         CompilationError::new(
             CompilationErrorKind::LexicalError,
-            &self.filepath(),
-            self.buffer.prev_pos(),
+            self.filepath(),
+            pos,
             msg
         )
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod token_stream_tests {
     use super::*;
     use crate::tokenization::{Token, Keyword, Operator, Punctuation, Relation};
@@ -453,6 +711,8 @@ mod token_stream_tests {
             "1.13e-12",
             "1.10e120",
             "1.13E1",
+            "1e5",
+            "25E-3",
         ];
 
         for num in numbers.iter() {
@@ -460,7 +720,7 @@ mod token_stream_tests {
             let token = ts.next().unwrap();
             match token {
                 Token::Number(lexeme) =>
-                    assert_eq!(lexeme, *num.to_lowercase()),
+                    assert_eq!(lexeme, num.trim().to_lowercase()),
                 _ => assert!(false)
             }
         }
@@ -485,6 +745,85 @@ mod token_stream_tests {
         }
     }
 
+    #[test]
+    fn test_next_number_then_dot() {
+        let expected = [
+            Token::Number("1".to_string()),
+            Token::P(Punctuation::Dot),
+            Token::Id("x".to_string()),
+            Token::Number("0".to_string()),
+            Token::P(Punctuation::Dot),
+            Token::EOF,
+        ];
+        assert_token_sequence(&expected, token_stream("1.x 0."));
+    }
+
+    #[test]
+    fn test_next_invalid_utf8() {
+        let b = SimpleBuffer::new(b"ab\xcd c '\xff'", None);
+        let mut ts = TokenStream::new(b);
+        let expected = [
+            Token::Id("ab".to_string()),
+            Token::Unknown,
+            Token::Id("c".to_string()),
+            Token::Literal("\u{fffd}".to_string()),
+            Token::EOF,
+        ];
+        for t in expected.iter() {
+            assert_eq!(*t, ts.next().unwrap());
+        }
+
+        let errs = ts.take_errors();
+        assert_eq!(errs.len(), 2);
+        assert_eq!(errs[0].pos(), FilePosition { line: 1, col: 3 });
+        assert!(errs[0].msg().contains("0xcd"), "{}", errs[0]);
+        assert_eq!(errs[1].pos(), FilePosition { line: 1, col: 7 });
+    }
+
+    #[test]
+    fn test_next_non_ascii() {
+        let input = "{ é } a := 'é';\n  naïve";
+        let mut ts = token_stream(input);
+        let expected = [
+            (Token::Id("a".to_string()), 1, 7),
+            (Token::O(Operator::Assign), 1, 9),
+            (Token::Literal("é".to_string()), 1, 12),
+            (Token::P(Punctuation::Semicolon), 1, 15),
+            (Token::Id("na".to_string()), 2, 3),
+            (Token::Unknown, 2, 5),
+            (Token::Id("ve".to_string()), 2, 6),
+        ];
+        for (t, line, col) in expected.iter() {
+            assert_eq!(*t, ts.next().unwrap());
+            let pos = FilePosition { line: *line, col: *col };
+            assert_eq!(ts.token_pos(), pos, "{:?}", t);
+        }
+
+        let errs = ts.take_errors();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].pos(), FilePosition { line: 2, col: 5 });
+        assert!(errs[0].msg().contains("'ï'"), "{}", errs[0]);
+    }
+
+    #[test]
+    fn test_next_reserved_words_any_case() {
+        let input = "PROGRAM Procedure downTo ProcedureS Programs";
+        let expected = [
+            Token::K(Keyword::Program),
+            Token::K(Keyword::Procedure),
+            Token::K(Keyword::Downto),
+            Token::Id("procedures".to_string()),
+            Token::Id("programs".to_string()),
+            Token::EOF,
+        ];
+        assert_token_sequence(&expected, token_stream(input));
+
+        let procedure = Some(Token::K(Keyword::Procedure));
+        assert_eq!(reserved_word("PROCEDURE"), procedure);
+        assert_eq!(reserved_word("procedure_"), None);
+        assert_eq!(reserved_word("DÖ"), None);
+    }
+
     #[test]
     fn test_next_keywords() {
         let keywords = [
@@ -493,6 +832,9 @@ mod token_stream_tests {
             ("end", Keyword::End),
             ("of", Keyword::Of),
             ("var", Keyword::Var),
+            ("packed", Keyword::Packed),
+            ("nil", Keyword::Nil),
+            ("case", Keyword::Case),
         ];
 
         for keyword in keywords.iter() {
@@ -542,9 +884,17 @@ mod token_stream_tests {
         }
     }
 
+    #[test]
+    fn test_next_literal_keeps_case() {
+        let mut ts = token_stream("'HeLLo' HeLLo");
+
+        assert_eq!(ts.next().unwrap(), Token::Literal("HeLLo".to_string()));
+        assert_eq!(ts.next().unwrap(), Token::Id("hello".to_string()));
+    }
+
     #[test]
     fn test_next_punctuation() {
-        let input = "()[],...";
+        let input = "()[],...^";
         let ts = token_stream(input);
 
         let expected_tokens = [
@@ -555,6 +905,7 @@ mod token_stream_tests {
             Token::P(Punctuation::Comma),
             Token::P(Punctuation::Range),
             Token::P(Punctuation::Dot),
+            Token::P(Punctuation::Caret),
         ];
 
         assert_token_sequence(&expected_tokens, ts);
@@ -667,7 +1018,7 @@ mod token_stream_tests {
         let mut ts = token_stream(input);
 
         let err = ts.next().unwrap_err();
-        assert_eq!(err.pos(), FilePosition { line: 1, col: 6 });
+        assert_eq!(err.pos(), FilePosition { line: 1, col: 1 });
     }
 
     #[test]
@@ -677,7 +1028,51 @@ mod token_stream_tests {
 
         ts.next().unwrap();
         let err = ts.next().unwrap_err();
-        assert_eq!(err.pos(), FilePosition { line: 2, col: 6 });
+        assert_eq!(err.pos(), FilePosition { line: 2, col: 1 });
+    }
+
+    #[test]
+    fn test_next_unexpected_character() {
+        let input = "a # b";
+        let mut ts = token_stream(input);
+
+        let expected_tokens = [
+            Token::Id("a".to_string()),
+            Token::Unknown,
+            Token::Id("b".to_string()),
+            Token::EOF
+        ];
+
+        for t in expected_tokens.iter() {
+            assert_eq!(*t, ts.next().unwrap());
+        }
+
+        let errs = ts.take_errors();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].pos(), FilePosition { line: 1, col: 3 });
+        assert!(ts.take_errors().is_empty());
+    }
+
+    #[test]
+    fn test_next_unterminated_literal_at_eof() {
+        let input = "c := 'abc";
+        let mut ts = token_stream(input);
+
+        ts.next().unwrap();
+        ts.next().unwrap();
+        let err = ts.next().unwrap_err();
+        assert_eq!(err.pos(), FilePosition { line: 1, col: 6 });
+        assert_eq!(ts.next().unwrap(), Token::EOF);
+    }
+
+    #[test]
+    fn test_next_unterminated_literal_recovers_at_line_end() {
+        let input = "  'abc;\nx";
+        let mut ts = token_stream(input);
+
+        let err = ts.next().unwrap_err();
+        assert_eq!(err.pos(), FilePosition { line: 1, col: 3 });
+        assert_eq!(ts.next().unwrap(), Token::Id("x".to_string()));
     }
 
     #[test]
@@ -710,13 +1105,43 @@ mod token_stream_tests {
         assert_eq!(FilePosition::new(1, 1), ts.prev_pos());
 
         ts.next().unwrap();
-        assert_eq!(FilePosition::new(1, 2), ts.prev_pos());
+        assert_eq!(FilePosition::new(1, 1), ts.token_pos());
+        assert_eq!(FilePosition::new(1, 1), ts.prev_pos());
+        assert_eq!(FilePosition::new(1, 2), ts.pos());
+
+        ts.next().unwrap();
+        assert_eq!(FilePosition::new(2, 1), ts.token_pos());
+        assert_eq!(FilePosition::new(2, 1), ts.prev_pos());
 
         ts.next().unwrap();
-        assert_eq!(FilePosition::new(2, 2), ts.prev_pos());
+        assert_eq!(FilePosition::new(3, 1), ts.token_pos());
+        assert_eq!(FilePosition::new(3, 1), ts.prev_pos());
 
         ts.next().unwrap();
-        assert_eq!(FilePosition::new(3, 2), ts.prev_pos());
+        ts.next().unwrap();
+        assert_eq!(FilePosition::new(3, 5), ts.token_pos());
+        assert_eq!(FilePosition::new(3, 5), ts.prev_pos());
+    }
+
+    #[test]
+    fn test_pos_multi_character_tokens() {
+        let input = "begin\n  x := 1.5e3;\nend.";
+        let mut ts = token_stream(input);
+        let expected = [
+            ((1, 1), (1, 5)),
+            ((2, 3), (2, 3)),
+            ((2, 5), (2, 6)),
+            ((2, 8), (2, 12)),
+            ((2, 13), (2, 13)),
+            ((3, 1), (3, 3)),
+            ((3, 4), (3, 4)),
+        ];
+
+        for &((line, col), (end_line, end_col)) in expected.iter() {
+            ts.next().unwrap();
+            assert_eq!(ts.token_pos(), FilePosition::new(line, col));
+            assert_eq!(ts.prev_pos(), FilePosition::new(end_line, end_col));
+        }
     }
 
     #[test]
@@ -736,6 +1161,27 @@ mod token_stream_tests {
         assert!(ts.available(&[Token::EOF]).unwrap());    
     }
 
+    #[test]
+    fn test_available_keeps_state() {
+        let input = "a\n  # b;\nc";
+        let mut ts = token_stream(input);
+        let mut plain = token_stream(input);
+
+        assert!(ts.available(&[Token::P(Punctuation::Semicolon)]).unwrap());
+        assert!(!ts.available(&[Token::K(Keyword::End)]).unwrap());
+        loop {
+            let token = ts.next().unwrap();
+            assert_eq!(token, plain.next().unwrap());
+            assert_eq!(ts.token_range(), plain.token_range());
+            assert_eq!(ts.token_pos(), plain.token_pos());
+            assert_eq!(ts.prev_pos(), plain.prev_pos());
+            assert_eq!(ts.take_errors().len(), plain.take_errors().len());
+            if token == Token::EOF {
+                break;
+            }
+        }
+    }
+
     #[test]
     fn test_real_semicolon() {
         let input = "0.0;";
@@ -749,6 +1195,71 @@ mod token_stream_tests {
         assert_token_sequence(&expected, ts);
     }
 
+    #[test]
+    fn test_real_exponent() {
+        let input = "1.5e3;";
+        let ts = token_stream(input);
+
+        let expected = [
+            Token::Number("1.5e3".to_string()),
+            Token::P(Punctuation::Semicolon),
+            Token::EOF
+        ];
+
+        assert_token_sequence(&expected, ts);
+    }
+
+    #[test]
+    fn test_comment_before_token() {
+        let input = "{ comment }begin";
+        let ts = token_stream(input);
+
+        assert_token_sequence(&[Token::K(Keyword::Begin)], ts);
+    }
+
+    #[test]
+    fn test_trivia() {
+        let input = "x  { one }\n{two}Begin";
+        let mut ts = token_stream(input).with_trivia();
+
+        assert!(ts.next_with_trivia().unwrap().leading.is_empty());
+
+        let begin = ts.next_with_trivia().unwrap();
+        assert_eq!(begin.token, Token::K(Keyword::Begin));
+        assert_eq!(begin.text, "Begin");
+        assert_eq!(ts.token_pos(), FilePosition::new(2, 6));
+        assert_eq!(ts.token_range(), 16..21);
+        assert_eq!(begin.leading, vec![
+            Trivia::Whitespace("  ".to_string()),
+            Trivia::Comment("{ one }".to_string()),
+            Trivia::Whitespace("\n".to_string()),
+            Trivia::Comment("{two}".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_trivia_round_trip() {
+        let input = "{ header }\nprogram P;\n\n  begin x := 'a' { c } end.\n";
+        let mut ts = token_stream(input).with_trivia();
+
+        let mut source = String::new();
+        loop {
+            let token = ts.next_with_trivia().unwrap();
+            source += &token.source();
+            if token.token == Token::EOF {
+                break;
+            }
+        }
+
+        assert_eq!(source, input);
+    }
+
+    #[test]
+    fn test_trivia_dropped_by_default() {
+        let mut ts = token_stream("{ comment } x");
+        assert!(ts.next_with_trivia().unwrap().leading.is_empty());
+    }
+
     fn assert_token_sequence<T: Buffer>(
         expected: &[Token], mut ts: TokenStream<T>
     ) {
@@ -756,4 +1267,22 @@ mod token_stream_tests {
             assert_eq!(*t, ts.next().unwrap());
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_tokens_serde() {
+        let mut ts = token_stream("a := b[1] + 'c'");
+        let mut tokens = Vec::new();
+        loop {
+            match ts.next().unwrap() {
+                Token::EOF => break,
+                token => tokens.push(token),
+            }
+        }
+
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert!(json.starts_with(r#"[{"Id":"a"},{"O":"Assign"},"#), "{}", json);
+        let back: Vec<Token> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, tokens);
+    }
 }
@@ -0,0 +1,7 @@
+/// A code generator that takes over after the WebAssembly module
+/// has been produced and validated, lowering it to another format.
+pub trait Backend {
+    /// Lowers the binary of a valid module, reporting the
+    /// constructs the backend does not support.
+    fn lower(&self, binary: &[u8]) -> Result<Vec<u8>, String>;
+}
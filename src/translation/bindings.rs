@@ -0,0 +1,535 @@
+use std::fmt::Write;
+
+use wasmparser::{
+    ExternalKind,
+    FuncType,
+    ImportSectionEntryType,
+    Parser,
+    Payload,
+    Type as WasmType,
+    TypeDef,
+};
+
+use crate::translation::backend::Backend;
+
+/// Generates a TypeScript module with the types of the imports and
+/// the exports of a module and an `instantiate` function taking
+/// the typed imports, for hosts running in JavaScript.
+pub struct TypeScriptBindings;
+
+/// Generates a Rust module with an `Imports` trait for the host to
+/// implement and an `instantiate` function returning a wrapper
+/// with a method for every export, for hosts embedding wasmi.
+pub struct RustBindings;
+
+/// What a module imports and exports
+#[derive(Debug, Default, PartialEq)]
+struct Interface {
+    imports: Vec<Import>,
+    exports: Vec<Export>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Import {
+    module: String,
+    name: String,
+    kind: Kind,
+}
+
+#[derive(Debug, PartialEq)]
+struct Export {
+    name: String,
+    kind: Kind,
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    /// A function with the types of its parameters and results
+    Function(Vec<WasmType>, Vec<WasmType>),
+    Global(WasmType),
+    /// A memory with its initial and maximum sizes in pages
+    Memory(u64, Option<u64>),
+}
+
+impl Interface {
+    fn parse(binary: &[u8]) -> Result<Self, String> {
+        let mut interface = Interface::default();
+        let mut types: Vec<FuncType> = Vec::new();
+        // The type of every function and global, the imported ones first
+        let mut functions = Vec::new();
+        let mut globals = Vec::new();
+
+        for payload in Parser::new(0).parse_all(binary) {
+            match payload.map_err(|e| e.to_string())? {
+                Payload::TypeSection(reader) => {
+                    for t in reader {
+                        let t = t.map_err(|e| e.to_string())?;
+                        if let TypeDef::Func(t) = t {
+                            types.push(t);
+                        }
+                    }
+                },
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.map_err(|e| e.to_string())?;
+                        let kind = match import.ty {
+                            ImportSectionEntryType::Function(t) => {
+                                functions.push(t);
+                                function(&types, t)?
+                            },
+                            ImportSectionEntryType::Global(t) => {
+                                globals.push(t.content_type);
+                                Kind::Global(t.content_type)
+                            },
+                            ImportSectionEntryType::Memory(m) => {
+                                Kind::Memory(m.initial, m.maximum)
+                            },
+                            _ => return Err(unsupported("imports")),
+                        };
+                        interface.imports.push(Import {
+                            module: import.module.to_string(),
+                            name: import.field.unwrap_or_default().to_string(),
+                            kind,
+                        });
+                    }
+                },
+                Payload::FunctionSection(reader) => {
+                    for t in reader {
+                        functions.push(t.map_err(|e| e.to_string())?);
+                    }
+                },
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        let global = global.map_err(|e| e.to_string())?;
+                        globals.push(global.ty.content_type);
+                    }
+                },
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export.map_err(|e| e.to_string())?;
+                        let index = export.index as usize;
+                        let kind = match export.kind {
+                            ExternalKind::Function => {
+                                function(&types, functions[index])?
+                            },
+                            ExternalKind::Global => {
+                                Kind::Global(globals[index])
+                            },
+                            ExternalKind::Memory => Kind::Memory(0, None),
+                            _ => return Err(unsupported("exports")),
+                        };
+                        interface.exports.push(Export {
+                            name: export.field.to_string(),
+                            kind,
+                        });
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        Ok(interface)
+    }
+}
+
+fn function(types: &[FuncType], index: u32) -> Result<Kind, String> {
+    let t = types.get(index as usize)
+        .ok_or_else(|| format!("unknown function type {}", index))?;
+    Ok(Kind::Function(t.params.to_vec(), t.returns.to_vec()))
+}
+
+fn unsupported(what: &str) -> String {
+    format!("the bindings do not support these {}", what)
+}
+
+impl Backend for TypeScriptBindings {
+    fn lower(&self, binary: &[u8]) -> Result<Vec<u8>, String> {
+        let interface = Interface::parse(binary)?;
+        let mut ts = String::from(
+            "// The imports and the exports of a module compiled by rupc.\n\n"
+        );
+
+        let mut modules: Vec<&str> = Vec::new();
+        for import in &interface.imports {
+            if !modules.contains(&import.module.as_str()) {
+                modules.push(&import.module);
+            }
+        }
+        ts += "export type Imports = {\n";
+        for module in modules {
+            writeln!(ts, "  {}: {{", ts_name(module)).unwrap();
+            let imports = interface.imports.iter()
+                .filter(|i| i.module == module);
+            for import in imports {
+                writeln!(
+                    ts, "    {}: {};",
+                    ts_name(&import.name), ts_type(&import.kind)
+                ).unwrap();
+            }
+            ts += "  };\n";
+        }
+        ts += "};\n\n";
+
+        ts += "export type Exports = {\n";
+        for export in &interface.exports {
+            writeln!(
+                ts, "  {}: {};", ts_name(&export.name), ts_type(&export.kind)
+            ).unwrap();
+        }
+        ts += "};\n\n";
+
+        ts += "/**\n \
+            * Compiles and instantiates the module,\n \
+            * running its start function if it has one.\n \
+            */\n\
+            export async function instantiate(\n  \
+              source: BufferSource,\n  \
+              imports: Imports\n\
+            ): Promise<Exports> {\n  \
+              const { instance } = \
+              await WebAssembly.instantiate(source, imports);\n  \
+              return instance.exports as unknown as Exports;\n\
+            }\n";
+
+        Ok(ts.into_bytes())
+    }
+}
+
+fn ts_type(kind: &Kind) -> String {
+    match kind {
+        Kind::Function(params, results) => {
+            let params: Vec<_> = params.iter().enumerate()
+                .map(|(i, _)| format!("p{}: number", i))
+                .collect();
+            let result = if results.is_empty() { "void" } else { "number" };
+            format!("({}) => {}", params.join(", "), result)
+        },
+        Kind::Global(_) => "WebAssembly.Global".to_string(),
+        Kind::Memory(..) => "WebAssembly.Memory".to_string(),
+    }
+}
+
+/// The name as a property name, quoted if it is not an identifier.
+fn ts_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl Backend for RustBindings {
+    fn lower(&self, binary: &[u8]) -> Result<Vec<u8>, String> {
+        let interface = Interface::parse(binary)?;
+        let mut rs = String::from(concat!(
+            "//! The imports and the exports of a module compiled by rupc,\n",
+            "//! for hosts embedding the wasmi interpreter.\n\n",
+            "use wasmi::{Engine, Error, Instance, Linker, Module, Store};\n\n",
+        ));
+
+        rs += "/// The functions the module imports from the host\n";
+        rs += "pub trait Imports {\n";
+        for import in &interface.imports {
+            if let Kind::Function(params, results) = &import.kind {
+                writeln!(
+                    rs, "    fn {}(&mut self{}) -> Result<{}, Error>;",
+                    rs_name(&import.name), rs_params(params), rs_result(results)
+                ).unwrap();
+            }
+        }
+        rs += "}\n\n";
+
+        rs += concat!(
+            "/// An instance of the module and the state of the host\n",
+            "pub struct Program<T> {\n",
+            "    pub store: Store<T>,\n",
+            "    pub instance: Instance,\n",
+            "}\n\n",
+            "/// Compiles and instantiates the module,\n",
+            "/// running its start function if it has one.\n",
+            "pub fn instantiate<T: Imports + 'static>(\n",
+            "    binary: &[u8],\n",
+            "    imports: T\n",
+            ") -> Result<Program<T>, Error> {\n",
+            "    let engine = Engine::default();\n",
+            "    let module = Module::new(&engine, binary)?;\n",
+            "    let mut store = Store::new(&engine, imports);\n",
+            "    let mut linker = Linker::<T>::new(&engine);\n",
+        );
+        for import in &interface.imports {
+            match &import.kind {
+                Kind::Function(params, _) => {
+                    let args: Vec<_> = (0..params.len())
+                        .map(|i| format!("p{}", i))
+                        .collect();
+                    writeln!(
+                        rs,
+                        concat!(
+                            "    linker.func_wrap(\n",
+                            "        {:?}, {:?},\n",
+                            "        |mut caller: wasmi::Caller<'_, T>{}| {{\n",
+                            "            caller.data_mut().{}({})\n",
+                            "        }}\n",
+                            "    )?;",
+                        ),
+                        import.module, import.name, rs_params(params),
+                        rs_name(&import.name), args.join(", ")
+                    ).unwrap();
+                },
+                Kind::Memory(initial, maximum) => {
+                    let maximum = match maximum {
+                        Some(max) => format!("Some({})", max),
+                        None => "None".to_string(),
+                    };
+                    writeln!(
+                        rs,
+                        concat!(
+                            "    let memory = wasmi::Memory::new(\n",
+                            "        &mut store,\n",
+                            "        wasmi::MemoryType::new({}, {})?\n",
+                            "    )?;\n",
+                            "    linker.define({:?}, {:?}, memory)?;",
+                        ),
+                        initial, maximum, import.module, import.name
+                    ).unwrap();
+                },
+                Kind::Global(_) => return Err(unsupported("imports")),
+            }
+        }
+        rs += concat!(
+            "    let instance = linker.instantiate(&mut store, &module)?\n",
+            "        .start(&mut store)?;\n",
+            "    Ok(Program { store, instance })\n",
+            "}\n\n",
+        );
+
+        let methods: Vec<_> = interface.exports.iter()
+            .map(|export| rs_method(&rs_name(&export.name), export))
+            .collect();
+        rs += "impl<T> Program<T> {\n";
+        rs += &methods.join("\n");
+        rs += "}\n";
+
+        Ok(rs.into_bytes())
+    }
+}
+
+/// The method of the wrapper that calls the exported function
+/// or reads the exported global or memory, with a setter for globals.
+fn rs_method(name: &str, export: &Export) -> String {
+    match &export.kind {
+        Kind::Function(params, results) => {
+            let types: Vec<_> = params.iter().map(|t| rs_type(*t)).collect();
+            let args: Vec<_> = (0..params.len())
+                .map(|i| format!("p{}", i))
+                .collect();
+            format!(
+                concat!(
+                    "    pub fn {}(&mut self{}) -> Result<{}, Error> {{\n",
+                    "        self.instance\n",
+                    "            .get_typed_func::<{}, {}>(&self.store, {:?})?\n",
+                    "            .call(&mut self.store, {})\n",
+                    "    }}\n",
+                ),
+                name, rs_params(params), rs_result(results),
+                tuple(&types), rs_result(results), export.name, tuple(&args)
+            )
+        },
+        Kind::Global(t) => {
+            let (get, set) = match t {
+                WasmType::F32 => (
+                    "f32().map(wasmi::core::F32::to_float)",
+                    "wasmi::Val::F32(value.into())"
+                ),
+                _ => ("i32()", "wasmi::Val::I32(value)"),
+            };
+            format!(
+                concat!(
+                    "    pub fn {name}(&self) -> {t} {{\n",
+                    "        self.instance.get_global(&self.store, {export:?})\n",
+                    "            .and_then(|g| g.get(&self.store).{get})\n",
+                    "            .expect(\"the module exports the global\")\n",
+                    "    }}\n\n",
+                    "    pub fn set_{bare}(&mut self, value: {t})",
+                    " -> Result<(), Error> {{\n",
+                    "        self.instance.get_global(&self.store, {export:?})\n",
+                    "            .expect(\"the module exports the global\")\n",
+                    "            .set(&mut self.store, {set})\n",
+                    "            .map_err(Error::from)\n",
+                    "    }}\n",
+                ),
+                name = name,
+                bare = name.trim_start_matches("r#"),
+                t = rs_type(*t),
+                export = export.name,
+                get = get,
+                set = set
+            )
+        },
+        Kind::Memory(..) => format!(
+            concat!(
+                "    pub fn {}(&self) -> wasmi::Memory {{\n",
+                "        self.instance.get_memory(&self.store, {:?})\n",
+                "            .expect(\"the module exports the memory\")\n",
+                "    }}\n",
+            ),
+            name, export.name
+        ),
+    }
+}
+
+fn rs_type(t: WasmType) -> &'static str {
+    match t {
+        WasmType::F32 => "f32",
+        WasmType::I64 => "i64",
+        WasmType::F64 => "f64",
+        _ => "i32",
+    }
+}
+
+fn rs_params(params: &[WasmType]) -> String {
+    params.iter().enumerate()
+        .map(|(i, t)| format!(", p{}: {}", i, rs_type(*t)))
+        .collect()
+}
+
+fn rs_result(results: &[WasmType]) -> String {
+    let types: Vec<_> = results.iter().map(|t| rs_type(*t)).collect();
+    tuple(&types)
+}
+
+fn tuple<S: AsRef<str>>(items: &[S]) -> String {
+    match items {
+        [item] => item.as_ref().to_string(),
+        items => format!(
+            "({})",
+            items.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// The name as a Rust identifier, raw if it is a keyword and with
+/// the characters not allowed in identifiers replaced.
+fn rs_name(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break",
+        "const", "continue", "do", "dyn", "else", "enum", "extern", "false",
+        "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro",
+        "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "static", "struct", "trait", "true", "type", "typeof",
+        "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+
+    let mut name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+
+    if KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else if ["self", "super", "crate", "Self"].contains(&name.as_str()) {
+        name + "_"
+    } else {
+        name
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+    name.starts_with(|c: char| !c.is_ascii_digit()) && name.chars().all(allowed)
+}
+
+#[cfg(test)]
+mod bindings_tests {
+    use super::*;
+
+    const MODULE: &str = r#"(module
+        (import "imports" "writeln_int" (func (param i32)))
+        (import "imports" "memory" (memory 1 4))
+        (global $a (mut i32) (i32.const 0))
+        (global $r (mut f32) (f32.const 0))
+        (func (export "program"))
+        (func (export "scale") (param f32 i32) (result f32) local.get 0)
+        (export "a" (global $a))
+        (export "r" (global $r))
+    )"#;
+
+    fn lower(backend: impl Backend) -> String {
+        let binary = wat::parse_str(MODULE).unwrap();
+        String::from_utf8(backend.lower(&binary).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_bindings_interface() {
+        let binary = wat::parse_str(MODULE).unwrap();
+        let interface = Interface::parse(&binary).unwrap();
+
+        assert_eq!(interface.imports, [
+            Import {
+                module: "imports".to_string(),
+                name: "writeln_int".to_string(),
+                kind: Kind::Function(vec![WasmType::I32], vec![]),
+            },
+            Import {
+                module: "imports".to_string(),
+                name: "memory".to_string(),
+                kind: Kind::Memory(1, Some(4)),
+            },
+        ]);
+        let exports: Vec<_> = interface.exports.iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(exports, ["program", "scale", "a", "r"]);
+        assert_eq!(
+            interface.exports[1].kind,
+            Kind::Function(
+                vec![WasmType::F32, WasmType::I32],
+                vec![WasmType::F32]
+            )
+        );
+        assert_eq!(interface.exports[3].kind, Kind::Global(WasmType::F32));
+    }
+
+    #[test]
+    fn test_bindings_typescript() {
+        let ts = lower(TypeScriptBindings);
+
+        assert!(ts.contains("export type Imports = {\n  \
+            imports: {\n    \
+              writeln_int: (p0: number) => void;\n    \
+              memory: WebAssembly.Memory;\n  \
+            };\n\
+            };\n"), "{}", ts);
+        assert!(ts.contains("  scale: (p0: number, p1: number) => number;\n"));
+        assert!(ts.contains("  a: WebAssembly.Global;\n"), "{}", ts);
+        assert!(ts.contains("export async function instantiate("), "{}", ts);
+    }
+
+    #[test]
+    fn test_bindings_rust() {
+        let rs = lower(RustBindings);
+
+        assert!(rs.contains("pub trait Imports {\n    \
+            fn writeln_int(&mut self, p0: i32) -> Result<(), Error>;\n\
+            }\n"), "{}", rs);
+        assert!(rs.contains("wasmi::MemoryType::new(1, Some(4))?"), "{}", rs);
+        assert!(rs.contains("linker.define(\"imports\", \"memory\", memory)?;"));
+        assert!(rs.contains(
+            "pub fn scale(&mut self, p0: f32, p1: i32) -> Result<f32, Error>"
+        ), "{}", rs);
+        assert!(rs.contains(".get_typed_func::<(f32, i32), f32>"), "{}", rs);
+        assert!(rs.contains("pub fn r(&self) -> f32 {"), "{}", rs);
+        assert!(rs.contains("pub fn set_a(&mut self, value: i32)"), "{}", rs);
+    }
+
+    #[test]
+    fn test_bindings_names() {
+        assert_eq!(rs_name("type"), "r#type");
+        assert_eq!(rs_name("self"), "self_");
+        assert_eq!(rs_name("2d-point"), "_2d_point");
+        assert_eq!(ts_name("writeln_int"), "writeln_int");
+        assert_eq!(ts_name("my-module"), "\"my-module\"");
+    }
+}
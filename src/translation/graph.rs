@@ -0,0 +1,356 @@
+use std::fmt::Write;
+
+use super::instruction::Instruction;
+
+/// A function generated for a program, as the optimizer left it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub body: Vec<Instruction>,
+}
+
+/// A straight-line run of instructions
+#[derive(Debug, Default, PartialEq)]
+pub struct BasicBlock {
+    pub instructions: Vec<Instruction>,
+}
+
+/// Where the control goes out of a function
+pub const EXIT: usize = usize::MAX;
+
+/// The control flow graph of a function body. The first block is the
+/// entry, and the edges to `EXIT` leave the function.
+#[derive(Debug, PartialEq)]
+pub struct ControlFlow {
+    pub blocks: Vec<BasicBlock>,
+    /// The source block, the target block and the condition, if any
+    pub edges: Vec<(usize, usize, Option<bool>)>,
+}
+
+/// A structured instruction whose `End` has not been reached yet
+enum Frame {
+    /// Branches go to the block after the `End`
+    Block { label: String, after: usize },
+    /// Branches go back to the header
+    Loop { label: String, header: usize },
+    If { condition: usize, after: usize, has_else: bool },
+}
+
+impl ControlFlow {
+    /// Splits the structured body into basic blocks, leaving out
+    /// those that are empty and cannot be reached.
+    pub fn of(body: &[Instruction]) -> Self {
+        let mut graph = Self {
+            blocks: vec![BasicBlock::default()],
+            edges: Vec::new(),
+        };
+        let mut frames = Vec::new();
+        let mut current = 0;
+
+        for instr in body {
+            match instr {
+                Instruction::Block(label) => frames.push(Frame::Block {
+                    label: label.clone(),
+                    after: graph.block(),
+                }),
+                Instruction::Loop(label) => {
+                    let header = graph.block();
+                    graph.edge(current, header, None);
+                    current = header;
+                    graph.push(current, instr);
+                    frames.push(Frame::Loop { label: label.clone(), header });
+                },
+                Instruction::If => {
+                    graph.push(current, instr);
+                    let then = graph.block();
+                    graph.edge(current, then, Some(true));
+                    frames.push(Frame::If {
+                        condition: current,
+                        after: graph.block(),
+                        has_else: false,
+                    });
+                    current = then;
+                },
+                Instruction::Else => {
+                    if let Some(Frame::If { condition, after, has_else }) =
+                        frames.last_mut()
+                    {
+                        *has_else = true;
+                        let otherwise = graph.block();
+                        graph.edge(current, *after, None);
+                        graph.edge(*condition, otherwise, Some(false));
+                        current = otherwise;
+                    }
+                },
+                Instruction::End => {
+                    let next = match frames.pop() {
+                        Some(Frame::Block { after, .. }) => after,
+                        Some(Frame::Loop { .. }) => graph.block(),
+                        Some(Frame::If { condition, after, has_else }) => {
+                            if !has_else {
+                                graph.edge(condition, after, Some(false));
+                            }
+                            after
+                        },
+                        None => continue,
+                    };
+                    graph.edge(current, next, None);
+                    current = next;
+                },
+                Instruction::Br(label) | Instruction::BrIf(label) => {
+                    graph.push(current, instr);
+                    let conditional = matches!(instr, Instruction::BrIf(_));
+                    let target = target(&frames, label).unwrap_or(EXIT);
+                    graph.edge(current, target, conditional.then_some(true));
+
+                    let next = graph.block();
+                    if conditional {
+                        graph.edge(current, next, Some(false));
+                    }
+                    current = next;
+                },
+                Instruction::Return | Instruction::Unreachable => {
+                    graph.push(current, instr);
+                    if *instr == Instruction::Return {
+                        graph.edge(current, EXIT, None);
+                    }
+                    current = graph.block();
+                },
+                _ => graph.push(current, instr),
+            }
+        }
+        graph.edge(current, EXIT, None);
+
+        graph.prune();
+        graph
+    }
+
+    fn block(&mut self) -> usize {
+        self.blocks.push(BasicBlock::default());
+        self.blocks.len() - 1
+    }
+
+    fn push(&mut self, block: usize, instr: &Instruction) {
+        self.blocks[block].instructions.push(instr.clone());
+    }
+
+    fn edge(&mut self, from: usize, to: usize, condition: Option<bool>) {
+        self.edges.push((from, to, condition));
+    }
+
+    /// Drops the empty blocks no edge leads to,
+    /// along with the edges that leave them.
+    fn prune(&mut self) {
+        loop {
+            let dead: Vec<_> = (1..self.blocks.len())
+                .filter(|&b| self.blocks[b].instructions.is_empty()
+                    && !self.edges.iter().any(|&(_, to, _)| to == b))
+                .collect();
+            if dead.is_empty() {
+                break;
+            }
+
+            let mut index = Vec::with_capacity(self.blocks.len());
+            let mut kept = 0;
+            for b in 0..self.blocks.len() {
+                index.push(kept);
+                if !dead.contains(&b) {
+                    kept += 1;
+                }
+            }
+            let renumber = |b: usize| if b == EXIT { EXIT } else { index[b] };
+
+            self.edges = self.edges.iter()
+                .filter(|(from, _, _)| !dead.contains(from))
+                .map(|&(from, to, c)| (renumber(from), renumber(to), c))
+                .collect();
+            let mut b = 0;
+            self.blocks.retain(|_| {
+                b += 1;
+                !dead.contains(&(b - 1))
+            });
+        }
+    }
+}
+
+/// The block a branch to the label goes to.
+fn target(frames: &[Frame], label: &str) -> Option<usize> {
+    frames.iter().rev().find_map(|frame| match frame {
+        Frame::Block { label: l, after } if l == label => Some(*after),
+        Frame::Loop { label: l, header } if l == label => Some(*header),
+        _ => None,
+    })
+}
+
+/// The functions the function calls, in the order of the first calls.
+pub fn callees(function: &Function) -> Vec<&str> {
+    let mut callees = Vec::new();
+    for instr in &function.body {
+        if let Instruction::Call(name) = instr {
+            if !callees.contains(&name.as_str()) {
+                callees.push(name.as_str());
+            }
+        }
+    }
+    callees
+}
+
+/// Renders the call graph of the functions in the Graphviz format.
+/// The functions that are only called, the imported ones, are dashed.
+pub fn call_graph_dot(functions: &[Function]) -> String {
+    let mut dot = String::from("digraph calls {\n  node [shape=box];\n");
+    let defined = |name: &str| functions.iter().any(|f| f.name == name);
+
+    let mut nodes: Vec<&str> = Vec::new();
+    for function in functions {
+        for name in std::iter::once(function.name.as_str())
+            .chain(callees(function))
+        {
+            if !nodes.contains(&name) {
+                nodes.push(name);
+            }
+        }
+    }
+    for name in nodes {
+        let style = if defined(name) { "" } else { " [style=dashed]" };
+        writeln!(dot, "  {}{};", quoted(name), style).unwrap();
+    }
+
+    for function in functions {
+        for callee in callees(function) {
+            writeln!(dot, "  {} -> {};", quoted(&function.name), quoted(callee))
+                .unwrap();
+        }
+    }
+
+    dot + "}\n"
+}
+
+/// Renders the control flow graphs of the functions in the Graphviz
+/// format, one cluster per function.
+pub fn control_flow_dot(functions: &[Function]) -> String {
+    let mut dot = String::from("digraph cfg {\n  node [shape=box];\n");
+
+    for (f, function) in functions.iter().enumerate() {
+        let graph = ControlFlow::of(&function.body);
+        let node = |b: usize| if b == EXIT {
+            format!("f{}_exit", f)
+        } else {
+            format!("f{}_{}", f, b)
+        };
+
+        writeln!(dot, "  subgraph cluster_{} {{", f).unwrap();
+        writeln!(dot, "    label={};", quoted(&function.name)).unwrap();
+        writeln!(dot, "    {} [shape=oval, label=\"exit\"];", node(EXIT))
+            .unwrap();
+        for (b, block) in graph.blocks.iter().enumerate() {
+            let label: String = block.instructions.iter()
+                .map(|i| format!("{}\\l", i))
+                .collect();
+            if label.is_empty() {
+                writeln!(dot, "    {} [shape=point];", node(b)).unwrap();
+            } else {
+                writeln!(dot, "    {} [label={}];", node(b), quoted(&label))
+                    .unwrap();
+            }
+        }
+        for (from, to, condition) in graph.edges {
+            let label = match condition {
+                Some(c) => format!(" [label=\"{}\"]", c),
+                None => String::new(),
+            };
+            writeln!(dot, "    {} -> {}{};", node(from), node(to), label)
+                .unwrap();
+        }
+        dot += "  }\n";
+    }
+
+    dot + "}\n"
+}
+
+/// The text as a Graphviz string, keeping the `\l` line ends.
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use crate::{
+        parsing::code::Code,
+        tokenization::{SimpleBuffer, TokenStream},
+        translation::names::{generated, LOOP_CONTINUE},
+    };
+    use std::{cell::RefCell, rc::Rc};
+    use super::*;
+
+    fn functions(input: &str) -> Vec<Function> {
+        let functions = Rc::new(RefCell::new(Vec::new()));
+        let collected = functions.clone();
+        let ts = TokenStream::new(SimpleBuffer::new(input.as_bytes(), None));
+        let errors = Code::new(ts, Box::new(std::io::sink()))
+            .with_function_handler(move |name, body| {
+                collected.borrow_mut().push(Function {
+                    name: name.to_string(),
+                    body: body.to_vec(),
+                })
+            })
+            .compile()
+            .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        functions.take()
+    }
+
+    #[test]
+    fn test_graph_calls() {
+        let input = "program P; var a: integer;\n\
+            begin writeln_int(1); a := 2 div a; writeln_int(a) end.";
+        let dot = call_graph_dot(&functions(input));
+
+        assert_eq!(dot, "digraph calls {\n  \
+            node [shape=box];\n  \
+            \"program\";\n  \
+            \"writeln_int\" [style=dashed];\n  \
+            \"program\" -> \"writeln_int\";\n\
+            }\n");
+    }
+
+    #[test]
+    fn test_graph_control_flow() {
+        let input = "program P; var a: integer;\n\
+            begin\n  \
+              while a < 3 do\n    \
+                if a = 1 then a := 2 else a := a + 1;\n  \
+              writeln_int(a)\n\
+            end.";
+        let functions = functions(input);
+        let graph = ControlFlow::of(&functions[0].body);
+
+        // The entry, the block after the loop, the loop header,
+        // the loop body up to the if, then, the join and else
+        assert_eq!(graph.blocks.len(), 7, "{:?}", graph);
+        let successors = |b: usize| -> Vec<_> {
+            graph.edges.iter()
+                .filter(|(from, _, _)| *from == b)
+                .map(|&(_, to, c)| (to, c))
+                .collect()
+        };
+        assert_eq!(successors(0), [(2, None)]);
+        assert_eq!(successors(2), [(1, Some(true)), (3, Some(false))]);
+        assert_eq!(successors(3), [(4, Some(true)), (6, Some(false))]);
+        assert_eq!(successors(4), [(5, None)]);
+        assert_eq!(successors(6), [(5, None)]);
+        assert_eq!(successors(5), [(2, None)]);
+        assert_eq!(successors(1), [(EXIT, None)]);
+        assert_eq!(
+            graph.blocks[2].instructions[0],
+            Instruction::Loop(generated(LOOP_CONTINUE, 0))
+        );
+        assert!(graph.blocks[1].instructions
+            .contains(&Instruction::Call("writeln_int".to_string())));
+
+        let dot = control_flow_dot(&functions);
+        assert!(dot.starts_with("digraph cfg {\n"), "{}", dot);
+        assert!(dot.contains("f0_2 -> f0_1 [label=\"true\"];"), "{}", dot);
+        assert!(dot.contains("f0_1 -> f0_exit;"), "{}", dot);
+    }
+}
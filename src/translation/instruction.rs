@@ -0,0 +1,151 @@
+use crate::{semantics::Type, tokenization::Relation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I32,
+    F32,
+    Unknown,
+}
+
+impl ValueType {
+    pub fn of(t: &Type) -> Self {
+        match t {
+            Type::Integer
+            | Type::Char
+            | Type::Scalar(_)
+            | Type::Pointer(_) => ValueType::I32,
+            Type::Real => ValueType::F32,
+            // A record or an array has no value of its own,
+            // only its fields or elements do
+            Type::Record(_) | Type::Array(_) | Type::Unknown => {
+                ValueType::Unknown
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    DivS,
+    RemS,
+    RemU,
+    And,
+    Or,
+    Xor,
+    Shl,
+    ShrS,
+    ShrU,
+}
+
+/// An operation on a single value, named as in the text format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// Converts a signed integer to a real
+    F32ConvertI32S,
+    /// Truncates a real to a signed integer,
+    /// trapping if it is out of range
+    I32TruncF32S,
+    /// Rounds a real to the nearest integral real, halves to even
+    F32Nearest,
+    F32Abs,
+}
+
+/// An instruction of a function body. Structured control
+/// instructions are flat: `Block`, `Loop` and `If` are closed by `End`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Const(ValueType, String),
+    LocalGet(String),
+    LocalSet(String),
+    LocalTee(String),
+    GlobalGet(String),
+    GlobalSet(String),
+    /// Reads the value at the address on top of the stack
+    /// plus the offset from the linear memory
+    Load(ValueType, u32),
+    /// Writes the value on top of the stack to the linear
+    /// memory at the address under it plus the offset
+    Store(ValueType, u32),
+    Binary(ValueType, BinaryOp),
+    Unary(UnaryOp),
+    Compare(ValueType, Relation),
+    Eqz(ValueType),
+    /// Picks the first of two values if the condition
+    /// on top of them holds, the second otherwise
+    Select,
+    Call(String),
+    Block(String),
+    Loop(String),
+    If,
+    Else,
+    End,
+    Br(String),
+    BrIf(String),
+    Return,
+    Unreachable,
+}
+
+impl Instruction {
+    /// Whether the instruction opens a structured block closed by `End`.
+    pub fn opens_block(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If
+        )
+    }
+}
+
+/// Finds the `Else` (if any) and the `End` matching
+/// the structured instruction at `start`.
+pub fn matching_end(
+    body: &[Instruction],
+    start: usize
+) -> Option<(Option<usize>, usize)> {
+    let mut depth = 0;
+    let mut else_index = None;
+    for (i, instr) in body.iter().enumerate().skip(start + 1) {
+        match instr {
+            instr if instr.opens_block() => depth += 1,
+            Instruction::Else if depth == 0 => else_index = Some(i),
+            Instruction::End if depth == 0 => return Some((else_index, i)),
+            Instruction::End => depth -= 1,
+            _ => ()
+        }
+    }
+
+    None
+}
+
+/// Finds where the instructions computing the value on top of the
+/// stack at `end` start. Gives up on anything but plain arithmetic.
+pub fn operand_start(body: &[Instruction], end: usize) -> Option<usize> {
+    let mut needed = 1;
+    for i in (0..end).rev() {
+        let (pops, pushes) = match &body[i] {
+            Instruction::Const(..)
+            | Instruction::LocalGet(_)
+            | Instruction::GlobalGet(_) => (0, 1),
+            Instruction::LocalTee(_)
+            | Instruction::Eqz(_)
+            | Instruction::Unary(_)
+            | Instruction::Load(..) => (1, 1),
+            Instruction::Binary(..) | Instruction::Compare(..) => (2, 1),
+            Instruction::Select => (3, 1),
+            _ => return None
+        };
+
+        if pushes > needed {
+            return None;
+        }
+        needed = needed - pushes + pops;
+        if needed == 0 {
+            return Some(i);
+        }
+    }
+
+    None
+}
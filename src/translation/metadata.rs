@@ -0,0 +1,104 @@
+use sha2::{Digest, Sha256};
+
+use crate::translation::Target;
+
+/// The name of the custom section describing how a binary was produced.
+pub const SECTION: &str = "rupc.metadata";
+
+/// Describes the compiler, the options and the source
+/// a binary was produced from, one `key=value` pair per line.
+pub fn metadata(source: &[u8], target: &Target, optimize: bool) -> String {
+    let mut procedure_modules: Vec<_> = target.procedure_modules.iter()
+        .map(|(procedure, module)| format!("{}={}", procedure, module))
+        .collect();
+    procedure_modules.sort();
+
+    let max_pages = target.memory.max_pages
+        .map(|max| max.to_string())
+        .unwrap_or_default();
+
+    let pairs = [
+        ("compiler", format!("rupc {}", env!("CARGO_PKG_VERSION"))),
+        ("source-sha256", hex(&Sha256::digest(source))),
+        ("optimize", optimize.to_string()),
+        ("import-module", target.import_module.clone()),
+        ("imports", procedure_modules.join(",")),
+        ("export-name", target.export_name.clone()),
+        ("globals", target.globals.to_string()),
+        ("import-memory", target.memory.import.to_string()),
+        ("memory-pages", target.memory.pages.to_string()),
+        ("max-memory-pages", max_pages),
+        ("runtime-errors", format!("{:?}", target.runtime_errors).to_lowercase()),
+        ("start", format!("{:?}", target.start).to_lowercase()),
+    ];
+
+    pairs.iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+/// Encodes a custom section, which can be appended to a binary.
+pub fn custom_section(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut content = leb128(name.len());
+    content.extend(name.as_bytes());
+    content.extend(data);
+
+    let mut section = vec![0];
+    section.extend(leb128(content.len()));
+    section.extend(content);
+    section
+}
+
+fn leb128(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use wasmparser::{Parser, Payload};
+
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let data = metadata(b"program a; begin end.", &Target::default(), true);
+
+        assert!(data.starts_with("compiler=rupc "));
+        assert!(data.contains(
+            "\nsource-sha256=\
+            5d6f1bddbb95b999744cec85400212484d613b24500fbf020f608ff999cdc636\n"
+        ));
+        assert!(data.contains("\noptimize=true\n"));
+        assert!(data.contains("\nstart=export\n"));
+    }
+
+    #[test]
+    fn test_custom_section() {
+        let mut binary = wat::parse_str("(module)").unwrap();
+        let data = vec![b'x'; 200];
+        binary.extend(custom_section(SECTION, &data));
+
+        let custom = Parser::new(0).parse_all(&binary)
+            .find_map(|payload| match payload.unwrap() {
+                Payload::CustomSection { name, data, .. } => {
+                    Some((name.to_string(), data.to_vec()))
+                },
+                _ => None
+            });
+
+        assert_eq!(custom, Some((SECTION.to_string(), data)));
+    }
+}
@@ -1,4 +1,15 @@
 mod wasm;
+pub mod instruction;
+pub mod graph;
 mod output;
+mod validation;
+mod target;
+pub mod metadata;
+pub mod names;
+pub mod backend;
+pub mod bindings;
+#[cfg(feature = "cranelift")]
+pub mod native;
 
 pub use wasm::Wasm;
+pub use target::{Memory, RandomSource, RuntimeErrors, Start, Target};
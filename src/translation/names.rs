@@ -0,0 +1,71 @@
+//! The names of the locals, globals and labels of the generated module.
+//! The variables of the program keep their names, so the name section
+//! of the binary module shows them as they are in the source. The names
+//! the compiler makes up for itself have a dot, which no identifier of
+//! the program can have, so the two never collide.
+
+/// The local that holds the final value of a `for` loop
+pub const FOR_LIMIT: &str = "for.limit";
+/// The label of the loop that starts the next iteration
+pub const LOOP_CONTINUE: &str = "loop.continue";
+/// The label of the block that leaves the loop
+pub const LOOP_END: &str = "loop.end";
+/// The local that holds the selector of a `case` statement
+pub const CASE_SELECTOR: &str = "case.selector";
+/// The local that holds the divisor while it is checked
+pub const DIVISOR: &str = "rt.divisor";
+/// The local that holds an array index while it is checked
+pub const INDEX: &str = "rt.index";
+/// The local that holds the address of an array
+/// whose elements are accessed one after another
+pub const ADDRESS: &str = "rt.address";
+/// The local that holds the address of the value another one
+/// is copied to or compared with
+pub const OTHER_ADDRESS: &str = "rt.other_address";
+/// The locals that hold the argument of a standard function
+/// that uses it more than once, an integer or a real one
+pub const INTEGER_OPERAND: &str = "rt.operand_i32";
+pub const REAL_OPERAND: &str = "rt.operand_f32";
+/// The function that draws the next number of the xorshift
+/// generator and the global that holds its state
+pub const RANDOM_FUNCTION: &str = "rt.random";
+pub const RANDOM_STATE: &str = "rt.random_state";
+
+/// The name made up for the `index`-th local of the `kind`.
+pub fn generated(kind: &str, index: usize) -> String {
+    format!("{}.{}", kind, index)
+}
+
+/// Whether the name was made up by the compiler
+/// rather than taken from the program.
+pub fn is_generated(name: &str) -> bool {
+    name.contains('.')
+}
+
+#[cfg(test)]
+mod names_tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_names_are_not_identifiers() {
+        let names = [
+            FOR_LIMIT,
+            LOOP_CONTINUE,
+            LOOP_END,
+            CASE_SELECTOR,
+            DIVISOR,
+            INDEX,
+            ADDRESS,
+            OTHER_ADDRESS,
+            INTEGER_OPERAND,
+            REAL_OPERAND,
+            RANDOM_FUNCTION,
+            RANDOM_STATE,
+        ];
+        for name in names {
+            assert!(is_generated(name), "{}", name);
+        }
+        assert!(is_generated(&generated("cse", 0)));
+        assert!(!is_generated("r0"));
+    }
+}
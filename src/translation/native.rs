@@ -0,0 +1,1009 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        types,
+        AbiParam,
+        Block,
+        FuncRef,
+        GlobalValue,
+        InstBuilder,
+        MemFlags,
+        Signature,
+        TrapCode,
+        UserFuncName,
+        Value,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_module::{
+    default_libcall_names,
+    DataDescription,
+    DataId,
+    FuncId,
+    Linkage,
+    Module,
+};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use wasmparser::{
+    ExternalKind,
+    FunctionBody,
+    FuncType,
+    ImportSectionEntryType,
+    MemoryImmediate,
+    Operator,
+    Parser,
+    Payload,
+    Type as WasmType,
+    TypeDef,
+    TypeOrFuncType,
+};
+
+use crate::translation::backend::Backend;
+
+/// The procedures imported by programs, linked into executables.
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+// Starts the program when it is run as an executable
+const ENTRY: &str = "main";
+const INITIALIZE: &str = "_initialize";
+const UNREACHABLE: TrapCode = TrapCode::unwrap_user(1);
+const PAGE_SIZE: u64 = 65536;
+
+/// Compiles modules to object files for the machine
+/// the compiler runs on with Cranelift.
+///
+/// Exported functions and globals keep their names, imported functions
+/// become undefined symbols named after the imported field. A `main`
+/// function calls the start function, the function exported as
+/// `_initialize` or the first exported function, in this order.
+/// The linear memory becomes a zero-initialized object of its initial
+/// size, and accesses to it are not checked against that size.
+pub struct Native;
+
+impl Backend for Native {
+    fn lower(&self, binary: &[u8]) -> Result<Vec<u8>, String> {
+        let parsed = ParsedModule::parse(binary)?;
+        let mut module = object_module()?;
+        let declarations = declare(&mut module, &parsed)?;
+
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+        for (i, body) in parsed.bodies.iter().enumerate() {
+            let index = parsed.imports.len() + i;
+            let id = declarations.functions[index];
+            ctx.func.signature =
+                signature(&module, &declarations.types[index])?;
+            ctx.func.name = UserFuncName::user(0, id.as_u32());
+
+            let builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+            FunctionTranslator::new(builder, &mut module, &declarations)
+                .translate(&declarations.types[index], body)?;
+
+            module.define_function(id, &mut ctx).map_err(|e| e.to_string())?;
+            module.clear_context(&mut ctx);
+        }
+
+        if let Some(entry) = parsed.entry() {
+            define_entry(&mut module, &declarations, entry)?;
+        }
+
+        module.finish().emit().map_err(|e| e.to_string())
+    }
+}
+
+/// Links an object file produced by `Native` and the runtime
+/// into an executable with the C compiler `cc`.
+pub fn link(cc: &str, object: &Path, executable: &Path) -> Result<(), String> {
+    let child = Command::new(cc)
+        .args(["-x", "c", "-", "-x", "none"])
+        .arg(object)
+        .arg("-o")
+        .arg(executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(format!(
+                "{} was not found, install a C compiler or pass \
+                its location with --cc",
+                cc
+            ))
+        },
+        Err(e) => return Err(format!("Failed to run {}: {}", cc, e))
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(RUNTIME.as_bytes())
+            .map_err(|e| format!("Failed to run {}: {}", cc, e))?;
+    }
+
+    let output = child.wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", cc, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut msg = format!(
+            "{} failed to link \"{}\" ({})",
+            cc, object.display(), output.status
+        );
+        if !stderr.trim().is_empty() {
+            msg += &format!(": {}", stderr.trim());
+        }
+        Err(msg)
+    }
+}
+
+/// The parts of a module the backend lowers.
+#[derive(Default)]
+struct ParsedModule<'a> {
+    types: Vec<FuncType>,
+    // The type of every function, the imported ones first
+    functions: Vec<u32>,
+    imports: Vec<&'a str>,
+    globals: Vec<(WasmType, [u8; 4])>,
+    // The initial size of the memory in pages, if there is one
+    memory: Option<u64>,
+    exports: Vec<(&'a str, ExternalKind, u32)>,
+    start: Option<u32>,
+    bodies: Vec<FunctionBody<'a>>,
+}
+
+impl<'a> ParsedModule<'a> {
+    fn parse(binary: &'a [u8]) -> Result<Self, String> {
+        let mut parsed = ParsedModule::default();
+        for payload in Parser::new(0).parse_all(binary) {
+            match payload.map_err(|e| e.to_string())? {
+                Payload::TypeSection(reader) => {
+                    for t in reader {
+                        match t.map_err(|e| e.to_string())? {
+                            TypeDef::Func(t) => parsed.types.push(t),
+                            _ => return Err(unsupported("type definitions"))
+                        }
+                    }
+                },
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.map_err(|e| e.to_string())?;
+                        match import.ty {
+                            ImportSectionEntryType::Function(t) => {
+                                parsed.imports.push(
+                                    import.field.unwrap_or(import.module)
+                                );
+                                parsed.functions.push(t);
+                            },
+                            ImportSectionEntryType::Memory(m) => {
+                                parsed.memory = Some(m.initial);
+                            },
+                            _ => return Err(unsupported("imports"))
+                        }
+                    }
+                },
+                Payload::FunctionSection(reader) => {
+                    for t in reader {
+                        parsed.functions.push(t.map_err(|e| e.to_string())?);
+                    }
+                },
+                Payload::MemorySection(reader) => {
+                    for memory in reader {
+                        let memory = memory.map_err(|e| e.to_string())?;
+                        parsed.memory = Some(memory.initial);
+                    }
+                },
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        let global = global.map_err(|e| e.to_string())?;
+                        let init = global.init_expr
+                            .get_operators_reader()
+                            .read()
+                            .map_err(|e| e.to_string())?;
+                        let bytes = match init {
+                            Operator::I32Const { value } => value.to_le_bytes(),
+                            Operator::F32Const { value } => {
+                                value.bits().to_le_bytes()
+                            },
+                            _ => return Err(unsupported("global initializers"))
+                        };
+                        parsed.globals.push((global.ty.content_type, bytes));
+                    }
+                },
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export.map_err(|e| e.to_string())?;
+                        parsed.exports.push(
+                            (export.field, export.kind, export.index)
+                        );
+                    }
+                },
+                Payload::StartSection { func, .. } => parsed.start = Some(func),
+                Payload::CodeSectionEntry(body) => parsed.bodies.push(body),
+                Payload::TableSection(_)
+                | Payload::ElementSection(_)
+                | Payload::DataSection(_) => {
+                    return Err(unsupported("tables and data segments"))
+                },
+                _ => ()
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn export_name(&self, function: bool, index: u32) -> Option<&'a str> {
+        self.exports.iter()
+            .find(|(_, kind, i)| *i == index && match kind {
+                ExternalKind::Function => function,
+                ExternalKind::Global => !function,
+                _ => false
+            })
+            .map(|(name, _, _)| *name)
+    }
+
+    /// The function started when the program is run.
+    fn entry(&self) -> Option<u32> {
+        let exported = |name: Option<&str>| {
+            self.exports.iter()
+                .find(|(field, kind, _)| {
+                    matches!(kind, ExternalKind::Function)
+                        && name.is_none_or(|name| name == *field)
+                })
+                .map(|(_, _, index)| *index)
+        };
+
+        self.start
+            .or_else(|| exported(Some(INITIALIZE)))
+            .or_else(|| exported(None))
+    }
+}
+
+/// The functions, globals and memory of the module
+/// as they are known to Cranelift.
+struct Declarations {
+    functions: Vec<FuncId>,
+    types: Vec<FuncType>,
+    globals: Vec<(DataId, types::Type)>,
+    memory: Option<DataId>,
+}
+
+fn object_module() -> Result<ObjectModule, String> {
+    let mut flags = settings::builder();
+    flags.set("is_pic", "true").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()
+        .map_err(|e| e.to_string())?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+    let builder = ObjectBuilder::new(isa, "program", default_libcall_names())
+        .map_err(|e| e.to_string())?;
+
+    Ok(ObjectModule::new(builder))
+}
+
+fn declare(
+    module: &mut ObjectModule,
+    parsed: &ParsedModule
+) -> Result<Declarations, String> {
+    if parsed.exports.iter().any(|(name, _, _)| *name == ENTRY) {
+        return Err(format!(
+            "the export \"{}\" clashes with the entry point", ENTRY
+        ));
+    }
+
+    let mut declarations = Declarations {
+        functions: Vec::new(),
+        types: Vec::new(),
+        globals: Vec::new(),
+        memory: None,
+    };
+
+    for (index, &t) in parsed.functions.iter().enumerate() {
+        let t = parsed.types.get(t as usize)
+            .ok_or_else(|| format!("function {} has no type", index))?;
+        let sig = signature(module, t)?;
+
+        let id = if index < parsed.imports.len() {
+            module.declare_function(parsed.imports[index], Linkage::Import, &sig)
+        } else if let Some(name) = parsed.export_name(true, index as u32) {
+            module.declare_function(name, Linkage::Export, &sig)
+        } else {
+            module.declare_anonymous_function(&sig)
+        }.map_err(|e| e.to_string())?;
+
+        declarations.functions.push(id);
+        declarations.types.push(t.clone());
+    }
+
+    for (index, (t, init)) in parsed.globals.iter().enumerate() {
+        let id = match parsed.export_name(false, index as u32) {
+            Some(name) => module.declare_data(name, Linkage::Export, true, false),
+            None => module.declare_anonymous_data(true, false)
+        }.map_err(|e| e.to_string())?;
+
+        let mut data = DataDescription::new();
+        data.define(Box::new(*init));
+        module.define_data(id, &data).map_err(|e| e.to_string())?;
+
+        declarations.globals.push((id, value_type(*t)?));
+    }
+
+    if let Some(pages) = parsed.memory.filter(|&pages| pages > 0) {
+        let size = usize::try_from(pages * PAGE_SIZE)
+            .map_err(|_| unsupported("memories this large"))?;
+        let id = module.declare_anonymous_data(true, false)
+            .map_err(|e| e.to_string())?;
+        let mut data = DataDescription::new();
+        data.define_zeroinit(size);
+        module.define_data(id, &data).map_err(|e| e.to_string())?;
+        declarations.memory = Some(id);
+    }
+
+    Ok(declarations)
+}
+
+fn define_entry(
+    module: &mut ObjectModule,
+    declarations: &Declarations,
+    entry: u32
+) -> Result<(), String> {
+    if !declarations.types[entry as usize].params.is_empty() {
+        return Err("the started function takes parameters".to_string());
+    }
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I32));
+    let id = module.declare_function(ENTRY, Linkage::Export, &sig)
+        .map_err(|e| e.to_string())?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    ctx.func.name = UserFuncName::user(0, id.as_u32());
+
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+    let block = builder.create_block();
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+
+    let callee = module.declare_func_in_func(
+        declarations.functions[entry as usize],
+        builder.func
+    );
+    builder.ins().call(callee, &[]);
+    let status = builder.ins().iconst(types::I32, 0);
+    builder.ins().return_(&[status]);
+    builder.finalize();
+
+    module.define_function(id, &mut ctx).map_err(|e| e.to_string())
+}
+
+fn signature(module: &ObjectModule, t: &FuncType) -> Result<Signature, String> {
+    let mut sig = module.make_signature();
+    for &param in t.params.iter() {
+        sig.params.push(AbiParam::new(value_type(param)?));
+    }
+    for &result in t.returns.iter() {
+        sig.returns.push(AbiParam::new(value_type(result)?));
+    }
+
+    Ok(sig)
+}
+
+fn value_type(t: WasmType) -> Result<types::Type, String> {
+    match t {
+        WasmType::I32 => Ok(types::I32),
+        WasmType::I64 => Ok(types::I64),
+        WasmType::F32 => Ok(types::F32),
+        WasmType::F64 => Ok(types::F64),
+        t => Err(unsupported(&format!("values of type {:?}", t)))
+    }
+}
+
+fn unsupported(what: &str) -> String {
+    format!("{} are not supported by the native backend", what)
+}
+
+/// A structured block being translated. Branches to a loop
+/// go to its header, branches to other blocks go past their end.
+enum Frame {
+    Block { next: Block, height: usize },
+    Loop { header: Block, next: Block, height: usize },
+    If { else_block: Block, next: Block, has_else: bool, height: usize },
+}
+
+impl Frame {
+    fn target(&self) -> Block {
+        match self {
+            Frame::Loop { header, .. } => *header,
+            Frame::Block { next, .. } | Frame::If { next, .. } => *next,
+        }
+    }
+}
+
+struct FunctionTranslator<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut ObjectModule,
+    declarations: &'a Declarations,
+    functions: HashMap<u32, FuncRef>,
+    globals: HashMap<u32, GlobalValue>,
+    memory: Option<GlobalValue>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // Where the function returns from, with the results as parameters
+    exit: Block,
+    results: usize,
+    reachable: bool,
+    // How many blocks were opened in unreachable code
+    unreachable_depth: usize,
+}
+
+impl<'a> FunctionTranslator<'a> {
+    fn new(
+        mut builder: FunctionBuilder<'a>,
+        module: &'a mut ObjectModule,
+        declarations: &'a Declarations
+    ) -> Self {
+        let exit = builder.create_block();
+        Self {
+            builder,
+            module,
+            declarations,
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            memory: None,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            exit,
+            results: 0,
+            reachable: true,
+            unreachable_depth: 0,
+        }
+    }
+
+    fn translate(mut self, t: &FuncType, body: &FunctionBody) -> Result<(), String> {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+
+        let mut locals = 0;
+        for (i, &param) in t.params.iter().enumerate() {
+            let value = self.builder.block_params(entry)[i];
+            self.declare_local(&mut locals, value_type(param)?, Some(value));
+        }
+
+        let mut reader = body.get_locals_reader().map_err(|e| e.to_string())?;
+        for _ in 0..reader.get_count() {
+            let (count, t) = reader.read().map_err(|e| e.to_string())?;
+            for _ in 0..count {
+                self.declare_local(&mut locals, value_type(t)?, None);
+            }
+        }
+
+        self.results = t.returns.len();
+        for &result in t.returns.iter() {
+            self.builder.append_block_param(self.exit, value_type(result)?);
+        }
+        let exit = self.exit;
+        self.frames.push(Frame::Block { next: exit, height: 0 });
+
+        let mut reader = body.get_operators_reader().map_err(|e| e.to_string())?;
+        while !reader.eof() {
+            let op = reader.read().map_err(|e| e.to_string())?;
+            if self.reachable {
+                self.operator(op)?;
+            } else {
+                self.unreachable_operator(op)?;
+            }
+        }
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Ok(())
+    }
+
+    fn declare_local(
+        &mut self,
+        locals: &mut u32,
+        t: types::Type,
+        value: Option<Value>
+    ) {
+        let var = Variable::from_u32(*locals);
+        *locals += 1;
+
+        self.builder.declare_var(var, t);
+        let value = value.unwrap_or_else(|| match t {
+            types::F32 => self.builder.ins().f32const(0.0),
+            types::F64 => self.builder.ins().f64const(0.0),
+            t => self.builder.ins().iconst(t, 0)
+        });
+        self.builder.def_var(var, value);
+    }
+
+    fn operator(&mut self, op: Operator) -> Result<(), String> {
+        match op {
+            Operator::Nop => (),
+            Operator::Drop => {
+                self.pop()?;
+            },
+            Operator::I32Const { value } => {
+                let value = self.builder.ins().iconst(types::I32, value as i64);
+                self.stack.push(value);
+            },
+            Operator::F32Const { value } => {
+                let value = f32::from_bits(value.bits());
+                let value = self.builder.ins().f32const(value);
+                self.stack.push(value);
+            },
+            Operator::LocalGet { local_index } => {
+                let value = self.builder.use_var(Variable::from_u32(local_index));
+                self.stack.push(value);
+            },
+            Operator::LocalSet { local_index } => {
+                let value = self.pop()?;
+                self.builder.def_var(Variable::from_u32(local_index), value);
+            },
+            Operator::LocalTee { local_index } => {
+                let value = *self.stack.last().ok_or_else(stack_underflow)?;
+                self.builder.def_var(Variable::from_u32(local_index), value);
+            },
+            Operator::GlobalGet { global_index } => {
+                let (address, t) = self.global(global_index)?;
+                let value = self.builder.ins()
+                    .load(t, MemFlags::trusted(), address, 0);
+                self.stack.push(value);
+            },
+            Operator::GlobalSet { global_index } => {
+                let value = self.pop()?;
+                let (address, _) = self.global(global_index)?;
+                self.builder.ins()
+                    .store(MemFlags::trusted(), value, address, 0);
+            },
+            Operator::I32Load { memarg } => self.load(types::I32, memarg)?,
+            Operator::F32Load { memarg } => self.load(types::F32, memarg)?,
+            Operator::I32Store { memarg } | Operator::F32Store { memarg } => {
+                let value = self.pop()?;
+                let address = self.address(memarg)?;
+                self.builder.ins()
+                    .store(MemFlags::trusted(), value, address, 0);
+            },
+            Operator::I32Add => self.binary(|b, x, y| b.ins().iadd(x, y))?,
+            Operator::I32Sub => self.binary(|b, x, y| b.ins().isub(x, y))?,
+            Operator::I32Mul => self.binary(|b, x, y| b.ins().imul(x, y))?,
+            Operator::I32DivS => self.binary(|b, x, y| b.ins().sdiv(x, y))?,
+            Operator::I32DivU => self.binary(|b, x, y| b.ins().udiv(x, y))?,
+            Operator::I32RemS => self.binary(|b, x, y| b.ins().srem(x, y))?,
+            Operator::I32RemU => self.binary(|b, x, y| b.ins().urem(x, y))?,
+            Operator::I32And => self.binary(|b, x, y| b.ins().band(x, y))?,
+            Operator::I32Or => self.binary(|b, x, y| b.ins().bor(x, y))?,
+            Operator::I32Xor => self.binary(|b, x, y| b.ins().bxor(x, y))?,
+            Operator::I32Shl => self.binary(|b, x, y| b.ins().ishl(x, y))?,
+            Operator::I32ShrS => self.binary(|b, x, y| b.ins().sshr(x, y))?,
+            Operator::I32ShrU => self.binary(|b, x, y| b.ins().ushr(x, y))?,
+            Operator::F32Add => self.binary(|b, x, y| b.ins().fadd(x, y))?,
+            Operator::F32Sub => self.binary(|b, x, y| b.ins().fsub(x, y))?,
+            Operator::F32Mul => self.binary(|b, x, y| b.ins().fmul(x, y))?,
+            Operator::F32Div => self.binary(|b, x, y| b.ins().fdiv(x, y))?,
+            Operator::F32ConvertI32S => {
+                self.unary(|b, x| b.ins().fcvt_from_sint(types::F32, x))?
+            },
+            Operator::I32TruncF32S => {
+                self.unary(|b, x| b.ins().fcvt_to_sint(types::I32, x))?
+            },
+            Operator::F32Nearest => self.unary(|b, x| b.ins().nearest(x))?,
+            Operator::F32Abs => self.unary(|b, x| b.ins().fabs(x))?,
+            Operator::Select => {
+                let c = self.pop()?;
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let value = self.builder.ins().select(c, x, y);
+                self.stack.push(value);
+            },
+            Operator::I32Eqz => {
+                let value = self.pop()?;
+                let c = self.builder.ins().icmp_imm(IntCC::Equal, value, 0);
+                self.push_condition(c);
+            },
+            Operator::I32Eq => self.icmp(IntCC::Equal)?,
+            Operator::I32Ne => self.icmp(IntCC::NotEqual)?,
+            Operator::I32LtS => self.icmp(IntCC::SignedLessThan)?,
+            Operator::I32LtU => self.icmp(IntCC::UnsignedLessThan)?,
+            Operator::I32GtS => self.icmp(IntCC::SignedGreaterThan)?,
+            Operator::I32GtU => self.icmp(IntCC::UnsignedGreaterThan)?,
+            Operator::I32LeS => self.icmp(IntCC::SignedLessThanOrEqual)?,
+            Operator::I32LeU => self.icmp(IntCC::UnsignedLessThanOrEqual)?,
+            Operator::I32GeS => self.icmp(IntCC::SignedGreaterThanOrEqual)?,
+            Operator::I32GeU => self.icmp(IntCC::UnsignedGreaterThanOrEqual)?,
+            Operator::F32Eq => self.fcmp(FloatCC::Equal)?,
+            Operator::F32Ne => self.fcmp(FloatCC::NotEqual)?,
+            Operator::F32Lt => self.fcmp(FloatCC::LessThan)?,
+            Operator::F32Gt => self.fcmp(FloatCC::GreaterThan)?,
+            Operator::F32Le => self.fcmp(FloatCC::LessThanOrEqual)?,
+            Operator::F32Ge => self.fcmp(FloatCC::GreaterThanOrEqual)?,
+            Operator::Call { function_index } => self.call(function_index)?,
+            Operator::Block { ty } => {
+                block_type(ty)?;
+                let next = self.builder.create_block();
+                let height = self.stack.len();
+                self.frames.push(Frame::Block { next, height });
+            },
+            Operator::Loop { ty } => {
+                block_type(ty)?;
+                let header = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                let height = self.stack.len();
+                self.frames.push(Frame::Loop { header, next, height });
+            },
+            Operator::If { ty } => {
+                block_type(ty)?;
+                let c = self.pop()?;
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.ins().brif(c, then_block, &[], else_block, &[]);
+                self.builder.switch_to_block(then_block);
+                let height = self.stack.len();
+                self.frames.push(
+                    Frame::If { else_block, next, has_else: false, height }
+                );
+            },
+            Operator::Else => self.else_()?,
+            Operator::End => self.end()?,
+            Operator::Br { relative_depth } => {
+                self.branch(relative_depth)?;
+                self.reachable = false;
+            },
+            Operator::BrIf { relative_depth } => {
+                let c = self.pop()?;
+                let taken = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.ins().brif(c, taken, &[], next, &[]);
+                self.builder.switch_to_block(taken);
+                self.branch(relative_depth)?;
+                self.builder.switch_to_block(next);
+            },
+            Operator::Return => {
+                let results = self.results()?;
+                self.builder.ins().return_(&results);
+                self.reachable = false;
+            },
+            Operator::Unreachable => {
+                self.builder.ins().trap(UNREACHABLE);
+                self.reachable = false;
+            },
+            op => {
+                return Err(unsupported(&format!("instructions like {:?}", op)))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unreachable_operator(&mut self, op: Operator) -> Result<(), String> {
+        match op {
+            Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. } => self.unreachable_depth += 1,
+            Operator::Else if self.unreachable_depth == 0 => self.else_()?,
+            Operator::End if self.unreachable_depth == 0 => self.end()?,
+            Operator::End => self.unreachable_depth -= 1,
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn else_(&mut self) -> Result<(), String> {
+        match self.frames.last_mut() {
+            Some(Frame::If { else_block, next, has_else, height }) => {
+                *has_else = true;
+                let (else_block, next) = (*else_block, *next);
+                self.stack.truncate(*height);
+                if self.reachable {
+                    self.builder.ins().jump(next, &[]);
+                }
+                self.builder.switch_to_block(else_block);
+                self.reachable = true;
+                Ok(())
+            },
+            _ => Err("else outside of an if".to_string())
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        let frame = self.frames.pop().ok_or("unbalanced end")?;
+        let next = match frame {
+            Frame::If { else_block, next, has_else, height } => {
+                self.stack.truncate(height);
+                if self.reachable {
+                    self.builder.ins().jump(next, &[]);
+                }
+                if !has_else {
+                    self.builder.switch_to_block(else_block);
+                    self.builder.ins().jump(next, &[]);
+                }
+                next
+            },
+            Frame::Block { next, height } | Frame::Loop { next, height, .. } => {
+                if self.reachable {
+                    let args = if self.frames.is_empty() {
+                        self.results()?
+                    } else {
+                        Vec::new()
+                    };
+                    self.builder.ins().jump(next, &args);
+                }
+                self.stack.truncate(height);
+                next
+            }
+        };
+
+        self.builder.switch_to_block(next);
+        self.reachable = true;
+
+        if self.frames.is_empty() {
+            let results = self.builder.block_params(next).to_vec();
+            self.builder.ins().return_(&results);
+        }
+
+        Ok(())
+    }
+
+    /// Branches out of the block `depth` levels up.
+    fn branch(&mut self, depth: u32) -> Result<(), String> {
+        let index = self.frames.len().checked_sub(depth as usize + 1)
+            .ok_or("branch out of the function")?;
+
+        let args = if index == 0 {
+            self.results()?
+        } else {
+            Vec::new()
+        };
+        let target = self.frames[index].target();
+        self.builder.ins().jump(target, &args);
+        Ok(())
+    }
+
+    fn call(&mut self, index: u32) -> Result<(), String> {
+        let t = self.declarations.types.get(index as usize)
+            .ok_or_else(|| format!("call to an unknown function {}", index))?;
+        let callee = match self.functions.get(&index) {
+            Some(callee) => *callee,
+            None => {
+                let id = self.declarations.functions[index as usize];
+                let callee = self.module
+                    .declare_func_in_func(id, self.builder.func);
+                self.functions.insert(index, callee);
+                callee
+            }
+        };
+
+        let start = self.stack.len().checked_sub(t.params.len())
+            .ok_or_else(stack_underflow)?;
+        let args = self.stack.split_off(start);
+        let call = self.builder.ins().call(callee, &args);
+        self.stack.extend_from_slice(self.builder.inst_results(call));
+        Ok(())
+    }
+
+    /// The address and the type of a global.
+    fn global(&mut self, index: u32) -> Result<(Value, types::Type), String> {
+        let &(id, t) = self.declarations.globals.get(index as usize)
+            .ok_or_else(|| format!("unknown global {}", index))?;
+        let global = match self.globals.get(&index) {
+            Some(global) => *global,
+            None => {
+                let global = self.module
+                    .declare_data_in_func(id, self.builder.func);
+                self.globals.insert(index, global);
+                global
+            }
+        };
+
+        let pointer = self.module.target_config().pointer_type();
+        Ok((self.builder.ins().global_value(pointer, global), t))
+    }
+
+    fn load(
+        &mut self,
+        t: types::Type,
+        memarg: MemoryImmediate
+    ) -> Result<(), String> {
+        let address = self.address(memarg)?;
+        let value = self.builder.ins()
+            .load(t, MemFlags::trusted(), address, 0);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// The machine address of the memory access with the address
+    /// in the memory on top of the stack.
+    fn address(&mut self, memarg: MemoryImmediate) -> Result<Value, String> {
+        let id = self.declarations.memory.ok_or("the module has no memory")?;
+        let memory = match self.memory {
+            Some(memory) => memory,
+            None => {
+                let memory = self.module
+                    .declare_data_in_func(id, self.builder.func);
+                self.memory = Some(memory);
+                memory
+            }
+        };
+
+        let pointer = self.module.target_config().pointer_type();
+        let address = self.pop()?;
+        let address = self.builder.ins().uextend(pointer, address);
+        let base = self.builder.ins().global_value(pointer, memory);
+        let address = self.builder.ins().iadd(base, address);
+        Ok(self.builder.ins().iadd_imm(address, memarg.offset as i64))
+    }
+
+    fn unary(
+        &mut self,
+        f: impl FnOnce(&mut FunctionBuilder, Value) -> Value
+    ) -> Result<(), String> {
+        let x = self.pop()?;
+        let value = f(&mut self.builder, x);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        f: impl FnOnce(&mut FunctionBuilder, Value, Value) -> Value
+    ) -> Result<(), String> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        let value = f(&mut self.builder, x, y);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn icmp(&mut self, cc: IntCC) -> Result<(), String> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        let c = self.builder.ins().icmp(cc, x, y);
+        self.push_condition(c);
+        Ok(())
+    }
+
+    fn fcmp(&mut self, cc: FloatCC) -> Result<(), String> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        let c = self.builder.ins().fcmp(cc, x, y);
+        self.push_condition(c);
+        Ok(())
+    }
+
+    // Comparisons give an i8, WebAssembly expects an i32
+    fn push_condition(&mut self, c: Value) {
+        let value = self.builder.ins().uextend(types::I32, c);
+        self.stack.push(value);
+    }
+
+    fn results(&mut self) -> Result<Vec<Value>, String> {
+        let start = self.stack.len().checked_sub(self.results)
+            .ok_or_else(stack_underflow)?;
+        Ok(self.stack[start..].to_vec())
+    }
+
+    fn pop(&mut self) -> Result<Value, String> {
+        self.stack.pop().ok_or_else(stack_underflow)
+    }
+}
+
+fn block_type(ty: TypeOrFuncType) -> Result<(), String> {
+    match ty {
+        TypeOrFuncType::Type(WasmType::EmptyBlockType) => Ok(()),
+        _ => Err(unsupported("blocks with results"))
+    }
+}
+
+fn stack_underflow() -> String {
+    "the operand stack is empty".to_string()
+}
+
+#[cfg(test)]
+mod native_tests {
+    use super::*;
+
+    fn lower(text: &str) -> Result<Vec<u8>, String> {
+        Native.lower(&wat::parse_str(text).unwrap())
+    }
+
+    #[test]
+    fn test_lower_program() {
+        let text = "(module
+            (func $writeln_int (import \"imports\" \"writeln_int\") (param i32))
+            (global $g (export \"g\") (mut f32) (f32.const 0))
+            (func (export \"program\") (local $i i32)
+                (block $end
+                    (loop $continue
+                        local.get $i
+                        i32.const 10
+                        i32.ge_s
+                        br_if $end
+                        local.get $i
+                        i32.const 2
+                        i32.rem_s
+                        i32.eqz
+                        (if
+                            (then
+                                local.get $i
+                                call $writeln_int)
+                            (else
+                                global.get $g
+                                f32.const 1.5
+                                f32.add
+                                global.set $g))
+                        local.get $i
+                        i32.const 1
+                        i32.add
+                        local.set $i
+                        br $continue))
+                return))";
+
+        let object = lower(text).unwrap();
+        assert!(!object.is_empty());
+    }
+
+    #[test]
+    fn test_lower_unreachable_code() {
+        let text = "(module (func (export \"program\")
+            (if (i32.const 1)
+                (then
+                    return
+                    i32.const 1
+                    drop)
+                (else
+                    unreachable))
+            i32.const 2
+            drop))";
+
+        assert!(lower(text).is_ok());
+    }
+
+    #[test]
+    fn test_lower_memory() {
+        let text = "(module (memory 1) (func (export \"program\")
+            i32.const 4
+            f32.const 2.5
+            f32.store offset=8
+            i32.const 0
+            i32.load offset=12
+            drop))";
+
+        assert!(lower(text).is_ok());
+    }
+
+    #[test]
+    fn test_lower_reports_unsupported() {
+        let text = "(module (memory 1) (func (export \"program\")
+            i32.const 0
+            i64.load
+            drop))";
+
+        let err = lower(text).unwrap_err();
+        assert!(err.contains("not supported"), "{}", err);
+    }
+
+    #[test]
+    fn test_lower_entry_clash() {
+        let text = "(module (func (export \"main\")))";
+
+        let err = lower(text).unwrap_err();
+        assert!(err.contains("entry point"), "{}", err);
+    }
+}
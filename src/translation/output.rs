@@ -1,24 +1,47 @@
-use std::{collections::LinkedList, io::{BufWriter, Write}};
+use std::{collections::VecDeque, fmt, io::{BufWriter, Write}};
 
+/// Stands for a type not known when the code is generated
 pub const TEMPLATE: &str = "UNKNOWN";
 
+const SPACES: &str = "                                ";
+
+/// The module text on its way to the writer. The text goes straight
+/// through unless some of it is held back: everything from a part
+/// reserved to be filled in later up to the point the part is filled,
+/// or the whole text if it is to be validated before it is written.
+/// The text is formatted straight into the writer or the part held
+/// back, so `write!` on the output allocates nothing of its own.
 pub struct Output {
     indent: usize,
-    parts: Vec<String>,
-    template_indices: LinkedList<usize>,
+    /// The parts held back, the reserved ones not filled yet empty.
+    /// The text written after a part is appended to it.
+    parts: VecDeque<Option<String>>,
+    /// The index of the first part held back
+    first: usize,
+    /// Whether to hold back the whole text until the flush
+    hold: bool,
     writer: BufWriter<Box<dyn Write>>,
+    written: usize,
 }
 
 impl Output {
     pub fn new(writer: Box<dyn Write>) -> Self {
         Self {
             indent: 0,
-            parts: Vec::with_capacity(16),
-            template_indices: LinkedList::new(),
+            parts: VecDeque::new(),
+            first: 0,
+            hold: false,
             writer: BufWriter::new(writer),
+            written: 0,
         }
     }
 
+    /// Makes the output hold back the whole text until the flush,
+    /// so that it can be read with `text` or discarded.
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
     pub fn indent_in(&mut self) {
         self.indent += 2;
     }
@@ -32,35 +55,197 @@ impl Output {
     }
 
     pub fn writenl(&mut self, msg: &str) {
-        let indent = " ".repeat(self.indent);
-        self.write(&format!("\n{}{}", indent, msg));
+        self.newline();
+        self.write(msg);
     }
 
-    pub fn write(&mut self, msg: &str) {
-        if msg.contains(TEMPLATE) {
-            self.template_indices.push_back(self.parts.len());
+    /// Writes the message as a line of its own at the current indentation.
+    pub fn writeln(&mut self, msg: &str) {
+        self.write_indent();
+        self.write(msg);
+        self.write("\n");
+    }
+
+    /// Starts a new line at the current indentation.
+    pub fn newline(&mut self) {
+        self.write("\n");
+        self.write_indent();
+    }
+
+    fn write_indent(&mut self) {
+        let mut indent = self.indent;
+        while indent > 0 {
+            let spaces = indent.min(SPACES.len());
+            self.write(&SPACES[..spaces]);
+            indent -= spaces;
         }
+    }
+
+    /// Returns the message on a new line at the current indentation.
+    pub fn indented(&self, msg: &str) -> String {
+        format!("\n{}{}", " ".repeat(self.indent), msg)
+    }
 
-        self.parts.push(msg.to_string());
+    /// Reserves a part to be filled in later, returning its index.
+    /// The text after it is held back until it is filled.
+    pub fn reserve(&mut self) -> usize {
+        self.parts.push_back(None);
+        self.first + self.parts.len() - 1
     }
 
-    pub fn fill_last_template(&mut self, with: &str) {
-        let maybe_index = self.template_indices.back();
-        if let Some(&index) = maybe_index {
-            let part = &self.parts[index];
-            self.parts[index] = part.replace(TEMPLATE, with);
-            self.template_indices.pop_back();
+    pub fn fill(&mut self, index: usize, msg: &str) {
+        self.parts[index - self.first] = Some(msg.to_string());
+        if !self.hold {
+            self.release();
         }
     }
 
-    pub fn flush(&mut self) {
-        for p in &self.parts {
-            self.writer.write_fmt(format_args!("{}", p))
-            .unwrap_or_else(|e| {
-                panic!("IO error occurred when generating code: {}", e);
-            });
+    pub fn write(&mut self, msg: &str) {
+        if self.hold || !self.parts.is_empty() {
+            match self.parts.back_mut() {
+                Some(Some(part)) => part.push_str(msg),
+                _ => self.parts.push_back(Some(msg.to_string())),
+            }
+        } else {
+            self.emit(msg);
         }
+    }
+
+    /// Writes the formatted text, which makes `write!` work on the output.
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        // Writing to the output fails only by panicking
+        fmt::Write::write_fmt(self, args).unwrap_or_default();
+    }
+
+    /// The text held back.
+    pub fn text(&self) -> String {
+        self.parts.iter().flatten().map(String::as_str).collect()
+    }
+
+    /// Drops the text held back.
+    pub fn discard(&mut self) {
+        self.first += self.parts.len();
         self.parts.clear();
-        self.template_indices.clear();
+    }
+
+    /// How many bytes have been flushed so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Writes out the text held back, leaving
+    /// the parts not filled yet empty.
+    pub fn flush(&mut self) {
+        while let Some(part) = self.parts.pop_front() {
+            self.first += 1;
+            self.emit(part.as_deref().unwrap_or_default());
+        }
+        self.writer.flush().unwrap_or_else(|e| {
+            panic!("IO error occurred when generating code: {}", e);
+        });
+    }
+
+    /// Writes out the parts held back before the first one not filled.
+    fn release(&mut self) {
+        while let Some(Some(_)) = self.parts.front() {
+            let part = self.parts.pop_front().flatten().unwrap_or_default();
+            self.first += 1;
+            self.emit(&part);
+        }
+    }
+
+    fn emit(&mut self, text: &str) {
+        self.written += text.len();
+        self.writer.write_all(text.as_bytes()).unwrap_or_else(|e| {
+            panic!("IO error occurred when generating code: {}", e);
+        });
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output() -> (Output, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        (Output::new(Box::new(buffer.clone())), buffer)
+    }
+
+    fn written(output: &mut Output, buffer: &SharedBuffer) -> String {
+        output.writer.flush().unwrap();
+        String::from_utf8(buffer.0.borrow().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_output_streams() {
+        let (mut output, buffer) = output();
+
+        output.write("(module");
+        output.indent_in();
+        output.newline();
+        write!(output, "(func ${}", 1);
+        output.writenl(")");
+        assert_eq!(written(&mut output, &buffer), "(module\n  (func $1\n  )");
+        assert_eq!(output.written(), 22);
+    }
+
+    #[test]
+    fn test_output_holds_back_after_reserved() {
+        let (mut output, buffer) = output();
+
+        output.write("a");
+        let first = output.reserve();
+        output.write("c");
+        let second = output.reserve();
+        output.write("e");
+        assert_eq!(written(&mut output, &buffer), "a");
+
+        output.fill(second, "d");
+        assert_eq!(written(&mut output, &buffer), "a");
+        output.fill(first, "b");
+        assert_eq!(written(&mut output, &buffer), "abcde");
+
+        output.write("f");
+        assert_eq!(written(&mut output, &buffer), "abcdef");
+    }
+
+    #[test]
+    fn test_output_hold() {
+        let (mut output, buffer) = output();
+        output.set_hold(true);
+
+        output.write("a");
+        let slot = output.reserve();
+        output.write("c");
+        output.fill(slot, "b");
+        assert_eq!(output.text(), "abc");
+        assert_eq!(written(&mut output, &buffer), "");
+
+        output.discard();
+        output.write("d");
+        output.flush();
+        assert_eq!(written(&mut output, &buffer), "d");
     }
 }
@@ -0,0 +1,144 @@
+/* The procedures a natively compiled program imports. */
+
+#include <math.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+/* Every line is flushed as soon as it is written,
+   so nothing is lost when the program traps. */
+static void writeln(const char *line) {
+    puts(line);
+    fflush(stdout);
+}
+
+void writeln_int(int value) {
+    char line[16];
+    snprintf(line, sizeof line, "%d", value);
+    writeln(line);
+}
+
+/* Prints the fewest digits that read back as the same value, rounding
+   halves up and without an exponent, which is how the embedded runtime
+   prints reals. */
+void writeln_real(float value) {
+    char exact[80], shortest[32], line[64];
+    char digits[64];
+    const char *mantissa;
+    int precision, exponent, count = 0, point, i;
+    size_t at = 0;
+
+    if (isnan(value)) {
+        writeln("NaN");
+        return;
+    }
+    if (isinf(value)) {
+        writeln(value < 0 ? "-inf" : "inf");
+        return;
+    }
+
+    /* The form is [-]d.ddde[+-]xx, with every digit of the value */
+    snprintf(exact, sizeof exact, "%.59e", value);
+    mantissa = exact;
+    if (*mantissa == '-') {
+        line[at++] = '-';
+        mantissa++;
+    }
+    for (; *mantissa != 'e'; mantissa++) {
+        if (*mantissa != '.') {
+            digits[count++] = *mantissa;
+        }
+    }
+    exponent = atoi(mantissa + 1);
+
+    /* Nine digits tell every float apart */
+    for (precision = 1; precision <= 9; precision++) {
+        char rounded[16];
+        int carry = 0;
+
+        memcpy(rounded, digits, precision);
+        rounded[precision] = '\0';
+        if (digits[precision] >= '5') {
+            for (i = precision - 1; i >= 0 && rounded[i] == '9'; i--) {
+                rounded[i] = '0';
+            }
+            if (i >= 0) {
+                rounded[i]++;
+            } else {
+                /* 9.5 becomes 10 */
+                rounded[0] = '1';
+                carry = 1;
+            }
+        }
+        snprintf(
+            shortest, sizeof shortest, "%s0.%se%d",
+            value < 0 ? "-" : "", rounded, exponent + carry + 1
+        );
+        if (strtof(shortest, NULL) == value || precision == 9) {
+            memcpy(digits, rounded, precision);
+            exponent += carry;
+            break;
+        }
+    }
+    count = precision;
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+
+    point = exponent + 1;
+    if (point <= 0) {
+        line[at++] = '0';
+        line[at++] = '.';
+        for (i = point; i < 0; i++) {
+            line[at++] = '0';
+        }
+    }
+    for (i = 0; i < count; i++) {
+        if (i == point && point > 0) {
+            line[at++] = '.';
+        }
+        line[at++] = digits[i];
+    }
+    for (i = count; i < point; i++) {
+        line[at++] = '0';
+    }
+    line[at] = '\0';
+    writeln(line);
+}
+
+void runtime_error(int code, int line) {
+    fflush(stdout);
+    fprintf(stderr, "Runtime error %d at line %d\n", code, line);
+    exit(code);
+}
+
+/* Reports a trap, such as a division by zero,
+   the way the embedded runtime does. */
+static void trapped(int signal) {
+    static const char message[] = "Runtime error: the program trapped\n";
+    (void) signal;
+    write(STDERR_FILENO, message, sizeof message - 1);
+    _exit(1);
+}
+
+__attribute__((constructor)) static void handle_traps(void) {
+    signal(SIGILL, trapped);
+    signal(SIGFPE, trapped);
+    signal(SIGTRAP, trapped);
+    signal(SIGSEGV, trapped);
+}
+
+int random_int(int bound) {
+    return bound > 0 ? rand() % bound : 0;
+}
+
+void randomize(void) {
+    srand((unsigned) time(NULL));
+}
+
+int current_time(void) {
+    return (int) time(NULL);
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+/// Settings of the generated module that decide
+/// how it fits into the host environment.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// The module imported procedures come from
+    pub import_module: String,
+    /// The modules of procedures imported from elsewhere
+    pub procedure_modules: HashMap<String, String>,
+    /// The name the program is exported under
+    pub export_name: String,
+    /// Whether program variables become exported globals
+    /// instead of locals of the program function
+    pub globals: bool,
+    pub memory: Memory,
+    pub runtime_errors: RuntimeErrors,
+    pub random: RandomSource,
+    pub start: Start,
+}
+
+/// How the program is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Start {
+    /// The host calls the exported program
+    Export,
+    /// The program runs when the module is instantiated
+    Section,
+    /// The program is also exported as `_initialize`,
+    /// which hosts following the WASI reactor convention call
+    Initialize,
+}
+
+/// What happens when the program fails at run time,
+/// such as on a division by zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrors {
+    /// The execution traps
+    Trap,
+    /// The imported `runtime_error(code, line)` is called
+    /// with the Turbo Pascal error code before trapping
+    Callback,
+}
+
+/// Where the numbers `random` returns come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomSource {
+    /// The imported `random_int(n)`, which the imported
+    /// `randomize()` reseeds
+    Import,
+    /// A xorshift generator defined in the module, which starts
+    /// from a fixed seed and which `randomize` reseeds with the
+    /// imported `current_time()`
+    Xorshift,
+}
+
+/// The linear memory of the module, declared when the program
+/// needs it or when it is imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    /// Whether the memory is imported from the host
+    /// instead of being defined and exported
+    pub import: bool,
+    /// The initial size in 64 KiB pages
+    pub pages: u32,
+    pub max_pages: Option<u32>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            import: false,
+            pages: 1,
+            max_pages: None,
+        }
+    }
+}
+
+impl Memory {
+    fn limits(&self, pages: u32) -> String {
+        let pages = pages.max(self.pages);
+        match self.max_pages {
+            Some(max) => format!("{} {}", pages, max),
+            None => pages.to_string()
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            import_module: "imports".to_string(),
+            procedure_modules: HashMap::new(),
+            export_name: "program".to_string(),
+            globals: false,
+            memory: Memory::default(),
+            runtime_errors: RuntimeErrors::Trap,
+            random: RandomSource::Import,
+            start: Start::Export,
+        }
+    }
+}
+
+impl Target {
+    /// Returns the declaration of the memory, which is an import
+    /// if `import` is set and a definition otherwise. The memory
+    /// starts with at least `pages` pages, which the variables need.
+    pub fn memory_declaration(&self, import: bool, pages: u32) -> String {
+        let limits = self.memory.limits(pages);
+        if import {
+            format!(
+                "(import \"{}\" \"memory\" (memory {}))",
+                self.import_module, limits
+            )
+        } else {
+            format!("(memory (export \"memory\") {})", limits)
+        }
+    }
+
+    pub fn import_module_of(&self, procedure: &str) -> &str {
+        self.procedure_modules.get(procedure)
+            .unwrap_or(&self.import_module)
+    }
+}
+
+#[cfg(test)]
+mod target_tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_declaration() {
+        let mut target = Target::default();
+        assert_eq!(
+            target.memory_declaration(false, 0),
+            "(memory (export \"memory\") 1)"
+        );
+        assert_eq!(
+            target.memory_declaration(false, 3),
+            "(memory (export \"memory\") 3)"
+        );
+
+        target.import_module = "env".to_string();
+        target.memory = Memory {
+            import: true,
+            pages: 2,
+            max_pages: Some(16),
+        };
+        assert_eq!(
+            target.memory_declaration(true, 1),
+            "(import \"env\" \"memory\" (memory 2 16))"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use wasmparser::{Parser, Payload};
+
+/// Assembles the module text and validates the resulting binary.
+/// Reports the offending instruction when validation fails.
+pub fn validate(text: &str) -> Result<Vec<u8>, String> {
+    let binary = wat::parse_str(text).map_err(|e| e.to_string())?;
+
+    wasmparser::validate(&binary).map_err(|e| {
+        match instruction_at(&binary, e.offset()) {
+            Some((body, op)) => format!(
+                "{} at instruction {} in function body {}",
+                e.message(), op, body
+            ),
+            None => e.message().to_string()
+        }
+    })?;
+
+    Ok(binary)
+}
+
+fn instruction_at(binary: &[u8], offset: usize) -> Option<(usize, String)> {
+    let bodies = Parser::new(0).parse_all(binary)
+        .filter_map(|payload| match payload {
+            Ok(Payload::CodeSectionEntry(body)) => Some(body),
+            _ => None
+        });
+
+    for (index, body) in bodies.enumerate() {
+        let mut reader = body.get_operators_reader().ok()?;
+        while !reader.eof() {
+            let (op, op_offset) = reader.read_with_offset().ok()?;
+            if op_offset == offset {
+                return Some((index, format!("{:?}", op)));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_correct_module() {
+        let text = "(module (func (export \"program\") (local $a i32)
+            i32.const 1
+            local.set $a))";
+
+        assert!(validate(text).is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_instruction() {
+        let text = "(module (func (export \"program\") (local $a f32)
+            i32.const 1
+            local.set $a))";
+
+        let err = validate(text).unwrap_err();
+        assert!(err.contains("type mismatch"), "{}", err);
+        assert!(err.contains("LocalSet"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_malformed_text() {
+        assert!(validate("(module (func i32.div))").is_err());
+    }
+}
@@ -1,229 +1,859 @@
-use std::io::Write;
+use std::{collections::HashSet, fmt, io::Write, time::Instant};
 
-use crate::{semantics::{Type, Types}, tokenization::{Operator, Relation}, translation::output::{Output, TEMPLATE}};
+use crate::{
+    metrics::Metrics,
+    optimization,
+    semantics::{Type, Types},
+    tokenization::{Operator, Relation},
+    translation::{
+        instruction::{BinaryOp, Instruction, UnaryOp, ValueType},
+        names::{
+            self,
+            ADDRESS,
+            CASE_SELECTOR,
+            DIVISOR,
+            INDEX,
+            OTHER_ADDRESS,
+            RANDOM_FUNCTION,
+            RANDOM_STATE
+        },
+        output::{Output, TEMPLATE},
+        validation,
+        RandomSource,
+        RuntimeErrors,
+        Start,
+        Target
+    }
+};
+
+const RUNTIME_ERROR: &str = "runtime_error";
+// Identifies the exported function when it is also started otherwise
+const PROGRAM: &str = "program";
+const DIVISION_BY_ZERO: i32 = 200;
+const RANGE_CHECK: i32 = 201;
+const PAGE_SIZE: u64 = 65536;
+const RANDOM: &str = "random_int";
+const RANDOMIZE: &str = "randomize";
+const TIME: &str = "current_time";
+// The seed of the xorshift generator until it is reseeded
+const RANDOM_SEED: u32 = 2463534242;
+
+pub type FunctionHandler = Box<dyn FnMut(&str, &[Instruction])>;
+
+/// The labels of a loop
+#[derive(Debug, Clone)]
+pub struct LoopLabels {
+    /// The label that starts the next iteration
+    pub next: String,
+    /// The label that leaves the loop
+    pub exit: String,
+}
 
 pub struct Wasm {
     output: Output,
     silenced: bool,
+    optimize: bool,
+    validate: bool,
+    target: Target,
+    body: Vec<Instruction>,
+    // The integer locals the generated code keeps its values in,
+    // declared along with the function
+    helpers: Vec<&'static str>,
+    // The locals holding the arguments of standard functions
+    // the function uses, by their types
+    operands: Vec<ValueType>,
+    // Import declarations held back until it is known which are called
+    imports: Vec<(String, String)>,
+    // Whether the module defines the xorshift generator
+    random_used: bool,
+    imports_slot: Option<usize>,
+    called: HashSet<String>,
+    globals: Vec<(String, ValueType)>,
+    // The addresses of the variables kept in the linear memory
+    addresses: Vec<(String, u32)>,
+    // Where the memory the variables do not take starts
+    data_end: u64,
+    memory_used: bool,
+    // Where the import of the memory goes, which is declared at the
+    // end of the module when it is known how much the variables need
+    memory_slot: Option<usize>,
+    // Whether a function was generated, which the definitions follow
+    has_functions: bool,
+    // The function being generated
+    function: String,
+    // The number of loops opened so far, which numbers their labels
+    loops: usize,
+    function_handler: Option<FunctionHandler>,
+    metrics: Metrics,
 }
 
 impl Wasm {
     pub fn new(writer: Box<dyn Write>) -> Self {
+        let mut output = Output::new(writer);
+        output.set_hold(true);
         Self {
             silenced: false,
-            output: Output::new(writer),
+            optimize: false,
+            validate: true,
+            target: Target::default(),
+            output,
+            body: Vec::new(),
+            helpers: Vec::new(),
+            operands: Vec::new(),
+            imports: Vec::new(),
+            random_used: false,
+            imports_slot: None,
+            called: HashSet::new(),
+            globals: Vec::new(),
+            addresses: Vec::new(),
+            data_end: 0,
+            memory_used: false,
+            memory_slot: None,
+            has_functions: false,
+            function: String::new(),
+            loops: 0,
+            function_handler: None,
+            metrics: Metrics::default(),
         }
     }
 
+    /// Enables the optimizer, which runs over each function body
+    /// before it is written out and drops the imports never called.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn optimizes(&self) -> bool {
+        self.optimize
+    }
+
+    /// Makes `finish` validate the module, which is then held back
+    /// until it is validated instead of streamed to the writer.
+    pub fn set_validation(&mut self, validate: bool) {
+        self.validate = validate;
+        self.output.set_hold(validate);
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
+
+    /// Sets a handler that receives the name and
+    /// the final body of every function generated.
+    pub fn set_function_handler(&mut self, handler: FunctionHandler) {
+        self.function_handler = Some(handler);
+    }
+
+    /// The time spent optimizing and emitting the code, the number
+    /// of instructions generated and the size of the module text.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            output_size: self.output.written(),
+            ..self.metrics.clone()
+        }
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Makes the module declare its linear memory.
+    pub fn use_memory(&mut self) {
+        self.memory_used = true;
+    }
+
     pub fn mod_start(&mut self) {
         if !self.silenced {
             self.output.write("(module");
             self.output.indent_in();
+            if self.target.memory.import {
+                self.memory_slot = Some(self.output.reserve());
+            }
         }
     }
 
     pub fn mod_end(&mut self) {
         if !self.silenced {
+            if self.random_used {
+                self.random_function();
+            }
+
+            let started = Instant::now();
+            if let Some(slot) = self.memory_slot.take() {
+                let pages = self.memory_pages() as u32;
+                let decl = self.target.memory_declaration(true, pages);
+                let decl = self.output.indented(&decl);
+                self.output.fill(slot, &decl);
+            }
+            if let Some(slot) = self.imports_slot.take() {
+                let called = &self.called;
+                let imports: String = self.imports.drain(..)
+                    .filter(|(name, _)| called.contains(name))
+                    .map(|(_, decl)| decl)
+                    .collect();
+                self.output.fill(slot, &imports);
+            }
+
+            // The definitions follow the functions, as it is
+            // only now known which of them the module needs
+            if self.has_functions {
+                self.definitions();
+            }
+
             self.output.write(")\n");
+            self.metrics.emission += started.elapsed();
+        }
+    }
+
+    fn definitions(&mut self) {
+        if self.memory_used && !self.target.memory.import {
+            let pages = self.memory_pages() as u32;
+            let decl = self.target.memory_declaration(false, pages);
+            self.output.writeln(&decl);
+        }
+
+        match self.target.start {
+            Start::Export => (),
+            Start::Section => {
+                self.output.newline();
+                write!(self.output, "(start ${})", PROGRAM);
+            },
+            Start::Initialize => {
+                self.output.newline();
+                write!(
+                    self.output,
+                    "(export \"_initialize\" (func ${}))", PROGRAM
+                );
+            }
+        }
+
+        if self.random_used {
+            self.output.newline();
+            write!(
+                self.output,
+                "(global ${} (mut i32) (i32.const {}))",
+                RANDOM_STATE, RANDOM_SEED
+            );
+        }
+
+        for (name, t) in &self.globals {
+            let t = valuename(*t);
+            self.output.newline();
+            write!(
+                self.output,
+                "(global ${} (export \"{}\") (mut {}) ({}.const 0))",
+                name, name, t, t
+            );
         }
     }
 
     pub fn func_import(&mut self, name: &str, types: &Types) {
+        let mut params = String::new();
+        for t in types {
+            params += "(param ";
+            params += self.typename(t);
+            params += ")";
+        }
+        self.import(name, &params, self.optimize);
+    }
+
+    /// Declares the imports the standard procedures call, which
+    /// are left out of the module unless they are called.
+    pub fn standard_imports(&mut self) {
+        match self.target.random {
+            RandomSource::Import => {
+                self.import(RANDOM, "(param i32) (result i32)", true);
+                self.import(RANDOMIZE, "", true);
+            },
+            RandomSource::Xorshift => self.import(TIME, "(result i32)", true)
+        }
+    }
+
+    /// Declares the imported function with the signature, holding the
+    /// declaration back until the end of the module if `on_demand`.
+    fn import(&mut self, name: &str, signature: &str, on_demand: bool) {
         if !self.silenced {
-            let mut params = String::new();
-            for t in types {
-                params += &format!("(param {})", self.typename(t))
+            let decl = format!(
+                "(func ${} (import {} \"{}\") {})",
+                name,
+                quoted(self.target.import_module_of(name)),
+                name,
+                signature
+            );
+
+            if on_demand {
+                if self.imports_slot.is_none() {
+                    self.imports_slot = Some(self.output.reserve());
+                }
+                let decl = self.output.indented(&decl);
+                self.imports.push((name.to_string(), decl));
+            } else {
+                self.output.writenl(&decl);
             }
-            
-            self.output.writenl(&format!(
-                "(func ${} (import \"imports\" \"{}\") {})",
-                name, name, params
-            ))
         }
     }
 
     pub fn func_start(&mut self, name: &str, export: bool) {
         if !self.silenced {
-            let export_part = if export {
-                format!("(export \"{}\")", name)
+            self.function = name.to_string();
+            self.has_functions = true;
+            self.output.newline();
+            if export && self.target.start != Start::Export {
+                write!(
+                    self.output, "(func ${} (export {})", PROGRAM, quoted(name)
+                );
+            } else if export {
+                write!(self.output, "(func (export {})", quoted(name));
             } else {
-                format!("${}", name)
-            };
-    
-            self.output.writenl(&format!("(func {}", export_part));
+                write!(self.output, "(func ${}", name);
+            }
             self.output.indent_in();
         }
     }
 
     pub fn func_local(&mut self, name: &str, type_: &Type) {
         if !self.silenced {
-            self.output.write(
-                &format!(" (local ${} {})",
-                name, self.typename(&type_))
-            )
+            write!(self.output, " (local ${} {})", name, self.typename(type_));
         }
     }
 
+    /// Declares a program variable, which is either a local of the
+    /// current function or an exported global, or is kept in the
+    /// linear memory if it is an array or a record.
+    pub fn variable(&mut self, name: &str, type_: &Type) {
+        debug_assert!(!names::is_generated(name), "{}", name);
+        if type_.is_structured() {
+            self.allocate(name, type_);
+        } else if self.target.globals {
+            if !self.silenced {
+                self.globals.push((name.to_string(), ValueType::of(type_)));
+            }
+        } else {
+            self.func_local(name, type_);
+        }
+    }
+
+    /// Reserves the memory for the variable after the variables
+    /// declared before it. The memory is reserved even if the code
+    /// is not generated, so that it is checked that it suffices.
+    fn allocate(&mut self, name: &str, type_: &Type) {
+        let size = u64::from(type_.size().unwrap_or(0));
+        self.addresses.push((name.to_string(), self.data_end as u32));
+        // Every value is aligned to four bytes
+        self.data_end += size.div_ceil(4) * 4;
+        self.memory_used = true;
+    }
+
+    /// Makes the name stand for the memory at the address, which
+    /// holds a part of a variable, such as a field of a record.
+    pub fn alias(&mut self, name: &str, address: u32) {
+        debug_assert!(names::is_generated(name), "{}", name);
+        self.addresses.push((name.to_string(), address));
+    }
+
+    /// The address of a variable kept in the linear memory,
+    /// or none if the variable is not kept there.
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.addresses.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, address)| *address)
+    }
+
+    /// How many pages of memory the variables kept there take.
+    pub fn memory_pages(&self) -> u64 {
+        self.data_end.div_ceil(PAGE_SIZE)
+    }
+
     pub fn func_result(&mut self, type_: &Type) {
         if !self.silenced {
-            self.output.write(&format!(" (result {})", self.typename(type_)));
+            write!(self.output, " (result {})", self.typename(type_));
         }
     }
-    
+
     pub fn func_end(&mut self) {
         if !self.silenced {
+            let mut body = std::mem::take(&mut self.body);
+            for name in std::mem::take(&mut self.helpers) {
+                self.func_local(name, &Type::Integer);
+            }
+            for t in std::mem::take(&mut self.operands) {
+                let name = operand_name(t);
+                write!(self.output, " (local ${} {})", name, valuename(t));
+            }
+
+            if self.optimize {
+                let started = Instant::now();
+                for (name, t) in optimization::optimize(&mut body) {
+                    write!(self.output, " (local ${} {})", name, valuename(t));
+                }
+                self.metrics.optimization += started.elapsed();
+            }
+            self.metrics.instructions += body.len();
+
+            for instr in &body {
+                if let Instruction::Call(name) = instr {
+                    self.called.insert(name.clone());
+                }
+            }
+            if let Some(handler) = &mut self.function_handler {
+                handler(&self.function, &body);
+            }
+
+            let started = Instant::now();
+            self.render(&body);
             self.output.write(")\n");
             self.output.indent_out();
+            self.metrics.emission += started.elapsed();
         }
     }
 
     pub fn constant(&mut self, value: &str, type_: &Type) {
-        if !self.silenced {
-            self.output.writenl(&format!(
-                "{}.const {}",
-                self.typename(type_), value
-            ))
-        }
+        self.emit(Instruction::Const(ValueType::of(type_), value.to_string()));
     }
 
     pub fn local_set(&mut self, name: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("local.set ${}", name));
+        if self.is_global(name) {
+            self.emit(Instruction::GlobalSet(name.to_string()));
+        } else {
+            self.emit(Instruction::LocalSet(name.to_string()));
         }
     }
 
     pub fn local_get(&mut self, name: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("local.get ${}", name));
+        if self.is_global(name) {
+            self.emit(Instruction::GlobalGet(name.to_string()));
+        } else {
+            self.emit(Instruction::LocalGet(name.to_string()));
         }
     }
 
     pub fn op(&mut self, op: &Operator, type_: &Type) {
-        if !self.silenced {
-            let cmd = match op {
-                Operator::Multiply => "mul",
-                Operator::Plus => "add",
-                Operator::Minus => "sub",
-                Operator::Divide => "div",
-                Operator::Or => "or",
-                Operator::Xor => "xor",
-                _ => todo!("Support other operators")
-            };
-    
-            self.output.writenl(&format!(
-                "{}.{}",
-                self.typename(type_).to_owned(),
-                cmd
-            ));
+        let op = match op {
+            Operator::Multiply => BinaryOp::Mul,
+            Operator::Plus => BinaryOp::Add,
+            Operator::Minus => BinaryOp::Sub,
+            Operator::Divide => BinaryOp::Div,
+            Operator::IntegerDivide => BinaryOp::DivS,
+            Operator::Modulus => BinaryOp::RemS,
+            Operator::And => BinaryOp::And,
+            Operator::Or => BinaryOp::Or,
+            Operator::Xor => BinaryOp::Xor,
+            _ => todo!("Support other operators")
+        };
+
+        self.emit(Instruction::Binary(ValueType::of(type_), op));
+    }
+
+    /// Reports a division by zero before an integer division
+    /// when runtime errors are passed to the host.
+    /// A constant non-zero divisor is not checked.
+    pub fn divisor_check(&mut self, line: usize) {
+        if self.target.runtime_errors != RuntimeErrors::Callback {
+            return;
+        }
+
+        if let Some(Instruction::Const(_, v)) = self.body.last() {
+            if v.parse::<i32>().is_ok_and(|v| v != 0) {
+                return;
+            }
         }
+
+        self.helper(DIVISOR);
+        self.emit(Instruction::LocalTee(DIVISOR.to_string()));
+        self.emit(Instruction::Eqz(ValueType::I32));
+        self.emit(Instruction::If);
+        self.runtime_error(DIVISION_BY_ZERO, line);
+        self.emit(Instruction::End);
+        self.emit(Instruction::LocalGet(DIVISOR.to_string()));
     }
 
-    pub fn relop(&mut self, op: &Relation, type_: &Type) {
-        if !self.silenced {
-            let cmd = match (op, type_) {
-                (Relation::Eq, _) => "eq",
-                (Relation::Le, Type::Integer) => "le_s",
-                (Relation::Lt, Type::Integer) => "lt_s",
-                (Relation::Gt, Type::Integer) => "gt_s",
-                (Relation::Ge, Type::Integer) => "ge_s",
-                (Relation::Le, Type::Real) => "le",
-                (Relation::Lt, Type::Real) => "lt",
-                (Relation::Gt, Type::Real) => "gt",
-                (Relation::Ge, Type::Real) => "ge",
-                (Relation::Ne, _) => "ne",
-                _ => todo!("Implement other relation operators")
-            };
-    
-            self.output.writenl(&format!(
-                "{}.{}",
-                self.typename(type_).to_owned(),
-                cmd
-            ));
+    /// Checks that the index on top of the stack is within the bounds,
+    /// failing with a runtime error or trapping if it is not.
+    pub fn index_check(&mut self, low: i32, high: i32, line: usize) {
+        let index = self.helper(INDEX);
+        let int = |v: i32| Instruction::Const(ValueType::I32, v.to_string());
+        self.emit(Instruction::LocalTee(index.to_string()));
+        self.emit(int(low));
+        self.emit(Instruction::Compare(ValueType::I32, Relation::Lt));
+        self.emit(Instruction::LocalGet(index.to_string()));
+        self.emit(int(high));
+        self.emit(Instruction::Compare(ValueType::I32, Relation::Gt));
+        self.emit(Instruction::Binary(ValueType::I32, BinaryOp::Or));
+        self.emit(Instruction::If);
+        if self.target.runtime_errors == RuntimeErrors::Callback {
+            self.runtime_error(RANGE_CHECK, line);
+        } else {
+            self.emit(Instruction::Unreachable);
         }
+        self.emit(Instruction::End);
+        self.emit(Instruction::LocalGet(index.to_string()));
+    }
+
+    /// The local the selector of a `case` statement is kept in while
+    /// it is compared with the labels. All of them are compared before
+    /// any statement of the case runs, so nested ones share it.
+    pub fn case_selector(&mut self) -> &'static str {
+        self.helper(CASE_SELECTOR)
+    }
+
+    /// The integer local the generated code keeps a value in,
+    /// declared along with the function.
+    fn helper(&mut self, name: &'static str) -> &'static str {
+        if !self.silenced && !self.helpers.contains(&name) {
+            self.helpers.push(name);
+        }
+        name
+    }
+
+    /// Declares the import that receives runtime errors,
+    /// if they are passed to the host.
+    pub fn runtime_error_import(&mut self) {
+        if self.target.runtime_errors == RuntimeErrors::Callback {
+            let types = vec![Type::Integer, Type::Integer];
+            self.func_import(RUNTIME_ERROR, &types);
+        }
+    }
+
+    fn runtime_error(&mut self, code: i32, line: usize) {
+        self.emit(Instruction::Const(ValueType::I32, code.to_string()));
+        self.emit(Instruction::Const(ValueType::I32, line.to_string()));
+        self.emit(Instruction::Call(RUNTIME_ERROR.to_string()));
+        self.emit(Instruction::Unreachable);
+    }
+
+    pub fn relop(&mut self, op: &Relation, type_: &Type) {
+        self.emit(Instruction::Compare(ValueType::of(type_), *op));
     }
 
     pub fn eqz(&mut self, type_: &Type) {
-        self.output.writenl(&format!(
-            "{}.eqz", self.typename(type_)
-        ));
+        self.emit(Instruction::Eqz(ValueType::of(type_)));
     }
 
-    pub fn call(&mut self, name: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("call ${}", name));
+    /// Reads the value at the address on the stack plus the offset.
+    pub fn load(&mut self, type_: &Type, offset: u32) {
+        self.emit(Instruction::Load(ValueType::of(type_), offset));
+    }
+
+    /// Writes the value on top of the stack to the address
+    /// under it plus the offset.
+    pub fn store(&mut self, type_: &Type, offset: u32) {
+        self.emit(Instruction::Store(ValueType::of(type_), offset));
+    }
+
+    /// Writes the characters of the text one after another
+    /// to the address on the stack plus the offset.
+    pub fn store_text(&mut self, text: &str, offset: u32) {
+        let address = self.reusable_address(ADDRESS);
+        for (i, c) in text.chars().enumerate() {
+            let code = (c as u32).to_string();
+            self.emit(address.clone());
+            self.emit(Instruction::Const(ValueType::I32, code));
+            let offset = char_offset(offset, i);
+            self.emit(Instruction::Store(ValueType::I32, offset));
         }
     }
 
-    pub fn if_start(&mut self) {
-        if !self.silenced {
-            self.output.writenl("(if");
-            self.output.indent_in();
+    /// Replaces the address on the stack with whether the characters
+    /// at it plus the offset are the ones of the text, or with
+    /// whether they are not if `equal` is not set.
+    pub fn compare_text(&mut self, text: &str, offset: u32, equal: bool) {
+        let address = self.reusable_address(ADDRESS);
+        for (i, c) in text.chars().enumerate() {
+            let code = (c as u32).to_string();
+            self.emit(address.clone());
+            let offset = char_offset(offset, i);
+            self.emit(Instruction::Load(ValueType::I32, offset));
+            self.emit(Instruction::Const(ValueType::I32, code));
+            self.emit(Instruction::Compare(ValueType::I32, Relation::Eq));
+            if i > 0 {
+                self.emit(Instruction::Binary(ValueType::I32, BinaryOp::And));
+            }
+        }
+        if !equal {
+            self.emit(Instruction::Eqz(ValueType::I32));
         }
     }
 
-    pub fn then_start(&mut self) {
-        if !self.silenced {
-            self.output.writenl("(then");
-            self.output.indent_in();
+    /// Copies the value of the type from the address on top of the stack
+    /// plus the second offset to the address under it plus the first
+    /// one, taking both addresses.
+    pub fn copy(&mut self, type_: &Type, offsets: (u32, u32)) {
+        let (target_offset, source_offset) = offsets;
+        let source = self.reusable_address(ADDRESS);
+        let target = self.reusable_address(OTHER_ADDRESS);
+        for (offset, t) in type_.leaves() {
+            let t = ValueType::of(t);
+            self.emit(target.clone());
+            self.emit(source.clone());
+            self.emit(Instruction::Load(t, source_offset + offset));
+            self.emit(Instruction::Store(t, target_offset + offset));
         }
     }
 
-    pub fn then_end(&mut self) {
-        if !self.silenced {
-            self.output.write(")");
-            self.output.indent_out();
+    /// Replaces the two addresses on top of the stack with whether the
+    /// values of the type at them plus the offsets are equal, or with
+    /// whether they are not if `equal` is not set. The values are equal
+    /// if each of their fields or elements is.
+    pub fn compare(
+        &mut self,
+        type_: &Type,
+        offsets: (u32, u32),
+        equal: bool
+    ) {
+        let (left_offset, right_offset) = offsets;
+        let right = self.reusable_address(ADDRESS);
+        let left = self.reusable_address(OTHER_ADDRESS);
+        let leaves = type_.leaves();
+        for (i, (offset, t)) in leaves.iter().enumerate() {
+            let t = ValueType::of(t);
+            self.emit(left.clone());
+            self.emit(Instruction::Load(t, left_offset + offset));
+            self.emit(right.clone());
+            self.emit(Instruction::Load(t, right_offset + offset));
+            self.emit(Instruction::Compare(t, Relation::Eq));
+            if i > 0 {
+                self.emit(Instruction::Binary(ValueType::I32, BinaryOp::And));
+            }
+        }
+        if leaves.is_empty() {
+            self.emit(Instruction::Const(ValueType::I32, "1".to_string()));
+        }
+        if !equal {
+            self.emit(Instruction::Eqz(ValueType::I32));
         }
     }
 
-    pub fn else_start(&mut self) {
-        if !self.silenced {
-            self.output.writenl("(else");
-            self.output.indent_in();
+    /// Takes the address on top of the stack, returning the instruction
+    /// that pushes it again: the constant address itself or the local
+    /// it is moved to, the helper local named.
+    fn reusable_address(&mut self, local: &'static str) -> Instruction {
+        if let Some(Instruction::Const(ValueType::I32, _)) = self.body.last() {
+            if let Some(address) = self.body.pop() {
+                return address;
+            }
         }
+
+        let address = self.helper(local);
+        self.emit(Instruction::LocalSet(address.to_string()));
+        Instruction::LocalGet(address.to_string())
     }
 
-    pub fn else_end(&mut self) {
+    /// Where the code generated next starts.
+    pub fn mark(&self) -> usize {
+        self.body.len()
+    }
+
+    /// Converts the integer on top of the stack to a real.
+    pub fn widen(&mut self) {
+        self.emit(Instruction::Unary(UnaryOp::F32ConvertI32S));
+    }
+
+    /// Converts the integer computed by the code ending at `mark`,
+    /// an operand under the ones computed after it, to a real.
+    pub fn widen_at(&mut self, mark: usize) {
         if !self.silenced {
-            self.output.write(")");
-            self.output.indent_out();
+            let widen = Instruction::Unary(UnaryOp::F32ConvertI32S);
+            self.body.insert(mark, widen);
         }
     }
 
-    pub fn if_end(&mut self) {
-        if !self.silenced {
-            self.output.writenl(")");
-            self.output.indent_out();
+    /// Converts the real on top of the stack to an integer,
+    /// dropping its fraction.
+    pub fn trunc(&mut self) {
+        self.emit(Instruction::Unary(UnaryOp::I32TruncF32S));
+    }
+
+    /// Replaces the integer on top of the stack with whether it is odd.
+    pub fn odd(&mut self) {
+        self.emit(Instruction::Const(ValueType::I32, "1".to_string()));
+        self.emit(Instruction::Binary(ValueType::I32, BinaryOp::And));
+    }
+
+    /// Squares the number on top of the stack.
+    pub fn sqr(&mut self, type_: &Type) {
+        let t = ValueType::of(type_);
+        let x = self.operand(t);
+        self.emit(Instruction::LocalTee(x.to_string()));
+        self.emit(Instruction::LocalGet(x.to_string()));
+        self.emit(Instruction::Binary(t, BinaryOp::Mul));
+    }
+
+    /// Replaces the number on top of the stack with its absolute value.
+    pub fn abs(&mut self, type_: &Type) {
+        let t = ValueType::of(type_);
+        if t == ValueType::F32 {
+            self.emit(Instruction::Unary(UnaryOp::F32Abs));
+            return;
         }
+
+        // x < 0 ? 0 - x : x
+        let x = self.operand(t);
+        self.emit(Instruction::LocalSet(x.to_string()));
+        self.emit(Instruction::Const(t, "0".to_string()));
+        self.emit(Instruction::LocalGet(x.to_string()));
+        self.emit(Instruction::Binary(t, BinaryOp::Sub));
+        self.emit(Instruction::LocalGet(x.to_string()));
+        self.emit(Instruction::LocalGet(x.to_string()));
+        self.emit(Instruction::Const(t, "0".to_string()));
+        self.emit(Instruction::Compare(t, Relation::Lt));
+        self.emit(Instruction::Select);
     }
 
-    pub fn loop_start(&mut self, continue_label: &str, end_label: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("(block ${}", end_label));
-            self.output.indent_in();
-            self.output.writenl(&format!("(loop ${}", continue_label));
-            self.output.indent_in();
+    /// The local holding a standard function argument of the type,
+    /// declared along with the function.
+    fn operand(&mut self, t: ValueType) -> &'static str {
+        if !self.silenced && !self.operands.contains(&t) {
+            self.operands.push(t);
         }
+        operand_name(t)
     }
 
-    pub fn br(&mut self, label: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("br ${}", label));
+    /// Replaces the integer on top of the stack with a random
+    /// number below it, or with 0 if it is not positive.
+    pub fn random(&mut self) {
+        match self.target.random {
+            RandomSource::Import => self.call(RANDOM),
+            RandomSource::Xorshift => {
+                self.random_used |= !self.silenced;
+                self.call(RANDOM_FUNCTION);
+            }
         }
     }
 
-    pub fn br_if(&mut self, label: &str) {
-        if !self.silenced {
-            self.output.writenl(&format!("br_if ${}", label))
+    /// Seeds the random numbers with the current time.
+    pub fn randomize(&mut self) {
+        match self.target.random {
+            RandomSource::Import => self.call(RANDOMIZE),
+            RandomSource::Xorshift => {
+                // The state of the generator must not be zero
+                self.random_used |= !self.silenced;
+                self.call(TIME);
+                self.emit(Instruction::Const(ValueType::I32, "1".to_string()));
+                self.emit(Instruction::Binary(ValueType::I32, BinaryOp::Or));
+                self.emit(Instruction::GlobalSet(RANDOM_STATE.to_string()));
+            }
+        }
+    }
+
+    /// Defines the function that advances the xorshift generator
+    /// and returns its state modulo the argument.
+    fn random_function(&mut self) {
+        let (bound, x) = ("rt.bound", "rt.x");
+        self.func_start(RANDOM_FUNCTION, false);
+        write!(self.output, " (param ${} i32) (result i32)", bound);
+        self.func_local(x, &Type::Integer);
+
+        let int = |v: &str| Instruction::Const(ValueType::I32, v.to_string());
+        let binary = |op| Instruction::Binary(ValueType::I32, op);
+        self.emit(Instruction::GlobalGet(RANDOM_STATE.to_string()));
+        for &(shift, op) in [
+            ("13", BinaryOp::Shl),
+            ("17", BinaryOp::ShrU),
+            ("5", BinaryOp::Shl)
+        ].iter() {
+            self.emit(Instruction::LocalTee(x.to_string()));
+            self.emit(Instruction::LocalGet(x.to_string()));
+            self.emit(int(shift));
+            self.emit(binary(op));
+            self.emit(binary(BinaryOp::Xor));
         }
+        self.emit(Instruction::GlobalSet(RANDOM_STATE.to_string()));
+
+        self.emit(Instruction::LocalGet(bound.to_string()));
+        self.emit(int("0"));
+        self.emit(Instruction::Compare(ValueType::I32, Relation::Le));
+        self.emit(Instruction::If);
+        self.emit(int("0"));
+        self.emit(Instruction::Return);
+        self.emit(Instruction::End);
+        self.emit(Instruction::GlobalGet(RANDOM_STATE.to_string()));
+        self.emit(Instruction::LocalGet(bound.to_string()));
+        self.emit(binary(BinaryOp::RemU));
+        self.func_end();
+    }
+
+    /// Converts the real on top of the stack to the nearest integer.
+    pub fn round(&mut self) {
+        self.emit(Instruction::Unary(UnaryOp::F32Nearest));
+        self.emit(Instruction::Unary(UnaryOp::I32TruncF32S));
+    }
+
+    pub fn call(&mut self, name: &str) {
+        self.emit(Instruction::Call(name.to_string()));
+    }
+
+    pub fn if_start(&mut self) {
+        self.emit(Instruction::If);
+    }
+
+    pub fn else_start(&mut self) {
+        self.emit(Instruction::Else);
+    }
+
+    pub fn if_end(&mut self) {
+        self.emit(Instruction::End);
+    }
+
+    /// Opens a loop, returning its labels, which no other loop
+    /// of the module has, so a branch always reaches the loop
+    /// it is meant for however the loops nest.
+    pub fn loop_start(&mut self) -> LoopLabels {
+        let labels = LoopLabels {
+            next: names::generated(names::LOOP_CONTINUE, self.loops),
+            exit: names::generated(names::LOOP_END, self.loops),
+        };
+        self.loops += 1;
+        self.emit(Instruction::Block(labels.exit.clone()));
+        self.emit(Instruction::Loop(labels.next.clone()));
+        labels
+    }
+
+    pub fn br(&mut self, label: &str) {
+        self.emit(Instruction::Br(label.to_string()));
+    }
+
+    pub fn br_if(&mut self, label: &str) {
+        self.emit(Instruction::BrIf(label.to_string()));
+    }
+
+    pub fn return_(&mut self) {
+        self.emit(Instruction::Return);
     }
 
     pub fn loop_end(&mut self) {
-        if !self.silenced {
-            for _ in 0..2 {
-                self.output.indent_out();
-                self.output.writenl(")");
+        self.emit(Instruction::End);
+        self.emit(Instruction::End);
+    }
+
+    /// Validates the generated module, if it is to be validated,
+    /// and writes it out. An invalid module is discarded instead,
+    /// as is the part of a module held back when it was silenced.
+    pub fn finish(&mut self) -> Result<(), String> {
+        let started = Instant::now();
+        if self.silenced {
+            self.output.discard();
+        } else if self.validate {
+            if let Err(e) = validation::validate(&self.output.text()) {
+                self.output.discard();
+                self.metrics.emission += started.elapsed();
+                return Err(e);
             }
         }
+
+        self.output.flush();
+        self.metrics.emission += started.elapsed();
+        Ok(())
+    }
+
+    /// Starts a transaction over the code generated from now on,
+    /// returning where it starts.
+    pub fn transaction(&self) -> usize {
+        self.body.len()
+    }
+
+    /// Drops the code generated since the transaction started.
+    pub fn rollback(&mut self, transaction: usize) {
+        self.body.truncate(transaction);
     }
 
     pub fn silence(&mut self) {
@@ -233,21 +863,219 @@ impl Wasm {
     }
 
     pub fn fill_nearest_unknown(&mut self, t: &Type) {
+        let nearest = self.body.iter_mut().rev().find_map(|instr| match instr {
+            Instruction::Const(vt, _)
+            | Instruction::Binary(vt, _)
+            | Instruction::Compare(vt, _)
+            | Instruction::Eqz(vt) if *vt == ValueType::Unknown => Some(vt),
+            _ => None
+        });
+
+        if let Some(vt) = nearest {
+            *vt = ValueType::of(t);
+        }
+    }
+
+    fn is_global(&self, name: &str) -> bool {
+        self.globals.iter().any(|(n, _)| n == name)
+    }
+
+    fn emit(&mut self, instr: Instruction) {
         if !self.silenced {
-            self.output.fill_last_template(&self.typename(&t));
+            self.body.push(instr);
+        }
+    }
+
+    fn render(&mut self, body: &[Instruction]) {
+        // Whether each of the open blocks is an `if`
+        let mut open_ifs = Vec::new();
+        for instr in body {
+            match instr {
+                Instruction::Block(label) => {
+                    self.output.newline();
+                    write!(self.output, "(block ${}", label);
+                    self.output.indent_in();
+                    open_ifs.push(false);
+                },
+                Instruction::Loop(label) => {
+                    self.output.newline();
+                    write!(self.output, "(loop ${}", label);
+                    self.output.indent_in();
+                    open_ifs.push(false);
+                },
+                Instruction::If => {
+                    self.output.writenl("(if");
+                    self.output.indent_in();
+                    self.output.writenl("(then");
+                    self.output.indent_in();
+                    open_ifs.push(true);
+                },
+                Instruction::Else => {
+                    self.output.write(")");
+                    self.output.indent_out();
+                    self.output.writenl("(else");
+                    self.output.indent_in();
+                },
+                Instruction::End => {
+                    if open_ifs.pop().unwrap_or(false) {
+                        self.output.write(")");
+                        self.output.indent_out();
+                        self.output.writenl(")");
+                        self.output.indent_out();
+                    } else {
+                        self.output.indent_out();
+                        self.output.writenl(")");
+                    }
+                },
+                instr => {
+                    self.output.newline();
+                    write!(self.output, "{}", instr);
+                },
+            }
         }
     }
 
-    fn typename(&self, t: &Type) -> String {
-        match t {
-            Type::Integer => "i32",
-            Type::Real => "f32",
-            Type::Scalar(_) => "i32",
-            Type::Unknown => {
-                TEMPLATE
+    fn typename(&self, t: &Type) -> &'static str {
+        valuename(ValueType::of(t))
+    }
+}
+
+/// The text of the instruction, the structured ones
+/// without the instructions they enclose.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Const(t, v) => {
+                write!(f, "{}.const {}", valuename(*t), v)
+            },
+            Instruction::LocalGet(name) => write!(f, "local.get ${}", name),
+            Instruction::LocalSet(name) => write!(f, "local.set ${}", name),
+            Instruction::LocalTee(name) => write!(f, "local.tee ${}", name),
+            Instruction::GlobalGet(name) => write!(f, "global.get ${}", name),
+            Instruction::GlobalSet(name) => write!(f, "global.set ${}", name),
+            Instruction::Load(t, offset) => {
+                let offset = offset_immediate(*offset);
+                write!(f, "{}.load{}", valuename(*t), offset)
+            },
+            Instruction::Store(t, offset) => {
+                let offset = offset_immediate(*offset);
+                write!(f, "{}.store{}", valuename(*t), offset)
+            },
+            Instruction::Binary(t, op) => {
+                write!(f, "{}.{}", valuename(*t), opname(*op))
             },
-            _ => unimplemented!("unsupported type")
-        }.to_string()
+            Instruction::Unary(op) => f.write_str(unaryname(*op)),
+            Instruction::Compare(t, rel) => {
+                write!(f, "{}.{}", valuename(*t), relname(*rel, *t))
+            },
+            Instruction::Eqz(t) => write!(f, "{}.eqz", valuename(*t)),
+            Instruction::Select => f.write_str("select"),
+            Instruction::Call(name) => write!(f, "call ${}", name),
+            Instruction::Block(label) => write!(f, "block ${}", label),
+            Instruction::Loop(label) => write!(f, "loop ${}", label),
+            Instruction::If => f.write_str("if"),
+            Instruction::Else => f.write_str("else"),
+            Instruction::End => f.write_str("end"),
+            Instruction::Br(label) => write!(f, "br ${}", label),
+            Instruction::BrIf(label) => write!(f, "br_if ${}", label),
+            Instruction::Return => f.write_str("return"),
+            Instruction::Unreachable => f.write_str("unreachable"),
+        }
+    }
+}
+
+/// The text as a string of the text format, where a quote,
+/// a backslash and the control characters are escaped.
+fn quoted(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            c if c.is_control() => {
+                let mut bytes = [0; 4];
+                for b in c.encode_utf8(&mut bytes).bytes() {
+                    quoted += &format!("\\{:02x}", b);
+                }
+            },
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The offset of the `i`-th character of a text stored at the offset,
+/// as a character takes four bytes.
+fn char_offset(offset: u32, i: usize) -> u32 {
+    offset + 4 * i as u32
+}
+
+fn offset_immediate(offset: u32) -> String {
+    if offset == 0 {
+        String::new()
+    } else {
+        format!(" offset={}", offset)
+    }
+}
+
+fn valuename(t: ValueType) -> &'static str {
+    match t {
+        ValueType::I32 => "i32",
+        ValueType::F32 => "f32",
+        ValueType::Unknown => TEMPLATE,
+    }
+}
+
+fn opname(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div",
+        BinaryOp::DivS => "div_s",
+        BinaryOp::RemS => "rem_s",
+        BinaryOp::RemU => "rem_u",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::Xor => "xor",
+        BinaryOp::Shl => "shl",
+        BinaryOp::ShrS => "shr_s",
+        BinaryOp::ShrU => "shr_u",
+    }
+}
+
+fn operand_name(t: ValueType) -> &'static str {
+    match t {
+        ValueType::F32 => names::REAL_OPERAND,
+        _ => names::INTEGER_OPERAND,
+    }
+}
+
+fn unaryname(op: UnaryOp) -> &'static str {
+    match op {
+        UnaryOp::F32ConvertI32S => "f32.convert_i32_s",
+        UnaryOp::I32TruncF32S => "i32.trunc_f32_s",
+        UnaryOp::F32Nearest => "f32.nearest",
+        UnaryOp::F32Abs => "f32.abs",
+    }
+}
+
+fn relname(rel: Relation, t: ValueType) -> &'static str {
+    let float = t == ValueType::F32;
+    match rel {
+        Relation::Eq => "eq",
+        Relation::Ne => "ne",
+        Relation::Le if float => "le",
+        Relation::Lt if float => "lt",
+        Relation::Gt if float => "gt",
+        Relation::Ge if float => "ge",
+        Relation::Le => "le_s",
+        Relation::Lt => "lt_s",
+        Relation::Gt => "gt_s",
+        Relation::Ge => "ge_s",
     }
 }
 
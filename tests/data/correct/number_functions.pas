@@ -0,0 +1,22 @@
+program NumberFunctions;
+
+var
+  i: integer;
+  x: real;
+
+begin
+  i := -7;
+  x := -2.5;
+
+  if odd(i) then
+    writeln_int(1);
+  if not odd(i + 1) then
+    writeln_int(2);
+
+  writeln_int(sqr(i));
+  writeln_real(sqr(x));
+  writeln_int(abs(i));
+  writeln_int(abs(-i));
+  writeln_real(abs(x));
+  writeln_int(sqr(abs(i) - 10) + abs(sqr(2) - 5))
+end.
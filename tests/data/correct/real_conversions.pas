@@ -0,0 +1,29 @@
+program RealConversions;
+
+var
+  i, j: integer;
+  x, y: real;
+
+begin
+  i := 7;
+  x := i;
+  writeln_real(x);
+
+  y := i / 2;
+  writeln_real(y);
+
+  x := i * 0.5 + 1;
+  writeln_real(x);
+
+  if i < y + 4 then
+    writeln_int(1);
+
+  writeln_real(i);
+
+  j := trunc(y);
+  writeln_int(j);
+  writeln_int(round(y + 0.25));
+  writeln_int(trunc(-2.7));
+  writeln_int(round(-2.6));
+  writeln_int(round(i div 2))
+end.
@@ -0,0 +1,23 @@
+program Records;
+
+type
+  point = record x, y: integer end;
+
+var
+  a, b: point;
+  segment: record start, finish: point end;
+
+begin
+  a.x := 1;
+  a.y := 2;
+  b := a;
+  if a = b then writeln_int(b.x + b.y);
+
+  b.y := 5;
+  if a <> b then writeln_int(b.y);
+
+  segment.start := a;
+  segment.finish := b;
+  with segment do
+    with finish do writeln_int(start.x + x + y)
+end.
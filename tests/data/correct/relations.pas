@@ -0,0 +1,38 @@
+program Relations;
+
+var
+  a, b: integer;
+  c, d: char;
+  fruit: (Apple, Banana);
+  flag, other: boolean;
+
+begin
+  a := 1;
+  b := 2;
+  c := 'a';
+  d := 'b';
+  fruit := banana;
+
+  flag := a < b;
+  if flag then
+    writeln_int(1);
+
+  if c < d then
+    writeln_int(2);
+
+  other := (a < b) = (c > d);
+  if flag <> other then
+    writeln_int(3);
+
+  if false < true then
+    writeln_int(4);
+
+  if fruit = banana then
+    writeln_int(5);
+
+  flag := not (fruit <> banana) and (c = 'a');
+  if not flag then
+    writeln_int(0)
+  else
+    writeln_int(6)
+end.
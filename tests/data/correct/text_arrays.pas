@@ -0,0 +1,22 @@
+program TextArrays;
+
+var
+  word: packed array [1..5] of char;
+  squares: array [-2..2] of integer;
+  i: integer;
+
+begin
+  word := 'hello';
+  if word = 'hello' then writeln_int(1);
+  if word <> 'world' then writeln_int(2);
+
+  word[1] := 'j';
+  if word = 'jello' then writeln_int(3);
+
+  i := -2;
+  while i <= 2 do begin
+    squares[i] := i * i;
+    i := i + 1
+  end;
+  writeln_int(squares[-2] + squares[1])
+end.